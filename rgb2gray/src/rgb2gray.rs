@@ -2,6 +2,8 @@ use gst::glib;
 use gst::prelude::*;
 use gst::Plugin;
 
+mod agc;
+mod bayer;
 mod imp;
 
 glib::wrapper! {
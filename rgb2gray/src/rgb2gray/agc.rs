@@ -0,0 +1,348 @@
+//! Automatic gain control for 16-bit gray input.
+//!
+//! Every mode ends up as a 65536-entry lookup table from raw counts to 8-bit
+//! gray, so the per-pixel work is the same whichever mode is selected.
+
+use gst::glib;
+
+pub const DEFAULT_AGC_MODE: AgcMode = AgcMode::MinMax;
+pub const DEFAULT_AGC_LOW: u32 = 0;
+pub const DEFAULT_AGC_HIGH: u32 = 65535;
+pub const DEFAULT_AGC_PERCENTILE_LOW: f64 = 1.0;
+pub const DEFAULT_AGC_PERCENTILE_HIGH: f64 = 99.0;
+pub const DEFAULT_AGC_PLATEAU: u32 = 0;
+pub const DEFAULT_AGC_SMOOTHING: f64 = 0.9;
+
+const NUM_BINS: usize = 1 << 16;
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstRsRgb2GrayAgcMode")]
+pub enum AgcMode {
+    #[enum_value(name = "Fixed: window given by agc-low and agc-high", nick = "fixed")]
+    Fixed = 0,
+    #[enum_value(name = "Min-max: window spans each frame", nick = "min-max")]
+    MinMax = 1,
+    #[enum_value(
+        name = "Percentile: window between two histogram percentiles",
+        nick = "percentile"
+    )]
+    Percentile = 2,
+    #[enum_value(name = "Plateau: plateau histogram equalization", nick = "plateau")]
+    Plateau = 3,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AgcSettings {
+    pub mode: AgcMode,
+    pub low: u32,
+    pub high: u32,
+    pub percentile_low: f64,
+    pub percentile_high: f64,
+    /// Histogram clip limit for plateau equalization, 0 picks one per frame.
+    pub plateau: u32,
+    /// Weight of the previous frame's mapping, 0 disables smoothing.
+    pub smoothing: f64,
+}
+
+impl Default for AgcSettings {
+    fn default() -> Self {
+        Self {
+            mode: DEFAULT_AGC_MODE,
+            low: DEFAULT_AGC_LOW,
+            high: DEFAULT_AGC_HIGH,
+            percentile_low: DEFAULT_AGC_PERCENTILE_LOW,
+            percentile_high: DEFAULT_AGC_PERCENTILE_HIGH,
+            plateau: DEFAULT_AGC_PLATEAU,
+            smoothing: DEFAULT_AGC_SMOOTHING,
+        }
+    }
+}
+
+/// Raw count window mapped to the full 8-bit output range.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Window {
+    pub low: u16,
+    pub high: u16,
+}
+
+pub struct Agc {
+    hist: Vec<u32>,
+    window: Option<(f64, f64)>,
+    /// Smoothed plateau equalization curve, in 0..=255.
+    curve: Option<Vec<f32>>,
+    lut: Vec<u8>,
+    /// Whether the last update changed the lookup table.
+    changed: bool,
+}
+
+impl Default for Agc {
+    fn default() -> Self {
+        Self {
+            hist: vec![0; NUM_BINS],
+            window: None,
+            curve: None,
+            lut: vec![0; NUM_BINS],
+            changed: true,
+        }
+    }
+}
+
+impl Agc {
+    /// Forgets the temporal history, e.g. after a caps change.
+    pub fn reset(&mut self) {
+        self.window = None;
+        self.curve = None;
+    }
+
+    /// Computes the mapping for a frame made of `samples` and returns the
+    /// window it covers.
+    pub fn update(&mut self, settings: &AgcSettings, samples: &[u16]) -> Window {
+        for bin in self.hist.iter_mut() {
+            *bin = 0;
+        }
+        for &v in samples {
+            self.hist[v as usize] += 1;
+        }
+
+        let total = samples.len() as u64;
+        let smoothing = settings.smoothing.clamp(0.0, 1.0);
+
+        if settings.mode == AgcMode::Plateau {
+            self.window = None;
+            return self.update_plateau(settings.plateau, total, smoothing as f32);
+        }
+        self.curve = None;
+
+        let (low, high) = match settings.mode {
+            AgcMode::Fixed => (settings.low as f64, settings.high as f64),
+            AgcMode::MinMax => (
+                self.percentile_bin(total, 0) as f64,
+                self.percentile_bin(total, total.saturating_sub(1)) as f64,
+            ),
+            AgcMode::Percentile => {
+                let rank = |p: f64| ((p.clamp(0.0, 100.0) / 100.0) * total as f64) as u64;
+                let low_rank = rank(settings.percentile_low);
+                let high_rank = rank(settings.percentile_high).min(total.saturating_sub(1));
+                (
+                    self.percentile_bin(total, low_rank) as f64,
+                    self.percentile_bin(total, high_rank) as f64,
+                )
+            }
+            AgcMode::Plateau => unreachable!(),
+        };
+
+        // A fixed window is exactly what the user asked for; do not smooth it.
+        let (low, high) = match self.window {
+            Some((prev_low, prev_high)) if settings.mode != AgcMode::Fixed => (
+                prev_low * smoothing + low * (1.0 - smoothing),
+                prev_high * smoothing + high * (1.0 - smoothing),
+            ),
+            _ => (low, high),
+        };
+        self.window = Some((low, high));
+
+        let low = low.round().clamp(0.0, 65535.0);
+        let high = high.round().clamp(low, 65535.0);
+        let span = (high - low).max(1.0);
+        self.changed = false;
+        for (v, out) in self.lut.iter_mut().enumerate() {
+            let mapped = (((v as f64 - low) * 255.0 / span).round()).clamp(0.0, 255.0) as u8;
+            self.changed |= *out != mapped;
+            *out = mapped;
+        }
+
+        Window {
+            low: low as u16,
+            high: high as u16,
+        }
+    }
+
+    /// Whether the last `update` changed the mapping, which tells if the
+    /// output of an identical frame can be reused.
+    pub fn changed(&self) -> bool {
+        self.changed
+    }
+
+    /// Maps a raw 16-bit count to 8-bit gray.
+    #[inline]
+    pub fn map(&self, v: u16) -> u8 {
+        self.lut[v as usize]
+    }
+
+    /// Value of the sample with the given rank in the current histogram.
+    fn percentile_bin(&self, total: u64, rank: u64) -> u16 {
+        if total == 0 {
+            return 0;
+        }
+
+        let mut acc = 0u64;
+        for (v, &count) in self.hist.iter().enumerate() {
+            acc += u64::from(count);
+            if acc > rank {
+                return v as u16;
+            }
+        }
+
+        u16::MAX
+    }
+
+    fn update_plateau(&mut self, plateau: u32, total: u64, smoothing: f32) -> Window {
+        let occupied = self.hist.iter().filter(|&&c| c > 0).count() as u64;
+        // Without an explicit limit, clip at the mean of the occupied bins so
+        // that large uniform areas (sky, background) do not eat up the range.
+        let plateau = if plateau == 0 {
+            (total / occupied.max(1)).max(1) as u32
+        } else {
+            plateau
+        };
+
+        let clipped_total: u64 = self.hist.iter().map(|&c| u64::from(c.min(plateau))).sum();
+
+        let mut curve = Vec::with_capacity(NUM_BINS);
+        let mut acc = 0u64;
+        for &count in &self.hist {
+            acc += u64::from(count.min(plateau));
+            curve.push(acc as f32 * 255.0 / clipped_total.max(1) as f32);
+        }
+
+        if let Some(prev) = self.curve.as_ref() {
+            for (v, prev) in curve.iter_mut().zip(prev.iter()) {
+                *v = prev * smoothing + *v * (1.0 - smoothing);
+            }
+        }
+
+        self.changed = false;
+        for (out, v) in self.lut.iter_mut().zip(curve.iter()) {
+            let mapped = v.round().clamp(0.0, 255.0) as u8;
+            self.changed |= *out != mapped;
+            *out = mapped;
+        }
+        self.curve = Some(curve);
+
+        // Report the range of raw counts that the curve actually spreads out.
+        let low = self.lut.iter().position(|&v| v > 0).unwrap_or(0);
+        let high = self.lut.iter().position(|&v| v == 255).unwrap_or(NUM_BINS - 1);
+
+        Window {
+            low: low as u16,
+            high: high as u16,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(mode: AgcMode) -> AgcSettings {
+        AgcSettings {
+            mode,
+            smoothing: 0.0,
+            ..AgcSettings::default()
+        }
+    }
+
+    #[test]
+    fn test_fixed() {
+        let mut agc = Agc::default();
+        let settings = AgcSettings {
+            low: 1000,
+            high: 2000,
+            ..settings(AgcMode::Fixed)
+        };
+
+        let window = agc.update(&settings, &[0, 65535]);
+        assert_eq!(window, Window { low: 1000, high: 2000 });
+        assert_eq!(agc.map(0), 0);
+        assert_eq!(agc.map(1000), 0);
+        assert_eq!(agc.map(1500), 128);
+        assert_eq!(agc.map(2000), 255);
+        assert_eq!(agc.map(65535), 255);
+    }
+
+    #[test]
+    fn test_min_max() {
+        let mut agc = Agc::default();
+
+        let window = agc.update(&settings(AgcMode::MinMax), &[300, 100, 200]);
+        assert_eq!(window, Window { low: 100, high: 300 });
+        assert_eq!(agc.map(100), 0);
+        assert_eq!(agc.map(200), 128);
+        assert_eq!(agc.map(300), 255);
+    }
+
+    #[test]
+    fn test_percentile() {
+        let mut agc = Agc::default();
+        let settings = AgcSettings {
+            percentile_low: 10.0,
+            percentile_high: 90.0,
+            ..settings(AgcMode::Percentile)
+        };
+        let samples = (0..100).collect::<Vec<u16>>();
+
+        let window = agc.update(&settings, &samples);
+        assert_eq!(window, Window { low: 10, high: 90 });
+        assert_eq!(agc.map(5), 0);
+        assert_eq!(agc.map(95), 255);
+    }
+
+    #[test]
+    fn test_plateau() {
+        let mut agc = Agc::default();
+        let mut samples = vec![100; 50];
+        samples.extend_from_slice(&[200; 50]);
+
+        let window = agc.update(&settings(AgcMode::Plateau), &samples);
+        assert_eq!(window, Window { low: 100, high: 200 });
+        assert_eq!(agc.map(99), 0);
+        assert_eq!(agc.map(100), 128);
+        assert_eq!(agc.map(200), 255);
+    }
+
+    #[test]
+    fn test_smoothing() {
+        let mut agc = Agc::default();
+        let settings = AgcSettings {
+            smoothing: 0.5,
+            ..settings(AgcMode::MinMax)
+        };
+
+        agc.update(&settings, &[0, 1000]);
+        let window = agc.update(&settings, &[1000, 2000]);
+        assert_eq!(window, Window { low: 500, high: 1500 });
+
+        agc.reset();
+        let window = agc.update(&settings, &[1000, 2000]);
+        assert_eq!(window, Window { low: 1000, high: 2000 });
+    }
+
+    #[test]
+    fn test_changed() {
+        let mut agc = Agc::default();
+        let settings = settings(AgcMode::MinMax);
+
+        agc.update(&settings, &[0, 1000]);
+        assert!(agc.changed());
+        agc.update(&settings, &[0, 1000]);
+        assert!(!agc.changed());
+        agc.update(&settings, &[0, 2000]);
+        assert!(agc.changed());
+    }
+
+    #[test]
+    fn test_fixed_is_not_smoothed() {
+        let mut agc = Agc::default();
+        let settings = AgcSettings {
+            low: 1000,
+            high: 2000,
+            smoothing: 0.5,
+            ..settings(AgcMode::Fixed)
+        };
+
+        agc.update(&AgcSettings { low: 0, ..settings }, &[0]);
+        let window = agc.update(&settings, &[0]);
+        assert_eq!(window, Window { low: 1000, high: 2000 });
+    }
+}
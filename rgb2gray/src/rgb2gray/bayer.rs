@@ -0,0 +1,210 @@
+//! Minimal support for `video/x-bayer` input.
+//!
+//! Only what is needed to compute luma is implemented here; there is no full
+//! demosaic. Samples are widened to 16 bits so that 8- and 16-bit mosaics go
+//! through the same code.
+
+use gst::glib;
+
+/// Values accepted in the `format` field of `video/x-bayer` caps.
+pub const BAYER_FORMATS: &[&str] = &[
+    "bggr",
+    "gbrg",
+    "grbg",
+    "rggb",
+    "bggr16le",
+    "bggr16be",
+    "gbrg16le",
+    "gbrg16be",
+    "grbg16le",
+    "grbg16be",
+    "rggb16le",
+    "rggb16be",
+];
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstRsRgb2GrayBayerMethod")]
+pub enum BayerMethod {
+    #[enum_value(name = "Half: one output pixel per 2x2 cell", nick = "half")]
+    Half = 0,
+    #[enum_value(
+        name = "Interpolate: full resolution luminance interpolation",
+        nick = "interpolate"
+    )]
+    Interpolate = 1,
+}
+
+impl BayerMethod {
+    /// Output dimension for a given Bayer input dimension.
+    pub fn output_dimension(self, dim: i32) -> i32 {
+        match self {
+            BayerMethod::Half => (dim / 2).max(1),
+            BayerMethod::Interpolate => dim,
+        }
+    }
+
+    /// Bayer input dimension for a given output dimension.
+    pub fn input_dimension(self, dim: i32) -> i32 {
+        match self {
+            BayerMethod::Half => dim.saturating_mul(2),
+            BayerMethod::Interpolate => dim,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pattern {
+    Bggr,
+    Gbrg,
+    Grbg,
+    Rggb,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Layout {
+    U8,
+    U16Le,
+    U16Be,
+}
+
+impl Layout {
+    fn bytes_per_sample(self) -> usize {
+        match self {
+            Layout::U8 => 1,
+            Layout::U16Le | Layout::U16Be => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BayerInfo {
+    pattern: Pattern,
+    layout: Layout,
+    width: usize,
+    height: usize,
+    stride: usize,
+}
+
+impl BayerInfo {
+    pub fn from_caps(caps: &gst::CapsRef) -> Option<Self> {
+        let s = caps.structure(0)?;
+        if s.name() != "video/x-bayer" {
+            return None;
+        }
+
+        let format = s.get::<&str>("format").ok()?;
+        let (pattern, layout) = match format {
+            "bggr" => (Pattern::Bggr, Layout::U8),
+            "gbrg" => (Pattern::Gbrg, Layout::U8),
+            "grbg" => (Pattern::Grbg, Layout::U8),
+            "rggb" => (Pattern::Rggb, Layout::U8),
+            "bggr16le" => (Pattern::Bggr, Layout::U16Le),
+            "bggr16be" => (Pattern::Bggr, Layout::U16Be),
+            "gbrg16le" => (Pattern::Gbrg, Layout::U16Le),
+            "gbrg16be" => (Pattern::Gbrg, Layout::U16Be),
+            "grbg16le" => (Pattern::Grbg, Layout::U16Le),
+            "grbg16be" => (Pattern::Grbg, Layout::U16Be),
+            "rggb16le" => (Pattern::Rggb, Layout::U16Le),
+            "rggb16be" => (Pattern::Rggb, Layout::U16Be),
+            _ => return None,
+        };
+
+        let width = s.get::<i32>("width").ok().filter(|w| *w > 0)? as usize;
+        let height = s.get::<i32>("height").ok().filter(|h| *h > 0)? as usize;
+
+        // Same row alignment as bayer2rgb.
+        let stride = (width * layout.bytes_per_sample() + 3) & !3;
+
+        Some(Self {
+            pattern,
+            layout,
+            width,
+            height,
+            stride,
+        })
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    /// Bytes of sample data in a line, without padding.
+    pub fn line_bytes(&self) -> usize {
+        self.width * self.layout.bytes_per_sample()
+    }
+
+    pub fn size(&self) -> usize {
+        self.stride * self.height
+    }
+
+    /// Colour channel (0 = R, 1 = G, 2 = B) of the photosite at `(x, y)`.
+    #[inline]
+    fn channel(&self, x: usize, y: usize) -> usize {
+        let cell = match self.pattern {
+            Pattern::Bggr => [2, 1, 1, 0],
+            Pattern::Gbrg => [1, 2, 0, 1],
+            Pattern::Grbg => [1, 0, 2, 1],
+            Pattern::Rggb => [0, 1, 1, 2],
+        };
+        cell[(y & 1) * 2 + (x & 1)]
+    }
+
+    /// Sample at column `x` of `line`, widened to 16 bits.
+    #[inline]
+    fn sample(&self, line: &[u8], x: usize) -> u32 {
+        match self.layout {
+            Layout::U8 => u32::from(line[x]) * 257,
+            Layout::U16Le => u32::from(u16::from_le_bytes([line[2 * x], line[2 * x + 1]])),
+            Layout::U16Be => u32::from(u16::from_be_bytes([line[2 * x], line[2 * x + 1]])),
+        }
+    }
+
+    /// Average R, G and B of the 2x2 window whose top-left photosite is
+    /// `(x, y)`. Any 2x2 window of a Bayer mosaic holds exactly one red, one
+    /// blue and two green photosites, whatever its phase.
+    #[inline]
+    fn window_rgb(&self, data: &[u8], x: usize, y: usize) -> [u32; 3] {
+        let x1 = (x + 1).min(self.width - 1);
+        let y1 = (y + 1).min(self.height - 1);
+
+        let mut sum = [0u32; 3];
+        let mut count = [0u32; 3];
+        for (sx, sy) in [(x, y), (x1, y), (x, y1), (x1, y1)] {
+            let line = &data[sy * self.stride..];
+            let c = self.channel(sx, sy);
+            sum[c] += self.sample(line, sx);
+            count[c] += 1;
+        }
+
+        // At the right and bottom edges the window is clamped and may miss a
+        // channel; fall back to green, which is always present.
+        let g = sum[1] / count[1].max(1);
+        let avg = |c: usize| sum[c].checked_div(count[c]).unwrap_or(g);
+
+        [avg(0), g, avg(2)]
+    }
+
+    /// Fills `out` with the 16-bit RGB of every output pixel of row `out_y`.
+    ///
+    /// With [`BayerMethod::Half`] each output pixel is one 2x2 cell, with
+    /// [`BayerMethod::Interpolate`] a 2x2 window slides over every photosite.
+    pub fn rgb_line(&self, method: BayerMethod, data: &[u8], out_y: usize, out: &mut [[u32; 3]]) {
+        match method {
+            BayerMethod::Half => {
+                for (out_x, out_p) in out.iter_mut().enumerate() {
+                    *out_p = self.window_rgb(data, 2 * out_x, 2 * out_y);
+                }
+            }
+            BayerMethod::Interpolate => {
+                for (x, out_p) in out.iter_mut().enumerate() {
+                    *out_p = self.window_rgb(data, x, out_y);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,308 @@
+use std::str::FromStr;
+
+use gst::prelude::*;
+
+fn init() {
+    use std::sync::Once;
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        gst::init().unwrap();
+        rgb2gray::plugin_register_static().expect("rgb2gray test");
+    });
+}
+
+#[test]
+fn test_colour_pad_carries_input() {
+    init();
+
+    let caps = "video/x-raw,format=BGRx,width=2,height=2,framerate=30/1";
+    let mut h = gst_check::Harness::with_padnames("rsrgb2gray", Some("sink"), Some("src"));
+    h.set_sink_caps_str("video/x-raw,format=GRAY8");
+    let element = h.element().unwrap();
+    let mut colour = gst_check::Harness::with_element(&element, None, Some("src_%u"));
+
+    h.set_src_caps_str(caps);
+    let data = vec![
+        0, 0, 0, 0, 255, 255, 255, 0, //
+        0, 0, 255, 0, 0, 255, 0, 0, //
+    ];
+    let mut buffer = gst::Buffer::from_mut_slice(data.clone());
+    buffer.get_mut().unwrap().set_pts(gst::ClockTime::SECOND);
+    assert_eq!(h.push(buffer), Ok(gst::FlowSuccess::Ok));
+
+    let gray = h.pull().unwrap();
+    let original = colour.pull().unwrap();
+
+    assert_eq!(gray.size(), 2 * 2);
+    assert_eq!(original.pts(), Some(gst::ClockTime::SECOND));
+    assert_eq!(original.pts(), gray.pts());
+    assert_eq!(original.map_readable().unwrap().as_slice(), data.as_slice());
+    assert_eq!(
+        colour.sinkpad().unwrap().current_caps().unwrap(),
+        gst::Caps::from_str(caps).unwrap()
+    );
+}
+
+#[test]
+fn test_dedup_counts_skipped_frames() {
+    init();
+
+    let mut h = gst_check::Harness::new("rsrgb2gray");
+    let element = h.element().unwrap();
+    element.set_property("dedup", true);
+    h.set_src_caps_str("video/x-raw,format=BGRx,width=16,height=16,framerate=30/1");
+
+    let input = gst::Buffer::from_mut_slice(vec![10; 16 * 16 * 4]);
+    let first = h.push_and_pull(input.clone()).unwrap();
+    // Only a copy of the input is kept.
+    assert!(input.is_writable());
+    let second = h
+        .push_and_pull(gst::Buffer::from_mut_slice(vec![10; 16 * 16 * 4]))
+        .unwrap();
+    assert_eq!(element.property::<u64>("skipped-frames"), 1);
+    assert_eq!(
+        first.map_readable().unwrap().as_slice(),
+        second.map_readable().unwrap().as_slice()
+    );
+
+    // Row 1 is not hashed, but the change must still be seen.
+    let mut data = vec![10; 16 * 16 * 4];
+    data[16 * 4] = 200;
+    let third = h.push_and_pull(gst::Buffer::from_mut_slice(data)).unwrap();
+    assert_eq!(element.property::<u64>("skipped-frames"), 1);
+    assert_ne!(
+        second.map_readable().unwrap().as_slice(),
+        third.map_readable().unwrap().as_slice()
+    );
+}
+
+#[test]
+fn test_dedup_keeps_agc_running() {
+    init();
+
+    let gray16_frame = |v: u16| {
+        let data = (0..16 * 16)
+            .flat_map(|i| [0, v / 2, v][i % 3].to_le_bytes())
+            .collect::<Vec<u8>>();
+        gst::Buffer::from_mut_slice(data)
+    };
+
+    let outputs = |dedup: bool| {
+        let mut h = gst_check::Harness::new("rsrgb2gray");
+        let element = h.element().unwrap();
+        element.set_property("dedup", dedup);
+        element.set_property("agc-smoothing", 0.5f64);
+        h.set_src_caps_str("video/x-raw,format=GRAY16_LE,width=16,height=16,framerate=30/1");
+
+        let outputs = [1000, 2000, 2000, 2000]
+            .iter()
+            .map(|&v| {
+                let outbuf = h.push_and_pull(gray16_frame(v)).unwrap();
+                let data = outbuf.map_readable().unwrap().to_vec();
+                data
+            })
+            .collect::<Vec<_>>();
+
+        (outputs, element.property::<u64>("skipped-frames"))
+    };
+
+    let (expected, _) = outputs(false);
+    let (actual, skipped) = outputs(true);
+
+    // The window still moves while smoothing converges on the repeated frame.
+    assert_ne!(expected[1], expected[2]);
+    assert_eq!(actual, expected);
+    assert_eq!(skipped, 0);
+}
+
+/// Red, green, blue and white BGRx pixels.
+const RGBW_BGRX: [u8; 16] = [
+    0, 0, 255, 0, 0, 255, 0, 0, 255, 0, 0, 0, 255, 255, 255, 0, //
+];
+/// BT.601 luma of `RGBW_BGRX`.
+const RGBW_LUMA: [u8; 4] = [76, 149, 29, 255];
+
+/// Format and luma of the output of `h` for `RGBW_BGRX`, or the flow error.
+fn convert_rgbw(h: &mut gst_check::Harness) -> Result<(String, Vec<u8>), gst::FlowError> {
+    h.push(gst::Buffer::from_slice(RGBW_BGRX))?;
+    let outbuf = h.pull().unwrap();
+    let caps = h.sinkpad().unwrap().current_caps().unwrap();
+    let format = caps.structure(0).unwrap().get::<String>("format").unwrap();
+    let data = outbuf.map_readable().unwrap();
+    let luma = if format == "BGRx" {
+        data.chunks_exact(4)
+            .map(|p| {
+                assert_eq!(p[0], p[1]);
+                assert_eq!(p[0], p[2]);
+                p[0]
+            })
+            .collect()
+    } else {
+        data.to_vec()
+    };
+    Ok((format, luma))
+}
+
+#[test]
+fn test_output_format() {
+    init();
+
+    for (output_format, downstream, expected) in [
+        ("auto", "video/x-raw", "GRAY8"),
+        ("auto", "video/x-raw,format=BGRx", "BGRx"),
+        ("gray8", "video/x-raw", "GRAY8"),
+        ("bgrx", "video/x-raw", "BGRx"),
+        ("bgrx", "video/x-raw,format={GRAY8,BGRx}", "BGRx"),
+    ] {
+        let mut h = gst_check::Harness::new("rsrgb2gray");
+        h.element().unwrap().set_property_from_str("output-format", output_format);
+        h.set_sink_caps_str(downstream);
+        h.set_src_caps_str("video/x-raw,format=BGRx,width=4,height=1,framerate=30/1");
+
+        assert_eq!(
+            convert_rgbw(&mut h),
+            Ok((String::from(expected), RGBW_LUMA.to_vec())),
+            "{} to {}",
+            output_format,
+            downstream
+        );
+    }
+
+    // A forced format downstream does not accept fails to negotiate.
+    let mut h = gst_check::Harness::new("rsrgb2gray");
+    h.element().unwrap().set_property_from_str("output-format", "gray8");
+    h.set_sink_caps_str("video/x-raw,format=BGRx");
+    h.set_src_caps_str("video/x-raw,format=BGRx,width=4,height=1,framerate=30/1");
+    assert_eq!(convert_rgbw(&mut h), Err(gst::FlowError::NotNegotiated));
+
+    // Changing the property renegotiates.
+    let mut h = gst_check::Harness::new("rsrgb2gray");
+    h.set_src_caps_str("video/x-raw,format=BGRx,width=4,height=1,framerate=30/1");
+    assert_eq!(convert_rgbw(&mut h).unwrap().0, "GRAY8");
+    h.element().unwrap().set_property_from_str("output-format", "bgrx");
+    assert_eq!(convert_rgbw(&mut h).unwrap().0, "BGRx");
+    h.element().unwrap().set_property_from_str("output-format", "auto");
+    assert_eq!(convert_rgbw(&mut h).unwrap().0, "GRAY8");
+}
+
+/// A 4x4 mosaic of `format` with R, G and B photosites of 200, 100 and 50.
+fn bayer_frame(format: &str) -> gst::Buffer {
+    let value = |channel: char| match channel {
+        'r' => 200u16,
+        'g' => 100,
+        _ => 50,
+    };
+    let pattern: Vec<char> = format.chars().take(4).collect();
+
+    let mut data = Vec::new();
+    for y in 0..4 {
+        for x in 0..4 {
+            let sample = value(pattern[(y % 2) * 2 + x % 2]);
+            match &format[4..] {
+                "" => data.push(sample as u8),
+                "16le" => data.extend_from_slice(&(sample * 257).to_le_bytes()),
+                _ => data.extend_from_slice(&(sample * 257).to_be_bytes()),
+            }
+        }
+    }
+    gst::Buffer::from_mut_slice(data)
+}
+
+#[test]
+fn test_bayer_luma() {
+    init();
+
+    // (200 * 0.299 + 100 * 0.587 + 50 * 0.114) truncated.
+    const LUMA: u8 = 124;
+
+    for format in [
+        "bggr", "gbrg", "grbg", "rggb", "bggr16le", "bggr16be", "gbrg16le", "gbrg16be", "grbg16le",
+        "grbg16be", "rggb16le", "rggb16be",
+    ] {
+        for (method, size) in [("half", 2), ("interpolate", 4)] {
+            let mut h = gst_check::Harness::new("rsrgb2gray");
+            h.element().unwrap().set_property_from_str("bayer-method", method);
+            h.set_sink_caps_str("video/x-raw,format=GRAY8");
+            h.set_src_caps_str(&format!(
+                "video/x-bayer,format={},width=4,height=4,framerate=30/1",
+                format
+            ));
+
+            let outbuf = h.push_and_pull(bayer_frame(format)).unwrap();
+            let caps = h.sinkpad().unwrap().current_caps().unwrap();
+            let s = caps.structure(0).unwrap();
+            assert_eq!(s.get::<i32>("width").unwrap(), size);
+            assert_eq!(s.get::<i32>("height").unwrap(), size);
+
+            // Interpolated windows at the right and bottom edges are
+            // clamped and miss channels.
+            let data = outbuf.map_readable().unwrap();
+            let inner = if method == "half" { 2 } else { 3 };
+            for y in 0..inner {
+                for x in 0..inner {
+                    assert_eq!(data[y * 4 + x], LUMA, "{} {} at {}x{}", format, method, x, y);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_bayer_dimensions() {
+    init();
+
+    // Half rounds odd sizes down but never below one pixel.
+    let mut h = gst_check::Harness::new("rsrgb2gray");
+    h.set_sink_caps_str("video/x-raw,format=GRAY8");
+    h.set_src_caps_str("video/x-bayer,format=rggb,width=5,height=1,framerate=30/1");
+    h.push_and_pull(gst::Buffer::from_mut_slice(vec![0; 8])).unwrap();
+    let caps = h.sinkpad().unwrap().current_caps().unwrap();
+    let s = caps.structure(0).unwrap();
+    assert_eq!((s.get::<i32>("width").unwrap(), s.get::<i32>("height").unwrap()), (2, 1));
+
+    // Upstream is asked for twice the size with half and the same size
+    // when interpolating.
+    for (method, expected) in [("half", (6, 4)), ("interpolate", (3, 2))] {
+        let mut h = gst_check::Harness::new("rsrgb2gray");
+        let element = h.element().unwrap();
+        element.set_property_from_str("bayer-method", method);
+        h.set_sink_caps_str("video/x-raw,format=GRAY8,width=3,height=2");
+
+        let caps = element.static_pad("sink").unwrap().query_caps(None);
+        let s = caps
+            .iter()
+            .find(|s| s.name() == "video/x-bayer")
+            .unwrap();
+        assert_eq!(
+            (s.get::<i32>("width").unwrap(), s.get::<i32>("height").unwrap()),
+            expected,
+            "{}",
+            method
+        );
+    }
+}
+
+#[test]
+fn test_colour_pad_errors_do_not_stop_gray() {
+    init();
+
+    let mut h = gst_check::Harness::with_padnames("rsrgb2gray", Some("sink"), Some("src"));
+    h.set_sink_caps_str("video/x-raw,format=GRAY8");
+    let element = h.element().unwrap();
+
+    let failing = gst::Pad::builder(Some("sink"), gst::PadDirection::Sink)
+        .chain_function(|_, _, _| Err(gst::FlowError::Error))
+        .build();
+    failing.set_active(true).unwrap();
+    let colour = element.request_pad_simple("src_%u").unwrap();
+    colour.link(&failing).unwrap();
+
+    h.set_src_caps_str("video/x-raw,format=BGRx,width=2,height=2,framerate=30/1");
+    for _ in 0..2 {
+        let gray = h
+            .push_and_pull(gst::Buffer::from_mut_slice(vec![255; 2 * 2 * 4]))
+            .unwrap();
+        assert_eq!(gray.map_readable().unwrap().as_slice(), [255; 4]);
+    }
+}
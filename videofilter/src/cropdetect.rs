@@ -0,0 +1,20 @@
+use gst::glib::{self, StaticType};
+
+mod detector;
+mod imp;
+
+glib::wrapper! {
+    pub struct CropDetect(ObjectSubclass<imp::CropDetect>) @extends gst_base::BaseTransform, gst::Element, gst::Object;
+}
+
+unsafe impl Send for CropDetect {}
+unsafe impl Sync for CropDetect {}
+
+pub(crate) fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "rscropdetect",
+        gst::Rank::None,
+        CropDetect::static_type(),
+    )
+}
@@ -0,0 +1,176 @@
+//! Black border detection on luma.
+//!
+//! Rows and columns are scanned inwards from each edge for as long as their
+//! mean luma is below the threshold. The borders are then the smallest of
+//! the last `window` frames, so that they grow only once a wider border has
+//! been seen for the whole window but shrink as soon as the picture reaches
+//! further out, for instance in a fade from black.
+
+use std::collections::VecDeque;
+
+use gst::glib;
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstCropDetectMode")]
+pub enum CropDetectMode {
+    #[enum_value(name = "Only post the detected borders", nick = "detect")]
+    Detect = 0,
+    #[enum_value(name = "Crop the detected borders", nick = "apply")]
+    Apply = 1,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Borders {
+    pub left: u32,
+    pub right: u32,
+    pub top: u32,
+    pub bottom: u32,
+}
+
+impl Borders {
+    fn min(self, other: Self) -> Self {
+        Self {
+            left: self.left.min(other.left),
+            right: self.right.min(other.right),
+            top: self.top.min(other.top),
+            bottom: self.bottom.min(other.bottom),
+        }
+    }
+}
+
+/// Borders of the `width` x `height` frame of `luma`, rounded down to
+/// multiples of `align_x` and `align_y`, or `None` if the frame is black.
+pub fn borders(luma: &[f32], width: usize, height: usize, threshold: f32, align_x: u32, align_y: u32) -> Option<Borders> {
+    let row_dark = |y: usize| luma[y * width..(y + 1) * width].iter().sum::<f32>() < threshold * width as f32;
+
+    let top = (0..height).position(|y| !row_dark(y))?;
+    let bottom = (0..height).rev().position(|y| !row_dark(y)).unwrap();
+
+    // Columns are only scanned within the rows of the picture.
+    let rows = top..height - bottom;
+    let column_dark = |x: usize| rows.clone().map(|y| luma[y * width + x]).sum::<f32>() < threshold * rows.len() as f32;
+
+    let left = (0..width).position(|x| !column_dark(x)).unwrap_or(0);
+    let right = (0..width).rev().position(|x| !column_dark(x)).unwrap_or(0);
+
+    let align = |v: usize, align: u32| v as u32 / align.max(1) * align.max(1);
+
+    Some(Borders {
+        left: align(left, align_x),
+        right: align(right, align_x),
+        top: align(top, align_y),
+        bottom: align(bottom, align_y),
+    })
+}
+
+#[derive(Debug, Default)]
+pub struct Detector {
+    window: VecDeque<Borders>,
+    stable: Borders,
+}
+
+impl Detector {
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn borders(&self) -> Borders {
+        self.stable
+    }
+
+    /// Adds the borders of a frame and returns the stable borders if they
+    /// changed.
+    pub fn push(&mut self, borders: Borders, window: u32) -> Option<Borders> {
+        let window = window.max(1) as usize;
+
+        self.window.push_back(borders);
+        while self.window.len() > window {
+            self.window.pop_front();
+        }
+
+        let min = self.window.iter().fold(borders, |min, &borders| min.min(borders));
+        // Wider borders have to be seen for the whole window first.
+        let stable = if self.window.len() < window {
+            self.stable.min(min)
+        } else {
+            min
+        };
+
+        if stable != self.stable {
+            self.stable = stable;
+            Some(stable)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 8;
+    const HEIGHT: usize = 6;
+
+    fn borders_of(left: u32, right: u32, top: u32, bottom: u32) -> Borders {
+        Borders {
+            left,
+            right,
+            top,
+            bottom,
+        }
+    }
+
+    #[test]
+    fn test_borders() {
+        // One row at the top, two at the bottom, two columns on the left and
+        // one on the right.
+        let mut luma: Vec<f32> = (0..WIDTH * HEIGHT)
+            .map(|i| {
+                let (x, y) = (i % WIDTH, i / WIDTH);
+                if (2..WIDTH - 1).contains(&x) && (1..HEIGHT - 2).contains(&y) {
+                    200.0
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+        // Something dim in the top border, above the left one, which is
+        // not enough to make the row or the column part of the picture.
+        luma[0] = 100.0;
+
+        assert_eq!(borders(&luma, WIDTH, HEIGHT, 16.0, 1, 1), Some(borders_of(2, 1, 1, 2)));
+        assert_eq!(borders(&luma, WIDTH, HEIGHT, 16.0, 2, 2), Some(borders_of(2, 0, 0, 2)));
+        assert_eq!(borders(&luma, WIDTH, HEIGHT, 0.0, 1, 1), Some(borders_of(0, 0, 0, 0)));
+        assert_eq!(borders(&[200.0; WIDTH * HEIGHT], WIDTH, HEIGHT, 16.0, 1, 1), Some(Borders::default()));
+        assert_eq!(borders(&[0.0; WIDTH * HEIGHT], WIDTH, HEIGHT, 16.0, 1, 1), None);
+    }
+
+    #[test]
+    fn test_detector() {
+        let mut detector = Detector::default();
+        let wide = borders_of(10, 10, 20, 20);
+        let narrow = borders_of(4, 10, 0, 20);
+
+        // Wider borders are only taken once seen for the whole window.
+        assert_eq!(detector.push(wide, 3), None);
+        assert_eq!(detector.push(wide, 3), None);
+        assert_eq!(detector.push(wide, 3), Some(wide));
+        assert_eq!(detector.push(wide, 3), None);
+
+        // Narrower ones are taken at once.
+        assert_eq!(detector.push(narrow, 3), Some(narrow));
+        assert_eq!(detector.push(wide, 3), None);
+        assert_eq!(detector.push(wide, 3), None);
+        assert_eq!(detector.push(wide, 3), Some(wide));
+        assert_eq!(detector.borders(), wide);
+
+        // Every border is the smallest of the window on its own.
+        assert_eq!(detector.push(borders_of(12, 8, 20, 20), 3), Some(borders_of(10, 8, 20, 20)));
+
+        detector.reset();
+        assert_eq!(detector.borders(), Borders::default());
+        assert_eq!(detector.push(wide, 1), Some(wide));
+    }
+}
@@ -0,0 +1,525 @@
+use gst::{glib, gst_debug, gst_info, gst_log, Buffer, BufferRef, Caps, ErrorMessage, Event, FlowError, FlowSuccess, LoggableError, PadDirection, PadTemplate, QueryRef};
+use gst::subclass::ElementMetadata;
+use gst::subclass::prelude::*;
+use gst::prelude::*;
+use gst_base::prelude::*;
+use gst_base::subclass::{*, prelude::*};
+use parking_lot::Mutex;
+use crate::glib::once_cell::sync::Lazy;
+
+use super::detector::{self, Borders, CropDetectMode, Detector};
+use crate::videofilter::{get_all_video_formats, luma, Geometry};
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rscropdetect",
+        gst::DebugColorFlags::empty(),
+        Some("Black border detection"),
+    )
+});
+
+const DEFAULT_MODE: CropDetectMode = CropDetectMode::Detect;
+const DEFAULT_THRESHOLD: f64 = 24.0;
+const DEFAULT_WINDOW: u32 = 30;
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    mode: CropDetectMode,
+    threshold: f64,
+    window: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            mode: DEFAULT_MODE,
+            threshold: DEFAULT_THRESHOLD,
+            window: DEFAULT_WINDOW,
+        }
+    }
+}
+
+struct State {
+    in_info: gst_video::VideoInfo,
+    out_info: gst_video::VideoInfo,
+    /// Crop of the negotiated caps, which lags behind the detected borders
+    /// until the next renegotiation.
+    geometry: Geometry,
+    use_crop_meta: bool,
+}
+
+#[derive(Default)]
+pub struct CropDetect {
+    settings: Mutex<Settings>,
+    state: Mutex<Option<State>>,
+    /// Kept across caps changes, as applying the borders changes the caps,
+    /// but reset when the input size changes.
+    detector: Mutex<Detector>,
+}
+
+fn crop(borders: Borders) -> Geometry {
+    Geometry {
+        crop_left: borders.left,
+        crop_right: borders.right,
+        crop_top: borders.top,
+        crop_bottom: borders.bottom,
+        ..Geometry::default()
+    }
+}
+
+impl CropDetect {
+    /// Crop to negotiate with the current settings and borders.
+    fn geometry(&self) -> Geometry {
+        match self.settings.lock().mode {
+            CropDetectMode::Detect => Geometry::default(),
+            CropDetectMode::Apply => crop(self.detector.lock().borders()),
+        }
+    }
+
+    /// Forgets the detected borders, renegotiating if they were applied.
+    fn reset_detector(&self, element: &super::CropDetect) {
+        let detected = {
+            let mut detector = self.detector.lock();
+            let detected = detector.borders() != Borders::default();
+            detector.reset();
+            detected
+        };
+
+        if detected && self.settings.lock().mode == CropDetectMode::Apply {
+            element.reconfigure_src();
+        }
+    }
+
+    fn detect(&self, element: &super::CropDetect, buf: &BufferRef) -> Result<(), FlowError> {
+        if buf.flags().contains(gst::BufferFlags::DISCONT) {
+            gst_debug!(CAT, obj: element, "Discontinuity, detecting the borders again");
+            self.reset_detector(element);
+        }
+
+        let settings = *self.settings.lock();
+        let info = match self.state.lock().as_ref() {
+            Some(state) => state.in_info.clone(),
+            None => {
+                gst::element_error!(element, gst::CoreError::Negotiation, ["Have no state yet"]);
+                return Err(FlowError::NotNegotiated);
+            }
+        };
+
+        let frame = gst_video::VideoFrameRef::from_buffer_ref_readable(buf, &info).map_err(|err| {
+            gst::element_error!(
+                element,
+                gst::CoreError::Failed,
+                [&format!("Failed to map buffer readable: {}", err)]
+            );
+            FlowError::Error
+        })?;
+        let luma = luma(&frame).map_err(|err| {
+            gst::element_error!(element, gst::CoreError::Failed, [&err]);
+            FlowError::Error
+        })?;
+
+        // Borders are kept on chroma sample boundaries.
+        let finfo = info.format_info();
+        let components = finfo.n_components() as usize;
+        let align_x = 1 << finfo.w_sub()[..components].iter().copied().max().unwrap_or(0);
+        let align_y = 1 << finfo.h_sub()[..components].iter().copied().max().unwrap_or(0);
+
+        let borders = match detector::borders(
+            &luma,
+            info.width() as usize,
+            info.height() as usize,
+            settings.threshold as f32,
+            align_x,
+            align_y,
+        ) {
+            Some(borders) => borders,
+            None => {
+                gst_log!(CAT, obj: element, "Skipping black frame");
+                return Ok(());
+            }
+        };
+
+        let stable = match self.detector.lock().push(borders, settings.window) {
+            Some(stable) => stable,
+            None => return Ok(()),
+        };
+
+        let (x, y, width, height) = crop(stable).crop_rect(info.width(), info.height());
+        gst_info!(CAT, obj: element, "Detected borders {:?}", stable);
+
+        let s = gst::Structure::builder("rscropdetect")
+            .field("timestamp", buf.pts())
+            .field("left", stable.left)
+            .field("right", stable.right)
+            .field("top", stable.top)
+            .field("bottom", stable.bottom)
+            .field("x", x)
+            .field("y", y)
+            .field("width", width)
+            .field("height", height)
+            .build();
+        let _ = element.post_message(gst::message::Element::builder(s).src(element).build());
+
+        if settings.mode == CropDetectMode::Apply {
+            element.reconfigure_src();
+        }
+
+        Ok(())
+    }
+
+    /// Crops `buf` by adding a `GstVideoCropMeta`, or by narrowing the one
+    /// it already has.
+    fn add_crop_meta(buf: &mut BufferRef, state: &State) -> Result<(), glib::BoolError> {
+        let info = &state.in_info;
+
+        if buf.meta::<gst_video::VideoMeta>().is_none() {
+            gst_video::VideoMeta::add_full(
+                buf,
+                gst_video::VideoFrameFlags::empty(),
+                info.format(),
+                info.width(),
+                info.height(),
+                info.offset(),
+                info.stride(),
+            )?;
+        }
+
+        let (x, y, width, height) = state.geometry.crop_rect(info.width(), info.height());
+        if let Some(mut meta) = buf.meta_mut::<gst_video::VideoCropMeta>() {
+            let (meta_x, meta_y, _, _) = meta.rect();
+            meta.set_rect((meta_x + x, meta_y + y, width, height));
+        } else {
+            gst_video::VideoCropMeta::add(buf, (x, y, width, height));
+        }
+
+        Ok(())
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for CropDetect {
+    const NAME: &'static str = "RsCropDetect";
+    type Type = super::CropDetect;
+    type ParentType = gst_base::BaseTransform;
+}
+
+impl ElementImpl for CropDetect {
+    fn metadata() -> Option<&'static ElementMetadata> {
+        static METADATA: Lazy<ElementMetadata> = Lazy::new(|| {
+            ElementMetadata::new(
+                "Black border detection",
+                "Filter/Analyzer/Video",
+                "Detect letterbox and pillarbox borders and optionally crop them",
+                "Seiichi Uchida <topecongiro@fastmail.com>",
+            )
+        });
+
+        Some(&*METADATA)
+    }
+
+    fn pad_templates() -> &'static [PadTemplate] {
+        static PAD_TEMPLATE: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let caps = gst::Caps::builder("video/x-raw")
+                .field("format", gst::List::from(get_all_video_formats()))
+                .field("width", gst::IntRange::new(1, i32::MAX))
+                .field("height", gst::IntRange::new(1, i32::MAX))
+                .field("framerate", gst::FractionRange::new(
+                    gst::Fraction::new(0, 1),
+                    gst::Fraction::new(i32::MAX, 1),
+                ))
+                .build();
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &caps,
+            ).unwrap();
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &caps,
+            ).unwrap();
+
+            vec![sink_pad_template, src_pad_template]
+        });
+
+        PAD_TEMPLATE.as_ref()
+    }
+}
+
+impl BaseTransformImpl for CropDetect {
+    const MODE: BaseTransformMode = BaseTransformMode::Both;
+    const PASSTHROUGH_ON_SAME_CAPS: bool = true;
+    const TRANSFORM_IP_ON_PASSTHROUGH: bool = true;
+
+    fn transform_caps(&self, element: &Self::Type, direction: PadDirection, caps: &Caps, filter: Option<&Caps>) -> Option<Caps> {
+        let other_caps = self.geometry().transform_caps(caps, direction);
+
+        gst_debug!(
+            CAT,
+            obj: element,
+            "Transformed caps from {} to {} in direction {:?}",
+            caps,
+            other_caps,
+            direction
+        );
+
+        if let Some(filter) = filter {
+            Some(filter.intersect_with_mode(&other_caps, gst::CapsIntersectMode::First))
+        } else {
+            Some(other_caps)
+        }
+    }
+
+    fn set_caps(&self, element: &Self::Type, incaps: &Caps, outcaps: &Caps) -> Result<(), LoggableError> {
+        let in_info = gst_video::VideoInfo::from_caps(incaps)
+            .map_err(|_| gst::loggable_error!(CAT, "Failed to parse input caps"))?;
+        let out_info = gst_video::VideoInfo::from_caps(outcaps)
+            .map_err(|_| gst::loggable_error!(CAT, "Failed to parse output caps"))?;
+
+        let out_size = Some((out_info.width(), out_info.height()));
+        let geometry = [self.geometry(), Geometry::default()]
+            .into_iter()
+            .find(|geometry| geometry.output_size(in_info.width(), in_info.height()) == out_size)
+            .ok_or_else(|| gst::loggable_error!(CAT, "Output size does not match the detected borders"))?;
+
+        gst_debug!(CAT, obj: element, "Configured for caps {} to {} with {:?}", incaps, outcaps, geometry);
+
+        *self.state.lock() = Some(State {
+            in_info,
+            out_info,
+            geometry,
+            use_crop_meta: false,
+        });
+
+        Ok(())
+    }
+
+    fn decide_allocation(
+        &self,
+        element: &Self::Type,
+        query: gst::query::Allocation<&mut QueryRef>,
+    ) -> Result<(), LoggableError> {
+        let crop_meta_supported = query.find_allocation_meta::<gst_video::VideoCropMeta>().is_some()
+            && query.find_allocation_meta::<gst_video::VideoMeta>().is_some();
+
+        let use_crop_meta = match self.state.lock().as_mut() {
+            Some(state) => {
+                state.use_crop_meta = crop_meta_supported && state.geometry.has_crop();
+                state.use_crop_meta
+            }
+            None => false,
+        };
+
+        // Like videocrop, the input buffers are then pushed as they are,
+        // with the output caps.
+        if use_crop_meta {
+            gst_info!(CAT, obj: element, "Cropping with GstVideoCropMeta");
+            element.set_passthrough(false);
+        }
+        element.set_in_place(use_crop_meta);
+
+        self.parent_decide_allocation(element, query)
+    }
+
+    fn unit_size(&self, _element: &Self::Type, caps: &Caps) -> Option<usize> {
+        gst_video::VideoInfo::from_caps(caps)
+            .as_ref()
+            .map(gst_video::VideoInfo::size)
+            .ok()
+    }
+
+    fn stop(&self, element: &Self::Type) -> Result<(), ErrorMessage> {
+        let _ = self.state.lock().take();
+        self.detector.lock().reset();
+
+        gst_info!(CAT, obj: element, "Stopped");
+
+        Ok(())
+    }
+
+    fn sink_event(&self, element: &Self::Type, event: Event) -> bool {
+        match event.view() {
+            gst::EventView::FlushStop(_) => self.reset_detector(element),
+            // The borders of another size do not apply, and have to be
+            // forgotten before the new caps are transformed.
+            gst::EventView::Caps(caps) => {
+                let size = gst_video::VideoInfo::from_caps(caps.caps())
+                    .ok()
+                    .map(|info| (info.width(), info.height()));
+                let current = self
+                    .state
+                    .lock()
+                    .as_ref()
+                    .map(|state| (state.in_info.width(), state.in_info.height()));
+                if current.is_some() && size != current {
+                    gst_debug!(CAT, obj: element, "Size changed, detecting the borders again");
+                    self.reset_detector(element);
+                }
+            }
+            _ => (),
+        }
+
+        self.parent_sink_event(element, event)
+    }
+
+    fn transform_ip_passthrough(&self, element: &Self::Type, buf: &Buffer) -> Result<FlowSuccess, FlowError> {
+        gst_log!(CAT, obj: element, "transform_ip_passthrough: {:?}", buf);
+
+        self.detect(element, buf)?;
+
+        Ok(FlowSuccess::Ok)
+    }
+
+    fn transform_ip(&self, element: &Self::Type, buf: &mut BufferRef) -> Result<FlowSuccess, FlowError> {
+        gst_log!(CAT, obj: element, "transform_ip: {:?}", buf);
+
+        self.detect(element, buf)?;
+
+        let state_guard = self.state.lock();
+        let state = state_guard.as_ref().ok_or(FlowError::NotNegotiated)?;
+        if state.use_crop_meta {
+            Self::add_crop_meta(buf, state).map_err(|err| {
+                gst::element_error!(
+                    element,
+                    gst::CoreError::Failed,
+                    [&format!("Failed to add video meta: {}", err)]
+                );
+                FlowError::Error
+            })?;
+        }
+
+        Ok(FlowSuccess::Ok)
+    }
+
+    fn transform(&self, element: &Self::Type, inbuf: &Buffer, outbuf: &mut BufferRef) -> Result<FlowSuccess, FlowError> {
+        gst_log!(CAT, obj: element, "transform: {:?}", inbuf);
+
+        self.detect(element, inbuf)?;
+
+        let state_guard = self.state.lock();
+        let state = state_guard.as_ref().ok_or(FlowError::NotNegotiated)?;
+
+        let in_frame = gst_video::VideoFrameRef::from_buffer_ref_readable(inbuf.as_ref(), &state.in_info)
+            .map_err(|err| {
+                gst::element_error!(
+                    element,
+                    gst::CoreError::Failed,
+                    [&format!("Failed to map input buffer readable: {}", err)]
+                );
+                FlowError::Error
+            })?;
+        let mut out_frame = gst_video::VideoFrameRef::from_buffer_ref_writable(outbuf, &state.out_info)
+            .map_err(|err| {
+                gst::element_error!(
+                    element,
+                    gst::CoreError::Failed,
+                    [&format!("Failed to map output buffer writable: {}", err)]
+                );
+                FlowError::Error
+            })?;
+        state.geometry.apply(&in_frame, &mut out_frame).map_err(|err| {
+            gst::element_error!(element, gst::CoreError::Failed, [&format!("Failed to crop: {}", err)]);
+            FlowError::Error
+        })?;
+
+        Ok(FlowSuccess::Ok)
+    }
+}
+
+impl GstObjectImpl for CropDetect {}
+
+impl ObjectImpl for CropDetect {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecEnum::new(
+                    "mode",
+                    "Mode",
+                    "Whether to only post the detected borders or to also crop them",
+                    CropDetectMode::static_type(),
+                    DEFAULT_MODE as i32,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpecDouble::new(
+                    "threshold",
+                    "Threshold",
+                    "Mean luma, in 8-bit code values, below which a row or column is part of a border",
+                    0.0,
+                    255.0,
+                    DEFAULT_THRESHOLD,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt::new(
+                    "window",
+                    "Window",
+                    "Number of frames wider borders have to be seen for",
+                    1,
+                    u32::MAX,
+                    DEFAULT_WINDOW,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(&self, obj: &Self::Type, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        match pspec.name() {
+            "mode" => {
+                let mut settings = self.settings.lock();
+                let mode = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing mode from {:?} to {:?}",
+                    settings.mode, mode
+                );
+                settings.mode = mode;
+            }
+            "threshold" => {
+                let mut settings = self.settings.lock();
+                let threshold = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing threshold from {} to {}",
+                    settings.threshold, threshold
+                );
+                settings.threshold = threshold;
+            }
+            "window" => {
+                let mut settings = self.settings.lock();
+                let window = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing window from {} to {}",
+                    settings.window, window
+                );
+                settings.window = window;
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn property(&self, _obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        match pspec.name() {
+            "mode" => {
+                let settings = self.settings.lock();
+                settings.mode.to_value()
+            }
+            "threshold" => {
+                let settings = self.settings.lock();
+                settings.threshold.to_value()
+            }
+            "window" => {
+                let settings = self.settings.lock();
+                settings.window.to_value()
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
@@ -1,9 +1,27 @@
 use gst::glib;
 
-mod videofilter;
+pub mod cropdetect;
+pub mod motiondetect;
+pub mod privacymask;
+pub mod qc;
+pub mod roioverlay;
+pub mod scenechange;
+pub mod scopes;
+pub mod videofilter;
+pub mod videotestsrc;
+pub mod watermark;
 
 fn plugin_init(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
     videofilter::register(plugin)?;
+    motiondetect::register(plugin)?;
+    scenechange::register(plugin)?;
+    qc::register(plugin)?;
+    cropdetect::register(plugin)?;
+    privacymask::register(plugin)?;
+    roioverlay::register(plugin)?;
+    watermark::register(plugin)?;
+    scopes::register(plugin)?;
+    videotestsrc::register(plugin)?;
     Ok(())
 }
 
@@ -0,0 +1,20 @@
+use gst::glib::{self, StaticType};
+
+mod imp;
+mod model;
+
+glib::wrapper! {
+    pub struct MotionDetect(ObjectSubclass<imp::MotionDetect>) @extends gst_base::BaseTransform, gst::Element, gst::Object;
+}
+
+unsafe impl Send for MotionDetect {}
+unsafe impl Sync for MotionDetect {}
+
+pub(crate) fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "rsmotiondetect",
+        gst::Rank::None,
+        MotionDetect::static_type(),
+    )
+}
@@ -0,0 +1,536 @@
+use gst::{glib, gst_debug, gst_info, gst_log, Buffer, BufferRef, Caps, ErrorMessage, Event, FlowError, FlowSuccess, LoggableError, PadDirection, PadTemplate};
+use gst::subclass::ElementMetadata;
+use gst::subclass::prelude::*;
+use gst::prelude::*;
+use gst_base::prelude::*;
+use gst_base::subclass::{*, prelude::*};
+use parking_lot::Mutex;
+use crate::glib::once_cell::sync::Lazy;
+
+use super::model::{self, BackgroundModel, BoundingBox, Model};
+use crate::videofilter::{get_all_video_formats, luma};
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rsmotiondetect",
+        gst::DebugColorFlags::empty(),
+        Some("Motion detection"),
+    )
+});
+
+const DEFAULT_MODEL: BackgroundModel = BackgroundModel::Mixture;
+const DEFAULT_HISTORY: u32 = 500;
+const DEFAULT_VARIANCE_THRESHOLD: f64 = 16.0;
+const DEFAULT_MOTION_THRESHOLD: f64 = 0.5;
+const DEFAULT_MIN_BLOB_AREA: u32 = 64;
+const DEFAULT_OUTPUT_MASK: bool = false;
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    model: BackgroundModel,
+    history: u32,
+    variance_threshold: f64,
+    motion_threshold: f64,
+    min_blob_area: u32,
+    output_mask: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            model: DEFAULT_MODEL,
+            history: DEFAULT_HISTORY,
+            variance_threshold: DEFAULT_VARIANCE_THRESHOLD,
+            motion_threshold: DEFAULT_MOTION_THRESHOLD,
+            min_blob_area: DEFAULT_MIN_BLOB_AREA,
+            output_mask: DEFAULT_OUTPUT_MASK,
+        }
+    }
+}
+
+struct State {
+    in_info: gst_video::VideoInfo,
+    out_info: gst_video::VideoInfo,
+    model: Model,
+    /// Foreground mask of the last frame, 255 for moving pixels.
+    mask: Vec<u8>,
+    in_motion: bool,
+}
+
+#[derive(Default)]
+pub struct MotionDetect {
+    settings: Mutex<Settings>,
+    state: Mutex<Option<State>>,
+}
+
+impl MotionDetect {
+    /// Updates the background model with `buf`, writing the foreground mask
+    /// to `mask_frame` if given, and returns the moving regions together
+    /// with the message to post if motion started or stopped.
+    fn detect(
+        &self,
+        element: &super::MotionDetect,
+        buf: &BufferRef,
+        mask_frame: Option<&mut gst_video::VideoFrameRef<&mut BufferRef>>,
+    ) -> Result<(Vec<BoundingBox>, Option<gst::Structure>), FlowError> {
+        let settings = *self.settings.lock();
+        let mut state_guard = self.state.lock();
+        let state = state_guard.as_mut().ok_or_else(|| {
+            gst::element_error!(element, gst::CoreError::Negotiation, ["Have no state yet"]);
+            FlowError::NotNegotiated
+        })?;
+
+        let luma = {
+            let frame = gst_video::VideoFrameRef::from_buffer_ref_readable(buf, &state.in_info).map_err(|err| {
+                gst::element_error!(
+                    element,
+                    gst::CoreError::Failed,
+                    [&format!("Failed to map buffer readable: {}", err)]
+                );
+                FlowError::Error
+            })?;
+            luma(&frame).map_err(|err| {
+                gst::element_error!(element, gst::CoreError::Failed, [&err]);
+                FlowError::Error
+            })?
+        };
+
+        let width = state.in_info.width() as usize;
+        let height = state.in_info.height() as usize;
+        state.mask.resize(width * height, 0);
+        let foreground = state.model.apply(
+            &luma,
+            &mut state.mask,
+            settings.history,
+            settings.variance_threshold as f32,
+        );
+        let changed = 100.0 * foreground as f64 / (width * height) as f64;
+
+        gst_log!(CAT, obj: element, "{:.2}% of the pixels changed", changed);
+
+        if let Some(mask_frame) = mask_frame {
+            let stride = mask_frame.plane_stride()[0] as usize;
+            let data = mask_frame.plane_data_mut(0).map_err(|_| FlowError::Error)?;
+            for (line, mask) in data.chunks_mut(stride).zip(state.mask.chunks_exact(width)) {
+                line[..width].copy_from_slice(mask);
+            }
+        }
+
+        // Stopping below half the threshold avoids flapping around it.
+        let in_motion = if state.in_motion {
+            changed >= settings.motion_threshold / 2.0
+        } else {
+            changed >= settings.motion_threshold
+        };
+
+        let message = if in_motion != state.in_motion {
+            state.in_motion = in_motion;
+            gst_info!(
+                CAT,
+                obj: element,
+                "Motion {} with {:.2}% of the pixels changed",
+                if in_motion { "started" } else { "stopped" },
+                changed
+            );
+
+            let pts = buf.pts();
+            let running_time = element
+                .segment()
+                .downcast_ref::<gst::ClockTime>()
+                .and_then(|segment| segment.to_running_time(pts));
+            let name = if in_motion {
+                "rsmotiondetect-start"
+            } else {
+                "rsmotiondetect-stop"
+            };
+
+            Some(
+                gst::Structure::builder(name)
+                    .field("changed", changed)
+                    .field("pts", pts)
+                    .field("running-time", running_time)
+                    .build(),
+            )
+        } else {
+            None
+        };
+
+        let blobs = model::blobs(&state.mask, width, height, settings.min_blob_area as usize);
+
+        Ok((blobs, message))
+    }
+
+    fn finish(
+        &self,
+        element: &super::MotionDetect,
+        buf: &mut BufferRef,
+        blobs: Vec<BoundingBox>,
+        message: Option<gst::Structure>,
+    ) {
+        for rect in blobs {
+            gst_video::VideoRegionOfInterestMeta::add(buf, "motion", rect);
+        }
+
+        if let Some(s) = message {
+            let _ = element.post_message(gst::message::Element::builder(s).src(element).build());
+        }
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for MotionDetect {
+    const NAME: &'static str = "RsMotionDetect";
+    type Type = super::MotionDetect;
+    type ParentType = gst_base::BaseTransform;
+}
+
+impl ElementImpl for MotionDetect {
+    fn metadata() -> Option<&'static ElementMetadata> {
+        static METADATA: Lazy<ElementMetadata> = Lazy::new(|| {
+            ElementMetadata::new(
+                "Motion detection",
+                "Filter/Analyzer/Video",
+                "Detect motion by background subtraction",
+                "Seiichi Uchida <topecongiro@fastmail.com>",
+            )
+        });
+
+        Some(&*METADATA)
+    }
+
+    fn pad_templates() -> &'static [PadTemplate] {
+        static PAD_TEMPLATE: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let caps = gst::Caps::builder("video/x-raw")
+                .field("format", gst::List::from(get_all_video_formats()))
+                .field("width", gst::IntRange::new(1, i32::MAX))
+                .field("height", gst::IntRange::new(1, i32::MAX))
+                .field("framerate", gst::FractionRange::new(
+                    gst::Fraction::new(0, 1),
+                    gst::Fraction::new(i32::MAX, 1),
+                ))
+                .build();
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &caps,
+            ).unwrap();
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &caps,
+            ).unwrap();
+
+            vec![sink_pad_template, src_pad_template]
+        });
+
+        PAD_TEMPLATE.as_ref()
+    }
+}
+
+impl BaseTransformImpl for MotionDetect {
+    const MODE: BaseTransformMode = BaseTransformMode::Both;
+    // Regions of interest are attached to the buffers, which must be
+    // writable, so the element works in place instead of in passthrough.
+    const PASSTHROUGH_ON_SAME_CAPS: bool = false;
+    const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+
+    fn transform_caps(&self, element: &Self::Type, direction: PadDirection, caps: &Caps, filter: Option<&Caps>) -> Option<Caps> {
+        let output_mask = self.settings.lock().output_mask;
+
+        let other_caps = if !output_mask {
+            caps.clone()
+        } else {
+            // The mask is GRAY8 of the input size, made from any input format.
+            let mut other_caps = Caps::new_empty();
+            {
+                let other_caps = other_caps.get_mut()?;
+                for s in caps.iter() {
+                    let mut s = s.to_owned();
+                    s.remove_fields(&["format", "colorimetry", "chroma-site"]);
+                    if direction == PadDirection::Sink {
+                        s.set("format", gst_video::VideoFormat::Gray8.to_str());
+                    }
+                    other_caps.append_structure(s);
+                }
+            }
+            let pad = if direction == PadDirection::Sink { "src" } else { "sink" };
+            other_caps.intersect_with_mode(
+                &element.static_pad(pad)?.pad_template_caps(),
+                gst::CapsIntersectMode::First,
+            )
+        };
+
+        gst_debug!(
+            CAT,
+            obj: element,
+            "Transformed caps from {} to {} in direction {:?}",
+            caps,
+            other_caps,
+            direction
+        );
+
+        if let Some(filter) = filter {
+            Some(filter.intersect_with_mode(&other_caps, gst::CapsIntersectMode::First))
+        } else {
+            Some(other_caps)
+        }
+    }
+
+    fn set_caps(&self, element: &Self::Type, incaps: &Caps, outcaps: &Caps) -> Result<(), LoggableError> {
+        let in_info = gst_video::VideoInfo::from_caps(incaps)
+            .map_err(|_| gst::loggable_error!(CAT, "Failed to parse input caps"))?;
+        let out_info = gst_video::VideoInfo::from_caps(outcaps)
+            .map_err(|_| gst::loggable_error!(CAT, "Failed to parse output caps"))?;
+        let settings = *self.settings.lock();
+
+        gst_debug!(CAT, obj: element, "Configured for caps {} to {}", incaps, outcaps);
+
+        // Without the mask the frames only get metas, which needs no copy.
+        element.set_in_place(!settings.output_mask);
+
+        *self.state.lock() = Some(State {
+            in_info,
+            out_info,
+            model: Model::new(settings.model),
+            mask: Vec::new(),
+            in_motion: false,
+        });
+
+        Ok(())
+    }
+
+    fn unit_size(&self, _element: &Self::Type, caps: &Caps) -> Option<usize> {
+        gst_video::VideoInfo::from_caps(caps)
+            .as_ref()
+            .map(gst_video::VideoInfo::size)
+            .ok()
+    }
+
+    fn stop(&self, element: &Self::Type) -> Result<(), ErrorMessage> {
+        let _ = self.state.lock().take();
+
+        gst_info!(CAT, obj: element, "Stopped");
+
+        Ok(())
+    }
+
+    fn sink_event(&self, element: &Self::Type, event: Event) -> bool {
+        // The background is learnt again after a flush.
+        if let gst::EventView::FlushStop(_) = event.view() {
+            if let Some(state) = self.state.lock().as_mut() {
+                state.model = Model::new(state.model.kind());
+                state.in_motion = false;
+            }
+        }
+
+        self.parent_sink_event(element, event)
+    }
+
+    fn transform_ip(&self, element: &Self::Type, buf: &mut BufferRef) -> Result<FlowSuccess, FlowError> {
+        gst_log!(CAT, obj: element, "transform_ip: {:?}", buf);
+
+        let (blobs, message) = self.detect(element, buf, None)?;
+        self.finish(element, buf, blobs, message);
+
+        Ok(FlowSuccess::Ok)
+    }
+
+    fn transform(&self, element: &Self::Type, inbuf: &Buffer, outbuf: &mut BufferRef) -> Result<FlowSuccess, FlowError> {
+        gst_log!(CAT, obj: element, "transform: {:?}", inbuf);
+
+        let out_info = match self.state.lock().as_ref() {
+            Some(state) => state.out_info.clone(),
+            None => {
+                gst::element_error!(element, gst::CoreError::Negotiation, ["Have no state yet"]);
+                return Err(FlowError::NotNegotiated);
+            }
+        };
+
+        let (blobs, message) = {
+            let mut out_frame = gst_video::VideoFrameRef::from_buffer_ref_writable(outbuf, &out_info)
+                .map_err(|err| {
+                    gst::element_error!(
+                        element,
+                        gst::CoreError::Failed,
+                        [&format!("Failed to map output buffer writable: {}", err)]
+                    );
+                    FlowError::Error
+                })?;
+            self.detect(element, inbuf, Some(&mut out_frame))?
+        };
+        self.finish(element, outbuf, blobs, message);
+
+        Ok(FlowSuccess::Ok)
+    }
+}
+
+impl GstObjectImpl for MotionDetect {}
+
+impl ObjectImpl for MotionDetect {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecEnum::new(
+                    "model",
+                    "Model",
+                    "Per-pixel background model",
+                    BackgroundModel::static_type(),
+                    DEFAULT_MODEL as i32,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpecUInt::new(
+                    "history",
+                    "History",
+                    "Number of frames the background model learns over",
+                    1,
+                    u32::MAX,
+                    DEFAULT_HISTORY,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecDouble::new(
+                    "variance-threshold",
+                    "Variance threshold",
+                    "Squared distance to the background, in variances, above which a pixel is foreground",
+                    0.0,
+                    f64::MAX,
+                    DEFAULT_VARIANCE_THRESHOLD,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecDouble::new(
+                    "motion-threshold",
+                    "Motion threshold",
+                    "Percentage of foreground pixels from which there is motion",
+                    0.0,
+                    100.0,
+                    DEFAULT_MOTION_THRESHOLD,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt::new(
+                    "min-blob-area",
+                    "Minimum blob area",
+                    "Minimum number of pixels of a moving region to be reported",
+                    1,
+                    u32::MAX,
+                    DEFAULT_MIN_BLOB_AREA,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecBoolean::new(
+                    "output-mask",
+                    "Output mask",
+                    "Output the foreground mask as GRAY8 instead of the input frames",
+                    DEFAULT_OUTPUT_MASK,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(&self, obj: &Self::Type, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        match pspec.name() {
+            "model" => {
+                let mut settings = self.settings.lock();
+                let model = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing model from {:?} to {:?}",
+                    settings.model, model
+                );
+                settings.model = model;
+            }
+            "history" => {
+                let mut settings = self.settings.lock();
+                let history = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing history from {} to {}",
+                    settings.history, history
+                );
+                settings.history = history;
+            }
+            "variance-threshold" => {
+                let mut settings = self.settings.lock();
+                let variance_threshold = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing variance-threshold from {} to {}",
+                    settings.variance_threshold, variance_threshold
+                );
+                settings.variance_threshold = variance_threshold;
+            }
+            "motion-threshold" => {
+                let mut settings = self.settings.lock();
+                let motion_threshold = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing motion-threshold from {} to {}",
+                    settings.motion_threshold, motion_threshold
+                );
+                settings.motion_threshold = motion_threshold;
+            }
+            "min-blob-area" => {
+                let mut settings = self.settings.lock();
+                let min_blob_area = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing min-blob-area from {} to {}",
+                    settings.min_blob_area, min_blob_area
+                );
+                settings.min_blob_area = min_blob_area;
+            }
+            "output-mask" => {
+                let mut settings = self.settings.lock();
+                let output_mask = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing output-mask from {} to {}",
+                    settings.output_mask, output_mask
+                );
+                settings.output_mask = output_mask;
+                drop(settings);
+
+                obj.reconfigure_src();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn property(&self, _obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        match pspec.name() {
+            "model" => {
+                let settings = self.settings.lock();
+                settings.model.to_value()
+            }
+            "history" => {
+                let settings = self.settings.lock();
+                settings.history.to_value()
+            }
+            "variance-threshold" => {
+                let settings = self.settings.lock();
+                settings.variance_threshold.to_value()
+            }
+            "motion-threshold" => {
+                let settings = self.settings.lock();
+                settings.motion_threshold.to_value()
+            }
+            "min-blob-area" => {
+                let settings = self.settings.lock();
+                settings.min_blob_area.to_value()
+            }
+            "output-mask" => {
+                let settings = self.settings.lock();
+                settings.output_mask.to_value()
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
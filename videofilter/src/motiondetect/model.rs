@@ -0,0 +1,319 @@
+//! Per-pixel background models on luma.
+//!
+//! Both follow the usual background subtraction scheme: a pixel is
+//! foreground when its squared distance to the background, in variances, is
+//! above `variance-threshold`, and the model learns with a rate of
+//! 1 / `history`, or faster while fewer frames than that have been seen.
+
+use gst::glib;
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstMotionDetectModel")]
+pub enum BackgroundModel {
+    #[enum_value(name = "Running Gaussian average", nick = "gaussian")]
+    Gaussian = 0,
+    #[enum_value(name = "Mixture of Gaussians", nick = "mixture")]
+    Mixture = 1,
+}
+
+/// Gaussians per pixel of the mixture model.
+const COMPONENTS: usize = 3;
+/// Fraction of the weights that make up the background in the mixture.
+const BACKGROUND_RATIO: f32 = 0.9;
+const VARIANCE_INIT: f32 = 15.0 * 15.0;
+const VARIANCE_MIN: f32 = 4.0;
+const VARIANCE_MAX: f32 = 5.0 * VARIANCE_INIT;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Gaussian {
+    weight: f32,
+    mean: f32,
+    variance: f32,
+}
+
+impl Gaussian {
+    fn new(weight: f32, mean: f32) -> Self {
+        Self {
+            weight,
+            mean,
+            variance: VARIANCE_INIT,
+        }
+    }
+
+    fn distance(&self, v: f32) -> f32 {
+        let d = v - self.mean;
+        d * d / self.variance
+    }
+
+    fn learn(&mut self, v: f32, rate: f32) {
+        let d = v - self.mean;
+        self.mean += rate * d;
+        self.variance = (self.variance + rate * (d * d - self.variance)).clamp(VARIANCE_MIN, VARIANCE_MAX);
+    }
+}
+
+pub struct Model {
+    kind: BackgroundModel,
+    /// `COMPONENTS` Gaussians per pixel for the mixture, sorted by
+    /// decreasing weight, or one for the running average.
+    gaussians: Vec<Gaussian>,
+    frames: u64,
+}
+
+impl Model {
+    pub fn new(kind: BackgroundModel) -> Self {
+        Self {
+            kind,
+            gaussians: Vec::new(),
+            frames: 0,
+        }
+    }
+
+    pub fn kind(&self) -> BackgroundModel {
+        self.kind
+    }
+
+    /// Classifies every pixel of `luma`, writing 255 to `mask` for the
+    /// foreground and 0 for the background, and learns from it. Returns the
+    /// number of foreground pixels.
+    pub fn apply(&mut self, luma: &[f32], mask: &mut [u8], history: u32, variance_threshold: f32) -> usize {
+        let per_pixel = match self.kind {
+            BackgroundModel::Gaussian => 1,
+            BackgroundModel::Mixture => COMPONENTS,
+        };
+
+        if self.gaussians.len() != luma.len() * per_pixel {
+            self.gaussians = luma
+                .iter()
+                .flat_map(|&v| {
+                    let mut gaussians = vec![Gaussian::default(); per_pixel];
+                    gaussians[0] = Gaussian::new(1.0, v);
+                    gaussians
+                })
+                .collect();
+            self.frames = 0;
+        }
+
+        self.frames += 1;
+        let rate = 1.0 / self.frames.min(u64::from(history.max(1))) as f32;
+
+        let mut foreground = 0;
+        for ((gaussians, &v), mask) in self.gaussians.chunks_exact_mut(per_pixel).zip(luma).zip(mask.iter_mut()) {
+            let is_foreground = match self.kind {
+                BackgroundModel::Gaussian => {
+                    let gaussian = &mut gaussians[0];
+                    let is_foreground = gaussian.distance(v) > variance_threshold;
+                    gaussian.learn(v, rate);
+                    is_foreground
+                }
+                BackgroundModel::Mixture => Self::apply_mixture(gaussians, v, rate, variance_threshold),
+            };
+
+            *mask = if is_foreground { 255 } else { 0 };
+            foreground += is_foreground as usize;
+        }
+
+        foreground
+    }
+
+    fn apply_mixture(gaussians: &mut [Gaussian], v: f32, rate: f32, variance_threshold: f32) -> bool {
+        let matched = gaussians
+            .iter()
+            .position(|gaussian| gaussian.weight > 0.0 && gaussian.distance(v) < variance_threshold);
+
+        // The heaviest Gaussians making up `BACKGROUND_RATIO` of the weight
+        // are the background.
+        let is_foreground = match matched {
+            Some(matched) => {
+                let before: f32 = gaussians[..matched].iter().map(|gaussian| gaussian.weight).sum();
+                before > BACKGROUND_RATIO
+            }
+            None => true,
+        };
+
+        for (i, gaussian) in gaussians.iter_mut().enumerate() {
+            let owned = (Some(i) == matched) as u8 as f32;
+            gaussian.weight += rate * (owned - gaussian.weight);
+        }
+
+        match matched {
+            Some(matched) => {
+                let gaussian = &mut gaussians[matched];
+                let rho = (rate / gaussian.weight).min(1.0);
+                gaussian.learn(v, rho);
+            }
+            None => {
+                let last = gaussians.len() - 1;
+                gaussians[last] = Gaussian::new(rate, v);
+            }
+        }
+
+        let total: f32 = gaussians.iter().map(|gaussian| gaussian.weight).sum();
+        gaussians.iter_mut().for_each(|gaussian| gaussian.weight /= total);
+        gaussians.sort_by(|a, b| b.weight.partial_cmp(&a.weight).unwrap_or(std::cmp::Ordering::Equal));
+
+        is_foreground
+    }
+}
+
+/// Bounding box as `x`, `y`, `width` and `height`.
+pub type BoundingBox = (u32, u32, u32, u32);
+
+/// Bounding boxes of the 4-connected foreground regions of `mask` having at
+/// least `min_area` pixels.
+pub fn blobs(mask: &[u8], width: usize, height: usize, min_area: usize) -> Vec<BoundingBox> {
+    let mut visited = vec![false; mask.len()];
+    let mut stack = Vec::new();
+    let mut blobs = Vec::new();
+
+    for start in 0..mask.len() {
+        if mask[start] == 0 || visited[start] {
+            continue;
+        }
+
+        let (mut x0, mut y0, mut x1, mut y1) = (usize::MAX, usize::MAX, 0, 0);
+        let mut area = 0;
+
+        visited[start] = true;
+        stack.push(start);
+        while let Some(i) = stack.pop() {
+            let (x, y) = (i % width, i / width);
+            x0 = x0.min(x);
+            y0 = y0.min(y);
+            x1 = x1.max(x);
+            y1 = y1.max(y);
+            area += 1;
+
+            let neighbours = [
+                (x > 0).then(|| i - 1),
+                (x + 1 < width).then(|| i + 1),
+                (y > 0).then(|| i - width),
+                (y + 1 < height).then(|| i + width),
+            ];
+            for j in neighbours.into_iter().flatten() {
+                if mask[j] != 0 && !visited[j] {
+                    visited[j] = true;
+                    stack.push(j);
+                }
+            }
+        }
+
+        if area >= min_area {
+            blobs.push((x0 as u32, y0 as u32, (x1 - x0 + 1) as u32, (y1 - y0 + 1) as u32));
+        }
+    }
+
+    blobs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 16;
+    const HEIGHT: usize = 8;
+
+    /// A gradient with a little deterministic noise that changes every
+    /// frame.
+    fn scene(frame: usize) -> Vec<f32> {
+        (0..WIDTH * HEIGHT)
+            .map(|i| (i % WIDTH * 10) as f32 + ((i * 7 + frame * 3) % 5) as f32 - 2.0)
+            .collect()
+    }
+
+    #[test]
+    fn test_static_scene_converges() {
+        for kind in [BackgroundModel::Gaussian, BackgroundModel::Mixture] {
+            let mut model = Model::new(kind);
+            let mut mask = vec![0; WIDTH * HEIGHT];
+
+            for frame in 0..50 {
+                let foreground = model.apply(&scene(frame), &mut mask, 20, 16.0);
+                if frame >= 10 {
+                    assert_eq!(foreground, 0, "{:?} at frame {}", kind, frame);
+                    assert!(mask.iter().all(|&v| v == 0));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_change_is_foreground() {
+        for kind in [BackgroundModel::Gaussian, BackgroundModel::Mixture] {
+            let mut model = Model::new(kind);
+            let mut mask = vec![0; WIDTH * HEIGHT];
+            for frame in 0..50 {
+                model.apply(&scene(frame), &mut mask, 20, 16.0);
+            }
+
+            // A bright 3x2 object over the gradient.
+            let mut luma = scene(50);
+            let object = [(4, 2), (5, 2), (6, 2), (4, 3), (5, 3), (6, 3)];
+            for &(x, y) in object.iter() {
+                luma[y * WIDTH + x] = 255.0;
+            }
+
+            assert_eq!(model.apply(&luma, &mut mask, 20, 16.0), object.len(), "{:?}", kind);
+            for &(x, y) in object.iter() {
+                assert_eq!(mask[y * WIDTH + x], 255);
+            }
+            assert_eq!(blobs(&mask, WIDTH, HEIGHT, 1), vec![(4, 2, 3, 2)]);
+        }
+    }
+
+    #[test]
+    fn test_size_change_resets() {
+        let mut model = Model::new(BackgroundModel::Gaussian);
+        let mut mask = vec![0; 4];
+        model.apply(&[0.0; 4], &mut mask, 20, 16.0);
+
+        // A new size starts a new background from the frame.
+        let mut mask = vec![0; 2];
+        assert_eq!(model.apply(&[255.0; 2], &mut mask, 20, 16.0), 0);
+    }
+
+    #[test]
+    fn test_mixture_learns_two_backgrounds() {
+        let mut gaussians = [Gaussian::new(1.0, 50.0), Gaussian::default(), Gaussian::default()];
+        let rate = 1.0 / 20.0;
+
+        // A new value is foreground and replaces the lightest Gaussian.
+        assert!(!Model::apply_mixture(&mut gaussians, 50.0, rate, 16.0));
+        assert!(Model::apply_mixture(&mut gaussians, 200.0, rate, 16.0));
+        assert_eq!(gaussians[1].mean, 200.0);
+
+        // A pixel flickering between two values ends up with both in the
+        // background.
+        for i in 0..200 {
+            Model::apply_mixture(&mut gaussians, if i % 2 == 0 { 50.0 } else { 200.0 }, rate, 16.0);
+        }
+        assert!(!Model::apply_mixture(&mut gaussians, 50.0, rate, 16.0));
+        assert!(!Model::apply_mixture(&mut gaussians, 200.0, rate, 16.0));
+        assert!(Model::apply_mixture(&mut gaussians, 125.0, rate, 16.0));
+
+        let total: f32 = gaussians.iter().map(|gaussian| gaussian.weight).sum();
+        assert!((total - 1.0).abs() < 1e-5);
+        assert!(gaussians.windows(2).all(|pair| pair[0].weight >= pair[1].weight));
+    }
+
+    #[test]
+    fn test_blobs() {
+        #[rustfmt::skip]
+        let mask = [
+            255, 255, 0,   0,   0,   0,
+            255, 0,   0,   255, 0,   0,
+            0,   0,   255, 255, 255, 0,
+            0,   0,   0,   255, 0,   0,
+            255, 0,   0,   0,   0,   255,
+        ];
+
+        // Diagonal neighbours are not connected.
+        assert_eq!(
+            blobs(&mask, 6, 5, 1),
+            vec![(0, 0, 2, 2), (2, 1, 3, 3), (0, 4, 1, 1), (5, 4, 1, 1)]
+        );
+        assert_eq!(blobs(&mask, 6, 5, 3), vec![(0, 0, 2, 2), (2, 1, 3, 3)]);
+        assert!(blobs(&[0; 30], 6, 5, 1).is_empty());
+    }
+}
@@ -0,0 +1,20 @@
+use gst::glib::{self, StaticType};
+
+mod imp;
+mod mask;
+
+glib::wrapper! {
+    pub struct PrivacyMask(ObjectSubclass<imp::PrivacyMask>) @extends gst_base::BaseTransform, gst::Element, gst::Object;
+}
+
+unsafe impl Send for PrivacyMask {}
+unsafe impl Sync for PrivacyMask {}
+
+pub(crate) fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "rsprivacymask",
+        gst::Rank::None,
+        PrivacyMask::static_type(),
+    )
+}
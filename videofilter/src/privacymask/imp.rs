@@ -0,0 +1,369 @@
+use gst::{glib, gst_debug, gst_info, gst_log, gst_warning, BufferRef, Caps, ErrorMessage, FlowError, FlowSuccess, LoggableError, PadTemplate};
+use gst::subclass::ElementMetadata;
+use gst::subclass::prelude::*;
+use gst::prelude::*;
+use gst_base::subclass::{*, prelude::*};
+use parking_lot::Mutex;
+use crate::glib::once_cell::sync::Lazy;
+
+use super::mask::{self, MaskMethod, Rect};
+use crate::videofilter::{get_all_video_formats, rgb_to_codes};
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rsprivacymask",
+        gst::DebugColorFlags::empty(),
+        Some("Privacy masking"),
+    )
+});
+
+const DEFAULT_METHOD: MaskMethod = MaskMethod::Pixelate;
+const DEFAULT_STRENGTH: u32 = 16;
+const DEFAULT_FILL_COLOR: u32 = 0xff00_0000;
+const DEFAULT_USE_ROI_META: bool = true;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    method: MaskMethod,
+    strength: u32,
+    fill_color: u32,
+    rectangles: Option<String>,
+    /// Parsed `rectangles`.
+    rects: Vec<Rect>,
+    use_roi_meta: bool,
+    roi_type: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            method: DEFAULT_METHOD,
+            strength: DEFAULT_STRENGTH,
+            fill_color: DEFAULT_FILL_COLOR,
+            rectangles: None,
+            rects: Vec::new(),
+            use_roi_meta: DEFAULT_USE_ROI_META,
+            roi_type: None,
+        }
+    }
+}
+
+struct State {
+    info: gst_video::VideoInfo,
+}
+
+#[derive(Default)]
+pub struct PrivacyMask {
+    settings: Mutex<Settings>,
+    state: Mutex<Option<State>>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for PrivacyMask {
+    const NAME: &'static str = "RsPrivacyMask";
+    type Type = super::PrivacyMask;
+    type ParentType = gst_base::BaseTransform;
+}
+
+impl ElementImpl for PrivacyMask {
+    fn metadata() -> Option<&'static ElementMetadata> {
+        static METADATA: Lazy<ElementMetadata> = Lazy::new(|| {
+            ElementMetadata::new(
+                "Privacy mask",
+                "Filter/Effect/Video",
+                "Blur, pixelate or fill rectangles and regions of interest",
+                "Seiichi Uchida <topecongiro@fastmail.com>",
+            )
+        });
+
+        Some(&*METADATA)
+    }
+
+    fn pad_templates() -> &'static [PadTemplate] {
+        static PAD_TEMPLATE: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let caps = gst::Caps::builder("video/x-raw")
+                .field("format", gst::List::from(get_all_video_formats()))
+                .field("width", gst::IntRange::new(1, i32::MAX))
+                .field("height", gst::IntRange::new(1, i32::MAX))
+                .field("framerate", gst::FractionRange::new(
+                    gst::Fraction::new(0, 1),
+                    gst::Fraction::new(i32::MAX, 1),
+                ))
+                .build();
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &caps,
+            ).unwrap();
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &caps,
+            ).unwrap();
+
+            vec![sink_pad_template, src_pad_template]
+        });
+
+        PAD_TEMPLATE.as_ref()
+    }
+}
+
+impl BaseTransformImpl for PrivacyMask {
+    const MODE: BaseTransformMode = BaseTransformMode::AlwaysInPlace;
+    const PASSTHROUGH_ON_SAME_CAPS: bool = false;
+    const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+
+    fn set_caps(&self, element: &Self::Type, incaps: &Caps, outcaps: &Caps) -> Result<(), LoggableError> {
+        let info = gst_video::VideoInfo::from_caps(incaps)
+            .map_err(|_| gst::loggable_error!(CAT, "Failed to parse input caps"))?;
+
+        gst_debug!(CAT, obj: element, "Configured for caps {} to {}", incaps, outcaps);
+
+        *self.state.lock() = Some(State { info });
+
+        Ok(())
+    }
+
+    fn stop(&self, element: &Self::Type) -> Result<(), ErrorMessage> {
+        let _ = self.state.lock().take();
+
+        gst_info!(CAT, obj: element, "Stopped");
+
+        Ok(())
+    }
+
+    fn transform_ip(&self, element: &Self::Type, buf: &mut BufferRef) -> Result<FlowSuccess, FlowError> {
+        gst_log!(CAT, obj: element, "transform_ip: {:?}", buf);
+
+        let settings = self.settings.lock().clone();
+
+        let mut rects = settings.rects.clone();
+        if settings.use_roi_meta {
+            rects.extend(
+                buf.iter_meta::<gst_video::VideoRegionOfInterestMeta>()
+                    .filter(|meta| settings.roi_type.as_deref().is_none_or(|roi_type| meta.roi_type() == roi_type))
+                    .map(|meta| meta.rect()),
+            );
+        }
+        if rects.is_empty() {
+            return Ok(FlowSuccess::Ok);
+        }
+
+        let state_guard = self.state.lock();
+        let state = state_guard.as_ref().ok_or_else(|| {
+            gst::element_error!(element, gst::CoreError::Negotiation, ["Have no state yet"]);
+            FlowError::NotNegotiated
+        })?;
+
+        let fill = if settings.method == MaskMethod::Fill {
+            let color = settings.fill_color;
+            let rgb = [
+                ((color >> 16) & 0xff) as f32 / 255.0,
+                ((color >> 8) & 0xff) as f32 / 255.0,
+                (color & 0xff) as f32 / 255.0,
+            ];
+            rgb_to_codes(&state.info, rgb).map_err(|err| {
+                gst::element_error!(element, gst::CoreError::Failed, [&err]);
+                FlowError::Error
+            })?
+        } else {
+            [0; 4]
+        };
+
+        let mut frame = gst_video::VideoFrameRef::from_buffer_ref_writable(buf, &state.info).map_err(|err| {
+            gst::element_error!(
+                element,
+                gst::CoreError::Failed,
+                [&format!("Failed to map buffer writable: {}", err)]
+            );
+            FlowError::Error
+        })?;
+
+        for rect in rects {
+            gst_log!(CAT, obj: element, "Masking {:?}", rect);
+            mask::apply(&mut frame, rect, settings.method, settings.strength, fill);
+        }
+
+        Ok(FlowSuccess::Ok)
+    }
+}
+
+impl GstObjectImpl for PrivacyMask {}
+
+impl ObjectImpl for PrivacyMask {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecEnum::new(
+                    "method",
+                    "Method",
+                    "How the regions are masked",
+                    MaskMethod::static_type(),
+                    DEFAULT_METHOD as i32,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt::new(
+                    "strength",
+                    "Strength",
+                    "Block size of pixelation or radius of blur, in luma samples",
+                    1,
+                    u32::MAX,
+                    DEFAULT_STRENGTH,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt::new(
+                    "fill-color",
+                    "Fill color",
+                    "Fill color in ARGB, the alpha being ignored",
+                    0,
+                    u32::MAX,
+                    DEFAULT_FILL_COLOR,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecString::new(
+                    "rectangles",
+                    "Rectangles",
+                    "Regions to mask as x,y,width,height separated by ';', invalid values being rejected",
+                    None,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecBoolean::new(
+                    "use-roi-meta",
+                    "Use ROI meta",
+                    "Also mask the regions of GstVideoRegionOfInterestMeta",
+                    DEFAULT_USE_ROI_META,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecString::new(
+                    "roi-type",
+                    "ROI type",
+                    "Only mask the regions of interest of this type (NULL = all)",
+                    None,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(&self, obj: &Self::Type, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        match pspec.name() {
+            "method" => {
+                let mut settings = self.settings.lock();
+                let method = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing method from {:?} to {:?}",
+                    settings.method, method
+                );
+                settings.method = method;
+            }
+            "strength" => {
+                let mut settings = self.settings.lock();
+                let strength = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing strength from {} to {}",
+                    settings.strength, strength
+                );
+                settings.strength = strength;
+            }
+            "fill-color" => {
+                let mut settings = self.settings.lock();
+                let fill_color = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing fill-color from {:08x} to {:08x}",
+                    settings.fill_color, fill_color
+                );
+                settings.fill_color = fill_color;
+            }
+            "rectangles" => {
+                let mut settings = self.settings.lock();
+                let rectangles: Option<String> = value.get().unwrap();
+                let rects = match rectangles.as_deref().map(mask::parse_rectangles).transpose() {
+                    Ok(rects) => rects.unwrap_or_default(),
+                    Err(err) => {
+                        // Keep masking the previous rectangles rather than
+                        // exposing what they cover.
+                        drop(settings);
+                        gst_warning!(CAT, obj: obj, "Rejecting rectangles {:?}: {}", rectangles, err);
+                        gst::element_warning!(
+                            obj,
+                            gst::LibraryError::Settings,
+                            ["Rejecting rectangles {:?}: {}", rectangles, err]
+                        );
+                        return;
+                    }
+                };
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing rectangles from {:?} to {:?}",
+                    settings.rectangles, rectangles
+                );
+                settings.rects = rects;
+                settings.rectangles = rectangles;
+            }
+            "use-roi-meta" => {
+                let mut settings = self.settings.lock();
+                let use_roi_meta = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing use-roi-meta from {} to {}",
+                    settings.use_roi_meta, use_roi_meta
+                );
+                settings.use_roi_meta = use_roi_meta;
+            }
+            "roi-type" => {
+                let mut settings = self.settings.lock();
+                let roi_type = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing roi-type from {:?} to {:?}",
+                    settings.roi_type, roi_type
+                );
+                settings.roi_type = roi_type;
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn property(&self, _obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        match pspec.name() {
+            "method" => {
+                let settings = self.settings.lock();
+                settings.method.to_value()
+            }
+            "strength" => {
+                let settings = self.settings.lock();
+                settings.strength.to_value()
+            }
+            "fill-color" => {
+                let settings = self.settings.lock();
+                settings.fill_color.to_value()
+            }
+            "rectangles" => {
+                let settings = self.settings.lock();
+                settings.rectangles.to_value()
+            }
+            "use-roi-meta" => {
+                let settings = self.settings.lock();
+                settings.use_roi_meta.to_value()
+            }
+            "roi-type" => {
+                let settings = self.settings.lock();
+                settings.roi_type.to_value()
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
@@ -0,0 +1,321 @@
+//! Blurring, pixelation and filling of rectangles.
+//!
+//! Rectangles are given in luma samples and cover, in subsampled
+//! components, every sample they partially cover. The block size and blur
+//! radius are scaled down the same way.
+
+use gst::glib;
+use gst::BufferRef;
+use gst_video::{VideoFormatFlags, VideoFrameRef};
+
+use crate::videofilter::{read_sample, write_sample};
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstPrivacyMaskMethod")]
+pub enum MaskMethod {
+    #[enum_value(name = "Blur", nick = "blur")]
+    Blur = 0,
+    #[enum_value(name = "Pixelate", nick = "pixelate")]
+    Pixelate = 1,
+    #[enum_value(name = "Fill with a solid colour", nick = "fill")]
+    Fill = 2,
+}
+
+pub type Rect = (u32, u32, u32, u32);
+
+/// Parses `x,y,width,height` rectangles separated by `;`.
+pub fn parse_rectangles(s: &str) -> Result<Vec<Rect>, String> {
+    s.split(';')
+        .map(str::trim)
+        .filter(|rect| !rect.is_empty())
+        .map(|rect| {
+            let values = rect
+                .split(',')
+                .map(|v| v.trim().parse::<u32>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| format!("Invalid rectangle {:?}: {}", rect, err))?;
+            match values[..] {
+                [x, y, width, height] => Ok((x, y, width, height)),
+                _ => Err(format!("Invalid rectangle {:?}: expected x,y,width,height", rect)),
+            }
+        })
+        .collect()
+}
+
+/// Box blur of `samples`, a `width` x `height` region, run three times in
+/// each direction, which is close to a Gaussian blur.
+fn blur(samples: &mut [f32], width: usize, height: usize, radius: usize) {
+    let mut line = Vec::new();
+
+    for _ in 0..3 {
+        for y in 0..height {
+            line.clear();
+            line.extend_from_slice(&samples[y * width..(y + 1) * width]);
+            for x in 0..width {
+                let (first, last) = (x.saturating_sub(radius), (x + radius).min(width - 1));
+                samples[y * width + x] = line[first..=last].iter().sum::<f32>() / (last - first + 1) as f32;
+            }
+        }
+
+        for x in 0..width {
+            line.clear();
+            line.extend((0..height).map(|y| samples[y * width + x]));
+            for y in 0..height {
+                let (first, last) = (y.saturating_sub(radius), (y + radius).min(height - 1));
+                samples[y * width + x] = line[first..=last].iter().sum::<f32>() / (last - first + 1) as f32;
+            }
+        }
+    }
+}
+
+/// Replaces every `block_width` x `block_height` block of `samples`, a
+/// `width` x `height` region, by its mean.
+fn pixelate(samples: &mut [f32], width: usize, height: usize, block_width: usize, block_height: usize) {
+    for by in (0..height).step_by(block_height) {
+        for bx in (0..width).step_by(block_width) {
+            let rows = by..(by + block_height).min(height);
+            let columns = bx..(bx + block_width).min(width);
+            let count = (rows.len() * columns.len()) as f32;
+
+            let mean = rows
+                .clone()
+                .map(|y| samples[y * width + columns.start..y * width + columns.end].iter().sum::<f32>())
+                .sum::<f32>()
+                / count;
+            for y in rows {
+                samples[y * width + columns.start..y * width + columns.end].fill(mean);
+            }
+        }
+    }
+}
+
+/// Masks `rect` of `frame` with `method`. `strength` is the block size or
+/// blur radius and `fill` the code values, before shifting, of the fill
+/// colour.
+pub fn apply(frame: &mut VideoFrameRef<&mut BufferRef>, rect: Rect, method: MaskMethod, strength: u32, fill: [u16; 4]) {
+    let (x, y, width, height) = rect;
+    let x1 = x.saturating_add(width).min(frame.width());
+    let y1 = y.saturating_add(height).min(frame.height());
+    if x >= x1 || y >= y1 {
+        return;
+    }
+
+    let finfo = frame.format_info();
+    let little_endian = finfo.flags().contains(VideoFormatFlags::LE);
+
+    for (c, &fill) in fill.iter().enumerate().take(finfo.n_components() as usize) {
+        let w_sub = finfo.w_sub()[c];
+        let h_sub = finfo.h_sub()[c];
+        let plane = finfo.plane()[c];
+        let stride = frame.plane_stride()[plane as usize] as usize;
+        let poffset = finfo.poffset()[c] as usize;
+        let pstride = finfo.pixel_stride()[c] as usize;
+        let shift = finfo.shift()[c];
+        let max = ((1u32 << finfo.depth()[c]) - 1) as f32;
+
+        let cx0 = (x >> w_sub) as usize;
+        let cy0 = (y >> h_sub) as usize;
+        let cx1 = ((x1 + (1 << w_sub) - 1) >> w_sub) as usize;
+        let cy1 = ((y1 + (1 << h_sub) - 1) >> h_sub) as usize;
+        let (cw, ch) = (cx1 - cx0, cy1 - cy0);
+        let scaled = |v: u32, sub: u32| (v >> sub).max(1) as usize;
+
+        let data = frame.plane_data_mut(plane).unwrap();
+
+        let mut samples = Vec::with_capacity(cw * ch);
+        for cy in cy0..cy1 {
+            let line = &data[cy * stride + poffset..];
+            samples.extend((cx0..cx1).map(|cx| {
+                let code = read_sample(line, cx, pstride, little_endian);
+                f32::from(code >> shift)
+            }));
+        }
+
+        match method {
+            MaskMethod::Blur => blur(&mut samples, cw, ch, scaled(strength, w_sub.max(h_sub))),
+            MaskMethod::Pixelate => pixelate(&mut samples, cw, ch, scaled(strength, w_sub), scaled(strength, h_sub)),
+            MaskMethod::Fill => samples.fill(f32::from(fill)),
+        }
+
+        for (cy, values) in (cy0..cy1).zip(samples.chunks_exact(cw)) {
+            let line = &mut data[cy * stride + poffset..];
+            for (cx, &v) in (cx0..cx1).zip(values) {
+                let code = (v.round().clamp(0.0, max) as u16) << shift;
+                write_sample(line, cx, pstride, little_endian, code);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+
+    use gst_video::{VideoFormat, VideoInfo};
+
+    use super::*;
+
+    const FORMATS: [VideoFormat; 3] = [VideoFormat::I420, VideoFormat::Y444, VideoFormat::Gbr];
+    const RECT: Rect = (3, 3, 4, 4);
+
+    /// Columns and rows of component `c` covered by `RECT`: subsampled
+    /// components cover every sample the rectangle partially covers.
+    fn covered(format: VideoFormat, c: usize) -> (Range<usize>, Range<usize>) {
+        if format == VideoFormat::I420 && c > 0 {
+            (1..4, 1..4)
+        } else {
+            (3..7, 3..7)
+        }
+    }
+
+    /// Masks `RECT` of an 8x8 `format` frame whose component `c` is
+    /// `value(c, x, y)` and returns the samples of each component, row by row.
+    fn masked(
+        format: VideoFormat,
+        value: impl Fn(usize, usize, usize) -> u8,
+        method: MaskMethod,
+        strength: u32,
+        fill: [u16; 4],
+    ) -> Vec<Vec<Vec<u8>>> {
+        gst::init().unwrap();
+
+        let info = VideoInfo::builder(format, 8, 8).build().unwrap();
+        let finfo = info.format_info();
+        let mut buffer = gst::Buffer::with_size(info.size()).unwrap();
+        let mut frame = VideoFrameRef::from_buffer_ref_writable(buffer.get_mut().unwrap(), &info).unwrap();
+
+        // Plane, stride, offset and pixel stride, width and height.
+        let layout = |c: usize| {
+            let plane = finfo.plane()[c];
+            (
+                plane,
+                info.stride()[plane as usize] as usize,
+                finfo.poffset()[c] as usize,
+                finfo.pixel_stride()[c] as usize,
+                8 >> finfo.w_sub()[c],
+                8 >> finfo.h_sub()[c],
+            )
+        };
+        let components = 0..finfo.n_components() as usize;
+
+        for c in components.clone() {
+            let (plane, stride, poffset, pstride, width, height) = layout(c);
+            let data = frame.plane_data_mut(plane).unwrap();
+            for y in 0..height {
+                for x in 0..width {
+                    data[y * stride + poffset + x * pstride] = value(c, x, y);
+                }
+            }
+        }
+
+        apply(&mut frame, RECT, method, strength, fill);
+
+        components
+            .map(|c| {
+                let (plane, stride, poffset, pstride, width, height) = layout(c);
+                let data = frame.plane_data(plane).unwrap();
+                (0..height)
+                    .map(|y| (0..width).map(|x| data[y * stride + poffset + x * pstride]).collect())
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn gradient(c: usize, x: usize, y: usize) -> u8 {
+        (c * 64 + x * 8 + y) as u8
+    }
+
+    #[test]
+    fn test_parse_rectangles() {
+        assert_eq!(parse_rectangles(""), Ok(vec![]));
+        assert_eq!(parse_rectangles("1,2,3,4"), Ok(vec![(1, 2, 3, 4)]));
+        assert_eq!(
+            parse_rectangles(" 1, 2 ,3,4 ; 5,6,7,8; "),
+            Ok(vec![(1, 2, 3, 4), (5, 6, 7, 8)])
+        );
+
+        for invalid in ["1,2,3", "1,2,3,4,5", "1,2,3,-4", "1,2,3,4;x", "1,,3,4"] {
+            assert!(parse_rectangles(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_fill() {
+        let fill = [200, 50, 100, 0];
+
+        for format in FORMATS {
+            let components = masked(format, gradient, MaskMethod::Fill, 1, fill);
+            for (c, rows) in components.iter().enumerate() {
+                let (columns, lines) = covered(format, c);
+                for (y, row) in rows.iter().enumerate() {
+                    for (x, &v) in row.iter().enumerate() {
+                        let expected = if columns.contains(&x) && lines.contains(&y) {
+                            fill[c] as u8
+                        } else {
+                            gradient(c, x, y)
+                        };
+                        assert_eq!(v, expected, "{:?} component {} at {}x{}", format, c, x, y);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_pixelate() {
+        for format in FORMATS {
+            // Blocks larger than the rectangle leave a single block.
+            let components = masked(format, gradient, MaskMethod::Pixelate, 8, [0; 4]);
+            for (c, rows) in components.iter().enumerate() {
+                let (columns, lines) = covered(format, c);
+                let count = (columns.len() * lines.len()) as f32;
+                let sum = lines
+                    .clone()
+                    .flat_map(|y| columns.clone().map(move |x| f32::from(gradient(c, x, y))))
+                    .sum::<f32>();
+                let mean = (sum / count).round() as u8;
+
+                for (y, row) in rows.iter().enumerate() {
+                    for (x, &v) in row.iter().enumerate() {
+                        let expected = if columns.contains(&x) && lines.contains(&y) {
+                            mean
+                        } else {
+                            gradient(c, x, y)
+                        };
+                        assert_eq!(v, expected, "{:?} component {} at {}x{}", format, c, x, y);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_blur() {
+        for format in FORMATS {
+            // A single white sample in the top left corner of the rectangle.
+            let spike = |c: usize, x: usize, y: usize| {
+                let (columns, lines) = covered(format, c);
+                if x == columns.start && y == lines.start {
+                    255
+                } else {
+                    0
+                }
+            };
+
+            let components = masked(format, spike, MaskMethod::Blur, 2, [0; 4]);
+            for (c, rows) in components.iter().enumerate() {
+                let (columns, lines) = covered(format, c);
+                for (y, row) in rows.iter().enumerate() {
+                    for (x, &v) in row.iter().enumerate() {
+                        if columns.contains(&x) && lines.contains(&y) {
+                            assert!(v > 0 && v < 255, "{:?} component {} at {}x{}: {}", format, c, x, y, v);
+                        } else {
+                            assert_eq!(v, 0, "{:?} component {} at {}x{}", format, c, x, y);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,20 @@
+use gst::glib::{self, StaticType};
+
+mod detector;
+mod imp;
+
+glib::wrapper! {
+    pub struct VideoQc(ObjectSubclass<imp::VideoQc>) @extends gst_base::BaseTransform, gst::Element, gst::Object;
+}
+
+unsafe impl Send for VideoQc {}
+unsafe impl Sync for VideoQc {}
+
+pub(crate) fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "rsvideoqc",
+        gst::Rank::None,
+        VideoQc::static_type(),
+    )
+}
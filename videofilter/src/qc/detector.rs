@@ -0,0 +1,413 @@
+//! Black, frozen and blurry frame detection on luma.
+//!
+//! Every kind of event has its own condition per frame and starts once the
+//! condition has held long enough: for `black-frames` frames for black
+//! frames, for `freeze-duration` for frozen frames and right away for blur.
+//! It ends at the first frame the condition does not hold for.
+
+use std::fmt::Write;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Kind {
+    Black,
+    Frozen,
+    Blur,
+}
+
+impl Kind {
+    const ALL: [Kind; 3] = [Kind::Black, Kind::Frozen, Kind::Blur];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Black => "black",
+            Kind::Frozen => "frozen",
+            Kind::Blur => "blur",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DetectorSettings {
+    /// Mean luma, in 8-bit code values, below which a frame is black.
+    pub black_threshold: f64,
+    pub black_frames: u32,
+    /// Mean absolute luma difference to the previous frame below which a
+    /// frame is frozen.
+    pub freeze_threshold: f64,
+    pub freeze_duration: gst::ClockTime,
+    /// Variance of the Laplacian of the luma below which a frame is blurry.
+    pub blur_threshold: f64,
+}
+
+/// A detected event. `end` is the PTS of the first frame after it.
+#[derive(Debug, Clone, Copy)]
+pub struct Event {
+    pub kind: Kind,
+    pub start: Option<gst::ClockTime>,
+    pub end: Option<gst::ClockTime>,
+    pub frames: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Change {
+    Start(Event),
+    End(Event),
+}
+
+/// Frame metrics, also useful for logging.
+#[derive(Debug, Clone, Copy)]
+pub struct Metrics {
+    pub mean: f64,
+    /// None for the first frame after a discontinuity.
+    pub difference: Option<f64>,
+    pub sharpness: f64,
+}
+
+#[derive(Debug, Default)]
+struct Tracker {
+    /// Event the condition has held for since its first frame, confirmed
+    /// or not.
+    candidate: Option<Event>,
+    active: bool,
+}
+
+impl Tracker {
+    fn update(
+        &mut self,
+        kind: Kind,
+        condition: bool,
+        start: Option<gst::ClockTime>,
+        pts: Option<gst::ClockTime>,
+        confirmed: impl Fn(&Event) -> bool,
+    ) -> Option<Change> {
+        if !condition {
+            let candidate = self.candidate.take();
+            if std::mem::take(&mut self.active) {
+                let mut event = candidate.unwrap();
+                event.end = pts;
+                return Some(Change::End(event));
+            }
+            return None;
+        }
+
+        let event = self.candidate.get_or_insert(Event {
+            kind,
+            start,
+            end: None,
+            frames: 0,
+        });
+        event.frames += 1;
+
+        if !self.active && confirmed(event) {
+            self.active = true;
+            return Some(Change::Start(*event));
+        }
+
+        None
+    }
+
+    fn finish(&mut self, end: Option<gst::ClockTime>) -> Option<Change> {
+        let candidate = self.candidate.take();
+        if std::mem::take(&mut self.active) {
+            let mut event = candidate.unwrap();
+            event.end = end;
+            Some(Change::End(event))
+        } else {
+            None
+        }
+    }
+}
+
+fn sharpness(luma: &[f32], width: usize, height: usize) -> f64 {
+    if width < 3 || height < 3 {
+        return f64::MAX;
+    }
+
+    let (mut sum, mut sum_sq) = (0.0f64, 0.0f64);
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let i = y * width + x;
+            let laplacian = f64::from(4.0 * luma[i] - luma[i - 1] - luma[i + 1] - luma[i - width] - luma[i + width]);
+            sum += laplacian;
+            sum_sq += laplacian * laplacian;
+        }
+    }
+    let n = ((width - 2) * (height - 2)) as f64;
+    let mean = sum / n;
+    sum_sq / n - mean * mean
+}
+
+#[derive(Debug, Default)]
+pub struct Detector {
+    previous: Option<Vec<f32>>,
+    previous_pts: Option<gst::ClockTime>,
+    trackers: [Tracker; 3],
+    frames: u64,
+    /// Ended events, for the report.
+    events: Vec<Event>,
+}
+
+impl Detector {
+    /// Forgets the previous frame, so that frozen frames are not detected
+    /// across a discontinuity.
+    pub fn discont(&mut self) {
+        self.previous = None;
+        self.previous_pts = None;
+    }
+
+    /// Analyzes the frame of `luma` and returns its metrics and the events
+    /// that started or ended with it.
+    pub fn push(
+        &mut self,
+        luma: Vec<f32>,
+        width: usize,
+        height: usize,
+        pts: Option<gst::ClockTime>,
+        settings: &DetectorSettings,
+    ) -> (Metrics, Vec<Change>) {
+        let n = luma.len().max(1) as f64;
+        let mean = luma.iter().map(|&v| f64::from(v)).sum::<f64>() / n;
+        let difference = self
+            .previous
+            .as_ref()
+            .filter(|previous| previous.len() == luma.len())
+            .map(|previous| {
+                previous
+                    .iter()
+                    .zip(luma.iter())
+                    .map(|(a, b)| f64::from((a - b).abs()))
+                    .sum::<f64>()
+                    / n
+            });
+        let sharpness = sharpness(&luma, width, height);
+        let metrics = Metrics {
+            mean,
+            difference,
+            sharpness,
+        };
+
+        let black = mean < settings.black_threshold;
+        let frozen = difference.is_some_and(|difference| difference < settings.freeze_threshold);
+        // Black frames have no detail either, but are reported as such.
+        let blur = !black && sharpness < settings.blur_threshold;
+
+        let mut changes = Vec::new();
+        for (tracker, kind) in self.trackers.iter_mut().zip(Kind::ALL) {
+            let change = match kind {
+                Kind::Black => tracker.update(kind, black, pts, pts, |event| {
+                    event.frames >= u64::from(settings.black_frames)
+                }),
+                // A frozen picture starts with the frame it is a copy of.
+                Kind::Frozen => tracker.update(kind, frozen, self.previous_pts, pts, |event| {
+                    match (event.start, pts) {
+                        (Some(start), Some(pts)) => pts.saturating_sub(start) >= settings.freeze_duration,
+                        _ => false,
+                    }
+                }),
+                Kind::Blur => tracker.update(kind, blur, pts, pts, |_| true),
+            };
+            changes.extend(change);
+        }
+
+        self.previous = Some(luma);
+        self.previous_pts = pts;
+        self.frames += 1;
+        self.record(&changes);
+
+        (metrics, changes)
+    }
+
+    /// Ends the events still going on at `end`, at EOS.
+    pub fn finish(&mut self, end: Option<gst::ClockTime>) -> Vec<Change> {
+        let changes: Vec<Change> = self.trackers.iter_mut().filter_map(|tracker| tracker.finish(end)).collect();
+        self.record(&changes);
+        changes
+    }
+
+    fn record(&mut self, changes: &[Change]) {
+        self.events.extend(changes.iter().filter_map(|change| match change {
+            Change::End(event) => Some(*event),
+            Change::Start(_) => None,
+        }));
+    }
+
+    /// JSON report of the frames analyzed and the events that ended, with
+    /// times in nanoseconds.
+    pub fn report(&self) -> String {
+        let time = |time: Option<gst::ClockTime>| time.map_or_else(|| String::from("null"), |time| time.nseconds().to_string());
+
+        let mut json = String::new();
+        let _ = writeln!(json, "{{");
+        let _ = writeln!(json, "  \"frames\": {},", self.frames);
+        let _ = write!(json, "  \"events\": [");
+        for (i, event) in self.events.iter().enumerate() {
+            let _ = write!(
+                json,
+                "{}\n    {{ \"type\": \"{}\", \"start\": {}, \"end\": {}, \"frames\": {} }}",
+                if i == 0 { "" } else { "," },
+                event.kind.name(),
+                time(event.start),
+                time(event.end),
+                event.frames
+            );
+        }
+        if !self.events.is_empty() {
+            json.push_str("\n  ");
+        }
+        let _ = writeln!(json, "]");
+        let _ = writeln!(json, "}}");
+        json
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 8;
+    const HEIGHT: usize = 8;
+
+    fn ms(ms: u64) -> Option<gst::ClockTime> {
+        Some(gst::ClockTime::from_mseconds(ms))
+    }
+
+    /// Settings with only the black frame detection enabled.
+    fn settings() -> DetectorSettings {
+        DetectorSettings {
+            black_threshold: 16.0,
+            black_frames: 3,
+            freeze_threshold: 0.0,
+            freeze_duration: gst::ClockTime::from_mseconds(80),
+            blur_threshold: 0.0,
+        }
+    }
+
+    fn flat(v: f32) -> Vec<f32> {
+        vec![v; WIDTH * HEIGHT]
+    }
+
+    fn checkers(phase: usize) -> Vec<f32> {
+        (0..WIDTH * HEIGHT)
+            .map(|i| if (i % WIDTH + i / WIDTH + phase).is_multiple_of(2) { 255.0 } else { 0.0 })
+            .collect()
+    }
+
+    /// Whether the change starts an event, then its kind, start, end and
+    /// frames.
+    type Summary = (bool, Kind, Option<gst::ClockTime>, Option<gst::ClockTime>, u64);
+
+    fn summary(changes: &[Change]) -> Vec<Summary> {
+        changes
+            .iter()
+            .map(|change| match change {
+                Change::Start(event) => (true, event.kind, event.start, event.end, event.frames),
+                Change::End(event) => (false, event.kind, event.start, event.end, event.frames),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_black() {
+        let settings = settings();
+        let mut detector = Detector::default();
+        let mut push = |luma, pts| summary(&detector.push(luma, WIDTH, HEIGHT, pts, &settings).1);
+
+        // Too short to be an event.
+        assert!(push(flat(0.0), ms(0)).is_empty());
+        assert!(push(flat(15.0), ms(40)).is_empty());
+        assert!(push(flat(128.0), ms(80)).is_empty());
+
+        assert!(push(flat(0.0), ms(120)).is_empty());
+        assert!(push(flat(0.0), ms(160)).is_empty());
+        assert_eq!(push(flat(0.0), ms(200)), vec![(true, Kind::Black, ms(120), None, 3)]);
+        assert!(push(flat(0.0), ms(240)).is_empty());
+        assert_eq!(push(flat(16.0), ms(280)), vec![(false, Kind::Black, ms(120), ms(280), 4)]);
+    }
+
+    #[test]
+    fn test_frozen() {
+        let settings = DetectorSettings {
+            black_frames: u32::MAX,
+            freeze_threshold: 1.0,
+            ..settings()
+        };
+        let mut detector = Detector::default();
+
+        let (metrics, changes) = detector.push(checkers(0), WIDTH, HEIGHT, ms(0), &settings);
+        assert_eq!(metrics.difference, None);
+        assert!(changes.is_empty());
+
+        // The event starts with the frame the others are copies of, once
+        // they lasted `freeze-duration`.
+        let (metrics, changes) = detector.push(checkers(0), WIDTH, HEIGHT, ms(40), &settings);
+        assert_eq!(metrics.difference, Some(0.0));
+        assert!(changes.is_empty());
+        let (_, changes) = detector.push(checkers(0), WIDTH, HEIGHT, ms(80), &settings);
+        assert_eq!(summary(&changes), vec![(true, Kind::Frozen, ms(0), None, 2)]);
+
+        let (metrics, changes) = detector.push(checkers(1), WIDTH, HEIGHT, ms(120), &settings);
+        assert_eq!(metrics.difference, Some(255.0));
+        assert_eq!(summary(&changes), vec![(false, Kind::Frozen, ms(0), ms(120), 2)]);
+
+        // Nothing is frozen across a discontinuity.
+        detector.discont();
+        let (metrics, changes) = detector.push(checkers(1), WIDTH, HEIGHT, ms(1000), &settings);
+        assert_eq!(metrics.difference, None);
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn test_blur() {
+        let settings = DetectorSettings {
+            blur_threshold: 100.0,
+            ..settings()
+        };
+        let mut detector = Detector::default();
+
+        let (metrics, changes) = detector.push(checkers(0), WIDTH, HEIGHT, ms(0), &settings);
+        assert_eq!(metrics.sharpness, 1020.0 * 1020.0);
+        assert!(changes.is_empty());
+
+        // Blur starts right away, but black frames are not blurry.
+        let (metrics, changes) = detector.push(flat(128.0), WIDTH, HEIGHT, ms(40), &settings);
+        assert_eq!(metrics.sharpness, 0.0);
+        assert_eq!(summary(&changes), vec![(true, Kind::Blur, ms(40), None, 1)]);
+        let (_, changes) = detector.push(flat(0.0), WIDTH, HEIGHT, ms(80), &settings);
+        assert_eq!(summary(&changes), vec![(false, Kind::Blur, ms(40), ms(80), 1)]);
+
+        // Too small to tell.
+        let (metrics, _) = detector.push(vec![128.0; 4], 2, 2, ms(120), &settings);
+        assert_eq!(metrics.sharpness, f64::MAX);
+    }
+
+    #[test]
+    fn test_report() {
+        let settings = DetectorSettings {
+            black_frames: 1,
+            ..settings()
+        };
+        let mut detector = Detector::default();
+        assert_eq!(detector.report(), "{\n  \"frames\": 0,\n  \"events\": []\n}\n");
+
+        detector.push(flat(0.0), WIDTH, HEIGHT, ms(0), &settings);
+        detector.push(flat(128.0), WIDTH, HEIGHT, ms(40), &settings);
+        detector.push(flat(0.0), WIDTH, HEIGHT, None, &settings);
+
+        // Events still going on end at EOS.
+        assert_eq!(summary(&detector.finish(ms(120))), vec![(false, Kind::Black, None, ms(120), 1)]);
+        assert!(detector.finish(ms(120)).is_empty());
+
+        assert_eq!(
+            detector.report(),
+            concat!(
+                "{\n",
+                "  \"frames\": 3,\n",
+                "  \"events\": [\n",
+                "    { \"type\": \"black\", \"start\": 0, \"end\": 40000000, \"frames\": 1 },\n",
+                "    { \"type\": \"black\", \"start\": null, \"end\": 120000000, \"frames\": 1 }\n",
+                "  ]\n",
+                "}\n",
+            )
+        );
+    }
+}
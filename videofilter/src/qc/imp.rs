@@ -0,0 +1,461 @@
+use gst::{glib, gst_debug, gst_info, gst_log, gst_warning, Buffer, BufferRef, Caps, ErrorMessage, Event, FlowError, FlowSuccess, LoggableError, PadTemplate};
+use gst::subclass::ElementMetadata;
+use gst::subclass::prelude::*;
+use gst::prelude::*;
+use gst_base::prelude::*;
+use gst_base::subclass::{*, prelude::*};
+use parking_lot::Mutex;
+use crate::glib::once_cell::sync::Lazy;
+
+use super::detector::{Change, Detector, DetectorSettings};
+use crate::videofilter::{get_all_video_formats, luma};
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rsvideoqc",
+        gst::DebugColorFlags::empty(),
+        Some("Video quality control"),
+    )
+});
+
+const DEFAULT_BLACK_THRESHOLD: f64 = 24.0;
+const DEFAULT_BLACK_FRAMES: u32 = 5;
+const DEFAULT_FREEZE_THRESHOLD: f64 = 0.5;
+const DEFAULT_FREEZE_DURATION: u64 = 2_000_000_000;
+const DEFAULT_BLUR_THRESHOLD: f64 = 20.0;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    black_threshold: f64,
+    black_frames: u32,
+    freeze_threshold: f64,
+    freeze_duration: u64,
+    blur_threshold: f64,
+    report_location: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            black_threshold: DEFAULT_BLACK_THRESHOLD,
+            black_frames: DEFAULT_BLACK_FRAMES,
+            freeze_threshold: DEFAULT_FREEZE_THRESHOLD,
+            freeze_duration: DEFAULT_FREEZE_DURATION,
+            blur_threshold: DEFAULT_BLUR_THRESHOLD,
+            report_location: None,
+        }
+    }
+}
+
+impl Settings {
+    fn detector_settings(&self) -> DetectorSettings {
+        DetectorSettings {
+            black_threshold: self.black_threshold,
+            black_frames: self.black_frames,
+            freeze_threshold: self.freeze_threshold,
+            freeze_duration: gst::ClockTime::from_nseconds(self.freeze_duration),
+            blur_threshold: self.blur_threshold,
+        }
+    }
+}
+
+struct State {
+    info: gst_video::VideoInfo,
+    /// Kept across caps changes, as the report covers the whole stream.
+    detector: Detector,
+    /// End of the last frame, where the events still going on at EOS end.
+    end: Option<gst::ClockTime>,
+}
+
+#[derive(Default)]
+pub struct VideoQc {
+    settings: Mutex<Settings>,
+    state: Mutex<Option<State>>,
+}
+
+impl VideoQc {
+    fn post_changes(&self, element: &super::VideoQc, changes: Vec<Change>) {
+        for change in changes {
+            let (name, event) = match change {
+                Change::Start(event) => ("rsvideoqc-start", event),
+                Change::End(event) => ("rsvideoqc-end", event),
+            };
+
+            gst_info!(CAT, obj: element, "{}: {:?}", name, event);
+
+            let running_time = element
+                .segment()
+                .downcast_ref::<gst::ClockTime>()
+                .and_then(|segment| segment.to_running_time(event.start));
+            let mut s = gst::Structure::builder(name)
+                .field("type", event.kind.name())
+                .field("timestamp", event.start)
+                .field("running-time", running_time)
+                .field("frames", event.frames)
+                .build();
+            if let Change::End(_) = change {
+                s.set("end", event.end);
+            }
+
+            let _ = element.post_message(gst::message::Element::builder(s).src(element).build());
+        }
+    }
+
+    fn analyze(&self, element: &super::VideoQc, buf: &BufferRef) -> Result<FlowSuccess, FlowError> {
+        let settings = self.settings.lock().detector_settings();
+
+        let changes = {
+            let mut state_guard = self.state.lock();
+            let state = state_guard.as_mut().ok_or_else(|| {
+                gst::element_error!(element, gst::CoreError::Negotiation, ["Have no state yet"]);
+                FlowError::NotNegotiated
+            })?;
+
+            if buf.flags().contains(gst::BufferFlags::DISCONT) {
+                state.detector.discont();
+            }
+
+            let frame = gst_video::VideoFrameRef::from_buffer_ref_readable(buf, &state.info).map_err(|err| {
+                gst::element_error!(
+                    element,
+                    gst::CoreError::Failed,
+                    [&format!("Failed to map buffer readable: {}", err)]
+                );
+                FlowError::Error
+            })?;
+            let luma = luma(&frame).map_err(|err| {
+                gst::element_error!(element, gst::CoreError::Failed, [&err]);
+                FlowError::Error
+            })?;
+
+            let pts = buf.pts();
+            let (metrics, changes) = state.detector.push(
+                luma,
+                state.info.width() as usize,
+                state.info.height() as usize,
+                pts,
+                &settings,
+            );
+            gst_log!(
+                CAT,
+                obj: element,
+                "Frame at {}: mean {:.2}, difference {:?}, sharpness {:.2}",
+                pts.display(),
+                metrics.mean,
+                metrics.difference,
+                metrics.sharpness,
+            );
+
+            state.end = pts.map(|pts| pts + buf.duration().unwrap_or(gst::ClockTime::ZERO));
+            changes
+        };
+
+        self.post_changes(element, changes);
+
+        Ok(FlowSuccess::Ok)
+    }
+
+    fn finish(&self, element: &super::VideoQc) {
+        let location = self.settings.lock().report_location.clone();
+
+        let (changes, report) = {
+            let mut state_guard = self.state.lock();
+            let state = match state_guard.as_mut() {
+                Some(state) => state,
+                None => return,
+            };
+            let changes = state.detector.finish(state.end);
+            (changes, state.detector.report())
+        };
+
+        self.post_changes(element, changes);
+
+        if let Some(location) = location {
+            gst_debug!(CAT, obj: element, "Writing report to {}", location);
+            if let Err(err) = std::fs::write(&location, report) {
+                gst_warning!(CAT, obj: element, "Failed to write report {}: {}", location, err);
+                gst::element_warning!(
+                    element,
+                    gst::ResourceError::Write,
+                    ["Failed to write report {}: {}", location, err]
+                );
+            }
+        }
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for VideoQc {
+    const NAME: &'static str = "RsVideoQc";
+    type Type = super::VideoQc;
+    type ParentType = gst_base::BaseTransform;
+}
+
+impl ElementImpl for VideoQc {
+    fn metadata() -> Option<&'static ElementMetadata> {
+        static METADATA: Lazy<ElementMetadata> = Lazy::new(|| {
+            ElementMetadata::new(
+                "Video quality control",
+                "Filter/Analyzer/Video",
+                "Detect black, frozen and blurry frames",
+                "Seiichi Uchida <topecongiro@fastmail.com>",
+            )
+        });
+
+        Some(&*METADATA)
+    }
+
+    fn pad_templates() -> &'static [PadTemplate] {
+        static PAD_TEMPLATE: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let caps = gst::Caps::builder("video/x-raw")
+                .field("format", gst::List::from(get_all_video_formats()))
+                .field("width", gst::IntRange::new(1, i32::MAX))
+                .field("height", gst::IntRange::new(1, i32::MAX))
+                .field("framerate", gst::FractionRange::new(
+                    gst::Fraction::new(0, 1),
+                    gst::Fraction::new(i32::MAX, 1),
+                ))
+                .build();
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &caps,
+            ).unwrap();
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &caps,
+            ).unwrap();
+
+            vec![sink_pad_template, src_pad_template]
+        });
+
+        PAD_TEMPLATE.as_ref()
+    }
+}
+
+impl BaseTransformImpl for VideoQc {
+    const MODE: BaseTransformMode = BaseTransformMode::AlwaysInPlace;
+    const PASSTHROUGH_ON_SAME_CAPS: bool = true;
+    const TRANSFORM_IP_ON_PASSTHROUGH: bool = true;
+
+    fn set_caps(&self, element: &Self::Type, incaps: &Caps, outcaps: &Caps) -> Result<(), LoggableError> {
+        let info = gst_video::VideoInfo::from_caps(incaps)
+            .map_err(|_| gst::loggable_error!(CAT, "Failed to parse input caps"))?;
+
+        gst_debug!(CAT, obj: element, "Configured for caps {} to {}", incaps, outcaps);
+
+        let mut state = self.state.lock();
+        let (mut detector, end) = state
+            .take()
+            .map(|state| (state.detector, state.end))
+            .unwrap_or_default();
+        detector.discont();
+        *state = Some(State { info, detector, end });
+
+        Ok(())
+    }
+
+    fn stop(&self, element: &Self::Type) -> Result<(), ErrorMessage> {
+        let _ = self.state.lock().take();
+
+        gst_info!(CAT, obj: element, "Stopped");
+
+        Ok(())
+    }
+
+    fn sink_event(&self, element: &Self::Type, event: Event) -> bool {
+        match event.view() {
+            gst::EventView::FlushStop(_) => {
+                if let Some(state) = self.state.lock().as_mut() {
+                    state.detector.discont();
+                }
+            }
+            gst::EventView::Eos(_) => self.finish(element),
+            _ => (),
+        }
+
+        self.parent_sink_event(element, event)
+    }
+
+    fn transform_ip_passthrough(&self, element: &Self::Type, buf: &Buffer) -> Result<FlowSuccess, FlowError> {
+        gst_log!(CAT, obj: element, "transform_ip_passthrough: {:?}", buf);
+
+        self.analyze(element, buf)
+    }
+
+    fn transform_ip(&self, element: &Self::Type, buf: &mut BufferRef) -> Result<FlowSuccess, FlowError> {
+        gst_log!(CAT, obj: element, "transform_ip: {:?}", buf);
+
+        self.analyze(element, buf)
+    }
+}
+
+impl GstObjectImpl for VideoQc {}
+
+impl ObjectImpl for VideoQc {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecDouble::new(
+                    "black-threshold",
+                    "Black threshold",
+                    "Mean luma, in 8-bit code values, below which a frame is black",
+                    0.0,
+                    255.0,
+                    DEFAULT_BLACK_THRESHOLD,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt::new(
+                    "black-frames",
+                    "Black frames",
+                    "Number of consecutive black frames reported as an event",
+                    1,
+                    u32::MAX,
+                    DEFAULT_BLACK_FRAMES,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecDouble::new(
+                    "freeze-threshold",
+                    "Freeze threshold",
+                    "Mean absolute luma difference to the previous frame below which a frame is frozen",
+                    0.0,
+                    255.0,
+                    DEFAULT_FREEZE_THRESHOLD,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt64::new(
+                    "freeze-duration",
+                    "Freeze duration",
+                    "Duration in nanoseconds of frozen frames reported as an event",
+                    0,
+                    u64::MAX,
+                    DEFAULT_FREEZE_DURATION,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecDouble::new(
+                    "blur-threshold",
+                    "Blur threshold",
+                    "Variance of the Laplacian of the luma below which a frame is blurry",
+                    0.0,
+                    f64::MAX,
+                    DEFAULT_BLUR_THRESHOLD,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecString::new(
+                    "report-location",
+                    "Report location",
+                    "File to write a JSON report of the events to at EOS",
+                    None,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(&self, obj: &Self::Type, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        match pspec.name() {
+            "black-threshold" => {
+                let mut settings = self.settings.lock();
+                let black_threshold = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing black-threshold from {} to {}",
+                    settings.black_threshold, black_threshold
+                );
+                settings.black_threshold = black_threshold;
+            }
+            "black-frames" => {
+                let mut settings = self.settings.lock();
+                let black_frames = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing black-frames from {} to {}",
+                    settings.black_frames, black_frames
+                );
+                settings.black_frames = black_frames;
+            }
+            "freeze-threshold" => {
+                let mut settings = self.settings.lock();
+                let freeze_threshold = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing freeze-threshold from {} to {}",
+                    settings.freeze_threshold, freeze_threshold
+                );
+                settings.freeze_threshold = freeze_threshold;
+            }
+            "freeze-duration" => {
+                let mut settings = self.settings.lock();
+                let freeze_duration = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing freeze-duration from {} to {}",
+                    settings.freeze_duration, freeze_duration
+                );
+                settings.freeze_duration = freeze_duration;
+            }
+            "blur-threshold" => {
+                let mut settings = self.settings.lock();
+                let blur_threshold = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing blur-threshold from {} to {}",
+                    settings.blur_threshold, blur_threshold
+                );
+                settings.blur_threshold = blur_threshold;
+            }
+            "report-location" => {
+                let mut settings = self.settings.lock();
+                let report_location = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing report-location from {:?} to {:?}",
+                    settings.report_location, report_location
+                );
+                settings.report_location = report_location;
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn property(&self, _obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        match pspec.name() {
+            "black-threshold" => {
+                let settings = self.settings.lock();
+                settings.black_threshold.to_value()
+            }
+            "black-frames" => {
+                let settings = self.settings.lock();
+                settings.black_frames.to_value()
+            }
+            "freeze-threshold" => {
+                let settings = self.settings.lock();
+                settings.freeze_threshold.to_value()
+            }
+            "freeze-duration" => {
+                let settings = self.settings.lock();
+                settings.freeze_duration.to_value()
+            }
+            "blur-threshold" => {
+                let settings = self.settings.lock();
+                settings.blur_threshold.to_value()
+            }
+            "report-location" => {
+                let settings = self.settings.lock();
+                settings.report_location.to_value()
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
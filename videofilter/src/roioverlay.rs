@@ -0,0 +1,19 @@
+use gst::glib::{self, StaticType};
+
+mod imp;
+
+glib::wrapper! {
+    pub struct RoiOverlay(ObjectSubclass<imp::RoiOverlay>) @extends gst_base::BaseTransform, gst::Element, gst::Object;
+}
+
+unsafe impl Send for RoiOverlay {}
+unsafe impl Sync for RoiOverlay {}
+
+pub(crate) fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "rsroioverlay",
+        gst::Rank::None,
+        RoiOverlay::static_type(),
+    )
+}
@@ -0,0 +1,417 @@
+use gst::{glib, gst_debug, gst_info, gst_log, gst_warning, BufferRef, Caps, ErrorMessage, FlowError, FlowSuccess, LoggableError, PadTemplate};
+use gst::subclass::ElementMetadata;
+use gst::subclass::prelude::*;
+use gst::prelude::*;
+use gst_base::subclass::{*, prelude::*};
+use parking_lot::Mutex;
+use crate::glib::once_cell::sync::Lazy;
+
+use crate::videofilter::{draw_outline, draw_text_at, get_all_video_formats, text_size};
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rsroioverlay",
+        gst::DebugColorFlags::empty(),
+        Some("Region of interest overlay"),
+    )
+});
+
+const DEFAULT_COLOR: u32 = 0xff00_ff00;
+const DEFAULT_LINE_WIDTH: u32 = 2;
+const DEFAULT_SHOW_LABELS: bool = true;
+const DEFAULT_TEXT_SCALE: u32 = 1;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    color: u32,
+    type_colors: Option<gst::Structure>,
+    line_width: u32,
+    show_labels: bool,
+    text_scale: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            color: DEFAULT_COLOR,
+            type_colors: None,
+            line_width: DEFAULT_LINE_WIDTH,
+            show_labels: DEFAULT_SHOW_LABELS,
+            text_scale: DEFAULT_TEXT_SCALE,
+        }
+    }
+}
+
+impl Settings {
+    /// Colour of the regions of `roi_type`, from `type-colors` if it has
+    /// a field of that name.
+    fn color(&self, roi_type: &str) -> u32 {
+        self.type_colors
+            .as_ref()
+            .and_then(|colors| {
+                colors
+                    .get::<u32>(roi_type)
+                    .ok()
+                    .or_else(|| colors.get::<i32>(roi_type).ok().map(|color| color as u32))
+            })
+            .unwrap_or(self.color)
+    }
+}
+
+/// Black or white, whichever reads better on `argb`.
+fn text_color(argb: u32) -> u32 {
+    let [r, g, b] = [(argb >> 16) & 0xff, (argb >> 8) & 0xff, argb & 0xff];
+    if 2126 * r + 7152 * g + 722 * b > 10_000 * 128 {
+        0xff00_0000
+    } else {
+        0xffff_ffff
+    }
+}
+
+/// The label of a region: a `label` field of its params, or its type
+/// otherwise, followed by a `confidence` field if there is one.
+fn label(meta: &gst_video::VideoRegionOfInterestMeta) -> String {
+    let params = meta.params().collect::<Vec<_>>();
+    let label = params
+        .iter()
+        .find_map(|s| s.get::<String>("label").ok())
+        .unwrap_or_else(|| meta.roi_type().to_string());
+    let confidence = params.iter().find_map(|s| {
+        s.get::<f64>("confidence")
+            .ok()
+            .or_else(|| s.get::<f32>("confidence").ok().map(f64::from))
+    });
+
+    match confidence {
+        Some(confidence) => format!("{} {:.2}", label, confidence),
+        None => label,
+    }
+}
+
+struct State {
+    info: gst_video::VideoInfo,
+}
+
+#[derive(Default)]
+pub struct RoiOverlay {
+    settings: Mutex<Settings>,
+    state: Mutex<Option<State>>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for RoiOverlay {
+    const NAME: &'static str = "RsRoiOverlay";
+    type Type = super::RoiOverlay;
+    type ParentType = gst_base::BaseTransform;
+}
+
+impl ElementImpl for RoiOverlay {
+    fn metadata() -> Option<&'static ElementMetadata> {
+        static METADATA: Lazy<ElementMetadata> = Lazy::new(|| {
+            ElementMetadata::new(
+                "Region of interest overlay",
+                "Filter/Effect/Video",
+                "Draw the regions of interest of the frames with their labels",
+                "Seiichi Uchida <topecongiro@fastmail.com>",
+            )
+        });
+
+        Some(&*METADATA)
+    }
+
+    fn pad_templates() -> &'static [PadTemplate] {
+        static PAD_TEMPLATE: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let caps = gst::Caps::builder("video/x-raw")
+                .field("format", gst::List::from(get_all_video_formats()))
+                .field("width", gst::IntRange::new(1, i32::MAX))
+                .field("height", gst::IntRange::new(1, i32::MAX))
+                .field("framerate", gst::FractionRange::new(
+                    gst::Fraction::new(0, 1),
+                    gst::Fraction::new(i32::MAX, 1),
+                ))
+                .build();
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &caps,
+            ).unwrap();
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &caps,
+            ).unwrap();
+
+            vec![sink_pad_template, src_pad_template]
+        });
+
+        PAD_TEMPLATE.as_ref()
+    }
+}
+
+impl BaseTransformImpl for RoiOverlay {
+    const MODE: BaseTransformMode = BaseTransformMode::AlwaysInPlace;
+    const PASSTHROUGH_ON_SAME_CAPS: bool = false;
+    const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+
+    fn set_caps(&self, element: &Self::Type, incaps: &Caps, outcaps: &Caps) -> Result<(), LoggableError> {
+        let info = gst_video::VideoInfo::from_caps(incaps)
+            .map_err(|_| gst::loggable_error!(CAT, "Failed to parse input caps"))?;
+
+        gst_debug!(CAT, obj: element, "Configured for caps {} to {}", incaps, outcaps);
+
+        *self.state.lock() = Some(State { info });
+
+        Ok(())
+    }
+
+    fn stop(&self, element: &Self::Type) -> Result<(), ErrorMessage> {
+        let _ = self.state.lock().take();
+
+        gst_info!(CAT, obj: element, "Stopped");
+
+        Ok(())
+    }
+
+    fn transform_ip(&self, element: &Self::Type, buf: &mut BufferRef) -> Result<FlowSuccess, FlowError> {
+        gst_log!(CAT, obj: element, "transform_ip: {:?}", buf);
+
+        let settings = self.settings.lock().clone();
+
+        let regions = buf
+            .iter_meta::<gst_video::VideoRegionOfInterestMeta>()
+            .map(|meta| (meta.rect(), settings.color(meta.roi_type()), label(&meta)))
+            .collect::<Vec<_>>();
+        if regions.is_empty() {
+            return Ok(FlowSuccess::Ok);
+        }
+
+        let state_guard = self.state.lock();
+        let state = state_guard.as_ref().ok_or_else(|| {
+            gst::element_error!(element, gst::CoreError::Negotiation, ["Have no state yet"]);
+            FlowError::NotNegotiated
+        })?;
+
+        let mut frame = gst_video::VideoFrameRef::from_buffer_ref_writable(buf, &state.info).map_err(|err| {
+            gst::element_error!(
+                element,
+                gst::CoreError::Failed,
+                [&format!("Failed to map buffer writable: {}", err)]
+            );
+            FlowError::Error
+        })?;
+
+        for (rect, color, label) in regions {
+            gst_log!(CAT, obj: element, "Drawing {:?} {:?}", label, rect);
+
+            let mut result = draw_outline(&mut frame, rect, settings.line_width, color);
+            if settings.show_labels && result.is_ok() {
+                // Above the box, or inside of it at the top of the frame.
+                let lines = [label];
+                let (_, text_height) = text_size(&lines, settings.text_scale);
+                let (x, y, _, _) = rect;
+                let y = (y as usize).checked_sub(text_height).unwrap_or(y as usize);
+                result = draw_text_at(&mut frame, &lines, x as usize, y, settings.text_scale, text_color(color), color);
+            }
+
+            if let Err(err) = result {
+                gst_warning!(CAT, obj: element, "Failed to draw region: {}", err);
+            }
+        }
+
+        Ok(FlowSuccess::Ok)
+    }
+}
+
+impl GstObjectImpl for RoiOverlay {}
+
+impl ObjectImpl for RoiOverlay {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecUInt::new(
+                    "color",
+                    "Color",
+                    "Color in ARGB of the regions without one in type-colors",
+                    0,
+                    u32::MAX,
+                    DEFAULT_COLOR,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecBoxed::new(
+                    "type-colors",
+                    "Type colors",
+                    "Colors in ARGB per region type, as a structure with a uint field per type",
+                    gst::Structure::static_type(),
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt::new(
+                    "line-width",
+                    "Line width",
+                    "Width of the rectangles in luma samples",
+                    1,
+                    u32::MAX,
+                    DEFAULT_LINE_WIDTH,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecBoolean::new(
+                    "show-labels",
+                    "Show labels",
+                    "Draw the label and confidence of the regions",
+                    DEFAULT_SHOW_LABELS,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt::new(
+                    "text-scale",
+                    "Text scale",
+                    "Integer scale factor of the 5x7 font of the labels",
+                    1,
+                    64,
+                    DEFAULT_TEXT_SCALE,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(&self, obj: &Self::Type, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        match pspec.name() {
+            "color" => {
+                let mut settings = self.settings.lock();
+                let color = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing color from {:08x} to {:08x}",
+                    settings.color, color
+                );
+                settings.color = color;
+            }
+            "type-colors" => {
+                let mut settings = self.settings.lock();
+                let type_colors = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing type-colors from {:?} to {:?}",
+                    settings.type_colors, type_colors
+                );
+                settings.type_colors = type_colors;
+            }
+            "line-width" => {
+                let mut settings = self.settings.lock();
+                let line_width = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing line-width from {} to {}",
+                    settings.line_width, line_width
+                );
+                settings.line_width = line_width;
+            }
+            "show-labels" => {
+                let mut settings = self.settings.lock();
+                let show_labels = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing show-labels from {} to {}",
+                    settings.show_labels, show_labels
+                );
+                settings.show_labels = show_labels;
+            }
+            "text-scale" => {
+                let mut settings = self.settings.lock();
+                let text_scale = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing text-scale from {} to {}",
+                    settings.text_scale, text_scale
+                );
+                settings.text_scale = text_scale;
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn property(&self, _obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        match pspec.name() {
+            "color" => {
+                let settings = self.settings.lock();
+                settings.color.to_value()
+            }
+            "type-colors" => {
+                let settings = self.settings.lock();
+                settings.type_colors.to_value()
+            }
+            "line-width" => {
+                let settings = self.settings.lock();
+                settings.line_width.to_value()
+            }
+            "show-labels" => {
+                let settings = self.settings.lock();
+                settings.show_labels.to_value()
+            }
+            "text-scale" => {
+                let settings = self.settings.lock();
+                settings.text_scale.to_value()
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_type_colors() {
+        let mut settings = Settings::default();
+        assert_eq!(settings.color("face"), DEFAULT_COLOR);
+
+        settings.type_colors = Some(
+            gst::Structure::builder("colors")
+                .field("face", 0xffff_0000u32)
+                .field("car", 0x0000_ff00i32)
+                .build(),
+        );
+        assert_eq!(settings.color("face"), 0xffff_0000);
+        assert_eq!(settings.color("car"), 0x0000_ff00);
+        assert_eq!(settings.color("dog"), DEFAULT_COLOR);
+    }
+
+    #[test]
+    fn test_text_color() {
+        assert_eq!(text_color(0xffff_ffff), 0xff00_0000);
+        assert_eq!(text_color(0xff00_ff00), 0xff00_0000);
+        assert_eq!(text_color(0xff00_0000), 0xffff_ffff);
+        assert_eq!(text_color(0xff00_00ff), 0xffff_ffff);
+    }
+
+    #[test]
+    fn test_label() {
+        gst::init().unwrap();
+
+        let mut buffer = gst::Buffer::with_size(16).unwrap();
+        {
+            let buffer = buffer.get_mut().unwrap();
+            gst_video::VideoRegionOfInterestMeta::add(buffer, "face", (0, 0, 2, 2));
+            let mut meta = gst_video::VideoRegionOfInterestMeta::add(buffer, "face", (0, 0, 2, 2));
+            meta.add_param(gst::Structure::builder("detection").field("label", "Alice").build());
+            meta.add_param(gst::Structure::builder("classification").field("confidence", 0.9f64).build());
+            let mut meta = gst_video::VideoRegionOfInterestMeta::add(buffer, "car", (0, 0, 2, 2));
+            meta.add_param(gst::Structure::builder("detection").field("confidence", 0.5f32).build());
+        }
+
+        let labels = buffer
+            .iter_meta::<gst_video::VideoRegionOfInterestMeta>()
+            .map(|meta| label(&meta))
+            .collect::<Vec<_>>();
+        assert_eq!(labels, ["face", "Alice 0.90", "car 0.50"]);
+    }
+}
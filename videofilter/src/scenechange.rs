@@ -0,0 +1,20 @@
+use gst::glib::{self, StaticType};
+
+mod detector;
+mod imp;
+
+glib::wrapper! {
+    pub struct SceneChange(ObjectSubclass<imp::SceneChange>) @extends gst_base::BaseTransform, gst::Element, gst::Object;
+}
+
+unsafe impl Send for SceneChange {}
+unsafe impl Sync for SceneChange {}
+
+pub(crate) fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "rsscenechange",
+        gst::Rank::None,
+        SceneChange::static_type(),
+    )
+}
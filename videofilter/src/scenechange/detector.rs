@@ -0,0 +1,209 @@
+//! Scene cut scoring on luma.
+//!
+//! Each frame is scored against the previous one between 0 and 1, and is a
+//! cut when its score is above both a fixed threshold and a multiple of the
+//! mean score of the recent frames, so that fast motion or flashes in a
+//! busy scene are less likely to trigger.
+
+use std::collections::VecDeque;
+
+use gst::glib;
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstSceneChangeMetric")]
+pub enum Metric {
+    #[enum_value(name = "Luma histogram distance", nick = "histogram")]
+    Histogram = 0,
+    #[enum_value(name = "Mean absolute luma difference", nick = "luma")]
+    Luma = 1,
+    #[enum_value(name = "Mean of the histogram and luma scores", nick = "combined")]
+    Combined = 2,
+}
+
+const BINS: usize = 64;
+
+#[derive(Debug, Clone, Copy)]
+pub struct DetectorSettings {
+    pub metric: Metric,
+    pub threshold: f64,
+    /// 0 disables the adaptive threshold.
+    pub adaptive_factor: f64,
+    /// Number of recent scores the adaptive threshold is computed from.
+    pub window: u32,
+    /// Minimum number of frames between two cuts.
+    pub min_distance: u32,
+}
+
+fn histogram(luma: &[f32]) -> [f64; BINS] {
+    let mut histogram = [0.0; BINS];
+    for &v in luma {
+        let bin = (v.clamp(0.0, 255.0) as usize * BINS / 256).min(BINS - 1);
+        histogram[bin] += 1.0;
+    }
+    let total = luma.len().max(1) as f64;
+    histogram.iter_mut().for_each(|count| *count /= total);
+    histogram
+}
+
+#[derive(Default)]
+pub struct Detector {
+    luma: Vec<f32>,
+    histogram: Option<[f64; BINS]>,
+    scores: VecDeque<f64>,
+    /// Frames since the last cut.
+    since_cut: u64,
+}
+
+impl Detector {
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Scores the frame of `luma` against the previous one and returns
+    /// the score if it is a cut.
+    pub fn push(&mut self, luma: Vec<f32>, settings: &DetectorSettings) -> Option<f64> {
+        let histogram = histogram(&luma);
+
+        let score = match self.histogram {
+            Some(previous) if self.luma.len() == luma.len() => {
+                let histogram_score = || {
+                    previous
+                        .iter()
+                        .zip(histogram.iter())
+                        .map(|(a, b)| (a - b).abs())
+                        .sum::<f64>()
+                        / 2.0
+                };
+                let luma_score = || {
+                    self.luma
+                        .iter()
+                        .zip(luma.iter())
+                        .map(|(a, b)| f64::from((a - b).abs()))
+                        .sum::<f64>()
+                        / (255.0 * luma.len().max(1) as f64)
+                };
+
+                Some(match settings.metric {
+                    Metric::Histogram => histogram_score(),
+                    Metric::Luma => luma_score(),
+                    Metric::Combined => (histogram_score() + luma_score()) / 2.0,
+                })
+            }
+            _ => None,
+        };
+
+        self.luma = luma;
+        self.histogram = Some(histogram);
+        self.since_cut += 1;
+
+        let score = score?;
+        let mean = if self.scores.is_empty() {
+            0.0
+        } else {
+            self.scores.iter().sum::<f64>() / self.scores.len() as f64
+        };
+        let cut = score >= settings.threshold
+            && score >= settings.adaptive_factor * mean
+            && self.since_cut > u64::from(settings.min_distance);
+
+        // The scores of cuts are kept out of the window so that a cut does
+        // not hide the next one.
+        if cut {
+            self.since_cut = 0;
+        } else {
+            self.scores.push_back(score);
+            while self.scores.len() > settings.window.max(1) as usize {
+                self.scores.pop_front();
+            }
+        }
+
+        if cut {
+            Some(score)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(metric: Metric, threshold: f64, adaptive_factor: f64, min_distance: u32) -> DetectorSettings {
+        DetectorSettings {
+            metric,
+            threshold,
+            adaptive_factor,
+            window: 4,
+            min_distance,
+        }
+    }
+
+    fn frame(v: f32) -> Vec<f32> {
+        vec![v; 16]
+    }
+
+    #[test]
+    fn test_cut() {
+        let settings = settings(Metric::Luma, 0.3, 0.0, 0);
+        let mut detector = Detector::default();
+
+        assert_eq!(detector.push(frame(0.0), &settings), None);
+        assert_eq!(detector.push(frame(0.0), &settings), None);
+        assert_eq!(detector.push(frame(255.0), &settings), Some(1.0));
+        assert_eq!(detector.push(frame(255.0), &settings), None);
+
+        // A new size starts over.
+        assert_eq!(detector.push(vec![0.0; 4], &settings), None);
+    }
+
+    #[test]
+    fn test_metrics() {
+        // Swapping the halves keeps the histogram but changes every pixel.
+        let first: Vec<f32> = (0..16).map(|i| if i < 8 { 0.0 } else { 255.0 }).collect();
+        let second: Vec<f32> = first.iter().rev().copied().collect();
+
+        // Everything is a cut with these settings, which gives the scores.
+        for (metric, expected) in [(Metric::Histogram, 0.0), (Metric::Luma, 1.0), (Metric::Combined, 0.5)] {
+            let settings = settings(metric, 0.0, 0.0, 0);
+            let mut detector = Detector::default();
+            assert_eq!(detector.push(first.clone(), &settings), None);
+            assert_eq!(detector.push(second.clone(), &settings), Some(expected), "{:?}", metric);
+        }
+
+        let settings = settings(Metric::Histogram, 0.0, 0.0, 0);
+        let mut detector = Detector::default();
+        detector.push(frame(0.0), &settings);
+        assert_eq!(detector.push(first, &settings), Some(0.5));
+    }
+
+    #[test]
+    fn test_adaptive_threshold() {
+        let settings = settings(Metric::Luma, 0.15, 3.0, 0);
+
+        // Scores of the recent frames, all below the threshold, and whether
+        // a jump of 0.2 after them is a cut.
+        for (step, expected) in [(10.2, true), (25.5, false)] {
+            let mut detector = Detector::default();
+            for i in 0..5 {
+                assert_eq!(detector.push(frame(100.0 + step * (i % 2) as f32), &settings), None);
+            }
+            assert_eq!(detector.push(frame(151.0), &settings).is_some(), expected, "{}", step);
+        }
+    }
+
+    #[test]
+    fn test_min_distance() {
+        let settings = settings(Metric::Luma, 0.5, 0.0, 1);
+        let mut detector = Detector::default();
+
+        assert_eq!(detector.push(frame(0.0), &settings), None);
+        assert_eq!(detector.push(frame(255.0), &settings), Some(1.0));
+        assert_eq!(detector.push(frame(0.0), &settings), None);
+        assert_eq!(detector.push(frame(255.0), &settings), Some(1.0));
+
+        detector.reset();
+        assert_eq!(detector.push(frame(0.0), &settings), None);
+    }
+}
@@ -0,0 +1,409 @@
+use gst::{glib, gst_debug, gst_info, gst_log, Buffer, BufferRef, Caps, ErrorMessage, Event, FlowError, FlowSuccess, LoggableError, PadTemplate};
+use gst::subclass::ElementMetadata;
+use gst::subclass::prelude::*;
+use gst::prelude::*;
+use gst_base::prelude::*;
+use gst_base::subclass::{*, prelude::*};
+use parking_lot::Mutex;
+use crate::glib::once_cell::sync::Lazy;
+
+use super::detector::{Detector, DetectorSettings, Metric};
+use crate::videofilter::{get_all_video_formats, luma};
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rsscenechange",
+        gst::DebugColorFlags::empty(),
+        Some("Scene change detection"),
+    )
+});
+
+const DEFAULT_METRIC: Metric = Metric::Combined;
+const DEFAULT_THRESHOLD: f64 = 0.3;
+const DEFAULT_ADAPTIVE_FACTOR: f64 = 3.0;
+const DEFAULT_WINDOW: u32 = 25;
+const DEFAULT_MIN_DISTANCE: u32 = 10;
+const DEFAULT_FORCE_KEY_UNIT: bool = false;
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    metric: Metric,
+    threshold: f64,
+    adaptive_factor: f64,
+    window: u32,
+    min_distance: u32,
+    force_key_unit: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            metric: DEFAULT_METRIC,
+            threshold: DEFAULT_THRESHOLD,
+            adaptive_factor: DEFAULT_ADAPTIVE_FACTOR,
+            window: DEFAULT_WINDOW,
+            min_distance: DEFAULT_MIN_DISTANCE,
+            force_key_unit: DEFAULT_FORCE_KEY_UNIT,
+        }
+    }
+}
+
+impl Settings {
+    fn detector_settings(&self) -> DetectorSettings {
+        DetectorSettings {
+            metric: self.metric,
+            threshold: self.threshold,
+            adaptive_factor: self.adaptive_factor,
+            window: self.window,
+            min_distance: self.min_distance,
+        }
+    }
+}
+
+struct State {
+    info: gst_video::VideoInfo,
+    detector: Detector,
+}
+
+#[derive(Default)]
+pub struct SceneChange {
+    settings: Mutex<Settings>,
+    state: Mutex<Option<State>>,
+}
+
+impl SceneChange {
+    fn detect(&self, element: &super::SceneChange, buf: &BufferRef) -> Result<FlowSuccess, FlowError> {
+        let settings = *self.settings.lock();
+
+        let score = {
+            let mut state_guard = self.state.lock();
+            let state = state_guard.as_mut().ok_or_else(|| {
+                gst::element_error!(element, gst::CoreError::Negotiation, ["Have no state yet"]);
+                FlowError::NotNegotiated
+            })?;
+
+            if buf.flags().contains(gst::BufferFlags::DISCONT) {
+                state.detector.reset();
+            }
+
+            let frame = gst_video::VideoFrameRef::from_buffer_ref_readable(buf, &state.info).map_err(|err| {
+                gst::element_error!(
+                    element,
+                    gst::CoreError::Failed,
+                    [&format!("Failed to map buffer readable: {}", err)]
+                );
+                FlowError::Error
+            })?;
+            let luma = luma(&frame).map_err(|err| {
+                gst::element_error!(element, gst::CoreError::Failed, [&err]);
+                FlowError::Error
+            })?;
+
+            state.detector.push(luma, &settings.detector_settings())
+        };
+
+        let score = match score {
+            Some(score) => score,
+            None => return Ok(FlowSuccess::Ok),
+        };
+
+        let pts = buf.pts();
+        let segment = element.segment();
+        let segment = segment.downcast_ref::<gst::ClockTime>();
+        let running_time = segment.and_then(|segment| segment.to_running_time(pts));
+        let stream_time = segment.and_then(|segment| segment.to_stream_time(pts));
+
+        gst_info!(CAT, obj: element, "Scene cut at {} with score {:.3}", pts.display(), score);
+
+        let s = gst::Structure::builder("rsscenechange")
+            .field("timestamp", pts)
+            .field("running-time", running_time)
+            .field("score", score)
+            .build();
+        let _ = element.post_message(gst::message::Element::builder(s).src(element).build());
+
+        // Sent before the buffer so that the encoder starts the new GOP
+        // with it.
+        if settings.force_key_unit {
+            let event = gst_video::DownstreamForceKeyUnitEvent::builder()
+                .timestamp(pts)
+                .stream_time(stream_time)
+                .running_time(running_time)
+                .all_headers(true)
+                .build();
+            if !element.static_pad("src").unwrap().push_event(event) {
+                gst_debug!(CAT, obj: element, "Force key unit event was not handled");
+            }
+        }
+
+        Ok(FlowSuccess::Ok)
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for SceneChange {
+    const NAME: &'static str = "RsSceneChange";
+    type Type = super::SceneChange;
+    type ParentType = gst_base::BaseTransform;
+}
+
+impl ElementImpl for SceneChange {
+    fn metadata() -> Option<&'static ElementMetadata> {
+        static METADATA: Lazy<ElementMetadata> = Lazy::new(|| {
+            ElementMetadata::new(
+                "Scene change detection",
+                "Filter/Analyzer/Video",
+                "Detect scene cuts and optionally request key units at them",
+                "Seiichi Uchida <topecongiro@fastmail.com>",
+            )
+        });
+
+        Some(&*METADATA)
+    }
+
+    fn pad_templates() -> &'static [PadTemplate] {
+        static PAD_TEMPLATE: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let caps = gst::Caps::builder("video/x-raw")
+                .field("format", gst::List::from(get_all_video_formats()))
+                .field("width", gst::IntRange::new(1, i32::MAX))
+                .field("height", gst::IntRange::new(1, i32::MAX))
+                .field("framerate", gst::FractionRange::new(
+                    gst::Fraction::new(0, 1),
+                    gst::Fraction::new(i32::MAX, 1),
+                ))
+                .build();
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &caps,
+            ).unwrap();
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &caps,
+            ).unwrap();
+
+            vec![sink_pad_template, src_pad_template]
+        });
+
+        PAD_TEMPLATE.as_ref()
+    }
+}
+
+impl BaseTransformImpl for SceneChange {
+    const MODE: BaseTransformMode = BaseTransformMode::AlwaysInPlace;
+    const PASSTHROUGH_ON_SAME_CAPS: bool = true;
+    const TRANSFORM_IP_ON_PASSTHROUGH: bool = true;
+
+    fn set_caps(&self, element: &Self::Type, incaps: &Caps, outcaps: &Caps) -> Result<(), LoggableError> {
+        let info = gst_video::VideoInfo::from_caps(incaps)
+            .map_err(|_| gst::loggable_error!(CAT, "Failed to parse input caps"))?;
+
+        gst_debug!(CAT, obj: element, "Configured for caps {} to {}", incaps, outcaps);
+
+        *self.state.lock() = Some(State {
+            info,
+            detector: Detector::default(),
+        });
+
+        Ok(())
+    }
+
+    fn stop(&self, element: &Self::Type) -> Result<(), ErrorMessage> {
+        let _ = self.state.lock().take();
+
+        gst_info!(CAT, obj: element, "Stopped");
+
+        Ok(())
+    }
+
+    fn sink_event(&self, element: &Self::Type, event: Event) -> bool {
+        if let gst::EventView::FlushStop(_) = event.view() {
+            if let Some(state) = self.state.lock().as_mut() {
+                state.detector.reset();
+            }
+        }
+
+        self.parent_sink_event(element, event)
+    }
+
+    fn transform_ip_passthrough(&self, element: &Self::Type, buf: &Buffer) -> Result<FlowSuccess, FlowError> {
+        gst_log!(CAT, obj: element, "transform_ip_passthrough: {:?}", buf);
+
+        self.detect(element, buf)
+    }
+
+    fn transform_ip(&self, element: &Self::Type, buf: &mut BufferRef) -> Result<FlowSuccess, FlowError> {
+        gst_log!(CAT, obj: element, "transform_ip: {:?}", buf);
+
+        self.detect(element, buf)
+    }
+}
+
+impl GstObjectImpl for SceneChange {}
+
+impl ObjectImpl for SceneChange {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecEnum::new(
+                    "metric",
+                    "Metric",
+                    "How consecutive frames are compared",
+                    Metric::static_type(),
+                    DEFAULT_METRIC as i32,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecDouble::new(
+                    "threshold",
+                    "Threshold",
+                    "Minimum score, between 0 and 1, of a cut",
+                    0.0,
+                    1.0,
+                    DEFAULT_THRESHOLD,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecDouble::new(
+                    "adaptive-factor",
+                    "Adaptive factor",
+                    "Minimum ratio of the score of a cut to the mean score of the recent frames (0 = disabled)",
+                    0.0,
+                    f64::MAX,
+                    DEFAULT_ADAPTIVE_FACTOR,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt::new(
+                    "window",
+                    "Window",
+                    "Number of recent frames the adaptive threshold is computed from",
+                    1,
+                    u32::MAX,
+                    DEFAULT_WINDOW,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt::new(
+                    "min-distance",
+                    "Minimum distance",
+                    "Minimum number of frames between two cuts",
+                    0,
+                    u32::MAX,
+                    DEFAULT_MIN_DISTANCE,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecBoolean::new(
+                    "force-key-unit",
+                    "Force key unit",
+                    "Send a force-key-unit event downstream at every cut",
+                    DEFAULT_FORCE_KEY_UNIT,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(&self, obj: &Self::Type, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        match pspec.name() {
+            "metric" => {
+                let mut settings = self.settings.lock();
+                let metric = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing metric from {:?} to {:?}",
+                    settings.metric, metric
+                );
+                settings.metric = metric;
+            }
+            "threshold" => {
+                let mut settings = self.settings.lock();
+                let threshold = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing threshold from {} to {}",
+                    settings.threshold, threshold
+                );
+                settings.threshold = threshold;
+            }
+            "adaptive-factor" => {
+                let mut settings = self.settings.lock();
+                let adaptive_factor = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing adaptive-factor from {} to {}",
+                    settings.adaptive_factor, adaptive_factor
+                );
+                settings.adaptive_factor = adaptive_factor;
+            }
+            "window" => {
+                let mut settings = self.settings.lock();
+                let window = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing window from {} to {}",
+                    settings.window, window
+                );
+                settings.window = window;
+            }
+            "min-distance" => {
+                let mut settings = self.settings.lock();
+                let min_distance = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing min-distance from {} to {}",
+                    settings.min_distance, min_distance
+                );
+                settings.min_distance = min_distance;
+            }
+            "force-key-unit" => {
+                let mut settings = self.settings.lock();
+                let force_key_unit = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing force-key-unit from {} to {}",
+                    settings.force_key_unit, force_key_unit
+                );
+                settings.force_key_unit = force_key_unit;
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn property(&self, _obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        match pspec.name() {
+            "metric" => {
+                let settings = self.settings.lock();
+                settings.metric.to_value()
+            }
+            "threshold" => {
+                let settings = self.settings.lock();
+                settings.threshold.to_value()
+            }
+            "adaptive-factor" => {
+                let settings = self.settings.lock();
+                settings.adaptive_factor.to_value()
+            }
+            "window" => {
+                let settings = self.settings.lock();
+                settings.window.to_value()
+            }
+            "min-distance" => {
+                let settings = self.settings.lock();
+                settings.min_distance.to_value()
+            }
+            "force-key-unit" => {
+                let settings = self.settings.lock();
+                settings.force_key_unit.to_value()
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
@@ -0,0 +1,20 @@
+use gst::glib::{self, StaticType};
+
+mod imp;
+mod render;
+
+glib::wrapper! {
+    pub struct Scopes(ObjectSubclass<imp::Scopes>) @extends gst_base::BaseTransform, gst::Element, gst::Object;
+}
+
+unsafe impl Send for Scopes {}
+unsafe impl Sync for Scopes {}
+
+pub(crate) fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "rsscopes",
+        gst::Rank::None,
+        Scopes::static_type(),
+    )
+}
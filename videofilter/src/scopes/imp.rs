@@ -0,0 +1,401 @@
+use gst::{glib, gst_debug, gst_info, gst_log, Buffer, BufferRef, Caps, ErrorMessage, FlowError, FlowSuccess, LoggableError, PadDirection, PadTemplate};
+use gst::subclass::ElementMetadata;
+use gst::subclass::prelude::*;
+use gst::prelude::*;
+use gst_base::prelude::*;
+use gst_base::subclass::{*, prelude::*};
+use parking_lot::Mutex;
+use crate::glib::once_cell::sync::Lazy;
+
+use super::render::{self, Scope};
+use crate::videofilter::get_all_video_formats;
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rsscopes",
+        gst::DebugColorFlags::empty(),
+        Some("Video scopes"),
+    )
+});
+
+const DEFAULT_SCOPE: Scope = Scope::Waveform;
+const DEFAULT_WIDTH: u32 = 512;
+const DEFAULT_HEIGHT: u32 = 256;
+const DEFAULT_INTERVAL: u32 = 1;
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    scope: Scope,
+    width: u32,
+    height: u32,
+    interval: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            scope: DEFAULT_SCOPE,
+            width: DEFAULT_WIDTH,
+            height: DEFAULT_HEIGHT,
+            interval: DEFAULT_INTERVAL,
+        }
+    }
+}
+
+struct State {
+    in_info: gst_video::VideoInfo,
+    out_info: gst_video::VideoInfo,
+    /// Input frames since the caps were set.
+    frames: u64,
+}
+
+#[derive(Default)]
+pub struct Scopes {
+    settings: Mutex<Settings>,
+    state: Mutex<Option<State>>,
+}
+
+/// `framerate` of the other side, the output having one frame per
+/// `interval` input frames. Anything but a fixed framerate, or one that
+/// does not fit a fraction once multiplied, becomes any.
+fn transform_framerate(framerate: Option<gst::Fraction>, direction: PadDirection, interval: u32) -> glib::SendValue {
+    let interval = interval as i32;
+    let framerate = framerate.and_then(|framerate| match direction {
+        PadDirection::Sink => framerate.denom().checked_mul(interval).map(|denom| gst::Fraction::new(framerate.numer(), denom)),
+        _ => framerate.numer().checked_mul(interval).map(|numer| gst::Fraction::new(numer, framerate.denom())),
+    });
+    match framerate {
+        Some(framerate) => framerate.to_send_value(),
+        None => gst::FractionRange::new(gst::Fraction::new(0, 1), gst::Fraction::new(i32::MAX, 1)).to_send_value(),
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for Scopes {
+    const NAME: &'static str = "RsScopes";
+    type Type = super::Scopes;
+    type ParentType = gst_base::BaseTransform;
+}
+
+impl ElementImpl for Scopes {
+    fn metadata() -> Option<&'static ElementMetadata> {
+        static METADATA: Lazy<ElementMetadata> = Lazy::new(|| {
+            ElementMetadata::new(
+                "Video scopes",
+                "Filter/Analyzer/Video",
+                "Render a waveform, parade, vectorscope or histogram of the frames",
+                "Seiichi Uchida <topecongiro@fastmail.com>",
+            )
+        });
+
+        Some(&*METADATA)
+    }
+
+    fn pad_templates() -> &'static [PadTemplate] {
+        static PAD_TEMPLATE: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let sink_caps = gst::Caps::builder("video/x-raw")
+                .field("format", gst::List::from(get_all_video_formats()))
+                .field("width", gst::IntRange::new(1, i32::MAX))
+                .field("height", gst::IntRange::new(1, i32::MAX))
+                .field("framerate", gst::FractionRange::new(
+                    gst::Fraction::new(0, 1),
+                    gst::Fraction::new(i32::MAX, 1),
+                ))
+                .build();
+            let src_caps = gst::Caps::builder("video/x-raw")
+                .field("format", gst::List::new([
+                    gst_video::VideoFormat::Gray8.to_str(),
+                    gst_video::VideoFormat::Bgrx.to_str(),
+                ]))
+                .field("width", gst::IntRange::new(16, i32::MAX))
+                .field("height", gst::IntRange::new(16, i32::MAX))
+                .field("framerate", gst::FractionRange::new(
+                    gst::Fraction::new(0, 1),
+                    gst::Fraction::new(i32::MAX, 1),
+                ))
+                .build();
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &sink_caps,
+            ).unwrap();
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &src_caps,
+            ).unwrap();
+
+            vec![sink_pad_template, src_pad_template]
+        });
+
+        PAD_TEMPLATE.as_ref()
+    }
+}
+
+impl BaseTransformImpl for Scopes {
+    const MODE: BaseTransformMode = BaseTransformMode::NeverInPlace;
+    const PASSTHROUGH_ON_SAME_CAPS: bool = false;
+    const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+
+    fn transform_caps(&self, element: &Self::Type, direction: PadDirection, caps: &Caps, filter: Option<&Caps>) -> Option<Caps> {
+        let settings = *self.settings.lock();
+
+        // The scope has the size of the properties whatever the input size.
+        let mut other_caps = Caps::new_empty();
+        {
+            let other_caps = other_caps.get_mut()?;
+            for s in caps.iter() {
+                let mut s = s.to_owned();
+                let framerate = transform_framerate(s.get::<gst::Fraction>("framerate").ok(), direction, settings.interval);
+                s.remove_fields(&["format", "width", "height", "pixel-aspect-ratio", "colorimetry", "chroma-site"]);
+                s.set_value("framerate", framerate);
+                if direction == PadDirection::Sink {
+                    s.set("width", settings.width as i32);
+                    s.set("height", settings.height as i32);
+                    s.set("pixel-aspect-ratio", gst::Fraction::new(1, 1));
+                }
+                other_caps.append_structure(s);
+            }
+        }
+        let pad = if direction == PadDirection::Sink { "src" } else { "sink" };
+        let other_caps = other_caps.intersect_with_mode(
+            &element.static_pad(pad)?.pad_template_caps(),
+            gst::CapsIntersectMode::First,
+        );
+
+        gst_debug!(
+            CAT,
+            obj: element,
+            "Transformed caps from {} to {} in direction {:?}",
+            caps,
+            other_caps,
+            direction
+        );
+
+        if let Some(filter) = filter {
+            Some(filter.intersect_with_mode(&other_caps, gst::CapsIntersectMode::First))
+        } else {
+            Some(other_caps)
+        }
+    }
+
+    fn set_caps(&self, element: &Self::Type, incaps: &Caps, outcaps: &Caps) -> Result<(), LoggableError> {
+        let in_info = gst_video::VideoInfo::from_caps(incaps)
+            .map_err(|_| gst::loggable_error!(CAT, "Failed to parse input caps"))?;
+        let out_info = gst_video::VideoInfo::from_caps(outcaps)
+            .map_err(|_| gst::loggable_error!(CAT, "Failed to parse output caps"))?;
+
+        gst_debug!(CAT, obj: element, "Configured for caps {} to {}", incaps, outcaps);
+
+        *self.state.lock() = Some(State {
+            in_info,
+            out_info,
+            frames: 0,
+        });
+
+        Ok(())
+    }
+
+    fn unit_size(&self, _element: &Self::Type, caps: &Caps) -> Option<usize> {
+        gst_video::VideoInfo::from_caps(caps)
+            .as_ref()
+            .map(gst_video::VideoInfo::size)
+            .ok()
+    }
+
+    fn stop(&self, element: &Self::Type) -> Result<(), ErrorMessage> {
+        let _ = self.state.lock().take();
+
+        gst_info!(CAT, obj: element, "Stopped");
+
+        Ok(())
+    }
+
+    fn transform(&self, element: &Self::Type, inbuf: &Buffer, outbuf: &mut BufferRef) -> Result<FlowSuccess, FlowError> {
+        gst_log!(CAT, obj: element, "transform: {:?}", inbuf);
+
+        let settings = *self.settings.lock();
+        let mut state_guard = self.state.lock();
+        let state = state_guard.as_mut().ok_or_else(|| {
+            gst::element_error!(element, gst::CoreError::Negotiation, ["Have no state yet"]);
+            FlowError::NotNegotiated
+        })?;
+
+        // Only every interval-th frame makes a scope.
+        let frame = state.frames;
+        state.frames += 1;
+        if frame % u64::from(settings.interval) != 0 {
+            return Ok(gst_base::BASE_TRANSFORM_FLOW_DROPPED);
+        }
+
+        let pixels = {
+            let in_frame = gst_video::VideoFrameRef::from_buffer_ref_readable(inbuf, &state.in_info)
+                .map_err(|err| {
+                    gst::element_error!(
+                        element,
+                        gst::CoreError::Failed,
+                        [&format!("Failed to map input buffer readable: {}", err)]
+                    );
+                    FlowError::Error
+                })?;
+            render::render(
+                settings.scope,
+                &in_frame,
+                state.out_info.width() as usize,
+                state.out_info.height() as usize,
+            )
+            .map_err(|err| {
+                gst::element_error!(element, gst::CoreError::Failed, [&err]);
+                FlowError::Error
+            })?
+        };
+
+        if let Some(duration) = inbuf.duration() {
+            outbuf.set_duration(gst::ClockTime::from_nseconds(duration.nseconds().saturating_mul(u64::from(settings.interval))));
+        }
+
+        let mut out_frame = gst_video::VideoFrameRef::from_buffer_ref_writable(outbuf, &state.out_info)
+            .map_err(|err| {
+                gst::element_error!(
+                    element,
+                    gst::CoreError::Failed,
+                    [&format!("Failed to map output buffer writable: {}", err)]
+                );
+                FlowError::Error
+            })?;
+        render::write(&pixels, &mut out_frame);
+
+        Ok(FlowSuccess::Ok)
+    }
+}
+
+impl GstObjectImpl for Scopes {}
+
+impl ObjectImpl for Scopes {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecEnum::new(
+                    "scope",
+                    "Scope",
+                    "Scope to render",
+                    Scope::static_type(),
+                    DEFAULT_SCOPE as i32,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt::new(
+                    "width",
+                    "Width",
+                    "Width of the scope image",
+                    16,
+                    i32::MAX as u32,
+                    DEFAULT_WIDTH,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt::new(
+                    "height",
+                    "Height",
+                    "Height of the scope image",
+                    16,
+                    i32::MAX as u32,
+                    DEFAULT_HEIGHT,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt::new(
+                    "interval",
+                    "Interval",
+                    "Number of input frames per scope image, dividing the framerate",
+                    1,
+                    i32::MAX as u32,
+                    DEFAULT_INTERVAL,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(&self, obj: &Self::Type, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        match pspec.name() {
+            "scope" => {
+                let mut settings = self.settings.lock();
+                let scope = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing scope from {:?} to {:?}",
+                    settings.scope, scope
+                );
+                settings.scope = scope;
+            }
+            "width" => {
+                let mut settings = self.settings.lock();
+                let width = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing width from {} to {}",
+                    settings.width, width
+                );
+                settings.width = width;
+                drop(settings);
+
+                obj.reconfigure_src();
+            }
+            "height" => {
+                let mut settings = self.settings.lock();
+                let height = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing height from {} to {}",
+                    settings.height, height
+                );
+                settings.height = height;
+                drop(settings);
+
+                obj.reconfigure_src();
+            }
+            "interval" => {
+                let mut settings = self.settings.lock();
+                let interval = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing interval from {} to {}",
+                    settings.interval, interval
+                );
+                settings.interval = interval;
+                drop(settings);
+
+                obj.reconfigure_src();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn property(&self, _obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        match pspec.name() {
+            "scope" => {
+                let settings = self.settings.lock();
+                settings.scope.to_value()
+            }
+            "width" => {
+                let settings = self.settings.lock();
+                settings.width.to_value()
+            }
+            "height" => {
+                let settings = self.settings.lock();
+                settings.height.to_value()
+            }
+            "interval" => {
+                let settings = self.settings.lock();
+                settings.interval.to_value()
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
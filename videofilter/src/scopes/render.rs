@@ -0,0 +1,344 @@
+//! Rendering of the scopes into R'G'B' pixels in 0..1.
+//!
+//! The waveform, parade and vectorscope count how many input samples land
+//! on every output pixel. The counts are shown relative to what a column
+//! of flat colour would collect, with a square root so that sparse traces
+//! stay visible.
+
+use gst::glib;
+use gst::BufferRef;
+use gst_video::{VideoFormat, VideoFrameRef};
+
+use crate::videofilter::{luma, rgb_planes, ycbcr_planes};
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstScopesScope")]
+pub enum Scope {
+    #[enum_value(name = "Luma waveform", nick = "waveform")]
+    Waveform = 0,
+    #[enum_value(name = "R'G'B' parade", nick = "parade")]
+    Parade = 1,
+    #[enum_value(name = "CbCr vectorscope", nick = "vectorscope")]
+    Vectorscope = 2,
+    #[enum_value(name = "R'G'B' histogram", nick = "histogram")]
+    Histogram = 3,
+}
+
+const TRACE_GAIN: f32 = 4.0;
+const GRATICULE: f32 = 0.25;
+const WHITE: [f32; 3] = [1.0, 1.0, 1.0];
+const CHANNELS: [[f32; 3]; 3] = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+/// Output pixels with the sample counts of the traces.
+struct Canvas {
+    width: usize,
+    height: usize,
+    counts: Vec<[f32; 3]>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            counts: vec![[0.0; 3]; width * height],
+        }
+    }
+
+    /// Row of `level`, 0..1 from the bottom to the top.
+    fn row(&self, level: f32) -> usize {
+        ((1.0 - level.clamp(0.0, 1.0)) * (self.height - 1) as f32).round() as usize
+    }
+
+    fn add(&mut self, x: usize, y: usize, color: [f32; 3]) {
+        let count = &mut self.counts[y * self.width + x];
+        for (count, c) in count.iter_mut().zip(color) {
+            *count += c;
+        }
+    }
+
+    /// Turns the counts into intensities, `expected` being the count of a
+    /// fully lit pixel.
+    fn finish(self, expected: f32) -> Vec<[f32; 3]> {
+        self.counts
+            .into_iter()
+            .map(|count| count.map(|c| ((c / expected).sqrt() * TRACE_GAIN).min(1.0)))
+            .collect()
+    }
+}
+
+/// Draws `color` at `(x, y)` where it is brighter than the pixel.
+fn lighten(pixels: &mut [[f32; 3]], width: usize, x: usize, y: usize, color: [f32; 3]) {
+    let pixel = &mut pixels[y * width + x];
+    for (p, c) in pixel.iter_mut().zip(color) {
+        *p = p.max(c);
+    }
+}
+
+/// Lines at 0, 25, 50, 75 and 100% of the levels of a waveform.
+fn level_lines(pixels: &mut [[f32; 3]], width: usize, height: usize) {
+    for level in [0.0, 0.25, 0.5, 0.75, 1.0] {
+        let y = ((1.0 - level) * (height - 1) as f32).round() as usize;
+        for x in 0..width {
+            lighten(pixels, width, x, y, [GRATICULE; 3]);
+        }
+    }
+}
+
+/// Luma of every column, Y' going up.
+fn waveform(frame: &VideoFrameRef<&BufferRef>, width: usize, height: usize) -> Result<Vec<[f32; 3]>, String> {
+    let in_width = frame.width() as usize;
+    let luma = luma(frame)?;
+
+    let mut canvas = Canvas::new(width, height);
+    for (i, y) in luma.iter().enumerate() {
+        let x = (i % in_width) * width / in_width;
+        let row = canvas.row(y / 255.0);
+        canvas.add(x, row, WHITE);
+    }
+
+    let mut pixels = canvas.finish((luma.len() / width).max(1) as f32);
+    level_lines(&mut pixels, width, height);
+
+    Ok(pixels)
+}
+
+/// Waveforms of R', G' and B' side by side.
+fn parade(frame: &VideoFrameRef<&BufferRef>, width: usize, height: usize) -> Result<Vec<[f32; 3]>, String> {
+    let in_width = frame.width() as usize;
+    let planes = rgb_planes(frame)?;
+    let section = (width / 3).max(1);
+
+    let mut canvas = Canvas::new(width, height);
+    for (k, plane) in planes.iter().enumerate() {
+        let x0 = (k * section).min(width - 1);
+        for (i, v) in plane.iter().enumerate() {
+            let x = (x0 + (i % in_width) * section / in_width).min(width - 1);
+            let row = canvas.row(*v);
+            canvas.add(x, row, CHANNELS[k]);
+        }
+    }
+
+    let mut pixels = canvas.finish((planes[0].len() / section).max(1) as f32);
+    level_lines(&mut pixels, width, height);
+
+    Ok(pixels)
+}
+
+/// Cb to the right and Cr up, in the largest centred square.
+fn vectorscope(frame: &VideoFrameRef<&BufferRef>, width: usize, height: usize) -> Result<Vec<[f32; 3]>, String> {
+    let [_, cb, cr] = ycbcr_planes(frame)?;
+    let size = width.min(height);
+    let (x0, y0) = ((width - size) / 2, (height - size) / 2);
+    let to_pixel = |v: f32| ((v + 0.5).clamp(0.0, 1.0) * (size - 1) as f32).round() as usize;
+
+    let mut canvas = Canvas::new(width, height);
+    for (cb, cr) in cb.iter().zip(cr.iter()) {
+        canvas.add(x0 + to_pixel(*cb), y0 + size - 1 - to_pixel(*cr), WHITE);
+    }
+
+    let mut pixels = canvas.finish((cb.len() / size).max(1) as f32);
+
+    // Axes and the circle of the largest chroma.
+    let center = (size - 1) as f32 / 2.0;
+    for i in 0..size {
+        lighten(&mut pixels, width, x0 + i, y0 + size / 2, [GRATICULE; 3]);
+        lighten(&mut pixels, width, x0 + size / 2, y0 + i, [GRATICULE; 3]);
+    }
+    let steps = (size * 4).max(16);
+    for i in 0..steps {
+        let angle = i as f32 * std::f32::consts::TAU / steps as f32;
+        let x = (center + center * angle.cos()).round() as usize;
+        let y = (center + center * angle.sin()).round() as usize;
+        lighten(&mut pixels, width, x0 + x, y0 + y, [GRATICULE; 3]);
+    }
+
+    Ok(pixels)
+}
+
+/// Histograms of R', G' and B' on top of each other, scaled to the
+/// highest column.
+fn histogram(frame: &VideoFrameRef<&BufferRef>, width: usize, height: usize) -> Result<Vec<[f32; 3]>, String> {
+    let planes = rgb_planes(frame)?;
+
+    let mut columns = vec![[0u32; 3]; width];
+    for (k, plane) in planes.iter().enumerate() {
+        for v in plane {
+            let x = (v.clamp(0.0, 1.0) * (width - 1) as f32).round() as usize;
+            columns[x][k] += 1;
+        }
+    }
+    let max = columns.iter().flatten().copied().max().unwrap_or(0).max(1) as f32;
+
+    let mut pixels = vec![[0.0; 3]; width * height];
+    for (x, column) in columns.iter().enumerate() {
+        for (k, &count) in column.iter().enumerate() {
+            let bar = (count as f32 / max * height as f32).round() as usize;
+            for y in height - bar..height {
+                pixels[y * width + x][k] = 1.0;
+            }
+        }
+    }
+
+    Ok(pixels)
+}
+
+/// Renders `scope` of `frame` as `width` x `height` pixels.
+pub fn render(scope: Scope, frame: &VideoFrameRef<&BufferRef>, width: usize, height: usize) -> Result<Vec<[f32; 3]>, String> {
+    match scope {
+        Scope::Waveform => waveform(frame, width, height),
+        Scope::Parade => parade(frame, width, height),
+        Scope::Vectorscope => vectorscope(frame, width, height),
+        Scope::Histogram => histogram(frame, width, height),
+    }
+}
+
+/// Writes `pixels` to `frame`, GRAY8 taking the brightest channel.
+pub fn write(pixels: &[[f32; 3]], frame: &mut VideoFrameRef<&mut BufferRef>) {
+    let width = frame.width() as usize;
+    let format = frame.format();
+    let stride = frame.plane_stride()[0] as usize;
+    let data = frame.plane_data_mut(0).unwrap();
+    let to_code = |v: f32| (v * 255.0).round().clamp(0.0, 255.0) as u8;
+
+    for (line, row) in data.chunks_mut(stride).zip(pixels.chunks_exact(width)) {
+        for (x, &[r, g, b]) in row.iter().enumerate() {
+            if format == VideoFormat::Gray8 {
+                line[x] = to_code(r.max(g).max(b));
+            } else {
+                line[x * 4..x * 4 + 4].copy_from_slice(&[to_code(b), to_code(g), to_code(r), 255]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: [f32; 3] = [0.0; 3];
+    const LINE: [f32; 3] = [GRATICULE; 3];
+
+    /// Renders `scope` of an 8x4 GBR frame of `color(x)` for every pixel
+    /// of column `x`.
+    fn render_frame(scope: Scope, color: impl Fn(usize) -> [f32; 3], width: usize, height: usize) -> Vec<[f32; 3]> {
+        gst::init().unwrap();
+
+        let info = gst_video::VideoInfo::builder(VideoFormat::Gbr, 8, 4).build().unwrap();
+        let mut buffer = gst::Buffer::with_size(info.size()).unwrap();
+        {
+            let buffer = buffer.get_mut().unwrap();
+            let mut frame = VideoFrameRef::from_buffer_ref_writable(buffer, &info).unwrap();
+            // GBR planes hold G', B' and R'.
+            for (plane, k) in [1, 2, 0].into_iter().enumerate() {
+                let stride = frame.plane_stride()[plane] as usize;
+                let data = frame.plane_data_mut(plane as u32).unwrap();
+                for line in data.chunks_mut(stride).take(4) {
+                    for (x, code) in line[..8].iter_mut().enumerate() {
+                        *code = (color(x)[k] * 255.0).round() as u8;
+                    }
+                }
+            }
+        }
+
+        let frame = VideoFrameRef::from_buffer_ref_readable(buffer.as_ref(), &info).unwrap();
+        render(scope, &frame, width, height).unwrap()
+    }
+
+    fn black_and_white(x: usize) -> [f32; 3] {
+        if x < 4 {
+            BLACK
+        } else {
+            WHITE
+        }
+    }
+
+    #[test]
+    fn test_waveform() {
+        let pixels = render_frame(Scope::Waveform, black_and_white, 8, 11);
+
+        // Level lines at 0, 25, 50, 75 and 100%.
+        for x in 0..8 {
+            let (bottom, top) = if x < 4 { (WHITE, LINE) } else { (LINE, WHITE) };
+            assert_eq!(pixels[10 * 8 + x], bottom);
+            assert_eq!(pixels[x], top);
+            for y in [3, 5, 8] {
+                assert_eq!(pixels[y * 8 + x], LINE);
+            }
+            for y in [1, 2, 4, 6, 7, 9] {
+                assert_eq!(pixels[y * 8 + x], BLACK);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parade() {
+        let pixels = render_frame(Scope::Parade, |_| [1.0, 0.0, 0.0], 9, 11);
+
+        // R' at the top of the first third, G' and B' at the bottom of the
+        // others.
+        for x in 0..9 {
+            let (top, bottom) = match x / 3 {
+                0 => ([1.0, GRATICULE, GRATICULE], LINE),
+                1 => (LINE, [GRATICULE, 1.0, GRATICULE]),
+                _ => (LINE, [GRATICULE, GRATICULE, 1.0]),
+            };
+            assert_eq!(pixels[x], top, "{}", x);
+            assert_eq!(pixels[10 * 9 + x], bottom, "{}", x);
+            assert_eq!(pixels[9 + x], BLACK, "{}", x);
+        }
+    }
+
+    #[test]
+    fn test_vectorscope() {
+        // Gray is in the centre, on the axes.
+        let pixels = render_frame(Scope::Vectorscope, |_| [0.5; 3], 9, 9);
+        assert_eq!(pixels[4 * 9 + 4], WHITE);
+        assert_eq!(pixels[4 * 9 + 1], LINE);
+        assert_eq!(pixels[9 + 4], LINE);
+        assert_eq!(pixels[2 * 9 + 2], BLACK);
+
+        // Red has the largest Cr and a negative Cb.
+        let pixels = render_frame(Scope::Vectorscope, |_| [1.0, 0.0, 0.0], 9, 9);
+        assert_eq!(pixels[3], WHITE);
+        assert_eq!(pixels[4 * 9 + 4], LINE);
+    }
+
+    #[test]
+    fn test_histogram() {
+        let pixels = render_frame(Scope::Histogram, black_and_white, 8, 4);
+
+        for (i, pixel) in pixels.iter().enumerate() {
+            let x = i % 8;
+            assert_eq!(*pixel, if x == 0 || x == 7 { WHITE } else { BLACK }, "{}", i);
+        }
+
+        // Bars are scaled to the highest one.
+        let pixels = render_frame(Scope::Histogram, |x| if x < 6 { BLACK } else { [1.0, 1.0, 0.0] }, 8, 4);
+        assert_eq!(pixels[0], [0.0, 0.0, 1.0]);
+        assert_eq!(pixels[8], WHITE);
+        assert_eq!(pixels[2 * 8 + 7], BLACK);
+        assert_eq!(pixels[3 * 8 + 7], [1.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn test_write() {
+        gst::init().unwrap();
+
+        let pixels = [[1.0, 0.0, 0.0], [0.0, 0.5, 0.25]];
+        for (format, expected) in [
+            (VideoFormat::Gray8, vec![255, 128]),
+            (VideoFormat::Bgrx, vec![0, 0, 255, 255, 64, 128, 0, 255]),
+        ] {
+            let info = gst_video::VideoInfo::builder(format, 2, 1).build().unwrap();
+            let mut buffer = gst::Buffer::with_size(info.size()).unwrap();
+            {
+                let buffer = buffer.get_mut().unwrap();
+                let mut frame = VideoFrameRef::from_buffer_ref_writable(buffer, &info).unwrap();
+                write(&pixels, &mut frame);
+            }
+            assert_eq!(buffer.map_readable().unwrap()[..expected.len()], expected[..], "{:?}", format);
+        }
+    }
+}
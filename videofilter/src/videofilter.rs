@@ -1,6 +1,22 @@
 use gst::glib::{self, StaticType};
+use gst::prelude::*;
 
+mod burnin;
+mod checksum;
+mod convert;
+mod denoise;
+mod dump;
+mod geometry;
 mod imp;
+mod scale;
+mod stats;
+
+pub(crate) use self::burnin::{draw_outline, draw_text_at, text_size};
+pub(crate) use self::convert::{
+    luma, pack_rgb_planes, read_sample, rgb_planes, rgb_to_codes, write_sample, ycbcr_planes,
+};
+pub(crate) use self::geometry::Geometry;
+pub(crate) use self::imp::get_all_video_formats;
 
 glib::wrapper! {
     pub struct VideoFilter(ObjectSubclass<imp::VideoFilter>) @extends gst_base::BaseTransform, gst::Element, gst::Object;
@@ -9,6 +25,47 @@ glib::wrapper! {
 unsafe impl Send for VideoFilter {}
 unsafe impl Sync for VideoFilter {}
 
+/// Timestamps of a frame passed to `VideoFilter::connect_frame` callbacks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameTimestamps {
+    pub pts: Option<gst::ClockTime>,
+    pub dts: Option<gst::ClockTime>,
+    pub duration: Option<gst::ClockTime>,
+}
+
+impl FrameTimestamps {
+    fn from_buffer(buffer: &gst::BufferRef) -> Self {
+        Self {
+            pts: buffer.pts(),
+            dts: buffer.dts(),
+            duration: buffer.duration(),
+        }
+    }
+}
+
+impl VideoFilter {
+    /// Connects to the `handoff` signal with typed, read-only access to the
+    /// pixels of every frame, its video info being `frame.info()`.
+    ///
+    /// The callback is only invoked while `signal-handoffs` is enabled.
+    pub fn connect_frame<F>(&self, func: F) -> glib::SignalHandlerId
+    where
+        F: Fn(&VideoFilter, &gst_video::VideoFrameRef<&gst::BufferRef>, &FrameTimestamps) + Send + Sync + 'static,
+    {
+        self.connect("handoff", false, move |args| {
+            let element = args[0].get::<VideoFilter>().unwrap();
+            let buffer = args[1].get::<gst::Buffer>().unwrap();
+            let info = args[2].get::<gst_video::VideoInfo>().unwrap();
+
+            if let Ok(frame) = gst_video::VideoFrameRef::from_buffer_ref_readable(buffer.as_ref(), &info) {
+                func(&element, &frame, &FrameTimestamps::from_buffer(buffer.as_ref()));
+            }
+
+            None
+        })
+    }
+}
+
 pub(crate) fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
     gst::Element::register(
         Some(plugin),
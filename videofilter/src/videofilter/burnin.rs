@@ -0,0 +1,515 @@
+//! Burn-in of timestamps, frame number and caps into the frame, using a
+//! built-in 5x7 bitmap font.
+
+use gst::glib;
+use gst::prelude::*;
+use gst::BufferRef;
+use gst_video::{VideoFormatFlags, VideoFrameRef, VideoInfo};
+
+use super::convert;
+
+#[glib::flags(name = "GstVideoFilterBurnIn")]
+pub enum BurnIn {
+    #[flags_value(name = "Presentation timestamp", nick = "pts")]
+    PTS = 0b0001,
+    #[flags_value(name = "Running time", nick = "running-time")]
+    RUNNING_TIME = 0b0010,
+    #[flags_value(name = "Frame number", nick = "frame")]
+    FRAME = 0b0100,
+    #[flags_value(name = "Resolution and format", nick = "caps")]
+    CAPS = 0b1000,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstVideoFilterBurnInPosition")]
+pub enum Position {
+    #[enum_value(name = "Top left", nick = "top-left")]
+    TopLeft = 0,
+    #[enum_value(name = "Top right", nick = "top-right")]
+    TopRight = 1,
+    #[enum_value(name = "Bottom left", nick = "bottom-left")]
+    BottomLeft = 2,
+    #[enum_value(name = "Bottom right", nick = "bottom-right")]
+    BottomRight = 3,
+}
+
+/// How the text is laid out and coloured. Colours are ARGB.
+#[derive(Debug, Clone, Copy)]
+pub struct Style {
+    pub position: Position,
+    pub scale: u32,
+    pub color: u32,
+    pub background: u32,
+}
+
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+/// Glyph plus one column of spacing.
+const CELL_WIDTH: usize = GLYPH_WIDTH + 1;
+/// Glyph plus one row of spacing above and below.
+const CELL_HEIGHT: usize = GLYPH_HEIGHT + 2;
+
+/// Printable ASCII from 0x20, one byte per column with the top row in the
+/// least significant bit.
+#[rustfmt::skip]
+static FONT: [[u8; GLYPH_WIDTH]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x00, 0x00, 0x5f, 0x00, 0x00], // '!'
+    [0x00, 0x07, 0x00, 0x07, 0x00], // '"'
+    [0x14, 0x7f, 0x14, 0x7f, 0x14], // '#'
+    [0x24, 0x2a, 0x7f, 0x2a, 0x12], // '$'
+    [0x23, 0x13, 0x08, 0x64, 0x62], // '%'
+    [0x36, 0x49, 0x55, 0x22, 0x50], // '&'
+    [0x00, 0x05, 0x03, 0x00, 0x00], // '''
+    [0x00, 0x1c, 0x22, 0x41, 0x00], // '('
+    [0x00, 0x41, 0x22, 0x1c, 0x00], // ')'
+    [0x14, 0x08, 0x3e, 0x08, 0x14], // '*'
+    [0x08, 0x08, 0x3e, 0x08, 0x08], // '+'
+    [0x00, 0x50, 0x30, 0x00, 0x00], // ','
+    [0x08, 0x08, 0x08, 0x08, 0x08], // '-'
+    [0x00, 0x60, 0x60, 0x00, 0x00], // '.'
+    [0x20, 0x10, 0x08, 0x04, 0x02], // '/'
+    [0x3e, 0x51, 0x49, 0x45, 0x3e], // '0'
+    [0x00, 0x42, 0x7f, 0x40, 0x00], // '1'
+    [0x42, 0x61, 0x51, 0x49, 0x46], // '2'
+    [0x21, 0x41, 0x45, 0x4b, 0x31], // '3'
+    [0x18, 0x14, 0x12, 0x7f, 0x10], // '4'
+    [0x27, 0x45, 0x45, 0x45, 0x39], // '5'
+    [0x3c, 0x4a, 0x49, 0x49, 0x30], // '6'
+    [0x01, 0x71, 0x09, 0x05, 0x03], // '7'
+    [0x36, 0x49, 0x49, 0x49, 0x36], // '8'
+    [0x06, 0x49, 0x49, 0x29, 0x1e], // '9'
+    [0x00, 0x36, 0x36, 0x00, 0x00], // ':'
+    [0x00, 0x56, 0x36, 0x00, 0x00], // ';'
+    [0x08, 0x14, 0x22, 0x41, 0x00], // '<'
+    [0x14, 0x14, 0x14, 0x14, 0x14], // '='
+    [0x00, 0x41, 0x22, 0x14, 0x08], // '>'
+    [0x02, 0x01, 0x51, 0x09, 0x06], // '?'
+    [0x32, 0x49, 0x79, 0x41, 0x3e], // '@'
+    [0x7e, 0x11, 0x11, 0x11, 0x7e], // 'A'
+    [0x7f, 0x49, 0x49, 0x49, 0x36], // 'B'
+    [0x3e, 0x41, 0x41, 0x41, 0x22], // 'C'
+    [0x7f, 0x41, 0x41, 0x22, 0x1c], // 'D'
+    [0x7f, 0x49, 0x49, 0x49, 0x41], // 'E'
+    [0x7f, 0x09, 0x09, 0x09, 0x01], // 'F'
+    [0x3e, 0x41, 0x49, 0x49, 0x7a], // 'G'
+    [0x7f, 0x08, 0x08, 0x08, 0x7f], // 'H'
+    [0x00, 0x41, 0x7f, 0x41, 0x00], // 'I'
+    [0x20, 0x40, 0x41, 0x3f, 0x01], // 'J'
+    [0x7f, 0x08, 0x14, 0x22, 0x41], // 'K'
+    [0x7f, 0x40, 0x40, 0x40, 0x40], // 'L'
+    [0x7f, 0x02, 0x0c, 0x02, 0x7f], // 'M'
+    [0x7f, 0x04, 0x08, 0x10, 0x7f], // 'N'
+    [0x3e, 0x41, 0x41, 0x41, 0x3e], // 'O'
+    [0x7f, 0x09, 0x09, 0x09, 0x06], // 'P'
+    [0x3e, 0x41, 0x51, 0x21, 0x5e], // 'Q'
+    [0x7f, 0x09, 0x19, 0x29, 0x46], // 'R'
+    [0x46, 0x49, 0x49, 0x49, 0x31], // 'S'
+    [0x01, 0x01, 0x7f, 0x01, 0x01], // 'T'
+    [0x3f, 0x40, 0x40, 0x40, 0x3f], // 'U'
+    [0x1f, 0x20, 0x40, 0x20, 0x1f], // 'V'
+    [0x3f, 0x40, 0x38, 0x40, 0x3f], // 'W'
+    [0x63, 0x14, 0x08, 0x14, 0x63], // 'X'
+    [0x07, 0x08, 0x70, 0x08, 0x07], // 'Y'
+    [0x61, 0x51, 0x49, 0x45, 0x43], // 'Z'
+    [0x00, 0x7f, 0x41, 0x41, 0x00], // '['
+    [0x02, 0x04, 0x08, 0x10, 0x20], // '\'
+    [0x00, 0x41, 0x41, 0x7f, 0x00], // ']'
+    [0x04, 0x02, 0x01, 0x02, 0x04], // '^'
+    [0x40, 0x40, 0x40, 0x40, 0x40], // '_'
+    [0x00, 0x01, 0x02, 0x04, 0x00], // '`'
+    [0x20, 0x54, 0x54, 0x54, 0x78], // 'a'
+    [0x7f, 0x48, 0x44, 0x44, 0x38], // 'b'
+    [0x38, 0x44, 0x44, 0x44, 0x20], // 'c'
+    [0x38, 0x44, 0x44, 0x48, 0x7f], // 'd'
+    [0x38, 0x54, 0x54, 0x54, 0x18], // 'e'
+    [0x08, 0x7e, 0x09, 0x01, 0x02], // 'f'
+    [0x0c, 0x52, 0x52, 0x52, 0x3e], // 'g'
+    [0x7f, 0x08, 0x04, 0x04, 0x78], // 'h'
+    [0x00, 0x44, 0x7d, 0x40, 0x00], // 'i'
+    [0x20, 0x40, 0x44, 0x3d, 0x00], // 'j'
+    [0x7f, 0x10, 0x28, 0x44, 0x00], // 'k'
+    [0x00, 0x41, 0x7f, 0x40, 0x00], // 'l'
+    [0x7c, 0x04, 0x18, 0x04, 0x78], // 'm'
+    [0x7c, 0x08, 0x04, 0x04, 0x78], // 'n'
+    [0x38, 0x44, 0x44, 0x44, 0x38], // 'o'
+    [0x7c, 0x14, 0x14, 0x14, 0x08], // 'p'
+    [0x08, 0x14, 0x14, 0x18, 0x7c], // 'q'
+    [0x7c, 0x08, 0x04, 0x04, 0x08], // 'r'
+    [0x48, 0x54, 0x54, 0x54, 0x20], // 's'
+    [0x04, 0x3f, 0x44, 0x40, 0x20], // 't'
+    [0x3c, 0x40, 0x40, 0x20, 0x7c], // 'u'
+    [0x1c, 0x20, 0x40, 0x20, 0x1c], // 'v'
+    [0x3c, 0x40, 0x30, 0x40, 0x3c], // 'w'
+    [0x44, 0x28, 0x10, 0x28, 0x44], // 'x'
+    [0x0c, 0x50, 0x50, 0x50, 0x3c], // 'y'
+    [0x44, 0x64, 0x54, 0x4c, 0x44], // 'z'
+    [0x00, 0x08, 0x36, 0x41, 0x00], // '{'
+    [0x00, 0x00, 0x7f, 0x00, 0x00], // '|'
+    [0x00, 0x41, 0x36, 0x08, 0x00], // '}'
+    [0x10, 0x08, 0x08, 0x10, 0x08], // '~'
+];
+
+fn glyph(c: char) -> &'static [u8; GLYPH_WIDTH] {
+    let index = match c {
+        ' '..='~' => c as usize - 0x20,
+        _ => '?' as usize - 0x20,
+    };
+
+    &FONT[index]
+}
+
+/// The lines of text selected by `flags`.
+pub fn lines(
+    flags: BurnIn,
+    pts: Option<gst::ClockTime>,
+    running_time: Option<gst::ClockTime>,
+    frame_number: u64,
+    info: &VideoInfo,
+) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    if flags.contains(BurnIn::PTS) {
+        lines.push(format!("PTS   {}", pts.display()));
+    }
+    if flags.contains(BurnIn::RUNNING_TIME) {
+        lines.push(format!("RT    {}", running_time.display()));
+    }
+    if flags.contains(BurnIn::FRAME) {
+        lines.push(format!("FRAME {}", frame_number));
+    }
+    if flags.contains(BurnIn::CAPS) {
+        lines.push(format!("{}x{} {}", info.width(), info.height(), info.format().to_str()));
+    }
+
+    lines
+}
+
+/// Width and height of the box `lines` are drawn in.
+pub fn text_size(lines: &[String], scale: u32) -> (usize, usize) {
+    let scale = scale.max(1) as usize;
+    let columns = lines.iter().map(|line| line.chars().count()).max().unwrap_or(0);
+
+    ((columns * CELL_WIDTH + 1) * scale, lines.len() * CELL_HEIGHT * scale)
+}
+
+/// Coverage of the text box at luma resolution.
+const OUTSIDE: u8 = 0;
+const BACKGROUND: u8 = 1;
+const TEXT: u8 = 2;
+
+struct Mask {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+    data: Vec<u8>,
+}
+
+impl Mask {
+    /// `lines` with their top left corner at `x`, `y`.
+    fn text(lines: &[String], scale: u32, x: usize, y: usize) -> Self {
+        let scale = scale.max(1) as usize;
+        let (width, height) = text_size(lines, scale as u32);
+
+        let mut data = vec![BACKGROUND; width * height];
+        for (row, line) in lines.iter().enumerate() {
+            for (column, c) in line.chars().enumerate() {
+                let glyph = glyph(c);
+                let left = (column * CELL_WIDTH + 1) * scale;
+                let top = (row * CELL_HEIGHT + 1) * scale;
+
+                for (gx, bits) in glyph.iter().enumerate() {
+                    for gy in (0..GLYPH_HEIGHT).filter(|gy| bits & (1 << gy) != 0) {
+                        for dy in 0..scale {
+                            let start = (top + gy * scale + dy) * width + left + gx * scale;
+                            data[start..start + scale].fill(TEXT);
+                        }
+                    }
+                }
+            }
+        }
+
+        Self {
+            x,
+            y,
+            width,
+            height,
+            data,
+        }
+    }
+
+    /// Solid `width` x `height` rectangle at `x`, `y`.
+    fn rectangle(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+            data: vec![TEXT; width * height],
+        }
+    }
+
+    /// Value at frame coordinates.
+    fn get(&self, x: usize, y: usize) -> u8 {
+        if x < self.x || y < self.y || x >= self.x + self.width || y >= self.y + self.height {
+            OUTSIDE
+        } else {
+            self.data[(y - self.y) * self.width + x - self.x]
+        }
+    }
+}
+
+fn argb_to_rgb(argb: u32) -> [f32; 3] {
+    [
+        ((argb >> 16) & 0xff) as f32 / 255.0,
+        ((argb >> 8) & 0xff) as f32 / 255.0,
+        (argb & 0xff) as f32 / 255.0,
+    ]
+}
+
+fn argb_alpha(argb: u32) -> f32 {
+    (argb >> 24) as f32 / 255.0
+}
+
+/// Draws `lines` into `frame`, blending the background box and then the text
+/// over it.
+pub fn draw(frame: &mut VideoFrameRef<&mut BufferRef>, lines: &[String], style: &Style) -> Result<(), String> {
+    if lines.is_empty() {
+        return Ok(());
+    }
+
+    let frame_width = frame.width() as usize;
+    let frame_height = frame.height() as usize;
+    let (width, height) = text_size(lines, style.scale);
+    let margin = 2 * style.scale.max(1) as usize;
+
+    let x = match style.position {
+        Position::TopLeft | Position::BottomLeft => margin,
+        Position::TopRight | Position::BottomRight => frame_width.saturating_sub(width + margin),
+    };
+    let y = match style.position {
+        Position::TopLeft | Position::TopRight => margin,
+        Position::BottomLeft | Position::BottomRight => frame_height.saturating_sub(height + margin),
+    };
+
+    blend(frame, &Mask::text(lines, style.scale, x, y), style.color, style.background)
+}
+
+/// Draws `lines` into `frame` with their top left corner at `x`, `y`. Colours
+/// are ARGB.
+pub fn draw_text_at(
+    frame: &mut VideoFrameRef<&mut BufferRef>,
+    lines: &[String],
+    x: usize,
+    y: usize,
+    scale: u32,
+    color: u32,
+    background: u32,
+) -> Result<(), String> {
+    if lines.is_empty() {
+        return Ok(());
+    }
+
+    blend(frame, &Mask::text(lines, scale, x, y), color, background)
+}
+
+/// Draws the outline of `rect`, `line_width` samples wide and inside of it,
+/// into `frame`. The colour is ARGB.
+pub fn draw_outline(
+    frame: &mut VideoFrameRef<&mut BufferRef>,
+    rect: (u32, u32, u32, u32),
+    line_width: u32,
+    color: u32,
+) -> Result<(), String> {
+    let (x, y, width, height) = rect;
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+
+    let (x, y, width, height) = (x as usize, y as usize, width as usize, height as usize);
+    let line_width = (line_width.max(1) as usize).min(width).min(height);
+    let inner_height = height.saturating_sub(2 * line_width);
+
+    // The top and bottom sides, then the left and right ones between them.
+    let sides = [
+        Mask::rectangle(x, y, width, line_width),
+        Mask::rectangle(x, y + height - line_width, width, line_width),
+        Mask::rectangle(x, y + line_width, line_width, inner_height),
+        Mask::rectangle(x + width - line_width, y + line_width, line_width, inner_height),
+    ];
+    for side in sides.iter() {
+        blend(frame, side, color, 0)?;
+    }
+
+    Ok(())
+}
+
+/// Blends the background and then the text colour of `mask` into `frame`.
+/// Subsampled components are blended with the coverage of the luma samples
+/// they span.
+fn blend(frame: &mut VideoFrameRef<&mut BufferRef>, mask: &Mask, color: u32, background: u32) -> Result<(), String> {
+    let info = frame.info().clone();
+    let finfo = info.format_info();
+    let width = info.width() as usize;
+    let height = info.height() as usize;
+    let little_endian = finfo.flags().contains(VideoFormatFlags::LE);
+
+    let text_codes = convert::rgb_to_codes(&info, argb_to_rgb(color))?;
+    let background_codes = convert::rgb_to_codes(&info, argb_to_rgb(background))?;
+    let text_alpha = argb_alpha(color);
+    let background_alpha = argb_alpha(background);
+
+    for c in 0..finfo.n_components() as usize {
+        let w_sub = finfo.w_sub()[c] as usize;
+        let h_sub = finfo.h_sub()[c] as usize;
+        let plane = finfo.plane()[c] as usize;
+        let stride = frame.plane_stride()[plane] as usize;
+        let poffset = finfo.poffset()[c] as usize;
+        let pstride = finfo.pixel_stride()[c] as usize;
+        let shift = finfo.shift()[c];
+        let max = ((1u32 << finfo.depth()[c]) - 1) as f32;
+        let comp_width = finfo.scale_width(c as u8, width as u32) as usize;
+        let comp_height = finfo.scale_height(c as u8, height as u32) as usize;
+
+        let x_range = (mask.x >> w_sub)..((mask.x + mask.width + (1 << w_sub) - 1) >> w_sub).min(comp_width);
+        let y_range = (mask.y >> h_sub)..((mask.y + mask.height + (1 << h_sub) - 1) >> h_sub).min(comp_height);
+        let samples = ((1 << w_sub) * (1 << h_sub)) as f32;
+
+        let dst = frame.plane_data_mut(plane as u32).map_err(|err| err.to_string())?;
+        for cy in y_range {
+            let line = &mut dst[cy * stride + poffset..];
+            for cx in x_range.clone() {
+                let mut background = 0;
+                let mut text = 0;
+                for y in (cy << h_sub)..((cy + 1) << h_sub) {
+                    for x in (cx << w_sub)..((cx + 1) << w_sub) {
+                        match mask.get(x, y) {
+                            BACKGROUND => background += 1,
+                            TEXT => text += 1,
+                            _ => (),
+                        }
+                    }
+                }
+                if background + text == 0 {
+                    continue;
+                }
+
+                let mut value = f32::from(convert::read_sample(line, cx, pstride, little_endian) >> shift);
+
+                // The text is drawn over the background box, which is
+                // drawn behind it too.
+                let background_weight = background_alpha * (background + text) as f32 / samples;
+                value += (f32::from(background_codes[c]) - value) * background_weight;
+                let text_weight = text_alpha * text as f32 / samples;
+                value += (f32::from(text_codes[c]) - value) * text_weight;
+
+                let code = (value.round().clamp(0.0, max) as u16) << shift;
+                convert::write_sample(line, cx, pstride, little_endian, code);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gst_video::VideoFormat;
+
+    use super::convert::{Converter, Dither};
+
+    const WIDTH: usize = 32;
+    const HEIGHT: usize = 16;
+
+    /// Luma of a black frame of `format` with a white "A" burnt in at the
+    /// top left on an opaque black box.
+    fn burn_in(format: VideoFormat) -> Vec<f32> {
+        gst::init().unwrap();
+
+        let gray_info = VideoInfo::builder(VideoFormat::Gray8, WIDTH as u32, HEIGHT as u32).build().unwrap();
+        let info = VideoInfo::builder(format, WIDTH as u32, HEIGHT as u32).build().unwrap();
+
+        let black = gst::Buffer::from_mut_slice(vec![0u8; gray_info.size()]);
+        let mut buffer = gst::Buffer::with_size(info.size()).unwrap();
+        {
+            let black = VideoFrameRef::from_buffer_ref_readable(black.as_ref(), &gray_info).unwrap();
+            let mut frame = VideoFrameRef::from_buffer_ref_writable(buffer.get_mut().unwrap(), &info).unwrap();
+            Converter::new(&gray_info, &info).unwrap().convert(&black, &mut frame, Dither::None);
+
+            let style = Style {
+                position: Position::TopLeft,
+                scale: 1,
+                color: 0xffff_ffff,
+                background: 0xff00_0000,
+            };
+            draw(&mut frame, &[String::from("A")], &style).unwrap();
+        }
+
+        let mut gray = gst::Buffer::with_size(gray_info.size()).unwrap();
+        {
+            let frame = VideoFrameRef::from_buffer_ref_readable(buffer.as_ref(), &info).unwrap();
+            let mut gray = VideoFrameRef::from_buffer_ref_writable(gray.get_mut().unwrap(), &gray_info).unwrap();
+            Converter::new(&info, &gray_info).unwrap().convert(&frame, &mut gray, Dither::None);
+        }
+
+        let frame = VideoFrameRef::from_buffer_ref_readable(gray.as_ref(), &gray_info).unwrap();
+        let stride = frame.plane_stride()[0] as usize;
+        let data = frame.plane_data(0).unwrap();
+        (0..HEIGHT)
+            .flat_map(|y| data[y * stride..y * stride + WIDTH].iter().map(|&v| f32::from(v)))
+            .collect()
+    }
+
+    #[test]
+    fn test_text_size() {
+        assert_eq!(text_size(&[String::from("PTS"), String::from("FRAME 1")], 1), (43, 18));
+        assert_eq!(text_size(&[String::from("A")], 2), (14, 18));
+        assert_eq!(text_size(&[], 1), (1, 0));
+    }
+
+    #[test]
+    fn test_draw_gray8() {
+        let luma = burn_in(VideoFormat::Gray8);
+
+        // The glyph starts one sample into the box, which is at the margin.
+        let glyph = glyph('A');
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let lit = (3..3 + GLYPH_WIDTH).contains(&x)
+                    && (3..3 + GLYPH_HEIGHT).contains(&y)
+                    && glyph[x - 3] & (1 << (y - 3)) != 0;
+                assert_eq!(luma[y * WIDTH + x], if lit { 255.0 } else { 0.0 }, "at {}x{}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_draw_formats() {
+        let (width, height) = text_size(&[String::from("A")], 1);
+        let lit = glyph('A').iter().map(|bits| bits.count_ones()).sum::<u32>() as usize;
+
+        for format in super::super::imp::get_all_video_formats() {
+            let format = VideoFormat::from_string(format.get::<&str>().unwrap());
+            let luma = burn_in(format);
+
+            // Subsampled chroma smears the edges of the glyph, but its luma
+            // is where the text is and nothing is drawn outside of the box.
+            let mut bright = 0;
+            for y in 0..HEIGHT {
+                for x in 0..WIDTH {
+                    let value = luma[y * WIDTH + x];
+                    let inside = (2..2 + width).contains(&x) && (2..2 + height).contains(&y);
+                    if !inside {
+                        assert!(value < 1.0, "{:?}: {} at {}x{}", format, value, x, y);
+                    } else if value > 128.0 {
+                        bright += 1;
+                    }
+                }
+            }
+            assert_eq!(bright, lit, "{:?}", format);
+        }
+    }
+}
@@ -0,0 +1,385 @@
+//! Frame checksums over the visible pixels.
+//!
+//! CRC32, XXH64 and MD5 are each a few dozen lines following their
+//! specifications and are checked against the reference test vectors below.
+//! None of them needs to be fast, as each frame is hashed once.
+
+use gst::glib;
+use gst::glib::once_cell::sync::Lazy;
+use gst::BufferRef;
+use gst_video::VideoFrameRef;
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstVideoFilterChecksum")]
+pub enum ChecksumType {
+    #[enum_value(name = "None", nick = "none")]
+    None = 0,
+    #[enum_value(name = "CRC32", nick = "crc32")]
+    Crc32 = 1,
+    #[enum_value(name = "XXH64", nick = "xxhash")]
+    Xxhash = 2,
+    #[enum_value(name = "MD5", nick = "md5")]
+    Md5 = 3,
+}
+
+/// Checksum of the visible pixels of `frame`, as a lowercase hex string.
+/// Stride padding and data outside the frame are ignored.
+pub fn frame_checksum(checksum_type: ChecksumType, frame: &VideoFrameRef<&BufferRef>) -> Option<String> {
+    let mut hasher = Hasher::new(checksum_type)?;
+
+    let finfo = frame.format_info();
+    for plane in 0..finfo.n_planes() {
+        let c = (0..finfo.n_components())
+            .find(|&c| finfo.plane()[c as usize] == plane)
+            .unwrap_or(plane);
+        let width = finfo.scale_width(c as u8, frame.width()) as usize;
+        let height = finfo.scale_height(c as u8, frame.height()) as usize;
+        let line_bytes = width * finfo.pixel_stride()[c as usize] as usize;
+        let stride = frame.plane_stride()[plane as usize] as usize;
+        let data = frame.plane_data(plane).ok()?;
+
+        for y in 0..height {
+            hasher.update(&data[y * stride..y * stride + line_bytes]);
+        }
+    }
+
+    Some(hasher.finish())
+}
+
+enum Hasher {
+    Crc32(u32),
+    Xxh64(Xxh64),
+    Md5(Md5),
+}
+
+impl Hasher {
+    fn new(checksum_type: ChecksumType) -> Option<Self> {
+        match checksum_type {
+            ChecksumType::None => None,
+            ChecksumType::Crc32 => Some(Hasher::Crc32(!0)),
+            ChecksumType::Xxhash => Some(Hasher::Xxh64(Xxh64::new(0))),
+            ChecksumType::Md5 => Some(Hasher::Md5(Md5::new())),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Crc32(crc) => *crc = crc32_update(*crc, data),
+            Hasher::Xxh64(h) => h.update(data),
+            Hasher::Md5(h) => h.update(data),
+        }
+    }
+
+    fn finish(self) -> String {
+        match self {
+            Hasher::Crc32(crc) => format!("{:08x}", !crc),
+            Hasher::Xxh64(h) => format!("{:016x}", h.finish()),
+            Hasher::Md5(h) => h.finish().iter().map(|b| format!("{:02x}", b)).collect(),
+        }
+    }
+}
+
+static CRC32_TABLE: Lazy<[u32; 256]> = Lazy::new(|| {
+    let mut table = [0u32; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+        let mut crc = i as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { 0xedb8_8320 ^ (crc >> 1) } else { crc >> 1 };
+        }
+        *entry = crc;
+    }
+    table
+});
+
+fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    let table = &*CRC32_TABLE;
+    for &b in data {
+        crc = table[((crc ^ u32::from(b)) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+const XXH_P1: u64 = 0x9e37_79b1_85eb_ca87;
+const XXH_P2: u64 = 0xc2b2_ae3d_27d4_eb4f;
+const XXH_P3: u64 = 0x1656_67b1_9e37_79f9;
+const XXH_P4: u64 = 0x85eb_ca77_c2b2_ae63;
+const XXH_P5: u64 = 0x27d4_eb2f_1656_67c5;
+
+struct Xxh64 {
+    seed: u64,
+    acc: [u64; 4],
+    buf: [u8; 32],
+    buf_len: usize,
+    total: u64,
+}
+
+impl Xxh64 {
+    fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            acc: [
+                seed.wrapping_add(XXH_P1).wrapping_add(XXH_P2),
+                seed.wrapping_add(XXH_P2),
+                seed,
+                seed.wrapping_sub(XXH_P1),
+            ],
+            buf: [0; 32],
+            buf_len: 0,
+            total: 0,
+        }
+    }
+
+    #[inline]
+    fn round(acc: u64, input: u64) -> u64 {
+        acc.wrapping_add(input.wrapping_mul(XXH_P2))
+            .rotate_left(31)
+            .wrapping_mul(XXH_P1)
+    }
+
+    #[inline]
+    fn merge(acc: u64, val: u64) -> u64 {
+        (acc ^ Self::round(0, val)).wrapping_mul(XXH_P1).wrapping_add(XXH_P4)
+    }
+
+    fn stripe(&mut self, stripe: &[u8]) {
+        for (acc, lane) in self.acc.iter_mut().zip(stripe.chunks_exact(8)) {
+            *acc = Self::round(*acc, u64::from_le_bytes(lane.try_into().unwrap()));
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.total += data.len() as u64;
+
+        if self.buf_len > 0 {
+            let n = (32 - self.buf_len).min(data.len());
+            self.buf[self.buf_len..self.buf_len + n].copy_from_slice(&data[..n]);
+            self.buf_len += n;
+            data = &data[n..];
+
+            if self.buf_len < 32 {
+                return;
+            }
+            let buf = self.buf;
+            self.stripe(&buf);
+            self.buf_len = 0;
+        }
+
+        let mut stripes = data.chunks_exact(32);
+        for stripe in &mut stripes {
+            self.stripe(stripe);
+        }
+
+        let rest = stripes.remainder();
+        self.buf[..rest.len()].copy_from_slice(rest);
+        self.buf_len = rest.len();
+    }
+
+    fn finish(&self) -> u64 {
+        let mut h = if self.total >= 32 {
+            let [v1, v2, v3, v4] = self.acc;
+            let mut h = v1
+                .rotate_left(1)
+                .wrapping_add(v2.rotate_left(7))
+                .wrapping_add(v3.rotate_left(12))
+                .wrapping_add(v4.rotate_left(18));
+            for v in self.acc {
+                h = Self::merge(h, v);
+            }
+            h
+        } else {
+            self.seed.wrapping_add(XXH_P5)
+        };
+        h = h.wrapping_add(self.total);
+
+        let mut rest = &self.buf[..self.buf_len];
+        while rest.len() >= 8 {
+            let k = Self::round(0, u64::from_le_bytes(rest[..8].try_into().unwrap()));
+            h = (h ^ k).rotate_left(27).wrapping_mul(XXH_P1).wrapping_add(XXH_P4);
+            rest = &rest[8..];
+        }
+        if rest.len() >= 4 {
+            let k = u64::from(u32::from_le_bytes(rest[..4].try_into().unwrap()));
+            h = (h ^ k.wrapping_mul(XXH_P1))
+                .rotate_left(23)
+                .wrapping_mul(XXH_P2)
+                .wrapping_add(XXH_P3);
+            rest = &rest[4..];
+        }
+        for &b in rest {
+            h = (h ^ u64::from(b).wrapping_mul(XXH_P5))
+                .rotate_left(11)
+                .wrapping_mul(XXH_P1);
+        }
+
+        h ^= h >> 33;
+        h = h.wrapping_mul(XXH_P2);
+        h ^= h >> 29;
+        h = h.wrapping_mul(XXH_P3);
+        h ^= h >> 32;
+        h
+    }
+}
+
+const MD5_S: [u32; 64] = [
+    7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, //
+    5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20, //
+    4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, //
+    6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+];
+
+const MD5_K: [u32; 64] = [
+    0xd76a_a478, 0xe8c7_b756, 0x2420_70db, 0xc1bd_ceee, 0xf57c_0faf, 0x4787_c62a, 0xa830_4613, 0xfd46_9501,
+    0x6980_98d8, 0x8b44_f7af, 0xffff_5bb1, 0x895c_d7be, 0x6b90_1122, 0xfd98_7193, 0xa679_438e, 0x49b4_0821,
+    0xf61e_2562, 0xc040_b340, 0x265e_5a51, 0xe9b6_c7aa, 0xd62f_105d, 0x0244_1453, 0xd8a1_e681, 0xe7d3_fbc8,
+    0x21e1_cde6, 0xc337_07d6, 0xf4d5_0d87, 0x455a_14ed, 0xa9e3_e905, 0xfcef_a3f8, 0x676f_02d9, 0x8d2a_4c8a,
+    0xfffa_3942, 0x8771_f681, 0x6d9d_6122, 0xfde5_380c, 0xa4be_ea44, 0x4bde_cfa9, 0xf6bb_4b60, 0xbebf_bc70,
+    0x289b_7ec6, 0xeaa1_27fa, 0xd4ef_3085, 0x0488_1d05, 0xd9d4_d039, 0xe6db_99e5, 0x1fa2_7cf8, 0xc4ac_5665,
+    0xf429_2244, 0x432a_ff97, 0xab94_23a7, 0xfc93_a039, 0x655b_59c3, 0x8f0c_cc92, 0xffef_f47d, 0x8584_5dd1,
+    0x6fa8_7e4f, 0xfe2c_e6e0, 0xa301_4314, 0x4e08_11a1, 0xf753_7e82, 0xbd3a_f235, 0x2ad7_d2bb, 0xeb86_d391,
+];
+
+struct Md5 {
+    state: [u32; 4],
+    buf: [u8; 64],
+    buf_len: usize,
+    total: u64,
+}
+
+impl Md5 {
+    fn new() -> Self {
+        Self {
+            state: [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476],
+            buf: [0; 64],
+            buf_len: 0,
+            total: 0,
+        }
+    }
+
+    fn block(&mut self, block: &[u8]) {
+        let mut m = [0u32; 16];
+        for (m, word) in m.iter_mut().zip(block.chunks_exact(4)) {
+            *m = u32::from_le_bytes(word.try_into().unwrap());
+        }
+
+        let [mut a, mut b, mut c, mut d] = self.state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let f = f.wrapping_add(a).wrapping_add(MD5_K[i]).wrapping_add(m[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(MD5_S[i]));
+        }
+
+        for (s, v) in self.state.iter_mut().zip([a, b, c, d]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.total += data.len() as u64;
+
+        if self.buf_len > 0 {
+            let n = (64 - self.buf_len).min(data.len());
+            self.buf[self.buf_len..self.buf_len + n].copy_from_slice(&data[..n]);
+            self.buf_len += n;
+            data = &data[n..];
+
+            if self.buf_len < 64 {
+                return;
+            }
+            let buf = self.buf;
+            self.block(&buf);
+            self.buf_len = 0;
+        }
+
+        let mut blocks = data.chunks_exact(64);
+        for block in &mut blocks {
+            self.block(block);
+        }
+
+        let rest = blocks.remainder();
+        self.buf[..rest.len()].copy_from_slice(rest);
+        self.buf_len = rest.len();
+    }
+
+    fn finish(mut self) -> [u8; 16] {
+        let bit_len = self.total.wrapping_mul(8);
+
+        let mut padding = vec![0x80u8];
+        let pad_zeros = (55usize.wrapping_sub(self.buf_len)) % 64;
+        padding.extend(std::iter::repeat_n(0, pad_zeros));
+        padding.extend_from_slice(&bit_len.to_le_bytes());
+        self.update(&padding);
+
+        let mut out = [0u8; 16];
+        for (out, s) in out.chunks_exact_mut(4).zip(self.state) {
+            out.copy_from_slice(&s.to_le_bytes());
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FOX: &[u8] = b"The quick brown fox jumps over the lazy dog";
+    const DIGITS: &[u8] = b"12345678901234567890123456789012345678901234567890123456789012345678901234567890";
+
+    fn checksum(checksum_type: ChecksumType, data: &[u8]) -> String {
+        let mut hasher = Hasher::new(checksum_type).unwrap();
+        hasher.update(data);
+        hasher.finish()
+    }
+
+    /// Same as `checksum`, but feeding the data in uneven pieces.
+    fn checksum_split(checksum_type: ChecksumType, data: &[u8]) -> String {
+        let mut hasher = Hasher::new(checksum_type).unwrap();
+        let mut data = data;
+        for n in [1, 0, 3, 31, 2, 64].iter().cycle() {
+            if data.is_empty() {
+                break;
+            }
+            let n = (*n).min(data.len());
+            hasher.update(&data[..n]);
+            data = &data[n..];
+        }
+        hasher.finish()
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(checksum(ChecksumType::Crc32, b""), "00000000");
+        assert_eq!(checksum(ChecksumType::Crc32, b"abc"), "352441c2");
+        assert_eq!(checksum(ChecksumType::Crc32, b"123456789"), "cbf43926");
+        assert_eq!(checksum(ChecksumType::Crc32, FOX), "414fa339");
+        assert_eq!(checksum_split(ChecksumType::Crc32, DIGITS), checksum(ChecksumType::Crc32, DIGITS));
+    }
+
+    #[test]
+    fn test_xxh64() {
+        assert_eq!(checksum(ChecksumType::Xxhash, b""), "ef46db3751d8e999");
+        assert_eq!(checksum(ChecksumType::Xxhash, b"abc"), "44bc2cf5ad770999");
+        assert_eq!(
+            checksum(ChecksumType::Xxhash, b"Nobody inspects the spammish repetition"),
+            "fbcea83c8a378bf1"
+        );
+        assert_eq!(checksum_split(ChecksumType::Xxhash, DIGITS), checksum(ChecksumType::Xxhash, DIGITS));
+    }
+
+    #[test]
+    fn test_md5() {
+        assert_eq!(checksum(ChecksumType::Md5, b""), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(checksum(ChecksumType::Md5, b"abc"), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(checksum(ChecksumType::Md5, FOX), "9e107d9d372bb6826bd81d3542a419d6");
+        assert_eq!(checksum(ChecksumType::Md5, DIGITS), "57edf4a22be3c955ac49da2e2107b67a");
+        assert_eq!(checksum_split(ChecksumType::Md5, DIGITS), checksum(ChecksumType::Md5, DIGITS));
+    }
+}
@@ -0,0 +1,801 @@
+//! Software conversion between the planar formats of `get_all_video_formats`.
+//!
+//! Frames are unpacked into full resolution floating point planes holding
+//! physical values (Y' in 0..1 and Cb/Cr in -0.5..0.5, or R'G'B' in 0..1, plus
+//! alpha), converted between colour models there and packed again. All the
+//! supported formats store exactly one component per plane, which keeps
+//! unpacking and packing generic over `VideoFormatInfo`.
+
+use gst::glib;
+use gst::BufferRef;
+use gst_video::{
+    VideoChromaSite, VideoColorMatrix, VideoColorRange, VideoFormat, VideoFormatFlags, VideoFrameRef, VideoInfo,
+};
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstVideoFilterDither")]
+pub enum Dither {
+    #[enum_value(name = "None", nick = "none")]
+    None = 0,
+    #[enum_value(name = "Ordered: 4x4 Bayer matrix", nick = "ordered")]
+    Ordered = 1,
+}
+
+const BAYER_4X4: [[f32; 4]; 4] = [
+    [0.0, 8.0, 2.0, 10.0],
+    [12.0, 4.0, 14.0, 6.0],
+    [3.0, 11.0, 1.0, 9.0],
+    [15.0, 7.0, 13.0, 5.0],
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Model {
+    Gray,
+    Yuv,
+    Rgb,
+}
+
+/// Everything about one side of the conversion that is not per-frame.
+#[derive(Debug, Clone)]
+struct Side {
+    info: VideoInfo,
+    model: Model,
+    full_range: bool,
+    kr: f32,
+    kb: f32,
+    h_cosited: bool,
+    v_cosited: bool,
+}
+
+impl Side {
+    fn new(info: &VideoInfo) -> Result<Self, String> {
+        let finfo = info.format_info();
+
+        let model = if finfo.is_gray() {
+            Model::Gray
+        } else if finfo.is_yuv() {
+            Model::Yuv
+        } else if finfo.is_rgb() {
+            Model::Rgb
+        } else {
+            return Err(format!("Unsupported format {}", info.format()));
+        };
+
+        let n_components = finfo.n_components() as usize;
+        if finfo.n_planes() as usize != n_components
+            || finfo.pixel_stride()[..n_components].iter().any(|&s| s > 2)
+        {
+            return Err(format!("Format {} is not fully planar", info.format()));
+        }
+
+        let colorimetry = info.colorimetry();
+        let full_range = match colorimetry.range() {
+            VideoColorRange::Range0_255 => true,
+            VideoColorRange::Range16_235 => false,
+            _ => model != Model::Yuv,
+        };
+
+        let (kr, kb) = match colorimetry.matrix() {
+            VideoColorMatrix::Bt709 => (0.2126, 0.0722),
+            VideoColorMatrix::Fcc => (0.30, 0.11),
+            VideoColorMatrix::Smpte240m => (0.212, 0.087),
+            VideoColorMatrix::Bt2020 => (0.2627, 0.0593),
+            _ => (0.299, 0.114),
+        };
+
+        let chroma_site = info.chroma_site();
+
+        Ok(Self {
+            info: info.clone(),
+            model,
+            full_range,
+            kr,
+            kb,
+            h_cosited: chroma_site.contains(VideoChromaSite::H_COSITED),
+            v_cosited: chroma_site.contains(VideoChromaSite::V_COSITED),
+        })
+    }
+
+    fn has_alpha(&self) -> bool {
+        self.info.format_info().has_alpha()
+    }
+
+    /// Whether component `c` holds chroma (Cb or Cr).
+    fn is_chroma(&self, c: usize) -> bool {
+        self.model == Model::Yuv && (c == 1 || c == 2)
+    }
+
+    /// Whether component `c` is alpha.
+    fn is_alpha(&self, c: usize) -> bool {
+        self.has_alpha() && c == 3
+    }
+
+    /// Offset and scale mapping a physical value of component `c` to a code
+    /// value: `code = value * scale + offset`.
+    fn code_transform(&self, c: usize) -> (f32, f32) {
+        let depth = self.info.format_info().depth()[c];
+        let max = ((1u32 << depth) - 1) as f32;
+        let unit = (1u32 << (depth - 8)) as f32;
+
+        if self.is_alpha(c) {
+            (0.0, max)
+        } else if self.is_chroma(c) {
+            if self.full_range {
+                ((1u32 << (depth - 1)) as f32, max)
+            } else {
+                (128.0 * unit, 224.0 * unit)
+            }
+        } else if self.full_range {
+            (0.0, max)
+        } else {
+            (16.0 * unit, 219.0 * unit)
+        }
+    }
+
+    /// Dimensions of component `c` for a frame of this side.
+    fn component_size(&self, c: usize) -> (usize, usize) {
+        let finfo = self.info.format_info();
+        let w_sub = finfo.w_sub()[c];
+        let h_sub = finfo.h_sub()[c];
+        let width = self.info.width() as usize;
+        let height = self.info.height() as usize;
+
+        (
+            (width + (1 << w_sub) - 1) >> w_sub,
+            (height + (1 << h_sub) - 1) >> h_sub,
+        )
+    }
+
+    /// Physical values of component `c` of `frame`, at its own resolution.
+    fn read_component(&self, frame: &VideoFrameRef<&BufferRef>, c: usize) -> Vec<f32> {
+        let finfo = self.info.format_info();
+        let little_endian = finfo.flags().contains(VideoFormatFlags::LE);
+        let (cw, ch) = self.component_size(c);
+        let plane = finfo.plane()[c] as usize;
+        let stride = frame.plane_stride()[plane] as usize;
+        let poffset = finfo.poffset()[c] as usize;
+        let pstride = finfo.pixel_stride()[c] as usize;
+        let shift = finfo.shift()[c];
+        let src = frame.plane_data(plane as u32).unwrap();
+        let (offset, scale) = self.code_transform(c);
+
+        let mut comp = Vec::with_capacity(cw * ch);
+        for y in 0..ch {
+            let line = &src[y * stride + poffset..];
+            comp.extend((0..cw).map(|x| {
+                let code = read_sample(line, x, pstride, little_endian);
+                (f32::from(code >> shift) - offset) / scale
+            }));
+        }
+
+        comp
+    }
+
+    /// Horizontal and vertical taps of every subsampled component, resampling
+    /// it from full resolution with `downsample_taps` when `down`, and to it
+    /// with `upsample_taps` otherwise.
+    fn chroma_taps(&self, down: bool) -> Vec<Option<(Taps, Taps)>> {
+        let finfo = self.info.format_info();
+        let width = self.info.width() as usize;
+        let height = self.info.height() as usize;
+
+        (0..finfo.n_components() as usize)
+            .map(|c| {
+                let (cw, ch) = self.component_size(c);
+                if (cw, ch) == (width, height) {
+                    return None;
+                }
+
+                let (off_x, off_y) = self.chroma_offsets(c);
+                let sx = (1u32 << finfo.w_sub()[c]) as f32;
+                let sy = (1u32 << finfo.h_sub()[c]) as f32;
+                Some(if down {
+                    (downsample_taps(width, cw, sx, off_x), downsample_taps(height, ch, sy, off_y))
+                } else {
+                    (upsample_taps(cw, width, sx, off_x), upsample_taps(ch, height, sy, off_y))
+                })
+            })
+            .collect()
+    }
+
+    /// Horizontal and vertical chroma sample offsets, in luma samples.
+    fn chroma_offsets(&self, c: usize) -> (f32, f32) {
+        let finfo = self.info.format_info();
+        let sx = (1u32 << finfo.w_sub()[c]) as f32;
+        let sy = (1u32 << finfo.h_sub()[c]) as f32;
+
+        (
+            if self.h_cosited { 0.0 } else { (sx - 1.0) / 2.0 },
+            if self.v_cosited { 0.0 } else { (sy - 1.0) / 2.0 },
+        )
+    }
+}
+
+/// Code of sample `x` of `line`, before shifting. Samples are one byte, or
+/// two in the given endianness when `pstride` is 2.
+#[inline]
+pub fn read_sample(line: &[u8], x: usize, pstride: usize, little_endian: bool) -> u16 {
+    if pstride == 2 {
+        let bytes = [line[x * 2], line[x * 2 + 1]];
+        if little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        }
+    } else {
+        u16::from(line[x])
+    }
+}
+
+/// Stores the shifted `code` as sample `x` of `line`, the inverse of
+/// `read_sample`.
+#[inline]
+pub fn write_sample(line: &mut [u8], x: usize, pstride: usize, little_endian: bool, code: u16) {
+    if pstride == 2 {
+        let bytes = if little_endian {
+            code.to_le_bytes()
+        } else {
+            code.to_be_bytes()
+        };
+        line[x * 2] = bytes[0];
+        line[x * 2 + 1] = bytes[1];
+    } else {
+        line[x] = code as u8;
+    }
+}
+
+/// Code values, before shifting, of every component of `info` for the opaque
+/// R'G'B' colour `rgb`, each channel being in 0..1.
+pub fn rgb_to_codes(info: &VideoInfo, rgb: [f32; 3]) -> Result<[u16; 4], String> {
+    let side = Side::new(info)?;
+    let finfo = info.format_info();
+
+    let [r, g, b] = rgb;
+    let y = side.kr * r + (1.0 - side.kr - side.kb) * g + side.kb * b;
+    let values = match side.model {
+        Model::Rgb => [r, g, b, 1.0],
+        Model::Gray | Model::Yuv => [
+            y,
+            (b - y) / (2.0 * (1.0 - side.kb)),
+            (r - y) / (2.0 * (1.0 - side.kr)),
+            1.0,
+        ],
+    };
+
+    let mut codes = [0; 4];
+    for c in 0..finfo.n_components() as usize {
+        let max = ((1u32 << finfo.depth()[c]) - 1) as f32;
+        let (offset, scale) = side.code_transform(c);
+        codes[c] = (values[c] * scale + offset).round().clamp(0.0, max) as u16;
+    }
+
+    Ok(codes)
+}
+
+/// Luma of every pixel of `frame` as full range 8-bit values, computed
+/// from R'G'B' with the matrix of the caps for RGB formats. This is what the
+/// analysis elements work on.
+pub fn luma(frame: &VideoFrameRef<&BufferRef>) -> Result<Vec<f32>, String> {
+    let side = Side::new(frame.info())?;
+
+    let mut y = match side.model {
+        Model::Gray | Model::Yuv => side.read_component(frame, 0),
+        Model::Rgb => {
+            let kg = 1.0 - side.kr - side.kb;
+            let r = side.read_component(frame, 0);
+            let g = side.read_component(frame, 1);
+            let b = side.read_component(frame, 2);
+            r.iter()
+                .zip(g.iter())
+                .zip(b.iter())
+                .map(|((r, g), b)| side.kr * r + kg * g + side.kb * b)
+                .collect()
+        }
+    };
+    y.iter_mut().for_each(|y| *y = (*y * 255.0).clamp(0.0, 255.0));
+
+    Ok(y)
+}
+
+/// Full resolution R'G'B' planes of `frame`, each in 0..1, converted with
+/// the matrix of the caps for YUV formats.
+pub fn rgb_planes(frame: &VideoFrameRef<&BufferRef>) -> Result<[Vec<f32>; 3], String> {
+    planes_as(frame, VideoFormat::Gbr)
+}
+
+/// Full resolution Y', Cb and Cr planes of `frame`, Y' in 0..1 and Cb/Cr in
+/// -0.5..0.5. YUV formats keep their matrix and the others use the default
+/// one for their size.
+pub fn ycbcr_planes(frame: &VideoFrameRef<&BufferRef>) -> Result<[Vec<f32>; 3], String> {
+    planes_as(frame, VideoFormat::Y444)
+}
+
+/// The first three physical planes of `frame` converted to the colour model
+/// of `format`, without packing them.
+fn planes_as(frame: &VideoFrameRef<&BufferRef>, format: VideoFormat) -> Result<[Vec<f32>; 3], String> {
+    let in_info = frame.info();
+    let mut builder = VideoInfo::builder(format, in_info.width(), in_info.height());
+    let colorimetry = in_info.colorimetry();
+    if in_info.format_info().is_yuv() && gst_video::VideoFormatInfo::from_format(format).is_yuv() {
+        builder = builder.colorimetry(&colorimetry);
+    }
+    let out_info = builder.build().map_err(|err| err.to_string())?;
+
+    let converter = Converter::new(in_info, &out_info)?;
+    let mut planes = converter.unpack(frame);
+    converter.convert_model(&mut planes);
+
+    let [first, second, third, _] = planes.data;
+    Ok([first, second, third])
+}
+
+/// Packs full resolution R'G'B' planes, each in 0..1, into `frame`, which
+/// is the inverse of `rgb_planes`. Alpha, if any, is opaque.
+pub fn pack_rgb_planes(planes: [Vec<f32>; 3], frame: &mut VideoFrameRef<&mut BufferRef>) -> Result<(), String> {
+    let out_info = frame.info().clone();
+    let in_info = VideoInfo::builder(VideoFormat::Gbr, out_info.width(), out_info.height())
+        .build()
+        .map_err(|err| err.to_string())?;
+    let converter = Converter::new(&in_info, &out_info)?;
+
+    let [r, g, b] = planes;
+    let alpha = vec![1.0; r.len()];
+    let mut planes = Planes {
+        model: Model::Rgb,
+        kr: converter.in_side.kr,
+        kb: converter.in_side.kb,
+        data: [r, g, b, alpha],
+    };
+    converter.convert_model(&mut planes);
+    converter.pack(&planes, frame, Dither::None);
+
+    Ok(())
+}
+
+/// Full resolution physical planes: [Y', Cb, Cr, A] or [R', G', B', A].
+struct Planes {
+    model: Model,
+    kr: f32,
+    kb: f32,
+    data: [Vec<f32>; 4],
+}
+
+/// For each destination sample, the source samples and weights it is made of.
+type Taps = Vec<Vec<(usize, f32)>>;
+
+/// Bilinear interpolation of `src_len` samples spaced `scale` apart and
+/// starting at `offset`, onto `dst_len` unit-spaced samples.
+fn upsample_taps(src_len: usize, dst_len: usize, scale: f32, offset: f32) -> Taps {
+    let last = (src_len - 1) as f32;
+
+    (0..dst_len)
+        .map(|x| {
+            let pos = ((x as f32 - offset) / scale).clamp(0.0, last);
+            let i0 = pos.floor() as usize;
+            let i1 = (i0 + 1).min(src_len - 1);
+            let frac = pos - i0 as f32;
+            vec![(i0, 1.0 - frac), (i1, frac)]
+        })
+        .collect()
+}
+
+/// Tent filter of radius `scale` centred on every `scale`-th unit-spaced
+/// sample, starting at `offset`, so that chroma ends up sited correctly.
+fn downsample_taps(src_len: usize, dst_len: usize, scale: f32, offset: f32) -> Taps {
+    let last = src_len as isize - 1;
+
+    (0..dst_len)
+        .map(|i| {
+            let center = i as f32 * scale + offset;
+            let lo = (center - scale).floor() as isize + 1;
+            let hi = (center + scale).ceil() as isize - 1;
+
+            let mut taps = Vec::with_capacity((hi - lo + 1).max(1) as usize);
+            let mut sum = 0.0;
+            for x in lo..=hi {
+                let weight = 1.0 - (x as f32 - center).abs() / scale;
+                if weight <= 0.0 {
+                    continue;
+                }
+                taps.push((x.clamp(0, last) as usize, weight));
+                sum += weight;
+            }
+            for (_, weight) in taps.iter_mut() {
+                *weight /= sum;
+            }
+
+            taps
+        })
+        .collect()
+}
+
+/// Resamples `src` (`width` x `height`) along one axis.
+fn apply_taps(src: &[f32], width: usize, height: usize, taps: &Taps, horizontal: bool) -> Vec<f32> {
+    if horizontal {
+        let out_width = taps.len();
+        let mut out = Vec::with_capacity(out_width * height);
+        for line in src.chunks_exact(width) {
+            out.extend(taps.iter().map(|t| t.iter().map(|&(i, w)| line[i] * w).sum::<f32>()));
+        }
+        out
+    } else {
+        let mut out = Vec::with_capacity(width * taps.len());
+        for t in taps {
+            out.extend((0..width).map(|x| t.iter().map(|&(i, w)| src[i * width + x] * w).sum::<f32>()));
+        }
+        out
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Converter {
+    in_side: Side,
+    out_side: Side,
+    /// Taps of `in_side.chroma_taps(false)`, computed once per caps.
+    upsample: Vec<Option<(Taps, Taps)>>,
+    /// Taps of `out_side.chroma_taps(true)`.
+    downsample: Vec<Option<(Taps, Taps)>>,
+}
+
+impl Converter {
+    pub fn new(in_info: &VideoInfo, out_info: &VideoInfo) -> Result<Self, String> {
+        if in_info.width() != out_info.width() || in_info.height() != out_info.height() {
+            return Err(format!(
+                "Cannot change size from {}x{} to {}x{}",
+                in_info.width(),
+                in_info.height(),
+                out_info.width(),
+                out_info.height()
+            ));
+        }
+
+        let in_side = Side::new(in_info)?;
+        let out_side = Side::new(out_info)?;
+
+        Ok(Self {
+            upsample: in_side.chroma_taps(false),
+            downsample: out_side.chroma_taps(true),
+            in_side,
+            out_side,
+        })
+    }
+
+    pub fn convert(
+        &self,
+        in_frame: &VideoFrameRef<&BufferRef>,
+        out_frame: &mut VideoFrameRef<&mut BufferRef>,
+        dither: Dither,
+    ) {
+        let mut planes = self.unpack(in_frame);
+        self.convert_model(&mut planes);
+        self.pack(&planes, out_frame, dither);
+    }
+
+    fn unpack(&self, frame: &VideoFrameRef<&BufferRef>) -> Planes {
+        let side = &self.in_side;
+        let finfo = side.info.format_info();
+        let width = side.info.width() as usize;
+        let height = side.info.height() as usize;
+
+        let mut data: [Vec<f32>; 4] = Default::default();
+        for (c, data) in data.iter_mut().enumerate().take(finfo.n_components() as usize) {
+            let (cw, ch) = side.component_size(c);
+            let mut comp = side.read_component(frame, c);
+
+            if let Some((h_taps, v_taps)) = &self.upsample[c] {
+                comp = apply_taps(&comp, cw, ch, h_taps, true);
+                comp = apply_taps(&comp, width, ch, v_taps, false);
+            }
+
+            *data = comp;
+        }
+
+        if !side.has_alpha() {
+            data[3] = vec![1.0; width * height];
+        }
+
+        Planes {
+            model: side.model,
+            kr: side.kr,
+            kb: side.kb,
+            data,
+        }
+    }
+
+    /// Converts `planes` in place to the output colour model and matrix.
+    fn convert_model(&self, planes: &mut Planes) {
+        let out = &self.out_side;
+        let same_matrix = planes.kr == out.kr && planes.kb == out.kb;
+
+        match (planes.model, out.model) {
+            (from, to) if from == to && (from != Model::Yuv || same_matrix) => (),
+            (Model::Gray, _) => {
+                // Gray is achromatic whatever the matrix.
+                let y = planes.data[0].clone();
+                if out.model == Model::Rgb {
+                    planes.data[1] = y.clone();
+                    planes.data[2] = y;
+                } else {
+                    planes.data[1] = vec![0.0; y.len()];
+                    planes.data[2] = vec![0.0; y.len()];
+                }
+            }
+            (Model::Yuv, Model::Gray) if same_matrix => (),
+            _ => {
+                if planes.model == Model::Yuv {
+                    self.yuv_to_rgb(planes);
+                }
+                if out.model != Model::Rgb {
+                    self.rgb_to_yuv(planes);
+                }
+            }
+        }
+
+        planes.model = out.model;
+        planes.kr = out.kr;
+        planes.kb = out.kb;
+    }
+
+    fn yuv_to_rgb(&self, planes: &mut Planes) {
+        let (kr, kb) = (planes.kr, planes.kb);
+        let kg = 1.0 - kr - kb;
+
+        let [y, cb, cr, _] = &mut planes.data;
+        for ((y, cb), cr) in y.iter_mut().zip(cb.iter_mut()).zip(cr.iter_mut()) {
+            let r = *y + 2.0 * (1.0 - kr) * *cr;
+            let b = *y + 2.0 * (1.0 - kb) * *cb;
+            let g = (*y - kr * r - kb * b) / kg;
+            *y = r;
+            *cb = g;
+            *cr = b;
+        }
+
+        planes.model = Model::Rgb;
+    }
+
+    fn rgb_to_yuv(&self, planes: &mut Planes) {
+        let (kr, kb) = (self.out_side.kr, self.out_side.kb);
+        let kg = 1.0 - kr - kb;
+
+        let [r, g, b, _] = &mut planes.data;
+        for ((r, g), b) in r.iter_mut().zip(g.iter_mut()).zip(b.iter_mut()) {
+            let y = kr * *r + kg * *g + kb * *b;
+            let cb = (*b - y) / (2.0 * (1.0 - kb));
+            let cr = (*r - y) / (2.0 * (1.0 - kr));
+            *r = y;
+            *g = cb;
+            *b = cr;
+        }
+
+        planes.model = Model::Yuv;
+    }
+
+    fn pack(&self, planes: &Planes, frame: &mut VideoFrameRef<&mut BufferRef>, dither: Dither) {
+        let side = &self.out_side;
+        let finfo = side.info.format_info();
+        let width = side.info.width() as usize;
+        let height = side.info.height() as usize;
+        let little_endian = finfo.flags().contains(VideoFormatFlags::LE);
+        let in_depth = self.in_side.info.format_info().depth().iter().copied().max().unwrap_or(8);
+
+        for c in 0..finfo.n_components() as usize {
+            let (cw, _) = side.component_size(c);
+            let plane = finfo.plane()[c] as usize;
+            let stride = frame.plane_stride()[plane] as usize;
+            let poffset = finfo.poffset()[c] as usize;
+            let pstride = finfo.pixel_stride()[c] as usize;
+            let shift = finfo.shift()[c];
+            let depth = finfo.depth()[c];
+            let max = ((1u32 << depth) - 1) as f32;
+            let (offset, scale) = side.code_transform(c);
+            let dither = dither == Dither::Ordered && depth < in_depth;
+
+            let resampled;
+            let comp = if let Some((h_taps, v_taps)) = &self.downsample[c] {
+                let tmp = apply_taps(&planes.data[c], width, height, h_taps, true);
+                resampled = apply_taps(&tmp, cw, height, v_taps, false);
+                &resampled
+            } else {
+                &planes.data[c]
+            };
+
+            let dst = frame.plane_data_mut(plane as u32).unwrap();
+            for (y, values) in comp.chunks_exact(cw).enumerate() {
+                let line = &mut dst[y * stride + poffset..];
+                for (x, &v) in values.iter().enumerate() {
+                    let bias = if dither {
+                        (BAYER_4X4[y & 3][x & 3] + 0.5) / 16.0 - 0.5
+                    } else {
+                        0.0
+                    };
+                    let code = ((v * scale + offset + bias).round().clamp(0.0, max) as u16) << shift;
+
+                    write_sample(line, x, pstride, little_endian, code);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gst_video::{VideoColorPrimaries, VideoColorimetry, VideoFormat, VideoTransferFunction};
+
+    fn yuv_info(format: VideoFormat, matrix: VideoColorMatrix, range: VideoColorRange) -> VideoInfo {
+        let colorimetry = VideoColorimetry::new(range, matrix, VideoTransferFunction::Bt709, VideoColorPrimaries::Bt709);
+        VideoInfo::builder(format, 4, 2).colorimetry(&colorimetry).build().unwrap()
+    }
+
+    /// A frame of `info` with every sample of component `c` set to `codes[c]`.
+    fn constant_frame(info: &VideoInfo, codes: [u16; 4]) -> gst::Buffer {
+        let side = Side::new(info).unwrap();
+        let finfo = info.format_info();
+        let mut buffer = gst::Buffer::with_size(info.size()).unwrap();
+        {
+            let mut frame = VideoFrameRef::from_buffer_ref_writable(buffer.get_mut().unwrap(), info).unwrap();
+            for (c, &code) in codes.iter().enumerate().take(finfo.n_components() as usize) {
+                let (cw, ch) = side.component_size(c);
+                let plane = finfo.plane()[c] as usize;
+                let stride = frame.plane_stride()[plane] as usize;
+                let pstride = finfo.pixel_stride()[c] as usize;
+                let code = code << finfo.shift()[c];
+                let dst = frame.plane_data_mut(plane as u32).unwrap();
+                for y in 0..ch {
+                    let line = &mut dst[y * stride + finfo.poffset()[c] as usize..];
+                    for x in 0..cw {
+                        if pstride == 2 {
+                            line[x * 2..x * 2 + 2].copy_from_slice(&code.to_le_bytes());
+                        } else {
+                            line[x] = code as u8;
+                        }
+                    }
+                }
+            }
+        }
+        buffer
+    }
+
+    /// Codes of every sample of component `c` of `buffer`.
+    fn codes(info: &VideoInfo, buffer: &gst::Buffer, c: usize) -> Vec<u16> {
+        let side = Side::new(info).unwrap();
+        let finfo = info.format_info();
+        let frame = VideoFrameRef::from_buffer_ref_readable(buffer.as_ref(), info).unwrap();
+        let (cw, ch) = side.component_size(c);
+        let plane = finfo.plane()[c] as usize;
+        let stride = frame.plane_stride()[plane] as usize;
+        let pstride = finfo.pixel_stride()[c] as usize;
+        let src = frame.plane_data(plane as u32).unwrap();
+        (0..ch)
+            .flat_map(|y| {
+                let line = &src[y * stride + finfo.poffset()[c] as usize..];
+                (0..cw).map(move |x| {
+                    let code = if pstride == 2 {
+                        u16::from_le_bytes([line[x * 2], line[x * 2 + 1]])
+                    } else {
+                        u16::from(line[x])
+                    };
+                    code >> finfo.shift()[c]
+                })
+            })
+            .collect()
+    }
+
+    fn convert(in_info: &VideoInfo, inbuf: &gst::Buffer, out_info: &VideoInfo) -> gst::Buffer {
+        let converter = Converter::new(in_info, out_info).unwrap();
+        let mut outbuf = gst::Buffer::with_size(out_info.size()).unwrap();
+        {
+            let in_frame = VideoFrameRef::from_buffer_ref_readable(inbuf.as_ref(), in_info).unwrap();
+            let mut out_frame = VideoFrameRef::from_buffer_ref_writable(outbuf.get_mut().unwrap(), out_info).unwrap();
+            converter.convert(&in_frame, &mut out_frame, Dither::None);
+        }
+        outbuf
+    }
+
+    #[test]
+    fn test_matrix() {
+        gst::init().unwrap();
+
+        let gbr = VideoInfo::builder(VideoFormat::Gbr, 4, 2).build().unwrap();
+        let first_codes = |info: &VideoInfo, buffer: &gst::Buffer| [0, 1, 2].map(|c| codes(info, buffer, c)[0]);
+
+        // Y', Cb and Cr of red, then of white.
+        for (matrix, range, red) in [
+            (VideoColorMatrix::Bt601, VideoColorRange::Range0_255, [76, 85, 255]),
+            (VideoColorMatrix::Bt601, VideoColorRange::Range16_235, [81, 90, 240]),
+            (VideoColorMatrix::Bt709, VideoColorRange::Range0_255, [54, 99, 255]),
+            (VideoColorMatrix::Bt709, VideoColorRange::Range16_235, [63, 102, 240]),
+            (VideoColorMatrix::Bt2020, VideoColorRange::Range0_255, [67, 92, 255]),
+            (VideoColorMatrix::Bt2020, VideoColorRange::Range16_235, [74, 97, 240]),
+        ] {
+            let info = yuv_info(VideoFormat::Y444, matrix, range);
+            let outbuf = convert(&gbr, &constant_frame(&gbr, [255, 0, 0, 0]), &info);
+            assert_eq!(first_codes(&info, &outbuf), red, "{:?} {:?}", matrix, range);
+            let white = if range == VideoColorRange::Range0_255 { 255 } else { 235 };
+            let outbuf = convert(&gbr, &constant_frame(&gbr, [255; 4]), &info);
+            assert_eq!(first_codes(&info, &outbuf), [white, 128, 128]);
+
+            // Converting back to R'G'B' uses the same matrix.
+            let rgb = [230, 128, 64];
+            let yuv = convert(&gbr, &constant_frame(&gbr, [rgb[0], rgb[1], rgb[2], 0]), &info);
+            let outbuf = convert(&info, &yuv, &gbr);
+            for (c, &expected) in rgb.iter().enumerate() {
+                for code in codes(&gbr, &outbuf, c) {
+                    assert!((i32::from(code) - i32::from(expected)).abs() <= 2, "{:?} {:?} {} {}", matrix, range, c, code);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_chroma_siting() {
+        gst::init().unwrap();
+
+        for (chroma_site, offsets) in [
+            (VideoChromaSite::NONE, (0.5, 0.5)),
+            (VideoChromaSite::H_COSITED, (0.0, 0.5)),
+            (VideoChromaSite::COSITED, (0.0, 0.0)),
+        ] {
+            let info = VideoInfo::builder(VideoFormat::I420, 4, 4).chroma_site(chroma_site).build().unwrap();
+            let side = Side::new(&info).unwrap();
+            assert_eq!(side.chroma_offsets(0), (0.0, 0.0));
+            assert_eq!(side.chroma_offsets(1), offsets, "{:?}", chroma_site);
+            assert!(side.chroma_taps(true)[0].is_none());
+            assert_eq!(side.chroma_taps(true)[1].as_ref().unwrap().0.len(), 2);
+            assert_eq!(side.chroma_taps(false)[2].as_ref().unwrap().1.len(), 4);
+        }
+
+        // Centred chroma sits between two luma samples, cosited on the first.
+        assert_eq!(
+            upsample_taps(2, 4, 2.0, 0.5),
+            [
+                vec![(0, 1.0), (1, 0.0)],
+                vec![(0, 0.75), (1, 0.25)],
+                vec![(0, 0.25), (1, 0.75)],
+                vec![(1, 1.0), (1, 0.0)],
+            ]
+        );
+        assert_eq!(
+            upsample_taps(2, 4, 2.0, 0.0),
+            [
+                vec![(0, 1.0), (1, 0.0)],
+                vec![(0, 0.5), (1, 0.5)],
+                vec![(1, 1.0), (1, 0.0)],
+                vec![(1, 1.0), (1, 0.0)],
+            ]
+        );
+        assert_eq!(
+            downsample_taps(4, 2, 2.0, 0.0),
+            [vec![(0, 0.25), (0, 0.5), (1, 0.25)], vec![(1, 0.25), (2, 0.5), (3, 0.25)]]
+        );
+        assert_eq!(
+            downsample_taps(4, 2, 2.0, 0.5),
+            [
+                vec![(0, 0.125), (0, 0.375), (1, 0.375), (2, 0.125)],
+                vec![(1, 0.125), (2, 0.375), (3, 0.375), (3, 0.125)],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_alpha() {
+        gst::init().unwrap();
+
+        let i420 = VideoInfo::builder(VideoFormat::I420, 4, 2).build().unwrap();
+        let a420 = VideoInfo::builder(VideoFormat::A420, 4, 2).build().unwrap();
+        let a422 = VideoInfo::builder(VideoFormat::A42210le, 4, 2).build().unwrap();
+
+        // Alpha is added opaque.
+        let outbuf = convert(&i420, &constant_frame(&i420, [100, 128, 128, 0]), &a420);
+        assert_eq!(codes(&a420, &outbuf, 0), [100; 8]);
+        assert_eq!(codes(&a420, &outbuf, 3), [255; 8]);
+
+        // Alpha is kept, and dropped when the output has none.
+        let inbuf = constant_frame(&a420, [100, 128, 128, 77]);
+        assert_eq!(codes(&a422, &convert(&a420, &inbuf, &a422), 3), [309; 8]);
+        let outbuf = convert(&a420, &inbuf, &i420);
+        assert_eq!(codes(&i420, &outbuf, 0), [100; 8]);
+        assert_eq!(codes(&i420, &outbuf, 1), [128; 2]);
+    }
+}
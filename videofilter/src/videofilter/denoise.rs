@@ -0,0 +1,257 @@
+//! Temporal denoising by averaging frames.
+//!
+//! Samples that differ from the history by more than the motion threshold
+//! are taken less and less from it, and not at all beyond twice the
+//! threshold, so that moving objects do not leave ghosts.
+
+use std::collections::VecDeque;
+
+use gst::glib;
+use gst::BufferRef;
+use gst_video::{VideoFormatFlags, VideoFrameRef};
+
+use super::convert;
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstVideoFilterDenoise")]
+pub enum DenoiseMode {
+    #[enum_value(name = "None", nick = "none")]
+    None = 0,
+    #[enum_value(name = "Exponential moving average", nick = "average")]
+    Average = 1,
+    #[enum_value(name = "Average of the last frames", nick = "window")]
+    Window = 2,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DenoiseSettings {
+    pub mode: DenoiseMode,
+    /// Weight of the history in `Average` mode.
+    pub strength: f64,
+    /// Number of frames averaged in `Window` mode, the current one included.
+    pub frames: u32,
+    /// In 8-bit code values, 0 disabling motion adaptation.
+    pub motion_threshold: u32,
+}
+
+/// Frame as one plane of code values per component.
+type Planes = Vec<Vec<f32>>;
+
+fn unpack(frame: &VideoFrameRef<&mut BufferRef>) -> Planes {
+    let finfo = frame.format_info();
+    let little_endian = finfo.flags().contains(VideoFormatFlags::LE);
+
+    (0..finfo.n_components() as usize)
+        .map(|c| {
+            let plane = finfo.plane()[c];
+            let stride = frame.plane_stride()[plane as usize] as usize;
+            let poffset = finfo.poffset()[c] as usize;
+            let pstride = finfo.pixel_stride()[c] as usize;
+            let shift = finfo.shift()[c];
+            let width = finfo.scale_width(c as u8, frame.width()) as usize;
+            let height = finfo.scale_height(c as u8, frame.height()) as usize;
+            let src = frame.plane_data(plane).unwrap();
+
+            let mut comp = Vec::with_capacity(width * height);
+            for y in 0..height {
+                let line = &src[y * stride + poffset..];
+                comp.extend((0..width).map(|x| {
+                    let code = convert::read_sample(line, x, pstride, little_endian);
+                    f32::from(code >> shift)
+                }));
+            }
+
+            comp
+        })
+        .collect()
+}
+
+fn pack(planes: &Planes, frame: &mut VideoFrameRef<&mut BufferRef>) {
+    let finfo = frame.format_info();
+    let little_endian = finfo.flags().contains(VideoFormatFlags::LE);
+
+    for (c, comp) in planes.iter().enumerate() {
+        let plane = finfo.plane()[c];
+        let stride = frame.plane_stride()[plane as usize] as usize;
+        let poffset = finfo.poffset()[c] as usize;
+        let pstride = finfo.pixel_stride()[c] as usize;
+        let shift = finfo.shift()[c];
+        let max = ((1u32 << finfo.depth()[c]) - 1) as f32;
+        let width = finfo.scale_width(c as u8, frame.width()) as usize;
+        let dst = frame.plane_data_mut(plane).unwrap();
+
+        for (y, values) in comp.chunks_exact(width).enumerate() {
+            let line = &mut dst[y * stride + poffset..];
+            for (x, &v) in values.iter().enumerate() {
+                let code = (v.round().clamp(0.0, max) as u16) << shift;
+                convert::write_sample(line, x, pstride, little_endian, code);
+            }
+        }
+    }
+}
+
+/// How much of a history sample differing by `diff` from the current one
+/// may be used.
+fn motion_weight(diff: f32, threshold: f32) -> f32 {
+    if threshold <= 0.0 {
+        1.0
+    } else {
+        (2.0 - diff / threshold).clamp(0.0, 1.0)
+    }
+}
+
+/// Frame history, reset on flushes, discontinuities and caps changes.
+#[derive(Default)]
+pub struct Denoiser {
+    mode: Option<DenoiseMode>,
+    /// Running average of `Average` mode.
+    average: Planes,
+    /// Previous input frames of `Window` mode, the oldest first.
+    window: VecDeque<Planes>,
+}
+
+impl Denoiser {
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Denoises `frame` in place and adds it to the history.
+    pub fn denoise(&mut self, frame: &mut VideoFrameRef<&mut BufferRef>, settings: &DenoiseSettings) {
+        if settings.mode == DenoiseMode::None {
+            self.reset();
+            return;
+        }
+        if self.mode != Some(settings.mode) {
+            self.reset();
+            self.mode = Some(settings.mode);
+        }
+
+        let finfo = frame.format_info();
+        let thresholds: Vec<f32> = finfo.depth()[..finfo.n_components() as usize]
+            .iter()
+            .map(|&depth| (settings.motion_threshold << (depth - 8)) as f32)
+            .collect();
+        let current = unpack(frame);
+
+        match settings.mode {
+            DenoiseMode::None => unreachable!(),
+            DenoiseMode::Average => {
+                if self.average.is_empty() {
+                    self.average = current;
+                    return;
+                }
+
+                let strength = settings.strength as f32;
+                for ((average, current), &threshold) in self.average.iter_mut().zip(current.iter()).zip(&thresholds) {
+                    for (average, &current) in average.iter_mut().zip(current.iter()) {
+                        let weight = strength * motion_weight((current - *average).abs(), threshold);
+                        *average = current + (*average - current) * weight;
+                    }
+                }
+                pack(&self.average, frame);
+            }
+            DenoiseMode::Window => {
+                if !self.window.is_empty() {
+                    let mut output = current.clone();
+                    for (c, output) in output.iter_mut().enumerate() {
+                        for (i, output) in output.iter_mut().enumerate() {
+                            let current = current[c][i];
+                            let (sum, weights) = self.window.iter().fold((current, 1.0), |(sum, weights), frame| {
+                                let sample = frame[c][i];
+                                let weight = motion_weight((sample - current).abs(), thresholds[c]);
+                                (sum + sample * weight, weights + weight)
+                            });
+                            *output = sum / weights;
+                        }
+                    }
+                    pack(&output, frame);
+                }
+
+                self.window.push_back(current);
+                while self.window.len() >= settings.frames.max(1) as usize {
+                    self.window.pop_front();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gst_video::{VideoFormat, VideoInfo};
+
+    use super::*;
+
+    /// Denoises an 8x1 GRAY8 frame of `values` and returns the output.
+    fn denoise(denoiser: &mut Denoiser, settings: &DenoiseSettings, values: [u8; 8]) -> [u8; 8] {
+        gst::init().unwrap();
+
+        let info = VideoInfo::builder(VideoFormat::Gray8, 8, 1).build().unwrap();
+        let mut buffer = gst::Buffer::from_mut_slice(values);
+        let mut frame = VideoFrameRef::from_buffer_ref_writable(buffer.get_mut().unwrap(), &info).unwrap();
+        denoiser.denoise(&mut frame, settings);
+        drop(frame);
+
+        let map = buffer.map_readable().unwrap();
+        map.as_slice().try_into().unwrap()
+    }
+
+    fn settings(mode: DenoiseMode) -> DenoiseSettings {
+        DenoiseSettings {
+            mode,
+            strength: 0.5,
+            frames: 4,
+            motion_threshold: 16,
+        }
+    }
+
+    /// 100 with +-4 of noise changing sign with the column and frame.
+    fn noisy(frame: usize) -> [u8; 8] {
+        std::array::from_fn(|x| if (x + frame).is_multiple_of(2) { 104 } else { 96 })
+    }
+
+    #[test]
+    fn test_static_noise_is_reduced() {
+        // Four frames of alternating noise average out exactly.
+        let mut denoiser = Denoiser::default();
+        let window = settings(DenoiseMode::Window);
+        assert_eq!(denoise(&mut denoiser, &window, noisy(0)), noisy(0));
+        let output = (1..8).map(|frame| denoise(&mut denoiser, &window, noisy(frame))).last();
+        assert_eq!(output, Some([100; 8]));
+
+        // The moving average converges to +-4/3.
+        let mut denoiser = Denoiser::default();
+        let average = settings(DenoiseMode::Average);
+        let output = (0..8).map(|frame| denoise(&mut denoiser, &average, noisy(frame))).last();
+        assert!(output.unwrap().iter().all(|&v| (99..=101).contains(&v)), "{:?}", output);
+    }
+
+    #[test]
+    fn test_moving_edge_passes_unchanged() {
+        for mode in [DenoiseMode::Average, DenoiseMode::Window] {
+            let mut denoiser = Denoiser::default();
+            for frame in 0..4 {
+                let edge = std::array::from_fn(|x| if x < 2 * frame { 0 } else { 200 });
+                assert_eq!(denoise(&mut denoiser, &settings(mode), edge), edge, "{:?} {}", mode, frame);
+            }
+        }
+    }
+
+    #[test]
+    fn test_reset() {
+        for mode in [DenoiseMode::Average, DenoiseMode::Window] {
+            let mut denoiser = Denoiser::default();
+            let settings = DenoiseSettings {
+                motion_threshold: 0,
+                ..settings(mode)
+            };
+
+            denoise(&mut denoiser, &settings, [100; 8]);
+            assert_eq!(denoise(&mut denoiser, &settings, [110; 8]), [105; 8], "{:?}", mode);
+
+            denoiser.reset();
+            assert_eq!(denoise(&mut denoiser, &settings, [120; 8]), [120; 8], "{:?}", mode);
+        }
+    }
+}
@@ -0,0 +1,363 @@
+//! Frame dumping to PGM, PPM and Y4M files.
+//!
+//! Frames are encoded on the streaming thread, which only copies the visible
+//! pixels, and written to disk from a background thread. At most
+//! `QUEUE_LENGTH` frames wait to be written, after which the streaming thread
+//! waits for the disk.
+
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+
+use gst::prelude::*;
+use gst::{gst_warning, BufferRef};
+use gst_video::{
+    VideoChromaSite, VideoFieldOrder, VideoFormat, VideoFormatFlags, VideoFrameRef, VideoInterlaceMode,
+};
+
+use super::convert;
+use super::imp::CAT;
+
+/// Number of encoded frames that can wait for the writer thread.
+const QUEUE_LENGTH: usize = 16;
+
+/// Expands a printf-style `pattern` with the frame `index`. Only `%d`,
+/// `%0<width>d` and `%%` are supported, like `multifilesink`.
+pub fn format_location(pattern: &str, index: u64) -> String {
+    let mut out = String::with_capacity(pattern.len() + 8);
+    let mut chars = pattern.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+
+        let mut spec = String::new();
+        while let Some(&d) = chars.peek() {
+            if d.is_ascii_digit() {
+                spec.push(d);
+                chars.next();
+            } else {
+                break;
+            }
+        }
+
+        match chars.next() {
+            Some('d') | Some('u') => {
+                let width = spec.parse::<usize>().unwrap_or(0);
+                if spec.starts_with('0') {
+                    out.push_str(&format!("{:0width$}", index, width = width));
+                } else {
+                    out.push_str(&format!("{:width$}", index, width = width));
+                }
+            }
+            Some('%') => out.push('%'),
+            Some(other) => {
+                out.push('%');
+                out.push_str(&spec);
+                out.push(other);
+            }
+            None => {
+                out.push('%');
+                out.push_str(&spec);
+            }
+        }
+    }
+
+    out
+}
+
+/// Sample `x` of row `y` of component `c`, in native endianness.
+#[inline]
+fn sample(frame: &VideoFrameRef<&BufferRef>, c: usize, x: usize, y: usize) -> u16 {
+    let finfo = frame.format_info();
+    let plane = finfo.plane()[c];
+    let stride = frame.plane_stride()[plane as usize] as usize;
+    let offset = y * stride + finfo.poffset()[c] as usize;
+    let data = frame.plane_data(plane).unwrap();
+
+    convert::read_sample(
+        &data[offset..],
+        x,
+        finfo.pixel_stride()[c] as usize,
+        finfo.flags().contains(VideoFormatFlags::LE),
+    )
+}
+
+/// Appends every visible sample of component `c`, 16-bit samples being
+/// written with the requested endianness.
+fn push_component(frame: &VideoFrameRef<&BufferRef>, c: usize, big_endian: bool, out: &mut Vec<u8>) {
+    let finfo = frame.format_info();
+    let width = finfo.scale_width(c as u8, frame.width()) as usize;
+    let height = finfo.scale_height(c as u8, frame.height()) as usize;
+    let wide = finfo.pixel_stride()[c] == 2;
+
+    for y in 0..height {
+        for x in 0..width {
+            let v = sample(frame, c, x, y);
+            if !wide {
+                out.push(v as u8);
+            } else if big_endian {
+                out.extend_from_slice(&v.to_be_bytes());
+            } else {
+                out.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+    }
+}
+
+fn encode_pgm(frame: &VideoFrameRef<&BufferRef>) -> Vec<u8> {
+    let max = (1u32 << frame.format_info().depth()[0]) - 1;
+    let mut out = format!("P5\n{} {}\n{}\n", frame.width(), frame.height(), max).into_bytes();
+    push_component(frame, 0, true, &mut out);
+    out
+}
+
+fn encode_ppm(frame: &VideoFrameRef<&BufferRef>) -> Vec<u8> {
+    let max = (1u32 << frame.format_info().depth()[0]) - 1;
+    let mut out = format!("P6\n{} {}\n{}\n", frame.width(), frame.height(), max).into_bytes();
+
+    for y in 0..frame.height() as usize {
+        for x in 0..frame.width() as usize {
+            // Components are R, G, B whatever the plane order.
+            for c in 0..3 {
+                let v = sample(frame, c, x, y);
+                if max > 255 {
+                    out.extend_from_slice(&v.to_be_bytes());
+                } else {
+                    out.push(v as u8);
+                }
+            }
+        }
+    }
+
+    out
+}
+
+fn y4m_colorspace(frame: &VideoFrameRef<&BufferRef>) -> Option<&'static str> {
+    let chroma_site = frame.info().chroma_site();
+
+    let colorspace = match frame.format() {
+        VideoFormat::I420 | VideoFormat::A420 => {
+            if chroma_site.contains(VideoChromaSite::H_COSITED | VideoChromaSite::V_COSITED) {
+                "420paldv"
+            } else if chroma_site.contains(VideoChromaSite::H_COSITED) {
+                "420mpeg2"
+            } else {
+                "420jpeg"
+            }
+        }
+        VideoFormat::I42010le | VideoFormat::I42010be => "420p10",
+        VideoFormat::Y42b => "422",
+        VideoFormat::I42210le | VideoFormat::I42210be | VideoFormat::A42210le | VideoFormat::A42210be => "422p10",
+        VideoFormat::Y444 => "444",
+        VideoFormat::Y44410le | VideoFormat::Y44410be | VideoFormat::A44410le | VideoFormat::A44410be => "444p10",
+        VideoFormat::Y41b => "411",
+        _ => return None,
+    };
+
+    Some(colorspace)
+}
+
+/// Single-frame YUV4MPEG2 stream. Alpha is dropped, as Y4M only knows it for
+/// 8-bit 4:4:4.
+fn encode_y4m(frame: &VideoFrameRef<&BufferRef>) -> Option<Vec<u8>> {
+    let info = frame.info();
+    let colorspace = y4m_colorspace(frame)?;
+
+    let interlace = if info.interlace_mode() == VideoInterlaceMode::Progressive {
+        'p'
+    } else {
+        match info.field_order() {
+            VideoFieldOrder::TopFieldFirst => 't',
+            VideoFieldOrder::BottomFieldFirst => 'b',
+            _ => 'm',
+        }
+    };
+    let fps = info.fps();
+    let par = info.par();
+
+    let mut out = format!(
+        "YUV4MPEG2 W{} H{} F{}:{} I{} A{}:{} C{}\nFRAME\n",
+        info.width(),
+        info.height(),
+        fps.numer(),
+        fps.denom(),
+        interlace,
+        par.numer(),
+        par.denom(),
+        colorspace
+    )
+    .into_bytes();
+
+    for c in 0..3 {
+        push_component(frame, c, false, &mut out);
+    }
+
+    Some(out)
+}
+
+/// Encodes the visible part of `frame` in the file format matching it.
+pub fn encode(frame: &VideoFrameRef<&BufferRef>) -> Option<Vec<u8>> {
+    let finfo = frame.format_info();
+
+    if finfo.is_gray() {
+        Some(encode_pgm(frame))
+    } else if finfo.is_rgb() {
+        Some(encode_ppm(frame))
+    } else {
+        encode_y4m(frame)
+    }
+}
+
+/// Background writer thread, joined when dropped.
+pub struct Dumper {
+    sender: Option<mpsc::SyncSender<(PathBuf, Vec<u8>)>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Dumper {
+    pub fn new(element: &super::VideoFilter) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<(PathBuf, Vec<u8>)>(QUEUE_LENGTH);
+        let element_weak = element.downgrade();
+
+        let thread = thread::Builder::new()
+            .name("videofilter-dump".into())
+            .spawn(move || {
+                for (path, data) in receiver {
+                    if let Err(err) = std::fs::write(&path, &data) {
+                        let element = match element_weak.upgrade() {
+                            Some(element) => element,
+                            None => break,
+                        };
+                        gst_warning!(CAT, obj: &element, "Failed to write {}: {}", path.display(), err);
+                        gst::element_warning!(
+                            element,
+                            gst::ResourceError::OpenWrite,
+                            ["Failed to write {}: {}", path.display(), err]
+                        );
+                    }
+                }
+            })
+            .ok();
+
+        Self {
+            sender: Some(sender),
+            thread,
+        }
+    }
+
+    /// Queues `data` to be written to `path`, waiting for room in the queue
+    /// if it is full.
+    pub fn write(&self, path: PathBuf, data: Vec<u8>) {
+        if let Some(ref sender) = self.sender {
+            let _ = sender.send((path, data));
+        }
+    }
+}
+
+impl Drop for Dumper {
+    fn drop(&mut self) {
+        // Closing the channel lets the thread finish the queued writes.
+        self.sender.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_location() {
+        assert_eq!(format_location("frame-%05d.pgm", 42), "frame-00042.pgm");
+        assert_eq!(format_location("frame-%d.pgm", 42), "frame-42.pgm");
+        assert_eq!(format_location("frame-%4d.pgm", 42), "frame-  42.pgm");
+        assert_eq!(format_location("%u%%", 7), "7%");
+        assert_eq!(format_location("%s-%d", 7), "%s-7");
+        assert_eq!(format_location("frame.pgm%", 7), "frame.pgm%");
+    }
+
+    fn encode_frame(info: &gst_video::VideoInfo, data: &[u8]) -> Option<Vec<u8>> {
+        gst::init().unwrap();
+
+        let mut buffer = gst::Buffer::with_size(info.size()).unwrap();
+        {
+            let buffer = buffer.get_mut().unwrap();
+            let mut frame = VideoFrameRef::from_buffer_ref_writable(buffer, info).unwrap();
+            let finfo = frame.format_info();
+            let mut data = data;
+            for plane in 0..frame.n_planes() {
+                let c = finfo.plane().iter().position(|&p| p == plane).unwrap() as u8;
+                let width = finfo.scale_width(c, info.width()) as usize * finfo.pixel_stride()[c as usize] as usize;
+                let height = finfo.scale_height(c, info.height()) as usize;
+                let stride = frame.plane_stride()[plane as usize] as usize;
+                let dst = frame.plane_data_mut(plane).unwrap();
+                for y in 0..height {
+                    dst[y * stride..y * stride + width].copy_from_slice(&data[..width]);
+                    data = &data[width..];
+                }
+            }
+        }
+
+        let frame = VideoFrameRef::from_buffer_ref_readable(buffer.as_ref(), info).unwrap();
+        encode(&frame)
+    }
+
+    fn info(format: VideoFormat, width: u32, height: u32) -> gst_video::VideoInfo {
+        gst_video::VideoInfo::builder(format, width, height)
+            .fps(gst::Fraction::new(30, 1))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_pgm() {
+        let out = encode_frame(&info(VideoFormat::Gray8, 2, 2), &[1, 2, 3, 4]).unwrap();
+        assert_eq!(out, b"P5\n2 2\n255\n\x01\x02\x03\x04");
+
+        // 16-bit samples are big-endian.
+        let out = encode_frame(&info(VideoFormat::Gray16Le, 2, 1), &[0x01, 0x02, 0x03, 0x04]).unwrap();
+        assert_eq!(out, b"P5\n2 1\n65535\n\x02\x01\x04\x03");
+    }
+
+    #[test]
+    fn test_ppm() {
+        // Planar G, B, R in, interleaved R, G, B out.
+        let out = encode_frame(&info(VideoFormat::Gbr, 2, 1), &[1, 2, 3, 4, 5, 6]).unwrap();
+        assert_eq!(out, b"P6\n2 1\n255\n\x05\x01\x03\x06\x02\x04");
+    }
+
+    #[test]
+    fn test_y4m() {
+        let info = gst_video::VideoInfo::builder(VideoFormat::I420, 4, 2)
+            .fps(gst::Fraction::new(25, 1))
+            .par(gst::Fraction::new(4, 3))
+            .chroma_site(VideoChromaSite::NONE)
+            .build()
+            .unwrap();
+        let data = (0..12).collect::<Vec<u8>>();
+        let out = encode_frame(&info, &data).unwrap();
+
+        let header = b"YUV4MPEG2 W4 H2 F25:1 Ip A4:3 C420jpeg\nFRAME\n";
+        assert_eq!(&out[..header.len()], header);
+        assert_eq!(&out[header.len()..], data.as_slice());
+
+        let info = gst_video::VideoInfo::builder(VideoFormat::Y42b, 2, 2)
+            .fps(gst::Fraction::new(25, 1))
+            .interlace_mode(VideoInterlaceMode::Interleaved)
+            .field_order(VideoFieldOrder::BottomFieldFirst)
+            .build()
+            .unwrap();
+        let out = encode_frame(&info, &[0; 8]).unwrap();
+        assert!(out.starts_with(b"YUV4MPEG2 W2 H2 F25:1 Ib A1:1 C422\nFRAME\n"));
+    }
+
+    #[test]
+    fn test_unsupported() {
+        assert_eq!(encode_frame(&info(VideoFormat::Yuv9, 4, 4), &[0; 18]), None);
+    }
+}
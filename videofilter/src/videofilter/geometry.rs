@@ -0,0 +1,508 @@
+//! Cropping, flipping, rotation and padding.
+//!
+//! The input is cropped first, then flipped, then rotated clockwise, and the
+//! result is padded. Subsampled components are resampled with the nearest
+//! sample, which only matters when rotating 4:2:2 and 4:1:1 formats.
+
+use gst::glib;
+use gst::glib::subclass::prelude::*;
+use gst::BufferRef;
+use gst_video::{VideoFrameRef, VideoOrientationMethod};
+
+use super::convert;
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstVideoFilterFlip")]
+pub enum Flip {
+    #[enum_value(name = "None", nick = "none")]
+    None = 0,
+    #[enum_value(name = "Horizontal", nick = "horizontal")]
+    Horizontal = 1,
+    #[enum_value(name = "Vertical", nick = "vertical")]
+    Vertical = 2,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstVideoFilterRotate")]
+pub enum Rotate {
+    #[enum_value(name = "None", nick = "none")]
+    None = 0,
+    #[enum_value(name = "90 degrees clockwise", nick = "90")]
+    Rotate90 = 90,
+    #[enum_value(name = "180 degrees", nick = "180")]
+    Rotate180 = 180,
+    #[enum_value(name = "270 degrees clockwise", nick = "270")]
+    Rotate270 = 270,
+}
+
+glib::wrapper! {
+    /// `GstVideoDirection`, which the bindings do not cover. It only has the
+    /// `video-direction` property, so implementing it takes no virtual
+    /// methods.
+    pub struct VideoDirection(Interface<gst_video::ffi::GstVideoDirection, gst_video::ffi::GstVideoDirectionInterface>);
+
+    match fn {
+        type_ => || gst_video::ffi::gst_video_direction_get_type(),
+    }
+}
+
+unsafe impl<T: ObjectSubclass> IsImplementable<T> for VideoDirection {}
+
+/// The flip and rotation equivalent to `method`, or `None` for the methods
+/// that are not a fixed orientation.
+pub fn from_orientation(method: VideoOrientationMethod) -> Option<(Flip, Rotate)> {
+    let orientation = match method {
+        VideoOrientationMethod::Identity => (Flip::None, Rotate::None),
+        VideoOrientationMethod::_90r => (Flip::None, Rotate::Rotate90),
+        VideoOrientationMethod::_180 => (Flip::None, Rotate::Rotate180),
+        VideoOrientationMethod::_90l => (Flip::None, Rotate::Rotate270),
+        VideoOrientationMethod::Horiz => (Flip::Horizontal, Rotate::None),
+        VideoOrientationMethod::Vert => (Flip::Vertical, Rotate::None),
+        VideoOrientationMethod::UlLr => (Flip::Vertical, Rotate::Rotate90),
+        VideoOrientationMethod::UrLl => (Flip::Horizontal, Rotate::Rotate90),
+        _ => return None,
+    };
+
+    Some(orientation)
+}
+
+/// The orientation method equivalent to flipping and then rotating.
+pub fn to_orientation(flip: Flip, rotate: Rotate) -> VideoOrientationMethod {
+    match (flip, rotate) {
+        (Flip::None, Rotate::None) => VideoOrientationMethod::Identity,
+        (Flip::None, Rotate::Rotate90) => VideoOrientationMethod::_90r,
+        (Flip::None, Rotate::Rotate180) => VideoOrientationMethod::_180,
+        (Flip::None, Rotate::Rotate270) => VideoOrientationMethod::_90l,
+        (Flip::Horizontal, Rotate::None) | (Flip::Vertical, Rotate::Rotate180) => VideoOrientationMethod::Horiz,
+        (Flip::Vertical, Rotate::None) | (Flip::Horizontal, Rotate::Rotate180) => VideoOrientationMethod::Vert,
+        (Flip::Vertical, Rotate::Rotate90) | (Flip::Horizontal, Rotate::Rotate270) => VideoOrientationMethod::UlLr,
+        (Flip::Horizontal, Rotate::Rotate90) | (Flip::Vertical, Rotate::Rotate270) => VideoOrientationMethod::UrLl,
+    }
+}
+
+/// Range of values of a `width` or `height` caps field.
+#[derive(Debug, Clone, Copy)]
+struct Dimension {
+    min: i32,
+    max: i32,
+}
+
+impl Dimension {
+    fn get(s: &gst::StructureRef, field: &str) -> Option<Self> {
+        if let Ok(v) = s.get::<i32>(field) {
+            Some(Self { min: v, max: v })
+        } else if let Ok(range) = s.get::<gst::IntRange<i32>>(field) {
+            Some(Self {
+                min: range.min(),
+                max: range.max(),
+            })
+        } else {
+            None
+        }
+    }
+
+    fn set(self, s: &mut gst::StructureRef, field: &str) {
+        if self.min == self.max {
+            s.set(field, self.min);
+        } else {
+            s.set(field, gst::IntRange::new(self.min, self.max));
+        }
+    }
+
+    /// Removes `by` samples, `None` if nothing is left.
+    fn shrink(self, by: u32) -> Option<Self> {
+        let by = by.min(i32::MAX as u32) as i32;
+        let max = self.max.checked_sub(by).filter(|&max| max >= 1)?;
+
+        Some(Self {
+            min: self.min.saturating_sub(by).max(1),
+            max,
+        })
+    }
+
+    fn grow(self, by: u32) -> Self {
+        let by = by.min(i32::MAX as u32) as i32;
+
+        Self {
+            min: self.min.saturating_add(by),
+            max: self.max.saturating_add(by),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Geometry {
+    pub crop_left: u32,
+    pub crop_right: u32,
+    pub crop_top: u32,
+    pub crop_bottom: u32,
+    pub flip: Flip,
+    pub rotate: Rotate,
+    pub pad_left: u32,
+    pub pad_right: u32,
+    pub pad_top: u32,
+    pub pad_bottom: u32,
+    /// ARGB.
+    pub pad_color: u32,
+}
+
+impl Default for Geometry {
+    fn default() -> Self {
+        Self {
+            crop_left: 0,
+            crop_right: 0,
+            crop_top: 0,
+            crop_bottom: 0,
+            flip: Flip::None,
+            rotate: Rotate::None,
+            pad_left: 0,
+            pad_right: 0,
+            pad_top: 0,
+            pad_bottom: 0,
+            pad_color: 0xff00_0000,
+        }
+    }
+}
+
+impl Geometry {
+    pub fn is_identity(&self) -> bool {
+        !self.has_crop() && self.is_crop_only()
+    }
+
+    pub fn has_crop(&self) -> bool {
+        self.crop_left != 0 || self.crop_right != 0 || self.crop_top != 0 || self.crop_bottom != 0
+    }
+
+    /// Whether the output is a rectangle of the input, as with
+    /// `GstVideoCropMeta`.
+    pub fn is_crop_only(&self) -> bool {
+        self.flip == Flip::None
+            && self.rotate == Rotate::None
+            && self.pad_left == 0
+            && self.pad_right == 0
+            && self.pad_top == 0
+            && self.pad_bottom == 0
+    }
+
+    fn swaps_axes(&self) -> bool {
+        matches!(self.rotate, Rotate::Rotate90 | Rotate::Rotate270)
+    }
+
+    /// `x`, `y`, `width` and `height` of the crop rectangle in a `width` x
+    /// `height` input.
+    pub fn crop_rect(&self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        (
+            self.crop_left,
+            self.crop_top,
+            width.saturating_sub(self.crop_left + self.crop_right),
+            height.saturating_sub(self.crop_top + self.crop_bottom),
+        )
+    }
+
+    /// Output size for a `width` x `height` input, `None` if everything is
+    /// cropped.
+    pub fn output_size(&self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let (_, _, width, height) = self.crop_rect(width, height);
+        if width == 0 || height == 0 {
+            return None;
+        }
+
+        let (width, height) = if self.swaps_axes() {
+            (height, width)
+        } else {
+            (width, height)
+        };
+
+        Some((
+            width + self.pad_left + self.pad_right,
+            height + self.pad_top + self.pad_bottom,
+        ))
+    }
+
+    /// Transforms the `width`, `height` and `pixel-aspect-ratio` fields of
+    /// `caps` from the sink to the src side, or the other way around for
+    /// `PadDirection::Src`. Structures whose size is entirely cropped are
+    /// dropped.
+    pub fn transform_caps(&self, caps: &gst::Caps, direction: gst::PadDirection) -> gst::Caps {
+        let mut out = gst::Caps::new_empty();
+
+        for s in caps.iter() {
+            let mut s = s.to_owned();
+
+            if let (Some(width), Some(height)) = (Dimension::get(&s, "width"), Dimension::get(&s, "height")) {
+                let size = if direction == gst::PadDirection::Sink {
+                    self.forward(width, height)
+                } else {
+                    self.backward(width, height)
+                };
+                let (width, height) = match size {
+                    Some(size) => size,
+                    None => continue,
+                };
+                width.set(&mut s, "width");
+                height.set(&mut s, "height");
+            }
+
+            if self.swaps_axes() {
+                if let Ok(par) = s.get::<gst::Fraction>("pixel-aspect-ratio") {
+                    if par.numer() != 0 {
+                        s.set("pixel-aspect-ratio", gst::Fraction::new(par.denom(), par.numer()));
+                    }
+                }
+            }
+
+            out.get_mut().unwrap().append_structure(s);
+        }
+
+        out
+    }
+
+    fn forward(&self, width: Dimension, height: Dimension) -> Option<(Dimension, Dimension)> {
+        let width = width.shrink(self.crop_left.saturating_add(self.crop_right))?;
+        let height = height.shrink(self.crop_top.saturating_add(self.crop_bottom))?;
+        let (width, height) = if self.swaps_axes() {
+            (height, width)
+        } else {
+            (width, height)
+        };
+
+        Some((
+            width.grow(self.pad_left.saturating_add(self.pad_right)),
+            height.grow(self.pad_top.saturating_add(self.pad_bottom)),
+        ))
+    }
+
+    fn backward(&self, width: Dimension, height: Dimension) -> Option<(Dimension, Dimension)> {
+        let width = width.shrink(self.pad_left.saturating_add(self.pad_right))?;
+        let height = height.shrink(self.pad_top.saturating_add(self.pad_bottom))?;
+        let (width, height) = if self.swaps_axes() {
+            (height, width)
+        } else {
+            (width, height)
+        };
+
+        Some((
+            width.grow(self.crop_left.saturating_add(self.crop_right)),
+            height.grow(self.crop_top.saturating_add(self.crop_bottom)),
+        ))
+    }
+
+    /// Input position of the output position `x`, `y`, or `None` in the
+    /// padding. `width` and `height` are the size of the cropped input.
+    fn source(&self, x: u32, y: u32, width: u32, height: u32) -> Option<(u32, u32)> {
+        let (rotated_width, rotated_height) = if self.swaps_axes() {
+            (height, width)
+        } else {
+            (width, height)
+        };
+        let x = x.checked_sub(self.pad_left).filter(|&x| x < rotated_width)?;
+        let y = y.checked_sub(self.pad_top).filter(|&y| y < rotated_height)?;
+
+        let (x, y) = match self.rotate {
+            Rotate::None => (x, y),
+            Rotate::Rotate90 => (y, height - 1 - x),
+            Rotate::Rotate180 => (width - 1 - x, height - 1 - y),
+            Rotate::Rotate270 => (width - 1 - y, x),
+        };
+        let (x, y) = match self.flip {
+            Flip::None => (x, y),
+            Flip::Horizontal => (width - 1 - x, y),
+            Flip::Vertical => (x, height - 1 - y),
+        };
+
+        Some((x + self.crop_left, y + self.crop_top))
+    }
+
+    /// Fills `out_frame` from `in_frame`, both being of the same format.
+    pub fn apply(
+        &self,
+        in_frame: &VideoFrameRef<&BufferRef>,
+        out_frame: &mut VideoFrameRef<&mut BufferRef>,
+    ) -> Result<(), String> {
+        let info = out_frame.info().clone();
+        let finfo = info.format_info();
+        let (_, _, width, height) = self.crop_rect(in_frame.width(), in_frame.height());
+
+        let [r, g, b] = [
+            ((self.pad_color >> 16) & 0xff) as f32 / 255.0,
+            ((self.pad_color >> 8) & 0xff) as f32 / 255.0,
+            (self.pad_color & 0xff) as f32 / 255.0,
+        ];
+        let mut pad_codes = convert::rgb_to_codes(&info, [r, g, b])?;
+        if finfo.has_alpha() {
+            let max = (1u32 << finfo.depth()[3]) - 1;
+            pad_codes[3] = ((self.pad_color >> 24) * max / 255) as u16;
+        }
+        let big_endian = !finfo.flags().contains(gst_video::VideoFormatFlags::LE);
+
+        for (c, &pad_code) in pad_codes.iter().enumerate().take(finfo.n_components() as usize) {
+            let w_sub = finfo.w_sub()[c];
+            let h_sub = finfo.h_sub()[c];
+            let plane = finfo.plane()[c];
+            let poffset = finfo.poffset()[c] as usize;
+            let pstride = finfo.pixel_stride()[c] as usize;
+            let in_stride = in_frame.plane_stride()[plane as usize] as usize;
+            let out_stride = out_frame.plane_stride()[plane as usize] as usize;
+            let in_width = finfo.scale_width(c as u8, in_frame.width()) as usize;
+            let in_height = finfo.scale_height(c as u8, in_frame.height()) as usize;
+            let out_width = finfo.scale_width(c as u8, info.width());
+            let out_height = finfo.scale_height(c as u8, info.height());
+
+            let pad = pad_code << finfo.shift()[c];
+            let pad = if big_endian { pad.to_be_bytes() } else { pad.to_le_bytes() };
+
+            let src = in_frame.plane_data(plane).map_err(|err| err.to_string())?;
+            let dst = out_frame.plane_data_mut(plane).map_err(|err| err.to_string())?;
+
+            for y in 0..out_height {
+                let line = &mut dst[y as usize * out_stride + poffset..];
+                for x in 0..out_width {
+                    let out = &mut line[x as usize * pstride..][..pstride];
+                    match self.source(x << w_sub, y << h_sub, width, height) {
+                        Some((sx, sy)) => {
+                            let sx = ((sx >> w_sub) as usize).min(in_width - 1);
+                            let sy = ((sy >> h_sub) as usize).min(in_height - 1);
+                            let offset = sy * in_stride + poffset + sx * pstride;
+                            out.copy_from_slice(&src[offset..offset + pstride]);
+                        }
+                        None => out.copy_from_slice(&pad[2 - pstride..]),
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gst_video::{VideoFormat, VideoInfo};
+
+    use super::*;
+
+    /// Applies `geometry` to a GRAY8 frame of `rows` and returns the rows of
+    /// the output.
+    fn apply(geometry: Geometry, rows: &[&[u8]]) -> Vec<Vec<u8>> {
+        gst::init().unwrap();
+
+        let (width, height) = (rows[0].len() as u32, rows.len() as u32);
+        let in_info = VideoInfo::builder(VideoFormat::Gray8, width, height).build().unwrap();
+        let (out_width, out_height) = geometry.output_size(width, height).unwrap();
+        let out_info = VideoInfo::builder(VideoFormat::Gray8, out_width, out_height).build().unwrap();
+
+        let mut inbuf = gst::Buffer::with_size(in_info.size()).unwrap();
+        {
+            let mut frame = VideoFrameRef::from_buffer_ref_writable(inbuf.get_mut().unwrap(), &in_info).unwrap();
+            let stride = frame.plane_stride()[0] as usize;
+            let data = frame.plane_data_mut(0).unwrap();
+            for (line, row) in data.chunks_mut(stride).zip(rows) {
+                line[..row.len()].copy_from_slice(row);
+            }
+        }
+
+        let mut outbuf = gst::Buffer::with_size(out_info.size()).unwrap();
+        let in_frame = VideoFrameRef::from_buffer_ref_readable(inbuf.as_ref(), &in_info).unwrap();
+        let mut out_frame = VideoFrameRef::from_buffer_ref_writable(outbuf.get_mut().unwrap(), &out_info).unwrap();
+        geometry.apply(&in_frame, &mut out_frame).unwrap();
+
+        let stride = out_frame.plane_stride()[0] as usize;
+        out_frame
+            .plane_data(0)
+            .unwrap()
+            .chunks(stride)
+            .take(out_height as usize)
+            .map(|line| line[..out_width as usize].to_vec())
+            .collect()
+    }
+
+    const FRAME: [&[u8]; 2] = [&[1, 2, 3], &[4, 5, 6]];
+
+    #[test]
+    fn test_apply_flip() {
+        let flip = |flip| Geometry { flip, ..Geometry::default() };
+
+        assert_eq!(apply(Geometry::default(), &FRAME), [[1, 2, 3], [4, 5, 6]]);
+        assert_eq!(apply(flip(Flip::Horizontal), &FRAME), [[3, 2, 1], [6, 5, 4]]);
+        assert_eq!(apply(flip(Flip::Vertical), &FRAME), [[4, 5, 6], [1, 2, 3]]);
+    }
+
+    #[test]
+    fn test_apply_rotate() {
+        let rotate = |rotate| Geometry { rotate, ..Geometry::default() };
+
+        assert_eq!(apply(rotate(Rotate::Rotate90), &FRAME), [[4, 1], [5, 2], [6, 3]]);
+        assert_eq!(apply(rotate(Rotate::Rotate180), &FRAME), [[6, 5, 4], [3, 2, 1]]);
+        assert_eq!(apply(rotate(Rotate::Rotate270), &FRAME), [[3, 6], [2, 5], [1, 4]]);
+
+        // Flipping comes first.
+        let geometry = Geometry {
+            flip: Flip::Horizontal,
+            rotate: Rotate::Rotate90,
+            ..Geometry::default()
+        };
+        assert_eq!(apply(geometry, &FRAME), [[6, 3], [5, 2], [4, 1]]);
+    }
+
+    #[test]
+    fn test_apply_crop_and_pad() {
+        let geometry = Geometry {
+            crop_left: 1,
+            pad_left: 1,
+            pad_top: 1,
+            pad_bottom: 1,
+            pad_color: 0xffff_ffff,
+            ..Geometry::default()
+        };
+
+        assert_eq!(
+            apply(geometry, &FRAME),
+            [[255, 255, 255], [255, 2, 3], [255, 5, 6], [255, 255, 255]]
+        );
+    }
+
+    #[test]
+    fn test_transform_caps() {
+        gst::init().unwrap();
+
+        let geometry = Geometry {
+            crop_left: 10,
+            crop_right: 20,
+            crop_top: 4,
+            crop_bottom: 6,
+            rotate: Rotate::Rotate90,
+            pad_left: 2,
+            pad_top: 3,
+            ..Geometry::default()
+        };
+
+        let sink = gst::Caps::builder("video/x-raw")
+            .field("width", 320)
+            .field("height", 240)
+            .field("pixel-aspect-ratio", gst::Fraction::new(4, 3))
+            .build();
+        let src = gst::Caps::builder("video/x-raw")
+            .field("width", 232)
+            .field("height", 293)
+            .field("pixel-aspect-ratio", gst::Fraction::new(3, 4))
+            .build();
+        assert_eq!(geometry.transform_caps(&sink, gst::PadDirection::Sink), src);
+        assert_eq!(geometry.transform_caps(&src, gst::PadDirection::Src), sink);
+
+        // Ranges are shifted, and structures entirely cropped dropped.
+        let caps = gst::Caps::builder("video/x-raw")
+            .field("width", gst::IntRange::new(100, 400))
+            .field("height", gst::IntRange::new(1, 240))
+            .build();
+        let expected = gst::Caps::builder("video/x-raw")
+            .field("width", gst::IntRange::new(3, 232))
+            .field("height", gst::IntRange::new(73, 373))
+            .build();
+        assert_eq!(geometry.transform_caps(&caps, gst::PadDirection::Sink), expected);
+
+        let caps = gst::Caps::builder("video/x-raw").field("width", 30).field("height", 240).build();
+        assert!(geometry.transform_caps(&caps, gst::PadDirection::Sink).is_empty());
+    }
+}
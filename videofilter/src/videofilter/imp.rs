@@ -1,14 +1,29 @@
-use gst::{Buffer, BufferRef, Caps, ErrorMessage, Event, FlowError, FlowSuccess, glib, gst_info, gst_warning, LoggableError, Meta, MetaRef, PadDirection, PadTemplate, QueryRef};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use gst::{Buffer, BufferRef, Caps, ErrorMessage, Event, FlowError, FlowSuccess, glib, gst_debug, gst_info, gst_log, gst_warning, LoggableError, PadDirection, PadTemplate, QueryRef};
 use gst::query::Allocation;
 use gst::subclass::ElementMetadata;
 use gst::subclass::prelude::*;
 use gst::prelude::*;
+use gst_base::prelude::*;
 use gst_base::subclass::{*, prelude::*};
-use gst_base::subclass::base_transform::{GenerateOutputSuccess, InputBuffer, PrepareOutputBufferSuccess};
 use parking_lot::Mutex;
 use crate::glib::once_cell::sync::Lazy;
 
-fn get_all_video_formats() -> Vec<glib::SendValue> {
+use super::burnin::{self, BurnIn, Position};
+use super::checksum::{self, ChecksumType};
+use super::convert::{Converter, Dither};
+use super::denoise::{DenoiseMode, DenoiseSettings, Denoiser};
+use super::dump::{self, Dumper};
+use super::geometry::{self, Flip, Geometry, Rotate};
+use super::scale::{self, ScaleMethod, Scaler};
+use super::stats::Measurements;
+
+pub(crate) fn get_all_video_formats() -> Vec<glib::SendValue> {
     use gst_video::VideoFormat;
 
     let values = [
@@ -39,7 +54,7 @@ fn get_all_video_formats() -> Vec<glib::SendValue> {
     values.iter().map(|i| i.to_str().to_send_value()).collect()
 }
 
-static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+pub(super) static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
     gst::DebugCategory::new(
         "videofilter",
         gst::DebugColorFlags::empty(),
@@ -47,12 +62,526 @@ static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
     )
 });
 
+const DEFAULT_SIGNAL_HANDOFFS: bool = true;
+const DEFAULT_DITHER: Dither = Dither::None;
+const DEFAULT_DUMP_INTERVAL: u32 = 1;
+const DEFAULT_CHECKSUM: ChecksumType = ChecksumType::None;
+const DEFAULT_STATS_INTERVAL: u32 = 1000;
+const DEFAULT_METHOD: ScaleMethod = ScaleMethod::Bilinear;
+const DEFAULT_DENOISE: DenoiseMode = DenoiseMode::None;
+const DEFAULT_DENOISE_STRENGTH: f64 = 0.75;
+const DEFAULT_DENOISE_FRAMES: u32 = 4;
+const DEFAULT_DENOISE_MOTION_THRESHOLD: u32 = 12;
+const DEFAULT_BURN_IN_POSITION: Position = Position::TopLeft;
+const DEFAULT_BURN_IN_SCALE: u32 = 2;
+const DEFAULT_BURN_IN_COLOR: u32 = 0xffff_ffff;
+const DEFAULT_BURN_IN_BACKGROUND: u32 = 0x8000_0000;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    signal_handoffs: bool,
+    dither: Dither,
+    dump_location: Option<String>,
+    dump_interval: u32,
+    checksum: ChecksumType,
+    checksum_file: Option<String>,
+    checksum_reference: Option<String>,
+    stats_interval: u32,
+    burn_in: BurnIn,
+    burn_in_position: Position,
+    burn_in_scale: u32,
+    burn_in_color: u32,
+    burn_in_background: u32,
+    geometry: Geometry,
+    method: ScaleMethod,
+    denoise: DenoiseMode,
+    denoise_strength: f64,
+    denoise_frames: u32,
+    denoise_motion_threshold: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            signal_handoffs: DEFAULT_SIGNAL_HANDOFFS,
+            dither: DEFAULT_DITHER,
+            dump_location: None,
+            dump_interval: DEFAULT_DUMP_INTERVAL,
+            checksum: DEFAULT_CHECKSUM,
+            checksum_file: None,
+            checksum_reference: None,
+            stats_interval: DEFAULT_STATS_INTERVAL,
+            burn_in: BurnIn::empty(),
+            burn_in_position: DEFAULT_BURN_IN_POSITION,
+            burn_in_scale: DEFAULT_BURN_IN_SCALE,
+            burn_in_color: DEFAULT_BURN_IN_COLOR,
+            burn_in_background: DEFAULT_BURN_IN_BACKGROUND,
+            geometry: Geometry::default(),
+            method: DEFAULT_METHOD,
+            denoise: DEFAULT_DENOISE,
+            denoise_strength: DEFAULT_DENOISE_STRENGTH,
+            denoise_frames: DEFAULT_DENOISE_FRAMES,
+            denoise_motion_threshold: DEFAULT_DENOISE_MOTION_THRESHOLD,
+        }
+    }
+}
+
+impl Settings {
+    fn burn_in_style(&self) -> burnin::Style {
+        burnin::Style {
+            position: self.burn_in_position,
+            scale: self.burn_in_scale,
+            color: self.burn_in_color,
+            background: self.burn_in_background,
+        }
+    }
+
+    fn denoise_settings(&self) -> DenoiseSettings {
+        DenoiseSettings {
+            mode: self.denoise,
+            strength: self.denoise_strength,
+            frames: self.denoise_frames,
+            motion_threshold: self.denoise_motion_threshold,
+        }
+    }
+}
+
 struct State {
+    in_info: gst_video::VideoInfo,
+    out_info: gst_video::VideoInfo,
+    /// Input size in the output format, i.e. what the converter outputs.
+    mid_info: gst_video::VideoInfo,
+    /// `None` when input and output formats are the same.
+    converter: Option<Converter>,
+    geometry: Geometry,
+    /// Size after cropping, rotation and padding, in the output format.
+    geo_info: gst_video::VideoInfo,
+    /// `None` when `geo_info` already has the output size.
+    scaler: Option<Scaler>,
+    /// Whether cropping is done by adding a `GstVideoCropMeta` to the input
+    /// buffers. The inspected frames are then the uncropped ones.
+    use_crop_meta: bool,
+    /// History of the temporal denoiser.
+    denoiser: Denoiser,
+    /// Number of frames output so far, kept across caps changes.
+    frame_count: u64,
+}
+
+/// Files of the checksum mode, opened on the first checksummed frame.
+#[derive(Default)]
+struct Checksums {
+    file: Option<BufWriter<File>>,
+    /// Expected checksum by frame number.
+    reference: Option<BTreeMap<u64, String>>,
 }
 
 #[derive(Default)]
 pub struct VideoFilter {
+    settings: Mutex<Settings>,
     state: Mutex<Option<State>>,
+    dumper: Mutex<Option<Dumper>>,
+    checksums: Mutex<Checksums>,
+    measurements: Mutex<Measurements>,
+}
+
+impl VideoFilter {
+    /// Runs everything that looks at output frames without modifying them.
+    fn inspect(&self, element: &super::VideoFilter, buf: &BufferRef) -> Result<(), FlowError> {
+        let (info, frame_number) = {
+            let mut state_guard = self.state.lock();
+            let state = match state_guard.as_mut() {
+                Some(state) => state,
+                None => return Ok(()),
+            };
+            state.frame_count += 1;
+            (state.out_info.clone(), state.frame_count - 1)
+        };
+
+        self.handoff(element, buf, &info);
+        self.dump(element, buf, &info, frame_number);
+        self.checksum(element, buf, &info, frame_number)?;
+
+        Ok(())
+    }
+
+    /// Computes the checksum of `buf` if enabled, posts it, writes it to the
+    /// checksum file and compares it against the reference file.
+    fn checksum(
+        &self,
+        element: &super::VideoFilter,
+        buf: &BufferRef,
+        info: &gst_video::VideoInfo,
+        frame_number: u64,
+    ) -> Result<(), FlowError> {
+        let settings = self.settings.lock().clone();
+        if settings.checksum == ChecksumType::None {
+            return Ok(());
+        }
+
+        let frame = gst_video::VideoFrameRef::from_buffer_ref_readable(buf, info).map_err(|_| {
+            gst::element_error!(element, gst::CoreError::Failed, ["Failed to map frame {}", frame_number]);
+            FlowError::Error
+        })?;
+        let sum = match checksum::frame_checksum(settings.checksum, &frame) {
+            Some(sum) => sum,
+            None => return Ok(()),
+        };
+        drop(frame);
+
+        let pts = buf.pts();
+        gst_log!(CAT, obj: element, "Frame {} has checksum {}", frame_number, sum);
+
+        {
+            let mut checksums = self.checksums.lock();
+
+            if let Some(ref location) = settings.checksum_file {
+                if checksums.file.is_none() {
+                    let file = File::create(location).map_err(|err| {
+                        gst::element_error!(
+                            element,
+                            gst::ResourceError::OpenWrite,
+                            ["Failed to create checksum file {}: {}", location, err]
+                        );
+                        FlowError::Error
+                    })?;
+                    checksums.file = Some(BufWriter::new(file));
+                }
+
+                let pts_str = pts.map_or_else(|| String::from("none"), |pts| pts.to_string());
+                let file = checksums.file.as_mut().unwrap();
+                writeln!(file, "{} {} {}", frame_number, pts_str, sum).map_err(|err| {
+                    gst::element_error!(
+                        element,
+                        gst::ResourceError::Write,
+                        ["Failed to write checksum file {}: {}", location, err]
+                    );
+                    FlowError::Error
+                })?;
+            }
+
+            if let Some(ref location) = settings.checksum_reference {
+                if checksums.reference.is_none() {
+                    checksums.reference = Some(Self::load_checksum_reference(element, location)?);
+                }
+
+                let expected = checksums.reference.as_ref().unwrap().get(&frame_number);
+                if expected != Some(&sum) {
+                    gst::element_error!(
+                        element,
+                        gst::StreamError::Failed,
+                        [
+                            "Checksum mismatch at frame {}: got {}, expected {}",
+                            frame_number,
+                            sum,
+                            expected.map_or("nothing", String::as_str)
+                        ]
+                    );
+                    return Err(FlowError::Error);
+                }
+            }
+        }
+
+        let s = gst::Structure::builder("videofilter-checksum")
+            .field("frame", frame_number)
+            .field("pts", pts)
+            .field("type", settings.checksum)
+            .field("checksum", &sum)
+            .build();
+        let _ = element.post_message(gst::message::Element::builder(s).src(element).build());
+
+        Ok(())
+    }
+
+    /// Reads a file in the format written to `checksum-file`: one
+    /// `<frame> [<pts>] <checksum>` line per frame, `#` starting comments.
+    fn load_checksum_reference(
+        element: &super::VideoFilter,
+        location: &str,
+    ) -> Result<BTreeMap<u64, String>, FlowError> {
+        let file = File::open(location).map_err(|err| {
+            gst::element_error!(
+                element,
+                gst::ResourceError::OpenRead,
+                ["Failed to open checksum reference {}: {}", location, err]
+            );
+            FlowError::Error
+        })?;
+
+        let mut reference = BTreeMap::new();
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|err| {
+                gst::element_error!(
+                    element,
+                    gst::ResourceError::Read,
+                    ["Failed to read checksum reference {}: {}", location, err]
+                );
+                FlowError::Error
+            })?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut tokens = line.split_whitespace();
+            let frame = tokens.next().and_then(|t| t.parse::<u64>().ok());
+            let sum = tokens.last();
+            match (frame, sum) {
+                (Some(frame), Some(sum)) => {
+                    reference.insert(frame, sum.to_lowercase());
+                }
+                _ => {
+                    gst_warning!(CAT, obj: element, "Ignoring malformed reference line {:?}", line);
+                }
+            }
+        }
+
+        Ok(reference)
+    }
+
+    fn running_time(element: &super::VideoFilter, pts: Option<gst::ClockTime>) -> Option<gst::ClockTime> {
+        element
+            .segment()
+            .downcast_ref::<gst::ClockTime>()
+            .and_then(|segment| segment.to_running_time(pts))
+    }
+
+    /// Draws the `burn-in` text into `frame`, if enabled.
+    fn burn_in(
+        &self,
+        element: &super::VideoFilter,
+        frame: &mut gst_video::VideoFrameRef<&mut BufferRef>,
+        frame_number: u64,
+    ) {
+        let (flags, style) = {
+            let settings = self.settings.lock();
+            (settings.burn_in, settings.burn_in_style())
+        };
+        if flags.is_empty() {
+            return;
+        }
+
+        let pts = frame.buffer().pts();
+        let lines = burnin::lines(flags, pts, Self::running_time(element, pts), frame_number, frame.info());
+        if let Err(err) = burnin::draw(frame, &lines, &style) {
+            gst_warning!(CAT, obj: element, "Failed to burn in text: {}", err);
+        }
+    }
+
+    /// Frames are modified while text is burnt in or denoised, so passthrough
+    /// is only possible with the same caps on both sides and neither enabled.
+    fn update_passthrough(&self, element: &super::VideoFilter) {
+        let modifies = {
+            let settings = self.settings.lock();
+            !settings.burn_in.is_empty() || settings.denoise != DenoiseMode::None
+        };
+        let unchanged = self
+            .state
+            .lock()
+            .as_ref()
+            .map(|state| state.in_info == state.out_info && state.geometry.is_identity());
+
+        if let Some(unchanged) = unchanged {
+            element.set_passthrough(unchanged && !modifies);
+        }
+    }
+
+    /// Applies a change of the geometry settings, at once if the size before
+    /// scaling stays the same and after renegotiation otherwise.
+    fn update_geometry(&self, element: &super::VideoFilter) {
+        let geometry = self.settings.lock().geometry;
+
+        if let Some(state) = self.state.lock().as_mut() {
+            let size = geometry.output_size(state.in_info.width(), state.in_info.height());
+            if size == Some((state.geo_info.width(), state.geo_info.height())) {
+                state.geometry = geometry;
+                if state.use_crop_meta && !(geometry.has_crop() && geometry.is_crop_only()) {
+                    // Frames have to be processed again until the next
+                    // allocation query, which `reconfigure_src` triggers.
+                    state.use_crop_meta = false;
+                    element.set_in_place(false);
+                }
+            }
+        }
+
+        self.update_passthrough(element);
+        element.reconfigure_src();
+    }
+
+    /// Converts, orients and scales `inbuf` into `out_frame`, going through
+    /// intermediate buffers for all but the last of these stages.
+    fn process(
+        state: &State,
+        inbuf: &BufferRef,
+        out_frame: &mut gst_video::VideoFrameRef<&mut BufferRef>,
+        dither: Dither,
+    ) -> Result<(), String> {
+        let enabled = [
+            state.converter.is_some(),
+            !state.geometry.is_identity(),
+            state.scaler.is_some(),
+        ];
+        let infos = [&state.mid_info, &state.geo_info, &state.out_info];
+
+        let last = match enabled.iter().rposition(|&enabled| enabled) {
+            Some(last) => last,
+            None => {
+                let in_frame = gst_video::VideoFrameRef::from_buffer_ref_readable(inbuf, &state.in_info)
+                    .map_err(|err| err.to_string())?;
+                return in_frame.copy(out_frame).map_err(|err| err.to_string());
+            }
+        };
+
+        let mut src_buffer: Option<Buffer> = None;
+        let mut src_info = &state.in_info;
+        for stage in (0..=last).filter(|&stage| enabled[stage]) {
+            let src = gst_video::VideoFrameRef::from_buffer_ref_readable(
+                src_buffer.as_ref().map_or(inbuf, |buffer| buffer.as_ref()),
+                src_info,
+            )
+            .map_err(|err| err.to_string())?;
+
+            let run = |dst: &mut gst_video::VideoFrameRef<&mut BufferRef>| match stage {
+                0 => {
+                    state.converter.as_ref().unwrap().convert(&src, dst, dither);
+                    Ok(())
+                }
+                1 => state.geometry.apply(&src, dst),
+                _ => {
+                    state.scaler.as_ref().unwrap().scale(&src, dst);
+                    Ok(())
+                }
+            };
+
+            if stage == last {
+                return run(out_frame);
+            }
+
+            let mut buffer = Buffer::with_size(infos[stage].size()).map_err(|err| err.to_string())?;
+            {
+                let mut dst = gst_video::VideoFrameRef::from_buffer_ref_writable(buffer.get_mut().unwrap(), infos[stage])
+                    .map_err(|err| err.to_string())?;
+                run(&mut dst)?;
+            }
+
+            drop(src);
+            src_buffer = Some(buffer);
+            src_info = infos[stage];
+        }
+
+        Ok(())
+    }
+
+    /// Crops `buf` by adding a `GstVideoCropMeta`, or by narrowing the one
+    /// it already has.
+    fn add_crop_meta(buf: &mut BufferRef, state: &State) -> Result<(), glib::BoolError> {
+        let info = &state.in_info;
+
+        if buf.meta::<gst_video::VideoMeta>().is_none() {
+            gst_video::VideoMeta::add_full(
+                buf,
+                gst_video::VideoFrameFlags::empty(),
+                info.format(),
+                info.width(),
+                info.height(),
+                info.offset(),
+                info.stride(),
+            )?;
+        }
+
+        let (x, y, width, height) = state.geometry.crop_rect(info.width(), info.height());
+        if let Some(mut meta) = buf.meta_mut::<gst_video::VideoCropMeta>() {
+            let (meta_x, meta_y, _, _) = meta.rect();
+            meta.set_rect((meta_x + x, meta_y + y, width, height));
+        } else {
+            gst_video::VideoCropMeta::add(buf, (x, y, width, height));
+        }
+
+        Ok(())
+    }
+
+    /// Updates the frame rate, jitter and lateness measurements with the
+    /// input buffer `buf`, which just arrived, and posts them once per
+    /// `stats-interval`.
+    fn measure(&self, element: &super::VideoFilter, buf: &BufferRef) {
+        let arrival = Instant::now();
+        let now = element.current_running_time();
+        let interval = self.settings.lock().stats_interval;
+
+        let pts = buf.pts();
+        let running_time = Self::running_time(element, pts);
+
+        let snapshot = {
+            let mut measurements = self.measurements.lock();
+            if buf.flags().contains(gst::BufferFlags::DISCONT) {
+                measurements.discont();
+            }
+            measurements.record(arrival, pts, running_time, now);
+            measurements.publish(arrival, Duration::from_millis(interval.into()))
+        };
+
+        if let Some(snapshot) = snapshot {
+            gst_debug!(CAT, obj: element, "Statistics: {:?}", snapshot);
+
+            let s = snapshot.to_structure("videofilter-stats");
+            let _ = element.post_message(gst::message::Element::builder(s).src(element).build());
+        }
+    }
+
+    /// Emits `handoff` for `buf` if enabled and connected to.
+    fn handoff(&self, element: &super::VideoFilter, buf: &BufferRef, info: &gst_video::VideoInfo) {
+        if !self.settings.lock().signal_handoffs {
+            return;
+        }
+
+        // Only take a new reference to the buffer and pay for the emission
+        // when somebody is listening.
+        let signal_id = Self::signals()[0].signal_id();
+        if !glib::signal::signal_has_handler_pending(element, signal_id, None, false) {
+            return;
+        }
+
+        element.emit_by_name::<()>("handoff", &[&buf.to_owned(), info]);
+    }
+
+    /// Queues `buf` for writing if a dump location is set and the frame
+    /// falls on the dump interval.
+    fn dump(&self, element: &super::VideoFilter, buf: &BufferRef, info: &gst_video::VideoInfo, frame_number: u64) {
+        let (location, interval) = {
+            let settings = self.settings.lock();
+            match settings.dump_location {
+                Some(ref location) => (location.clone(), settings.dump_interval),
+                None => return,
+            }
+        };
+
+        if interval == 0 || !frame_number.is_multiple_of(u64::from(interval)) {
+            return;
+        }
+
+        let frame = match gst_video::VideoFrameRef::from_buffer_ref_readable(buf, info) {
+            Ok(frame) => frame,
+            Err(_) => {
+                gst_warning!(CAT, obj: element, "Failed to map frame {} for dumping", frame_number);
+                return;
+            }
+        };
+
+        let data = match dump::encode(&frame) {
+            Some(data) => data,
+            None => {
+                gst_warning!(CAT, obj: element, "Cannot dump frames in format {}", info.format());
+                return;
+            }
+        };
+
+        let path = PathBuf::from(dump::format_location(&location, frame_number));
+        gst_log!(CAT, obj: element, "Dumping frame {} to {}", frame_number, path.display());
+
+        self.dumper
+            .lock()
+            .get_or_insert_with(|| Dumper::new(element))
+            .write(path, data);
+    }
 }
 
 #[glib::object_subclass]
@@ -60,6 +589,7 @@ impl ObjectSubclass for VideoFilter {
     const NAME: &'static str = "VideoFilter";
     type Type = super::VideoFilter;
     type ParentType = gst_base::BaseTransform;
+    type Interfaces = (geometry::VideoDirection,);
 }
 
 impl ElementImpl for VideoFilter {
@@ -68,7 +598,7 @@ impl ElementImpl for VideoFilter {
             ElementMetadata::new(
                 "Video filter",
                 "Filter",
-                "View video data",
+                "View and convert video data",
                 "Seiichi Uchida <topecongiro@fastmail.com>",
             )
         });
@@ -119,20 +649,1144 @@ impl ElementImpl for VideoFilter {
 impl BaseTransformImpl for VideoFilter {
     const MODE: BaseTransformMode = BaseTransformMode::Both;
     const PASSTHROUGH_ON_SAME_CAPS: bool = true;
-    const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+    const TRANSFORM_IP_ON_PASSTHROUGH: bool = true;
 
     fn transform_caps(&self, element: &Self::Type, direction: PadDirection, caps: &Caps, filter: Option<&Caps>) -> Option<Caps> {
-        let other_caps = caps.clone();
-        gst_warning!(CAT, "transform_ip: {:?} {:?}", caps, element);
-        Some(other_caps)
+        if direction != PadDirection::Sink && direction != PadDirection::Src {
+            return None;
+        }
+
+        // Anything in the src template can be converted and scaled to
+        // anything else in it, so both directions are restricted to those
+        // formats. The unchanged caps come first to prefer passthrough.
+        let template_caps = element.static_pad("src")?.pad_template_caps();
+        let caps = caps.intersect_with_mode(&template_caps, gst::CapsIntersectMode::First);
+
+        let mut other_caps = caps.clone();
+        {
+            let other_caps = other_caps.get_mut()?;
+            for s in caps.iter() {
+                let mut s = s.to_owned();
+                s.remove_fields(&["format", "colorimetry", "chroma-site"]);
+                s.set("width", gst::IntRange::new(1, i32::MAX));
+                s.set("height", gst::IntRange::new(1, i32::MAX));
+                if s.has_field("pixel-aspect-ratio") {
+                    s.set(
+                        "pixel-aspect-ratio",
+                        gst::FractionRange::new(gst::Fraction::new(1, i32::MAX), gst::Fraction::new(i32::MAX, 1)),
+                    );
+                }
+                other_caps.append_structure(s);
+            }
+        }
+        let geometry = self.settings.lock().geometry;
+        let other_caps = geometry
+            .transform_caps(&other_caps, direction)
+            .intersect_with_mode(&template_caps, gst::CapsIntersectMode::First);
+
+        gst_debug!(
+            CAT,
+            obj: element,
+            "Transformed caps from {} to {} in direction {:?}",
+            caps,
+            other_caps,
+            direction
+        );
+
+        if let Some(filter) = filter {
+            Some(filter.intersect_with_mode(&other_caps, gst::CapsIntersectMode::First))
+        } else {
+            Some(other_caps)
+        }
+    }
+
+    fn fixate_caps(&self, element: &Self::Type, direction: PadDirection, caps: &Caps, othercaps: Caps) -> Caps {
+        // Prefer keeping the caps of the other side as they are, so that the
+        // element stays in passthrough whenever possible.
+        let same_caps = othercaps.intersect_with_mode(caps, gst::CapsIntersectMode::First);
+        if !same_caps.is_empty() {
+            return self.parent_fixate_caps(element, direction, caps, same_caps);
+        }
+
+        // Otherwise keep the display aspect ratio of the cropped, rotated and
+        // padded frames when the size is not fixed.
+        let mut othercaps = othercaps;
+        othercaps.truncate();
+        let geometry = self.settings.lock().geometry;
+        let from = geometry.transform_caps(caps, direction);
+        if let (Some(from), Some(to)) = (from.structure(0), othercaps.make_mut().structure_mut(0)) {
+            scale::fixate_size(from, to);
+        }
+
+        gst_debug!(CAT, obj: element, "Fixated {} to {}", caps, othercaps);
+
+        self.parent_fixate_caps(element, direction, caps, othercaps)
+    }
+
+    fn set_caps(&self, element: &Self::Type, incaps: &Caps, outcaps: &Caps) -> Result<(), LoggableError> {
+        let in_info = gst_video::VideoInfo::from_caps(incaps)
+            .map_err(|_| gst::loggable_error!(CAT, "Failed to parse input caps"))?;
+        let out_info = gst_video::VideoInfo::from_caps(outcaps)
+            .map_err(|_| gst::loggable_error!(CAT, "Failed to parse output caps"))?;
+
+        let (geometry, method) = {
+            let settings = self.settings.lock();
+            (settings.geometry, settings.method)
+        };
+        let (geo_width, geo_height) = geometry
+            .output_size(in_info.width(), in_info.height())
+            .ok_or_else(|| gst::loggable_error!(CAT, "Everything is cropped"))?;
+
+        let mid_info = gst_video::VideoInfo::builder(out_info.format(), in_info.width(), in_info.height())
+            .colorimetry(&out_info.colorimetry())
+            .chroma_site(out_info.chroma_site())
+            .interlace_mode(in_info.interlace_mode())
+            .par(in_info.par())
+            .fps(in_info.fps())
+            .build()
+            .map_err(|_| gst::loggable_error!(CAT, "Failed to build intermediate video info"))?;
+
+        let converter = if in_info.format() == mid_info.format()
+            && in_info.colorimetry() == mid_info.colorimetry()
+            && in_info.chroma_site() == mid_info.chroma_site()
+        {
+            None
+        } else {
+            Some(
+                Converter::new(&in_info, &mid_info)
+                    .map_err(|err| gst::loggable_error!(CAT, "Failed to create converter: {}", err))?,
+            )
+        };
+
+        let geo_info = gst_video::VideoInfo::builder(out_info.format(), geo_width, geo_height)
+            .colorimetry(&out_info.colorimetry())
+            .chroma_site(out_info.chroma_site())
+            .interlace_mode(in_info.interlace_mode())
+            .fps(in_info.fps())
+            .build()
+            .map_err(|_| gst::loggable_error!(CAT, "Failed to build intermediate video info"))?;
+
+        let scaler = if (geo_width, geo_height) == (out_info.width(), out_info.height()) {
+            None
+        } else {
+            Some(Scaler::new(&geo_info, &out_info, method))
+        };
+
+        gst_debug!(CAT, obj: element, "Configured for caps {} to {}", incaps, outcaps);
+
+        {
+            let mut state = self.state.lock();
+            let frame_count = state.as_ref().map_or(0, |state| state.frame_count);
+            *state = Some(State {
+                in_info,
+                out_info,
+                mid_info,
+                converter,
+                geometry,
+                geo_info,
+                scaler,
+                use_crop_meta: false,
+                denoiser: Denoiser::default(),
+                frame_count,
+            });
+        }
+        self.update_passthrough(element);
+
+        Ok(())
+    }
+
+    fn decide_allocation(
+        &self,
+        element: &Self::Type,
+        query: Allocation<&mut QueryRef>,
+    ) -> Result<(), LoggableError> {
+        let crop_meta_supported = query.find_allocation_meta::<gst_video::VideoCropMeta>().is_some()
+            && query.find_allocation_meta::<gst_video::VideoMeta>().is_some();
+        let modifies = {
+            let settings = self.settings.lock();
+            !settings.burn_in.is_empty() || settings.denoise != DenoiseMode::None
+        };
+
+        let use_crop_meta = match self.state.lock().as_mut() {
+            Some(state) => {
+                state.use_crop_meta = crop_meta_supported
+                    && !modifies
+                    && state.converter.is_none()
+                    && state.scaler.is_none()
+                    && state.geometry.has_crop()
+                    && state.geometry.is_crop_only();
+                state.use_crop_meta
+            }
+            None => false,
+        };
+
+        // Like videocrop, the input buffers are then pushed as they are,
+        // with the output caps. This is decided again on every negotiation.
+        if use_crop_meta {
+            gst_info!(CAT, obj: element, "Cropping with GstVideoCropMeta");
+            element.set_passthrough(false);
+        }
+        element.set_in_place(use_crop_meta);
+
+        self.parent_decide_allocation(element, query)
+    }
+
+    fn unit_size(&self, _element: &Self::Type, caps: &Caps) -> Option<usize> {
+        gst_video::VideoInfo::from_caps(caps)
+            .as_ref()
+            .map(gst_video::VideoInfo::size)
+            .ok()
+    }
+
+    fn stop(&self, element: &Self::Type) -> Result<(), ErrorMessage> {
+        let _ = self.state.lock().take();
+        // Waits for the pending writes.
+        let _ = self.dumper.lock().take();
+        if let Some(mut file) = self.checksums.lock().file.take() {
+            let _ = file.flush();
+        }
+        *self.checksums.lock() = Checksums::default();
+        *self.measurements.lock() = Measurements::default();
+
+        gst_info!(CAT, obj: element, "Stopped");
+
+        Ok(())
+    }
+
+    fn sink_event(&self, element: &Self::Type, event: Event) -> bool {
+        if let gst::EventView::FlushStop(_) = event.view() {
+            self.measurements.lock().discont();
+            if let Some(state) = self.state.lock().as_mut() {
+                state.denoiser.reset();
+            }
+        }
+
+        self.parent_sink_event(element, event)
     }
 
     fn transform_ip_passthrough(&self, element: &Self::Type, buf: &Buffer) -> Result<FlowSuccess, FlowError> {
-        gst_warning!(CAT, "transform_ip_passthrough: {:?} {:?}", element, buf);
+        gst_log!(CAT, obj: element, "transform_ip_passthrough: {:?}", buf);
+        self.measure(element, buf.as_ref());
+
+        self.inspect(element, buf.as_ref())?;
+
+        Ok(FlowSuccess::Ok)
+    }
+
+    /// Only called in place, which is only enabled for cropping with
+    /// `GstVideoCropMeta`: every other change goes through `transform`.
+    fn transform_ip(&self, element: &Self::Type, buf: &mut BufferRef) -> Result<FlowSuccess, FlowError> {
+        gst_log!(CAT, obj: element, "transform_ip: {:?}", buf);
+        self.measure(element, buf);
+
+        {
+            let state_guard = self.state.lock();
+            let state = state_guard.as_ref().ok_or_else(|| {
+                gst::element_error!(element, gst::CoreError::Negotiation, ["Have no state yet"]);
+                FlowError::NotNegotiated
+            })?;
+
+            Self::add_crop_meta(buf, state).map_err(|err| {
+                gst::element_error!(
+                    element,
+                    gst::CoreError::Failed,
+                    [&format!("Failed to add video meta: {}", err)]
+                );
+                FlowError::Error
+            })?;
+        }
+
+        self.inspect(element, buf)?;
+
+        Ok(FlowSuccess::Ok)
+    }
+
+    fn transform(&self, element: &Self::Type, inbuf: &Buffer, outbuf: &mut BufferRef) -> Result<FlowSuccess, FlowError> {
+        self.measure(element, inbuf.as_ref());
+
+        let (dither, denoise) = {
+            let settings = self.settings.lock();
+            (settings.dither, settings.denoise_settings())
+        };
+        let mut state_guard = self.state.lock();
+        let state = state_guard.as_mut().ok_or_else(|| {
+            gst::element_error!(element, gst::CoreError::Negotiation, ["Have no state yet"]);
+            FlowError::NotNegotiated
+        })?;
+
+        let mut out_frame = gst_video::VideoFrameRef::from_buffer_ref_writable(outbuf, &state.out_info)
+            .map_err(|err| {
+                gst::element_error!(
+                    element,
+                    gst::CoreError::Failed,
+                    [&format!("Failed to map output buffer writable: {}", err)]
+                );
+                FlowError::Error
+            })?;
+
+        Self::process(state, inbuf.as_ref(), &mut out_frame, dither).map_err(|err| {
+            gst::element_error!(element, gst::CoreError::Failed, [&format!("Failed to transform: {}", err)]);
+            FlowError::Error
+        })?;
+
+        if inbuf.flags().contains(gst::BufferFlags::DISCONT) {
+            state.denoiser.reset();
+        }
+        state.denoiser.denoise(&mut out_frame, &denoise);
+        self.burn_in(element, &mut out_frame, state.frame_count);
+
+        drop(out_frame);
+        drop(state_guard);
+
+        self.inspect(element, outbuf)?;
+
         Ok(FlowSuccess::Ok)
     }
 }
 
 impl GstObjectImpl for VideoFilter {}
 
-impl ObjectImpl for VideoFilter {}
\ No newline at end of file
+impl ObjectImpl for VideoFilter {
+    fn signals() -> &'static [glib::subclass::Signal] {
+        static SIGNALS: Lazy<Vec<glib::subclass::Signal>> = Lazy::new(|| {
+            vec![glib::subclass::Signal::builder(
+                "handoff",
+                &[
+                    Buffer::static_type().into(),
+                    gst_video::VideoInfo::static_type().into(),
+                ],
+                glib::Type::UNIT.into(),
+            )
+            .build()]
+        });
+
+        SIGNALS.as_ref()
+    }
+
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecBoolean::new(
+                    "signal-handoffs",
+                    "Signal handoffs",
+                    "Send a handoff signal with every frame",
+                    DEFAULT_SIGNAL_HANDOFFS,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecEnum::new(
+                    "dither",
+                    "Dither",
+                    "Dithering used when reducing the bit depth",
+                    Dither::static_type(),
+                    DEFAULT_DITHER as i32,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecString::new(
+                    "dump-location",
+                    "Dump location",
+                    "Pattern of the files frames are dumped to, e.g. frame-%05d.pgm (unset = no dumping)",
+                    None,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt::new(
+                    "dump-interval",
+                    "Dump interval",
+                    "Dump every n-th frame (0 = never)",
+                    0,
+                    u32::MAX,
+                    DEFAULT_DUMP_INTERVAL,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecEnum::new(
+                    "checksum",
+                    "Checksum",
+                    "Checksum computed over the visible pixels of every frame",
+                    ChecksumType::static_type(),
+                    DEFAULT_CHECKSUM as i32,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpecString::new(
+                    "checksum-file",
+                    "Checksum file",
+                    "File the per-frame checksums are written to",
+                    None,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpecString::new(
+                    "checksum-reference",
+                    "Checksum reference",
+                    "File of expected checksums; a mismatch is an error",
+                    None,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpecUInt::new(
+                    "stats-interval",
+                    "Statistics interval",
+                    "Interval in milliseconds at which statistics are measured and posted (0 = never)",
+                    0,
+                    u32::MAX,
+                    DEFAULT_STATS_INTERVAL,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecFlags::new(
+                    "burn-in",
+                    "Burn in",
+                    "Information drawn into the frames",
+                    BurnIn::static_type(),
+                    BurnIn::empty().bits(),
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecEnum::new(
+                    "burn-in-position",
+                    "Burn-in position",
+                    "Corner the burnt in text is drawn in",
+                    Position::static_type(),
+                    DEFAULT_BURN_IN_POSITION as i32,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt::new(
+                    "burn-in-scale",
+                    "Burn-in scale",
+                    "Size in pixels of one pixel of the burnt in font",
+                    1,
+                    64,
+                    DEFAULT_BURN_IN_SCALE,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt::new(
+                    "burn-in-color",
+                    "Burn-in color",
+                    "Color of the burnt in text, in big-endian ARGB",
+                    0,
+                    u32::MAX,
+                    DEFAULT_BURN_IN_COLOR,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt::new(
+                    "burn-in-background",
+                    "Burn-in background",
+                    "Color of the box behind the burnt in text, in big-endian ARGB",
+                    0,
+                    u32::MAX,
+                    DEFAULT_BURN_IN_BACKGROUND,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt::new(
+                    "crop-left",
+                    "Crop left",
+                    "Pixels to crop at the left",
+                    0,
+                    i32::MAX as u32,
+                    0,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt::new(
+                    "crop-right",
+                    "Crop right",
+                    "Pixels to crop at the right",
+                    0,
+                    i32::MAX as u32,
+                    0,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt::new(
+                    "crop-top",
+                    "Crop top",
+                    "Pixels to crop at the top",
+                    0,
+                    i32::MAX as u32,
+                    0,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt::new(
+                    "crop-bottom",
+                    "Crop bottom",
+                    "Pixels to crop at the bottom",
+                    0,
+                    i32::MAX as u32,
+                    0,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecEnum::new(
+                    "flip",
+                    "Flip",
+                    "Flip applied after cropping",
+                    Flip::static_type(),
+                    Flip::None as i32,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecEnum::new(
+                    "rotate",
+                    "Rotate",
+                    "Clockwise rotation applied after flipping",
+                    Rotate::static_type(),
+                    Rotate::None as i32,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecOverride::for_interface::<geometry::VideoDirection>("video-direction"),
+                glib::ParamSpecEnum::new(
+                    "method",
+                    "Method",
+                    "Method used when scaling",
+                    ScaleMethod::static_type(),
+                    DEFAULT_METHOD as i32,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecEnum::new(
+                    "denoise",
+                    "Denoise",
+                    "Temporal denoising",
+                    DenoiseMode::static_type(),
+                    DEFAULT_DENOISE as i32,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecDouble::new(
+                    "denoise-strength",
+                    "Denoise strength",
+                    "Weight of the history in the moving average",
+                    0.0,
+                    1.0,
+                    DEFAULT_DENOISE_STRENGTH,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt::new(
+                    "denoise-frames",
+                    "Denoise frames",
+                    "Number of frames averaged in window mode, the current one included",
+                    1,
+                    64,
+                    DEFAULT_DENOISE_FRAMES,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt::new(
+                    "denoise-motion-threshold",
+                    "Denoise motion threshold",
+                    "Difference, in 8-bit code values, above which the history is faded out to avoid ghosting (0 = disabled)",
+                    0,
+                    255,
+                    DEFAULT_DENOISE_MOTION_THRESHOLD,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt::new(
+                    "pad-left",
+                    "Pad left",
+                    "Pixels to pad at the left after rotating",
+                    0,
+                    i32::MAX as u32,
+                    0,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt::new(
+                    "pad-right",
+                    "Pad right",
+                    "Pixels to pad at the right after rotating",
+                    0,
+                    i32::MAX as u32,
+                    0,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt::new(
+                    "pad-top",
+                    "Pad top",
+                    "Pixels to pad at the top after rotating",
+                    0,
+                    i32::MAX as u32,
+                    0,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt::new(
+                    "pad-bottom",
+                    "Pad bottom",
+                    "Pixels to pad at the bottom after rotating",
+                    0,
+                    i32::MAX as u32,
+                    0,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt::new(
+                    "pad-color",
+                    "Pad color",
+                    "Color of the padding, in big-endian ARGB",
+                    0,
+                    u32::MAX,
+                    Geometry::default().pad_color,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecDouble::new(
+                    "measured-fps",
+                    "Measured FPS",
+                    "Frame rate measured on arrival over the last interval",
+                    0.0,
+                    f64::MAX,
+                    0.0,
+                    glib::ParamFlags::READABLE,
+                ),
+                glib::ParamSpecUInt64::new(
+                    "pts-jitter",
+                    "PTS jitter",
+                    "Standard deviation of the PTS deltas over the last interval, in nanoseconds",
+                    0,
+                    u64::MAX,
+                    0,
+                    glib::ParamFlags::READABLE,
+                ),
+                glib::ParamSpecInt64::new(
+                    "lateness",
+                    "Lateness",
+                    "Mean difference between arrival and buffer running time over the last interval, in nanoseconds",
+                    i64::MIN,
+                    i64::MAX,
+                    0,
+                    glib::ParamFlags::READABLE,
+                ),
+                glib::ParamSpecInt64::new(
+                    "max-lateness",
+                    "Maximum lateness",
+                    "Largest difference between arrival and buffer running time over the last interval, in nanoseconds",
+                    i64::MIN,
+                    i64::MAX,
+                    0,
+                    glib::ParamFlags::READABLE,
+                ),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(&self, obj: &Self::Type, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        match pspec.name() {
+            "signal-handoffs" => {
+                let mut settings = self.settings.lock();
+                let signal_handoffs = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing signal-handoffs from {} to {}",
+                    settings.signal_handoffs, signal_handoffs
+                );
+                settings.signal_handoffs = signal_handoffs;
+            }
+            "dither" => {
+                let mut settings = self.settings.lock();
+                let dither = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing dither from {:?} to {:?}",
+                    settings.dither, dither
+                );
+                settings.dither = dither;
+            }
+            "dump-location" => {
+                let mut settings = self.settings.lock();
+                let dump_location = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing dump-location from {:?} to {:?}",
+                    settings.dump_location, dump_location
+                );
+                settings.dump_location = dump_location;
+            }
+            "dump-interval" => {
+                let mut settings = self.settings.lock();
+                let dump_interval = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing dump-interval from {} to {}",
+                    settings.dump_interval, dump_interval
+                );
+                settings.dump_interval = dump_interval;
+            }
+            "checksum" => {
+                let mut settings = self.settings.lock();
+                let checksum = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing checksum from {:?} to {:?}",
+                    settings.checksum, checksum
+                );
+                settings.checksum = checksum;
+            }
+            "checksum-file" => {
+                let mut settings = self.settings.lock();
+                let checksum_file = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing checksum-file from {:?} to {:?}",
+                    settings.checksum_file, checksum_file
+                );
+                settings.checksum_file = checksum_file;
+            }
+            "checksum-reference" => {
+                let mut settings = self.settings.lock();
+                let checksum_reference = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing checksum-reference from {:?} to {:?}",
+                    settings.checksum_reference, checksum_reference
+                );
+                settings.checksum_reference = checksum_reference;
+            }
+            "stats-interval" => {
+                let mut settings = self.settings.lock();
+                let stats_interval = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing stats-interval from {} to {}",
+                    settings.stats_interval, stats_interval
+                );
+                settings.stats_interval = stats_interval;
+            }
+            "crop-left" => {
+                let mut settings = self.settings.lock();
+                let crop_left = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing crop-left from {} to {}",
+                    settings.geometry.crop_left, crop_left
+                );
+                settings.geometry.crop_left = crop_left;
+                drop(settings);
+
+                self.update_geometry(obj);
+            }
+            "crop-right" => {
+                let mut settings = self.settings.lock();
+                let crop_right = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing crop-right from {} to {}",
+                    settings.geometry.crop_right, crop_right
+                );
+                settings.geometry.crop_right = crop_right;
+                drop(settings);
+
+                self.update_geometry(obj);
+            }
+            "crop-top" => {
+                let mut settings = self.settings.lock();
+                let crop_top = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing crop-top from {} to {}",
+                    settings.geometry.crop_top, crop_top
+                );
+                settings.geometry.crop_top = crop_top;
+                drop(settings);
+
+                self.update_geometry(obj);
+            }
+            "crop-bottom" => {
+                let mut settings = self.settings.lock();
+                let crop_bottom = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing crop-bottom from {} to {}",
+                    settings.geometry.crop_bottom, crop_bottom
+                );
+                settings.geometry.crop_bottom = crop_bottom;
+                drop(settings);
+
+                self.update_geometry(obj);
+            }
+            "pad-left" => {
+                let mut settings = self.settings.lock();
+                let pad_left = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing pad-left from {} to {}",
+                    settings.geometry.pad_left, pad_left
+                );
+                settings.geometry.pad_left = pad_left;
+                drop(settings);
+
+                self.update_geometry(obj);
+            }
+            "pad-right" => {
+                let mut settings = self.settings.lock();
+                let pad_right = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing pad-right from {} to {}",
+                    settings.geometry.pad_right, pad_right
+                );
+                settings.geometry.pad_right = pad_right;
+                drop(settings);
+
+                self.update_geometry(obj);
+            }
+            "pad-top" => {
+                let mut settings = self.settings.lock();
+                let pad_top = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing pad-top from {} to {}",
+                    settings.geometry.pad_top, pad_top
+                );
+                settings.geometry.pad_top = pad_top;
+                drop(settings);
+
+                self.update_geometry(obj);
+            }
+            "pad-bottom" => {
+                let mut settings = self.settings.lock();
+                let pad_bottom = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing pad-bottom from {} to {}",
+                    settings.geometry.pad_bottom, pad_bottom
+                );
+                settings.geometry.pad_bottom = pad_bottom;
+                drop(settings);
+
+                self.update_geometry(obj);
+            }
+            "pad-color" => {
+                let mut settings = self.settings.lock();
+                let pad_color = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing pad-color from {:08x} to {:08x}",
+                    settings.geometry.pad_color, pad_color
+                );
+                settings.geometry.pad_color = pad_color;
+                drop(settings);
+
+                self.update_geometry(obj);
+            }
+            "flip" => {
+                let mut settings = self.settings.lock();
+                let flip = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing flip from {:?} to {:?}",
+                    settings.geometry.flip, flip
+                );
+                settings.geometry.flip = flip;
+                drop(settings);
+
+                self.update_geometry(obj);
+            }
+            "rotate" => {
+                let mut settings = self.settings.lock();
+                let rotate = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing rotate from {:?} to {:?}",
+                    settings.geometry.rotate, rotate
+                );
+                settings.geometry.rotate = rotate;
+                drop(settings);
+
+                self.update_geometry(obj);
+            }
+            "method" => {
+                let mut settings = self.settings.lock();
+                let method = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing method from {:?} to {:?}",
+                    settings.method, method
+                );
+                settings.method = method;
+                drop(settings);
+
+                if let Some(state) = self.state.lock().as_mut() {
+                    if state.scaler.is_some() {
+                        state.scaler = Some(Scaler::new(&state.geo_info, &state.out_info, method));
+                    }
+                }
+            }
+            "denoise" => {
+                let mut settings = self.settings.lock();
+                let denoise = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing denoise from {:?} to {:?}",
+                    settings.denoise, denoise
+                );
+                settings.denoise = denoise;
+                drop(settings);
+
+                self.update_passthrough(obj);
+            }
+            "denoise-strength" => {
+                let mut settings = self.settings.lock();
+                let denoise_strength = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing denoise-strength from {} to {}",
+                    settings.denoise_strength, denoise_strength
+                );
+                settings.denoise_strength = denoise_strength;
+            }
+            "denoise-frames" => {
+                let mut settings = self.settings.lock();
+                let denoise_frames = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing denoise-frames from {} to {}",
+                    settings.denoise_frames, denoise_frames
+                );
+                settings.denoise_frames = denoise_frames;
+            }
+            "denoise-motion-threshold" => {
+                let mut settings = self.settings.lock();
+                let denoise_motion_threshold = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing denoise-motion-threshold from {} to {}",
+                    settings.denoise_motion_threshold, denoise_motion_threshold
+                );
+                settings.denoise_motion_threshold = denoise_motion_threshold;
+            }
+            "video-direction" => {
+                let mut settings = self.settings.lock();
+                let method = value.get().unwrap();
+                let (flip, rotate) = match geometry::from_orientation(method) {
+                    Some(orientation) => orientation,
+                    None => {
+                        gst_warning!(CAT, obj: obj, "Unsupported video-direction {:?}", method);
+                        return;
+                    }
+                };
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing video-direction from {:?} to {:?}",
+                    geometry::to_orientation(settings.geometry.flip, settings.geometry.rotate),
+                    method
+                );
+                settings.geometry.flip = flip;
+                settings.geometry.rotate = rotate;
+                drop(settings);
+
+                self.update_geometry(obj);
+            }
+            "burn-in" => {
+                let mut settings = self.settings.lock();
+                let burn_in = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing burn-in from {:?} to {:?}",
+                    settings.burn_in, burn_in
+                );
+                settings.burn_in = burn_in;
+                drop(settings);
+
+                self.update_passthrough(obj);
+            }
+            "burn-in-position" => {
+                let mut settings = self.settings.lock();
+                let burn_in_position = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing burn-in-position from {:?} to {:?}",
+                    settings.burn_in_position, burn_in_position
+                );
+                settings.burn_in_position = burn_in_position;
+            }
+            "burn-in-scale" => {
+                let mut settings = self.settings.lock();
+                let burn_in_scale = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing burn-in-scale from {} to {}",
+                    settings.burn_in_scale, burn_in_scale
+                );
+                settings.burn_in_scale = burn_in_scale;
+            }
+            "burn-in-color" => {
+                let mut settings = self.settings.lock();
+                let burn_in_color = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing burn-in-color from {:08x} to {:08x}",
+                    settings.burn_in_color, burn_in_color
+                );
+                settings.burn_in_color = burn_in_color;
+            }
+            "burn-in-background" => {
+                let mut settings = self.settings.lock();
+                let burn_in_background = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing burn-in-background from {:08x} to {:08x}",
+                    settings.burn_in_background, burn_in_background
+                );
+                settings.burn_in_background = burn_in_background;
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn property(&self, _obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        match pspec.name() {
+            "signal-handoffs" => {
+                let settings = self.settings.lock();
+                settings.signal_handoffs.to_value()
+            }
+            "dither" => {
+                let settings = self.settings.lock();
+                settings.dither.to_value()
+            }
+            "dump-location" => {
+                let settings = self.settings.lock();
+                settings.dump_location.to_value()
+            }
+            "dump-interval" => {
+                let settings = self.settings.lock();
+                settings.dump_interval.to_value()
+            }
+            "checksum" => {
+                let settings = self.settings.lock();
+                settings.checksum.to_value()
+            }
+            "checksum-file" => {
+                let settings = self.settings.lock();
+                settings.checksum_file.to_value()
+            }
+            "checksum-reference" => {
+                let settings = self.settings.lock();
+                settings.checksum_reference.to_value()
+            }
+            "stats-interval" => {
+                let settings = self.settings.lock();
+                settings.stats_interval.to_value()
+            }
+            "crop-left" => {
+                let settings = self.settings.lock();
+                settings.geometry.crop_left.to_value()
+            }
+            "crop-right" => {
+                let settings = self.settings.lock();
+                settings.geometry.crop_right.to_value()
+            }
+            "crop-top" => {
+                let settings = self.settings.lock();
+                settings.geometry.crop_top.to_value()
+            }
+            "crop-bottom" => {
+                let settings = self.settings.lock();
+                settings.geometry.crop_bottom.to_value()
+            }
+            "pad-left" => {
+                let settings = self.settings.lock();
+                settings.geometry.pad_left.to_value()
+            }
+            "pad-right" => {
+                let settings = self.settings.lock();
+                settings.geometry.pad_right.to_value()
+            }
+            "pad-top" => {
+                let settings = self.settings.lock();
+                settings.geometry.pad_top.to_value()
+            }
+            "pad-bottom" => {
+                let settings = self.settings.lock();
+                settings.geometry.pad_bottom.to_value()
+            }
+            "pad-color" => {
+                let settings = self.settings.lock();
+                settings.geometry.pad_color.to_value()
+            }
+            "flip" => {
+                let settings = self.settings.lock();
+                settings.geometry.flip.to_value()
+            }
+            "rotate" => {
+                let settings = self.settings.lock();
+                settings.geometry.rotate.to_value()
+            }
+            "method" => {
+                let settings = self.settings.lock();
+                settings.method.to_value()
+            }
+            "denoise" => {
+                let settings = self.settings.lock();
+                settings.denoise.to_value()
+            }
+            "denoise-strength" => {
+                let settings = self.settings.lock();
+                settings.denoise_strength.to_value()
+            }
+            "denoise-frames" => {
+                let settings = self.settings.lock();
+                settings.denoise_frames.to_value()
+            }
+            "denoise-motion-threshold" => {
+                let settings = self.settings.lock();
+                settings.denoise_motion_threshold.to_value()
+            }
+            "video-direction" => {
+                let settings = self.settings.lock();
+                geometry::to_orientation(settings.geometry.flip, settings.geometry.rotate).to_value()
+            }
+            "burn-in" => {
+                let settings = self.settings.lock();
+                settings.burn_in.to_value()
+            }
+            "burn-in-position" => {
+                let settings = self.settings.lock();
+                settings.burn_in_position.to_value()
+            }
+            "burn-in-scale" => {
+                let settings = self.settings.lock();
+                settings.burn_in_scale.to_value()
+            }
+            "burn-in-color" => {
+                let settings = self.settings.lock();
+                settings.burn_in_color.to_value()
+            }
+            "burn-in-background" => {
+                let settings = self.settings.lock();
+                settings.burn_in_background.to_value()
+            }
+            "measured-fps" => {
+                let measurements = self.measurements.lock();
+                measurements.last().fps.to_value()
+            }
+            "pts-jitter" => {
+                let measurements = self.measurements.lock();
+                (measurements.last().jitter as u64).to_value()
+            }
+            "lateness" => {
+                let measurements = self.measurements.lock();
+                (measurements.last().lateness as i64).to_value()
+            }
+            "max-lateness" => {
+                let measurements = self.measurements.lock();
+                measurements.last().max_lateness.to_value()
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
\ No newline at end of file
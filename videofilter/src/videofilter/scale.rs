@@ -0,0 +1,444 @@
+//! Resampling to another resolution.
+//!
+//! Every component is scaled on its own, horizontally into a floating point
+//! buffer and then vertically. The rows of each output plane are split into
+//! one band per thread for large frames, each band scaling only the source
+//! rows it needs and writing its own rows of the output. When
+//! downscaling, the kernels are widened by the scale factor so that they
+//! also low-pass filter.
+
+use std::ops::Range;
+use std::thread;
+
+use gst::glib;
+use gst::BufferRef;
+use gst_video::{VideoFormatFlags, VideoFrameRef, VideoInfo};
+
+use super::convert;
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstVideoFilterScaleMethod")]
+pub enum ScaleMethod {
+    #[enum_value(name = "Nearest neighbour", nick = "nearest")]
+    Nearest = 0,
+    #[enum_value(name = "Bilinear", nick = "bilinear")]
+    Bilinear = 1,
+    #[enum_value(name = "Bicubic (Catmull-Rom)", nick = "bicubic")]
+    Bicubic = 2,
+    #[enum_value(name = "Lanczos, 3 lobes", nick = "lanczos")]
+    Lanczos = 3,
+}
+
+impl ScaleMethod {
+    /// Radius of the kernel, in source samples.
+    fn support(self) -> f32 {
+        match self {
+            ScaleMethod::Nearest => 0.5,
+            ScaleMethod::Bilinear => 1.0,
+            ScaleMethod::Bicubic => 2.0,
+            ScaleMethod::Lanczos => 3.0,
+        }
+    }
+
+    fn kernel(self, x: f32) -> f32 {
+        let x = x.abs();
+
+        match self {
+            ScaleMethod::Nearest => {
+                if x < 0.5 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            ScaleMethod::Bilinear => (1.0 - x).max(0.0),
+            ScaleMethod::Bicubic => {
+                const A: f32 = -0.5;
+                if x < 1.0 {
+                    ((A + 2.0) * x - (A + 3.0)) * x * x + 1.0
+                } else if x < 2.0 {
+                    ((A * x - 5.0 * A) * x + 8.0 * A) * x - 4.0 * A
+                } else {
+                    0.0
+                }
+            }
+            ScaleMethod::Lanczos => {
+                if x == 0.0 {
+                    1.0
+                } else if x < 3.0 {
+                    let px = std::f32::consts::PI * x;
+                    3.0 * px.sin() * (px / 3.0).sin() / (px * px)
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+/// For each destination sample, the source samples and weights it is made of.
+type Taps = Vec<Vec<(usize, f32)>>;
+
+fn taps(method: ScaleMethod, src_len: usize, dst_len: usize) -> Taps {
+    let scale = src_len as f32 / dst_len as f32;
+
+    if method == ScaleMethod::Nearest {
+        return (0..dst_len)
+            .map(|x| vec![((((x as f32 + 0.5) * scale) as usize).min(src_len - 1), 1.0)])
+            .collect();
+    }
+
+    let stretch = scale.max(1.0);
+    let radius = method.support() * stretch;
+
+    (0..dst_len)
+        .map(|x| {
+            let center = (x as f32 + 0.5) * scale - 0.5;
+            let first = (center - radius).ceil() as i64;
+            let last = (center + radius).floor() as i64;
+
+            // Samples outside of the source repeat the edge.
+            let mut taps: Vec<(usize, f32)> = Vec::new();
+            for i in first..=last {
+                let weight = method.kernel((i as f32 - center) / stretch);
+                if weight == 0.0 {
+                    continue;
+                }
+
+                let i = i.clamp(0, src_len as i64 - 1) as usize;
+                match taps.iter_mut().find(|tap| tap.0 == i) {
+                    Some(tap) => tap.1 += weight,
+                    None => taps.push((i, weight)),
+                }
+            }
+
+            let sum: f32 = taps.iter().map(|tap| tap.1).sum();
+            if sum != 0.0 {
+                taps.iter_mut().for_each(|tap| tap.1 /= sum);
+            } else {
+                taps = vec![((center.round().max(0.0) as usize).min(src_len - 1), 1.0)];
+            }
+
+            taps
+        })
+        .collect()
+}
+
+/// One component of the format, with the taps of both passes.
+struct Component {
+    horizontal: Taps,
+    vertical: Taps,
+    plane: u32,
+    poffset: usize,
+    pstride: usize,
+    shift: u32,
+    max: f32,
+    little_endian: bool,
+}
+
+impl Component {
+    /// Scales the output rows `rows` into `dst`, which starts with the first
+    /// of them and whose lines are `dst_stride` bytes apart, from the source
+    /// samples `src` whose lines are `stride` bytes apart. Only the source
+    /// rows the band is made of are scaled horizontally.
+    fn band(&self, src: &[u8], stride: usize, rows: Range<usize>, dst: &mut [u8], dst_stride: usize) {
+        if rows.is_empty() {
+            return;
+        }
+        let out_width = self.horizontal.len();
+
+        let sources = || self.vertical[rows.clone()].iter().flatten().map(|&(sy, _)| sy);
+        let first = sources().min().unwrap_or(0);
+        let last = sources().max().map_or(first, |sy| sy + 1);
+
+        let mut tmp = vec![0.0f32; out_width * (last - first)];
+        for (y, row) in (first..last).zip(tmp.chunks_exact_mut(out_width)) {
+            let line = &src[y * stride + self.poffset..];
+            for (out, taps) in row.iter_mut().zip(self.horizontal.iter()) {
+                *out = taps
+                    .iter()
+                    .map(|&(x, weight)| {
+                        let code = convert::read_sample(line, x, self.pstride, self.little_endian);
+                        f32::from(code >> self.shift) * weight
+                    })
+                    .sum();
+            }
+        }
+
+        for (i, y) in rows.enumerate() {
+            let taps = &self.vertical[y];
+            let line = &mut dst[i * dst_stride + self.poffset..];
+            for x in 0..out_width {
+                let v: f32 = taps.iter().map(|&(sy, weight)| tmp[(sy - first) * out_width + x] * weight).sum();
+                let code = (v.round().clamp(0.0, self.max) as u16) << self.shift;
+                convert::write_sample(line, x, self.pstride, self.little_endian, code);
+            }
+        }
+    }
+}
+
+/// Output samples below which a band is not worth a thread of its own.
+const MIN_BAND_SAMPLES: usize = 64 * 1024;
+
+pub struct Scaler {
+    components: Vec<Component>,
+    /// Number of bands, and of threads, each plane is split into.
+    threads: usize,
+}
+
+impl Scaler {
+    /// Scales frames of `in_info` to the size of `out_info`, both being of the
+    /// same format.
+    pub fn new(in_info: &VideoInfo, out_info: &VideoInfo, method: ScaleMethod) -> Self {
+        let finfo = out_info.format_info();
+        let little_endian = finfo.flags().contains(VideoFormatFlags::LE);
+
+        let components: Vec<Component> = (0..finfo.n_components() as usize)
+            .map(|c| {
+                let in_width = finfo.scale_width(c as u8, in_info.width()) as usize;
+                let in_height = finfo.scale_height(c as u8, in_info.height()) as usize;
+                let out_width = finfo.scale_width(c as u8, out_info.width()) as usize;
+                let out_height = finfo.scale_height(c as u8, out_info.height()) as usize;
+
+                Component {
+                    horizontal: taps(method, in_width, out_width),
+                    vertical: taps(method, in_height, out_height),
+                    plane: finfo.plane()[c],
+                    poffset: finfo.poffset()[c] as usize,
+                    pstride: finfo.pixel_stride()[c] as usize,
+                    shift: finfo.shift()[c],
+                    max: ((1u32 << finfo.depth()[c]) - 1) as f32,
+                    little_endian,
+                }
+            })
+            .collect();
+
+        let samples = out_info.width() as usize * out_info.height() as usize;
+        let threads = thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .min(samples / MIN_BAND_SAMPLES)
+            .max(1);
+
+        Self { components, threads }
+    }
+
+    pub fn scale(&self, in_frame: &VideoFrameRef<&BufferRef>, out_frame: &mut VideoFrameRef<&mut BufferRef>) {
+        for plane in 0..out_frame.n_planes() {
+            let components: Vec<&Component> = self.components.iter().filter(|c| c.plane == plane).collect();
+            let height = match components.first() {
+                Some(component) => component.vertical.len(),
+                None => continue,
+            };
+
+            let src = in_frame.plane_data(plane).unwrap();
+            let stride = in_frame.plane_stride()[plane as usize] as usize;
+            let dst_stride = out_frame.plane_stride()[plane as usize] as usize;
+            let dst = out_frame.plane_data_mut(plane).unwrap();
+
+            let band = |rows: Range<usize>, dst: &mut [u8]| {
+                for component in &components {
+                    component.band(src, stride, rows.clone(), dst, dst_stride);
+                }
+            };
+
+            if self.threads == 1 {
+                band(0..height, dst);
+                continue;
+            }
+
+            // Every band writes its own rows of the plane. The first band is
+            // made on this thread.
+            let band_height = height.div_ceil(self.threads);
+            let mut bands = dst
+                .chunks_mut(band_height * dst_stride)
+                .enumerate()
+                .map(|(i, dst)| (i * band_height..((i + 1) * band_height).min(height), dst));
+            let band = &band;
+            thread::scope(|scope| {
+                let first = bands.next();
+                for (rows, dst) in bands {
+                    scope.spawn(move || band(rows, dst));
+                }
+                if let Some((rows, dst)) = first {
+                    band(rows, dst);
+                }
+            });
+        }
+    }
+}
+
+/// Fixates the size and pixel-aspect-ratio of `to` so that it shows the
+/// fixed `from` with the same display aspect ratio, keeping whatever is
+/// already fixed in `to`.
+pub fn fixate_size(from: &gst::StructureRef, to: &mut gst::StructureRef) {
+    let (from_width, from_height) = match (from.get::<i32>("width"), from.get::<i32>("height")) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => (width, height),
+        _ => return,
+    };
+    let from_par = from
+        .get::<gst::Fraction>("pixel-aspect-ratio")
+        .unwrap_or_else(|_| gst::Fraction::new(1, 1));
+    let dar = f64::from(from_width) * f64::from(from_par.numer())
+        / (f64::from(from_height) * f64::from(from_par.denom()));
+
+    let to_width = to.get::<i32>("width").ok();
+    let to_height = to.get::<i32>("height").ok();
+
+    if let (Some(width), Some(height)) = (to_width, to_height) {
+        // Only the pixel-aspect-ratio can still preserve the display aspect
+        // ratio.
+        if to.has_field("pixel-aspect-ratio") && to.get::<gst::Fraction>("pixel-aspect-ratio").is_err() {
+            let par = dar * f64::from(height) / f64::from(width);
+            to.fixate_field_nearest_fraction(
+                "pixel-aspect-ratio",
+                gst::Fraction::new((par * 10000.0).round() as i32, 10000),
+            );
+        }
+        return;
+    }
+
+    if to.has_field("pixel-aspect-ratio") {
+        to.fixate_field_nearest_fraction("pixel-aspect-ratio", from_par);
+    }
+    let to_par = to
+        .get::<gst::Fraction>("pixel-aspect-ratio")
+        .unwrap_or_else(|_| gst::Fraction::new(1, 1));
+    let par = f64::from(to_par.numer()) / f64::from(to_par.denom());
+
+    match (to_width, to_height) {
+        (Some(width), None) => {
+            let height = (f64::from(width) * par / dar).round().max(1.0);
+            to.fixate_field_nearest_int("height", height.min(f64::from(i32::MAX)) as i32);
+        }
+        (None, height) => {
+            if height.is_none() {
+                to.fixate_field_nearest_int("height", from_height);
+            }
+            if let Ok(height) = to.get::<i32>("height") {
+                let width = (f64::from(height) * dar / par).round().max(1.0);
+                to.fixate_field_nearest_int("width", width.min(f64::from(i32::MAX)) as i32);
+            }
+        }
+        (Some(_), Some(_)) => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gst_video::VideoFormat;
+
+    use super::*;
+
+    const METHODS: [ScaleMethod; 4] = [
+        ScaleMethod::Nearest,
+        ScaleMethod::Bilinear,
+        ScaleMethod::Bicubic,
+        ScaleMethod::Lanczos,
+    ];
+    const FORMATS: [VideoFormat; 3] = [VideoFormat::Gray8, VideoFormat::I420, VideoFormat::Nv12];
+
+    /// Samples of every component of a frame, row by row.
+    type Components = Vec<Vec<Vec<u8>>>;
+
+    /// Components of an 8-bit `frame`.
+    fn components(frame: &VideoFrameRef<&BufferRef>) -> Components {
+        let finfo = frame.format_info();
+
+        (0..finfo.n_components() as usize)
+            .map(|c| {
+                let plane = finfo.plane()[c];
+                let stride = frame.plane_stride()[plane as usize] as usize;
+                let poffset = finfo.poffset()[c] as usize;
+                let pstride = finfo.pixel_stride()[c] as usize;
+                let width = finfo.scale_width(c as u8, frame.width()) as usize;
+                let height = finfo.scale_height(c as u8, frame.height()) as usize;
+                let data = frame.plane_data(plane).unwrap();
+
+                (0..height)
+                    .map(|y| (0..width).map(|x| data[y * stride + poffset + x * pstride]).collect())
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Scales a `format` frame of `in_size` whose component `c` is
+    /// `value(c, x, y)` to `out_size`, and returns the components of both.
+    fn scale(
+        format: VideoFormat,
+        method: ScaleMethod,
+        in_size: (u32, u32),
+        out_size: (u32, u32),
+        value: impl Fn(usize, usize, usize) -> u8,
+    ) -> (Components, Components) {
+        gst::init().unwrap();
+
+        let in_info = VideoInfo::builder(format, in_size.0, in_size.1).build().unwrap();
+        let out_info = VideoInfo::builder(format, out_size.0, out_size.1).build().unwrap();
+        let finfo = in_info.format_info();
+
+        let mut inbuf = gst::Buffer::with_size(in_info.size()).unwrap();
+        {
+            let mut frame = VideoFrameRef::from_buffer_ref_writable(inbuf.get_mut().unwrap(), &in_info).unwrap();
+            for c in 0..finfo.n_components() as usize {
+                let plane = finfo.plane()[c];
+                let stride = frame.plane_stride()[plane as usize] as usize;
+                let poffset = finfo.poffset()[c] as usize;
+                let pstride = finfo.pixel_stride()[c] as usize;
+                let width = finfo.scale_width(c as u8, in_size.0) as usize;
+                let height = finfo.scale_height(c as u8, in_size.1) as usize;
+                let data = frame.plane_data_mut(plane).unwrap();
+                for y in 0..height {
+                    for x in 0..width {
+                        data[y * stride + poffset + x * pstride] = value(c, x, y);
+                    }
+                }
+            }
+        }
+
+        let mut outbuf = gst::Buffer::with_size(out_info.size()).unwrap();
+        let in_frame = VideoFrameRef::from_buffer_ref_readable(inbuf.as_ref(), &in_info).unwrap();
+        let mut out_frame = VideoFrameRef::from_buffer_ref_writable(outbuf.get_mut().unwrap(), &out_info).unwrap();
+        Scaler::new(&in_info, &out_info, method).scale(&in_frame, &mut out_frame);
+        drop(out_frame);
+
+        let out_frame = VideoFrameRef::from_buffer_ref_readable(outbuf.as_ref(), &out_info).unwrap();
+        (components(&in_frame), components(&out_frame))
+    }
+
+    #[test]
+    fn test_identity() {
+        let pattern = |c: usize, x: usize, y: usize| ((x * 7 + y * 13 + c * 50) % 256) as u8;
+
+        for format in FORMATS {
+            for method in METHODS {
+                let (input, output) = scale(format, method, (37, 23), (37, 23), pattern);
+                assert_eq!(output, input, "{:?} {:?}", format, method);
+            }
+        }
+    }
+
+    #[test]
+    fn test_constant() {
+        let constant = |c: usize, _: usize, _: usize| (60 + c * 40) as u8;
+
+        for format in FORMATS {
+            for method in METHODS {
+                // Up, down, and large enough to be split into bands.
+                for (in_size, out_size) in [((37, 23), (80, 50)), ((37, 23), (13, 9)), ((300, 200), (640, 400))] {
+                    let (_, output) = scale(format, method, in_size, out_size, constant);
+                    for (c, rows) in output.iter().enumerate() {
+                        assert!(
+                            rows.iter().flatten().all(|&v| v == constant(c, 0, 0)),
+                            "{:?} {:?} {:?} component {}",
+                            format,
+                            method,
+                            out_size,
+                            c
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
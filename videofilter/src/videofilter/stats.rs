@@ -0,0 +1,188 @@
+//! Frame rate, PTS jitter and lateness measurements.
+
+use std::time::{Duration, Instant};
+
+/// Statistics over one measurement interval.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Snapshot {
+    /// Frames per second, measured on arrival.
+    pub fps: f64,
+    /// Mean PTS delta between consecutive frames, in nanoseconds.
+    pub pts_delta: f64,
+    /// Standard deviation of the PTS deltas, in nanoseconds.
+    pub jitter: f64,
+    /// Mean of arrival running time minus buffer running time, in
+    /// nanoseconds. Positive values mean frames arrive late.
+    pub lateness: f64,
+    /// Largest lateness seen, in nanoseconds.
+    pub max_lateness: i64,
+}
+
+impl Snapshot {
+    pub fn to_structure(self, name: &str) -> gst::Structure {
+        gst::Structure::builder(name)
+            .field("fps", self.fps)
+            .field("pts-delta", self.pts_delta)
+            .field("jitter", self.jitter)
+            .field("lateness", self.lateness)
+            .field("max-lateness", self.max_lateness)
+            .build()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Measurements {
+    window_start: Option<Instant>,
+    frames: u64,
+    last_pts: Option<gst::ClockTime>,
+    delta_count: u64,
+    delta_sum: f64,
+    delta_sum_sq: f64,
+    lateness_count: u64,
+    lateness_sum: f64,
+    max_lateness: Option<i64>,
+    last: Snapshot,
+}
+
+impl Measurements {
+    /// Accounts for one frame arriving at `arrival`. `running_time` is the
+    /// running time of its PTS and `now` the running time of the pipeline
+    /// clock on arrival.
+    pub fn record(
+        &mut self,
+        arrival: Instant,
+        pts: Option<gst::ClockTime>,
+        running_time: Option<gst::ClockTime>,
+        now: Option<gst::ClockTime>,
+    ) {
+        self.window_start.get_or_insert(arrival);
+        self.frames += 1;
+
+        if let (Some(pts), Some(last_pts)) = (pts, self.last_pts) {
+            let delta = pts.nseconds() as f64 - last_pts.nseconds() as f64;
+            self.delta_count += 1;
+            self.delta_sum += delta;
+            self.delta_sum_sq += delta * delta;
+        }
+        if pts.is_some() {
+            self.last_pts = pts;
+        }
+
+        if let (Some(running_time), Some(now)) = (running_time, now) {
+            let lateness = now.nseconds() as i64 - running_time.nseconds() as i64;
+            self.lateness_count += 1;
+            self.lateness_sum += lateness as f64;
+            self.max_lateness = Some(self.max_lateness.map_or(lateness, |max| max.max(lateness)));
+        }
+    }
+
+    /// Forgets the previous PTS, e.g. after a flush or discontinuity, so that
+    /// the gap does not show up as jitter.
+    pub fn discont(&mut self) {
+        self.last_pts = None;
+    }
+
+    /// Closes the current window if it is at least `interval` long at `now`
+    /// and returns its statistics.
+    pub fn publish(&mut self, now: Instant, interval: Duration) -> Option<Snapshot> {
+        let elapsed = now.saturating_duration_since(self.window_start?);
+        if interval.is_zero() || elapsed < interval {
+            return None;
+        }
+
+        let mean = |sum: f64, count: u64| if count > 0 { sum / count as f64 } else { 0.0 };
+        let pts_delta = mean(self.delta_sum, self.delta_count);
+        let variance = mean(self.delta_sum_sq, self.delta_count) - pts_delta * pts_delta;
+
+        let snapshot = Snapshot {
+            fps: self.frames as f64 / elapsed.as_secs_f64(),
+            pts_delta,
+            jitter: variance.max(0.0).sqrt(),
+            lateness: mean(self.lateness_sum, self.lateness_count),
+            max_lateness: self.max_lateness.unwrap_or(0),
+        };
+
+        *self = Measurements {
+            last_pts: self.last_pts,
+            last: snapshot,
+            ..Default::default()
+        };
+
+        Some(snapshot)
+    }
+
+    /// Statistics of the last completed window.
+    pub fn last(&self) -> Snapshot {
+        self.last
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Option<gst::ClockTime> {
+        Some(gst::ClockTime::from_mseconds(ms))
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let start = Instant::now();
+        let mut measurements = Measurements::default();
+
+        // PTS deltas of 40, 20 and 60 ms, frames 10, 20, 30 and 0 ms late.
+        for (i, (pts, late)) in [(0, 10), (40, 20), (60, 30), (120, 0)].iter().enumerate() {
+            let arrival = start + Duration::from_millis(100 * i as u64);
+            measurements.record(arrival, ms(*pts), ms(*pts), ms(pts + late));
+        }
+
+        let interval = Duration::from_secs(1);
+        assert_eq!(measurements.publish(start + Duration::from_millis(500), interval), None);
+
+        let snapshot = measurements.publish(start + Duration::from_secs(2), interval).unwrap();
+        assert_eq!(snapshot.fps, 2.0);
+        assert_eq!(snapshot.pts_delta, 40_000_000.0);
+        // Deviations of 0, -20 and 20 ms.
+        assert!((snapshot.jitter - (800.0f64 / 3.0).sqrt() * 1_000_000.0).abs() < 1.0);
+        assert_eq!(snapshot.lateness, 15_000_000.0);
+        assert_eq!(snapshot.max_lateness, 30_000_000);
+        assert_eq!(measurements.last(), snapshot);
+    }
+
+    #[test]
+    fn test_windows_are_independent() {
+        let start = Instant::now();
+        let mut measurements = Measurements::default();
+        let interval = Duration::from_secs(1);
+
+        measurements.record(start, ms(0), ms(0), ms(50));
+        measurements.record(start, ms(40), ms(40), ms(40));
+        measurements.publish(start + interval, interval).unwrap();
+
+        // The PTS delta to the previous window is still measured.
+        let start = start + interval;
+        measurements.record(start, ms(80), ms(80), ms(70));
+        let snapshot = measurements.publish(start + interval, interval).unwrap();
+        assert_eq!(snapshot.fps, 1.0);
+        assert_eq!(snapshot.pts_delta, 40_000_000.0);
+        assert_eq!(snapshot.jitter, 0.0);
+        assert_eq!(snapshot.lateness, -10_000_000.0);
+        assert_eq!(snapshot.max_lateness, -10_000_000);
+    }
+
+    #[test]
+    fn test_discont() {
+        let start = Instant::now();
+        let mut measurements = Measurements::default();
+
+        measurements.record(start, ms(0), None, None);
+        measurements.discont();
+        measurements.record(start, ms(1000), None, None);
+        measurements.record(start, ms(1040), None, None);
+
+        let snapshot = measurements.publish(start + Duration::from_secs(1), Duration::from_secs(1)).unwrap();
+        assert_eq!(snapshot.pts_delta, 40_000_000.0);
+        assert_eq!(snapshot.lateness, 0.0);
+        assert_eq!(snapshot.max_lateness, 0);
+    }
+}
@@ -0,0 +1,20 @@
+use gst::glib::{self, StaticType};
+
+mod imp;
+mod pattern;
+
+glib::wrapper! {
+    pub struct VideoTestSrc(ObjectSubclass<imp::VideoTestSrc>) @extends gst_base::PushSrc, gst_base::BaseSrc, gst::Element, gst::Object;
+}
+
+unsafe impl Send for VideoTestSrc {}
+unsafe impl Sync for VideoTestSrc {}
+
+pub(crate) fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "rsvideotestsrc",
+        gst::Rank::None,
+        VideoTestSrc::static_type(),
+    )
+}
@@ -0,0 +1,387 @@
+use gst::{glib, gst_debug, gst_info, gst_log, BufferRef, Caps, ErrorMessage, FlowError, FlowSuccess, LoggableError, PadTemplate};
+use gst::subclass::ElementMetadata;
+use gst::subclass::prelude::*;
+use gst::prelude::*;
+use gst_base::prelude::*;
+use gst_base::subclass::prelude::*;
+use parking_lot::Mutex;
+use crate::glib::once_cell::sync::Lazy;
+
+use super::pattern::{self, Pattern};
+use crate::videofilter::{get_all_video_formats, pack_rgb_planes};
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rsvideotestsrc",
+        gst::DebugColorFlags::empty(),
+        Some("Video test source"),
+    )
+});
+
+const DEFAULT_PATTERN: Pattern = Pattern::Bars;
+const DEFAULT_COLOR: u32 = 0xffff_ffff;
+const DEFAULT_SEED: u32 = 0;
+const DEFAULT_IS_LIVE: bool = false;
+
+const DEFAULT_WIDTH: i32 = 320;
+const DEFAULT_HEIGHT: i32 = 240;
+const DEFAULT_FRAMERATE: i32 = 30;
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    pattern: Pattern,
+    color: u32,
+    seed: u32,
+    is_live: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            pattern: DEFAULT_PATTERN,
+            color: DEFAULT_COLOR,
+            seed: DEFAULT_SEED,
+            is_live: DEFAULT_IS_LIVE,
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    info: Option<gst_video::VideoInfo>,
+    /// Frames produced since the source started, which drives the
+    /// animation and the timestamps.
+    frames: u64,
+}
+
+#[derive(Default)]
+pub struct VideoTestSrc {
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+/// Timestamp of frame number `frame` at `framerate`.
+fn frame_time(frame: u64, framerate: gst::Fraction) -> Option<gst::ClockTime> {
+    gst::ClockTime::SECOND.mul_div_floor(frame * framerate.denom() as u64, framerate.numer() as u64)
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for VideoTestSrc {
+    const NAME: &'static str = "RsVideoTestSrc";
+    type Type = super::VideoTestSrc;
+    type ParentType = gst_base::PushSrc;
+}
+
+impl ElementImpl for VideoTestSrc {
+    fn metadata() -> Option<&'static ElementMetadata> {
+        static METADATA: Lazy<ElementMetadata> = Lazy::new(|| {
+            ElementMetadata::new(
+                "Video test source",
+                "Source/Video",
+                "Produce bit-exact test patterns in every format of videofilter",
+                "Seiichi Uchida <topecongiro@fastmail.com>",
+            )
+        });
+
+        Some(&*METADATA)
+    }
+
+    fn pad_templates() -> &'static [PadTemplate] {
+        static PAD_TEMPLATE: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let mut formats = get_all_video_formats();
+            formats.push(gst_video::VideoFormat::Bgrx.to_str().to_send_value());
+
+            let caps = gst::Caps::builder("video/x-raw")
+                .field("format", gst::List::from(formats))
+                .field("width", gst::IntRange::new(1, i32::MAX))
+                .field("height", gst::IntRange::new(1, i32::MAX))
+                .field("framerate", gst::FractionRange::new(
+                    gst::Fraction::new(0, 1),
+                    gst::Fraction::new(i32::MAX, 1),
+                ))
+                .build();
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &caps,
+            ).unwrap();
+
+            vec![src_pad_template]
+        });
+
+        PAD_TEMPLATE.as_ref()
+    }
+}
+
+impl BaseSrcImpl for VideoTestSrc {
+    fn fixate(&self, element: &Self::Type, mut caps: Caps) -> Caps {
+        caps.truncate();
+        {
+            let caps = caps.make_mut();
+            let s = caps.structure_mut(0).unwrap();
+            s.fixate_field_nearest_int("width", DEFAULT_WIDTH);
+            s.fixate_field_nearest_int("height", DEFAULT_HEIGHT);
+            s.fixate_field_nearest_fraction("framerate", gst::Fraction::new(DEFAULT_FRAMERATE, 1));
+            if s.has_field("pixel-aspect-ratio") {
+                s.fixate_field_nearest_fraction("pixel-aspect-ratio", gst::Fraction::new(1, 1));
+            }
+        }
+
+        self.parent_fixate(element, caps)
+    }
+
+    fn set_caps(&self, element: &Self::Type, caps: &Caps) -> Result<(), LoggableError> {
+        let info = gst_video::VideoInfo::from_caps(caps)
+            .map_err(|_| gst::loggable_error!(CAT, "Failed to parse caps"))?;
+
+        gst_debug!(CAT, obj: element, "Configured for caps {}", caps);
+
+        // Without a pool the buffers are allocated with the block size.
+        element.set_blocksize(info.size() as u32);
+        self.state.lock().info = Some(info);
+
+        Ok(())
+    }
+
+    fn start(&self, element: &Self::Type) -> Result<(), ErrorMessage> {
+        *self.state.lock() = State::default();
+
+        gst_info!(CAT, obj: element, "Started");
+
+        Ok(())
+    }
+
+    fn stop(&self, element: &Self::Type) -> Result<(), ErrorMessage> {
+        *self.state.lock() = State::default();
+
+        gst_info!(CAT, obj: element, "Stopped");
+
+        Ok(())
+    }
+
+    fn is_seekable(&self, _element: &Self::Type) -> bool {
+        false
+    }
+
+    fn query(&self, element: &Self::Type, query: &mut gst::QueryRef) -> bool {
+        match query.view_mut() {
+            // A live source is one frame late.
+            gst::QueryView::Latency(ref mut q) => {
+                let state = self.state.lock();
+                match state.info.as_ref() {
+                    Some(info) if info.fps().numer() > 0 => {
+                        let latency = frame_time(1, info.fps()).unwrap_or(gst::ClockTime::ZERO);
+                        q.set(element.is_live(), latency, gst::ClockTime::NONE);
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            _ => BaseSrcImplExt::parent_query(self, element, query),
+        }
+    }
+
+    fn times(&self, element: &Self::Type, buffer: &BufferRef) -> (Option<gst::ClockTime>, Option<gst::ClockTime>) {
+        // Only live sources wait for the clock.
+        if !element.is_live() {
+            return (None, None);
+        }
+
+        let start = buffer.pts();
+        let end = start.zip(buffer.duration()).map(|(start, duration)| start + duration);
+        (start, end)
+    }
+}
+
+impl PushSrcImpl for VideoTestSrc {
+    fn fill(&self, element: &Self::Type, buffer: &mut BufferRef) -> Result<FlowSuccess, FlowError> {
+        let settings = *self.settings.lock();
+        let mut state = self.state.lock();
+        let info = state.info.clone().ok_or_else(|| {
+            gst::element_error!(element, gst::CoreError::Negotiation, ["Have no caps yet"]);
+            FlowError::NotNegotiated
+        })?;
+
+        // A still picture is a single frame.
+        let fps = info.fps();
+        let frame = state.frames;
+        if fps.numer() == 0 && frame > 0 {
+            return Err(FlowError::Eos);
+        }
+        state.frames += 1;
+        drop(state);
+
+        let pts = frame_time(frame, fps).unwrap_or(gst::ClockTime::ZERO);
+        buffer.set_pts(pts);
+        buffer.set_offset(frame);
+        buffer.set_offset_end(frame + 1);
+        if fps.numer() > 0 {
+            buffer.set_duration(frame_time(frame + 1, fps).map(|end| end - pts));
+        }
+
+        gst_log!(CAT, obj: element, "Producing frame {} at {}", frame, pts);
+
+        let width = info.width() as usize;
+        let height = info.height() as usize;
+        let pixels = pattern::render(settings.pattern, width, height, frame, settings.seed, settings.color);
+
+        let mut vframe = gst_video::VideoFrameRef::from_buffer_ref_writable(buffer, &info).map_err(|err| {
+            gst::element_error!(
+                element,
+                gst::CoreError::Failed,
+                [&format!("Failed to map buffer writable: {}", err)]
+            );
+            FlowError::Error
+        })?;
+
+        if info.format() == gst_video::VideoFormat::Bgrx {
+            let stride = vframe.plane_stride()[0] as usize;
+            let data = vframe.plane_data_mut(0).unwrap();
+            for (line, row) in data.chunks_mut(stride).zip(pixels.chunks_exact(width)) {
+                for (x, &[r, g, b]) in row.iter().enumerate() {
+                    line[x * 4..x * 4 + 4].copy_from_slice(&[b, g, r, 255]);
+                }
+            }
+        } else {
+            let mut planes: [Vec<f32>; 3] = Default::default();
+            for (c, plane) in planes.iter_mut().enumerate() {
+                *plane = pixels.iter().map(|p| f32::from(p[c]) / 255.0).collect();
+            }
+            pack_rgb_planes(planes, &mut vframe).map_err(|err| {
+                gst::element_error!(element, gst::CoreError::Failed, [&err]);
+                FlowError::Error
+            })?;
+        }
+
+        Ok(FlowSuccess::Ok)
+    }
+}
+
+impl GstObjectImpl for VideoTestSrc {}
+
+impl ObjectImpl for VideoTestSrc {
+    fn constructed(&self, obj: &Self::Type) {
+        self.parent_constructed(obj);
+
+        obj.set_live(DEFAULT_IS_LIVE);
+        obj.set_format(gst::Format::Time);
+    }
+
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecEnum::new(
+                    "pattern",
+                    "Pattern",
+                    "Test pattern to produce",
+                    Pattern::static_type(),
+                    DEFAULT_PATTERN as i32,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt::new(
+                    "color",
+                    "Color",
+                    "Color in ARGB of the solid pattern and of the moving box, the alpha being ignored",
+                    0,
+                    u32::MAX,
+                    DEFAULT_COLOR,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt::new(
+                    "seed",
+                    "Seed",
+                    "Seed of the noise pattern, combined with the frame number",
+                    0,
+                    u32::MAX,
+                    DEFAULT_SEED,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecBoolean::new(
+                    "is-live",
+                    "Is live",
+                    "Whether to act as a live source",
+                    DEFAULT_IS_LIVE,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(&self, obj: &Self::Type, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        match pspec.name() {
+            "pattern" => {
+                let mut settings = self.settings.lock();
+                let pattern = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing pattern from {:?} to {:?}",
+                    settings.pattern, pattern
+                );
+                settings.pattern = pattern;
+            }
+            "color" => {
+                let mut settings = self.settings.lock();
+                let color = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing color from {:08x} to {:08x}",
+                    settings.color, color
+                );
+                settings.color = color;
+            }
+            "seed" => {
+                let mut settings = self.settings.lock();
+                let seed = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing seed from {} to {}",
+                    settings.seed, seed
+                );
+                settings.seed = seed;
+            }
+            "is-live" => {
+                let mut settings = self.settings.lock();
+                let is_live = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing is-live from {} to {}",
+                    settings.is_live, is_live
+                );
+                settings.is_live = is_live;
+                drop(settings);
+
+                obj.set_live(is_live);
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn property(&self, _obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        match pspec.name() {
+            "pattern" => {
+                let settings = self.settings.lock();
+                settings.pattern.to_value()
+            }
+            "color" => {
+                let settings = self.settings.lock();
+                settings.color.to_value()
+            }
+            "seed" => {
+                let settings = self.settings.lock();
+                settings.seed.to_value()
+            }
+            "is-live" => {
+                let settings = self.settings.lock();
+                settings.is_live.to_value()
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
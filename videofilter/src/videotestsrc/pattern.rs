@@ -0,0 +1,116 @@
+//! Test patterns as full resolution R'G'B' planes.
+//!
+//! Every pattern is a function of the frame size, the frame number and the
+//! settings only, with values that are exact in 8 bits, so the same caps
+//! always give the same bytes.
+
+use gst::glib;
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstRsVideoTestSrcPattern")]
+pub enum Pattern {
+    #[enum_value(name = "75% colour bars", nick = "bars")]
+    Bars = 0,
+    #[enum_value(name = "Horizontal gray ramp", nick = "gradient")]
+    Gradient = 1,
+    #[enum_value(name = "Black and white checkerboard", nick = "checkers")]
+    Checkers = 2,
+    #[enum_value(name = "Box bouncing on black", nick = "moving-box")]
+    MovingBox = 3,
+    #[enum_value(name = "Solid colour", nick = "solid")]
+    Solid = 4,
+    #[enum_value(name = "Seeded random noise", nick = "noise")]
+    Noise = 5,
+}
+
+const CHECKER_SIZE: usize = 8;
+/// Distance the box moves each frame, in luma samples.
+const BOX_SPEED: u64 = 2;
+
+/// White, yellow, cyan, green, magenta, red and blue.
+const BARS: [[u8; 3]; 7] = [
+    [191, 191, 191],
+    [191, 191, 0],
+    [0, 191, 191],
+    [0, 191, 0],
+    [191, 0, 191],
+    [191, 0, 0],
+    [0, 0, 191],
+];
+
+/// SplitMix64, which is small, fast and the same everywhere.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// Position along `range` of something bouncing between its ends.
+fn bounce(distance: u64, range: usize) -> usize {
+    if range == 0 {
+        return 0;
+    }
+    let range = range as u64;
+    let position = distance % (2 * range);
+    (if position < range { position } else { 2 * range - position }) as usize
+}
+
+/// 8-bit R'G'B' samples of `pattern` for frame number `frame`. `color` is
+/// the ARGB colour of the solid pattern and of the moving box.
+pub fn render(pattern: Pattern, width: usize, height: usize, frame: u64, seed: u32, color: u32) -> Vec<[u8; 3]> {
+    let color = [(color >> 16) as u8, (color >> 8) as u8, color as u8];
+
+    match pattern {
+        Pattern::Bars => (0..height)
+            .flat_map(|_| (0..width).map(move |x| BARS[x * BARS.len() / width]))
+            .collect(),
+        Pattern::Gradient => (0..height)
+            .flat_map(|_| {
+                (0..width).map(move |x| {
+                    let v = if width > 1 { (x * 255 + (width - 1) / 2) / (width - 1) } else { 0 };
+                    [v as u8; 3]
+                })
+            })
+            .collect(),
+        Pattern::Checkers => (0..height)
+            .flat_map(|y| {
+                (0..width).map(move |x| {
+                    if (x / CHECKER_SIZE + y / CHECKER_SIZE).is_multiple_of(2) {
+                        [255; 3]
+                    } else {
+                        [0; 3]
+                    }
+                })
+            })
+            .collect(),
+        Pattern::MovingBox => {
+            let size = (width.min(height) / 8).max(1);
+            let distance = frame.wrapping_mul(BOX_SPEED);
+            let x0 = bounce(distance, width - size);
+            let y0 = bounce(distance, height - size);
+
+            let mut pixels = vec![[0; 3]; width * height];
+            for y in y0..y0 + size {
+                pixels[y * width + x0..y * width + x0 + size].fill(color);
+            }
+            pixels
+        }
+        Pattern::Solid => vec![color; width * height],
+        Pattern::Noise => {
+            let mut rng = SplitMix64((u64::from(seed) << 32) ^ frame);
+            (0..width * height)
+                .map(|_| {
+                    let [r, g, b, ..] = rng.next().to_le_bytes();
+                    [r, g, b]
+                })
+                .collect()
+        }
+    }
+}
@@ -0,0 +1,20 @@
+use gst::glib::{self, StaticType};
+
+mod image;
+mod imp;
+
+glib::wrapper! {
+    pub struct Watermark(ObjectSubclass<imp::Watermark>) @extends gst_base::BaseTransform, gst::Element, gst::Object;
+}
+
+unsafe impl Send for Watermark {}
+unsafe impl Sync for Watermark {}
+
+pub(crate) fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "rswatermark",
+        gst::Rank::None,
+        Watermark::static_type(),
+    )
+}
@@ -0,0 +1,554 @@
+//! Watermark images.
+//!
+//! PNG, PPM/PGM/PAM and QOI files are decoded to straight R'G'B'A, scaled,
+//! and converted once to the code values and alpha of every component of
+//! the negotiated format, so that blending a frame is only a weighted sum.
+
+use std::path::Path;
+
+use gst::BufferRef;
+use gst_video::{VideoFormatFlags, VideoFrameRef, VideoInfo};
+
+use crate::videofilter::{read_sample, rgb_to_codes, write_sample};
+
+/// Largest number of pixels of an image, against corrupt headers.
+const MAX_PIXELS: usize = 1 << 28;
+
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    /// Straight R'G'B'A, each channel being in 0..1.
+    pub pixels: Vec<[f32; 4]>,
+}
+
+fn checked_size(width: usize, height: usize) -> Result<usize, String> {
+    match width.checked_mul(height) {
+        Some(count) if count > 0 && count <= MAX_PIXELS => Ok(count),
+        _ => Err(format!("Unsupported image size {}x{}", width, height)),
+    }
+}
+
+fn bytes(data: &[u8], pos: usize, len: usize) -> Result<&[u8], String> {
+    data.get(pos..pos + len).ok_or_else(|| String::from("Truncated image"))
+}
+
+/// Expands 1 to 4 channels of gray, gray and alpha, RGB or RGBA to RGBA.
+fn rgba(channels: &[f32]) -> [f32; 4] {
+    match *channels {
+        [y] => [y, y, y, 1.0],
+        [y, a] => [y, y, y, a],
+        [r, g, b] => [r, g, b, 1.0],
+        [r, g, b, a] => [r, g, b, a],
+        _ => [0.0; 4],
+    }
+}
+
+fn decode_png(data: &[u8]) -> Result<Image, String> {
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().map_err(|err| err.to_string())?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).map_err(|err| err.to_string())?;
+
+    let width = info.width as usize;
+    let height = info.height as usize;
+    checked_size(width, height)?;
+    let channels = info.color_type.samples();
+
+    let mut pixels = Vec::with_capacity(width * height);
+    for line in buf.chunks(info.line_size).take(height) {
+        pixels.extend(line[..width * channels].chunks_exact(channels).map(|px| {
+            let px: Vec<f32> = px.iter().map(|&v| f32::from(v) / 255.0).collect();
+            rgba(&px)
+        }));
+    }
+
+    Ok(Image { width, height, pixels })
+}
+
+/// Reads the whitespace separated header fields of a P5 or P6 file, skipping
+/// comments, and returns them with the offset of the pixel data.
+fn pnm_fields(data: &[u8], count: usize) -> Result<(Vec<usize>, usize), String> {
+    let mut fields = Vec::with_capacity(count);
+    let mut pos = 2;
+
+    while fields.len() < count {
+        match data.get(pos) {
+            Some(b'#') => {
+                while data.get(pos).is_some_and(|&c| c != b'\n') {
+                    pos += 1;
+                }
+            }
+            Some(c) if c.is_ascii_whitespace() => pos += 1,
+            Some(c) if c.is_ascii_digit() => {
+                let start = pos;
+                while data.get(pos).is_some_and(u8::is_ascii_digit) {
+                    pos += 1;
+                }
+                let field = std::str::from_utf8(&data[start..pos]).unwrap();
+                fields.push(field.parse().map_err(|_| format!("Invalid header field {}", field))?);
+            }
+            _ => return Err(String::from("Invalid PNM header")),
+        }
+    }
+
+    // A single whitespace character separates the header from the pixels.
+    Ok((fields, pos + 1))
+}
+
+/// Reads the header of a P7 file, returning the width, height, depth and
+/// maxval with the offset of the pixel data.
+fn pam_header(data: &[u8]) -> Result<([usize; 4], usize), String> {
+    let mut values = [0; 4];
+    let mut pos = 3;
+
+    loop {
+        let end = data[pos..]
+            .iter()
+            .position(|&c| c == b'\n')
+            .map(|len| pos + len)
+            .ok_or_else(|| String::from("Invalid PAM header"))?;
+        let line = std::str::from_utf8(&data[pos..end]).map_err(|_| String::from("Invalid PAM header"))?;
+        pos = end + 1;
+
+        let mut tokens = line.split_whitespace();
+        let key = match tokens.next() {
+            Some(key) => key,
+            None => continue,
+        };
+        let index = match key {
+            "ENDHDR" => break,
+            "WIDTH" => 0,
+            "HEIGHT" => 1,
+            "DEPTH" => 2,
+            "MAXVAL" => 3,
+            _ => continue,
+        };
+        values[index] = tokens
+            .next()
+            .and_then(|value| value.parse().ok())
+            .ok_or_else(|| format!("Invalid PAM header line {:?}", line))?;
+    }
+
+    Ok((values, pos))
+}
+
+fn decode_pnm(data: &[u8]) -> Result<Image, String> {
+    let ([width, height, channels, maxval], pos) = match &data[..2] {
+        b"P5" => {
+            let (fields, pos) = pnm_fields(data, 3)?;
+            ([fields[0], fields[1], 1, fields[2]], pos)
+        }
+        b"P6" => {
+            let (fields, pos) = pnm_fields(data, 3)?;
+            ([fields[0], fields[1], 3, fields[2]], pos)
+        }
+        _ => pam_header(data)?,
+    };
+
+    let count = checked_size(width, height)?;
+    if !(1..=4).contains(&channels) || !(1..=65535).contains(&maxval) {
+        return Err(format!("Unsupported depth {} or maxval {}", channels, maxval));
+    }
+
+    let sample_size = if maxval > 255 { 2 } else { 1 };
+    let data = bytes(data, pos, count * channels * sample_size)?;
+    let max = maxval as f32;
+
+    let pixels = data
+        .chunks_exact(channels * sample_size)
+        .map(|px| {
+            let px: Vec<f32> = px
+                .chunks_exact(sample_size)
+                .map(|v| {
+                    let v = if sample_size == 2 {
+                        u16::from_be_bytes([v[0], v[1]])
+                    } else {
+                        u16::from(v[0])
+                    };
+                    (f32::from(v) / max).min(1.0)
+                })
+                .collect();
+            rgba(&px)
+        })
+        .collect();
+
+    Ok(Image { width, height, pixels })
+}
+
+fn decode_qoi(data: &[u8]) -> Result<Image, String> {
+    let header = bytes(data, 0, 14)?;
+    let width = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    let height = u32::from_be_bytes([header[8], header[9], header[10], header[11]]) as usize;
+    let count = checked_size(width, height)?;
+
+    let mut index = [[0u8; 4]; 64];
+    let mut px = [0u8, 0, 0, 255];
+    let mut pos = 14;
+    let mut run = 0;
+    let mut pixels = Vec::with_capacity(count);
+
+    while pixels.len() < count {
+        if run > 0 {
+            run -= 1;
+        } else {
+            let op = bytes(data, pos, 1)?[0];
+            pos += 1;
+
+            match op {
+                0xfe => {
+                    px[..3].copy_from_slice(bytes(data, pos, 3)?);
+                    pos += 3;
+                }
+                0xff => {
+                    px.copy_from_slice(bytes(data, pos, 4)?);
+                    pos += 4;
+                }
+                _ => match op >> 6 {
+                    0 => px = index[usize::from(op & 0x3f)],
+                    1 => {
+                        let diff = |shift: u8| i32::from((op >> shift) & 0x03) - 2;
+                        px[0] = (i32::from(px[0]) + diff(4)) as u8;
+                        px[1] = (i32::from(px[1]) + diff(2)) as u8;
+                        px[2] = (i32::from(px[2]) + diff(0)) as u8;
+                    }
+                    2 => {
+                        let next = bytes(data, pos, 1)?[0];
+                        pos += 1;
+                        let dg = i32::from(op & 0x3f) - 32;
+                        let dr = dg + i32::from(next >> 4) - 8;
+                        let db = dg + i32::from(next & 0x0f) - 8;
+                        px[0] = (i32::from(px[0]) + dr) as u8;
+                        px[1] = (i32::from(px[1]) + dg) as u8;
+                        px[2] = (i32::from(px[2]) + db) as u8;
+                    }
+                    _ => run = usize::from(op & 0x3f),
+                },
+            }
+
+            let hash = (usize::from(px[0]) * 3 + usize::from(px[1]) * 5 + usize::from(px[2]) * 7 + usize::from(px[3]) * 11) % 64;
+            index[hash] = px;
+        }
+
+        pixels.push(px.map(|v| f32::from(v) / 255.0));
+    }
+
+    Ok(Image { width, height, pixels })
+}
+
+impl Image {
+    /// Loads the PNG, PPM, PGM, PAM or QOI file at `path`, recognised by its
+    /// contents.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = std::fs::read(path).map_err(|err| err.to_string())?;
+
+        if data.starts_with(b"\x89PNG") {
+            decode_png(&data)
+        } else if data.starts_with(b"P5") || data.starts_with(b"P6") || data.starts_with(b"P7\n") {
+            decode_pnm(&data)
+        } else if data.starts_with(b"qoif") {
+            decode_qoi(&data)
+        } else {
+            Err(String::from("Unknown image format"))
+        }
+    }
+
+    /// The image scaled by `scale`, averaging the pixels each output pixel
+    /// covers when downscaling and interpolating linearly otherwise, with
+    /// premultiplied alpha. Fails if the scaled image is too large.
+    pub fn scaled(&self, scale: f64) -> Result<Self, String> {
+        if scale == 1.0 {
+            return Ok(self.clone());
+        }
+
+        let width = ((self.width as f64 * scale).round() as usize).max(1);
+        let height = ((self.height as f64 * scale).round() as usize).max(1);
+        let count = checked_size(width, height)?;
+        let sx = self.width as f64 / width as f64;
+        let sy = self.height as f64 / height as f64;
+
+        let premultiplied = |x: usize, y: usize| {
+            let [r, g, b, a] = self.pixels[y * self.width + x];
+            [r * a, g * a, b * a, a]
+        };
+
+        let mut pixels = Vec::with_capacity(count);
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = [0.0f32; 4];
+                let mut add = |px: [f32; 4], weight: f32| {
+                    for (total, v) in sum.iter_mut().zip(px) {
+                        *total += v * weight;
+                    }
+                };
+
+                if scale < 1.0 {
+                    let x0 = (x as f64 * sx) as usize;
+                    let x1 = (((x + 1) as f64 * sx).ceil() as usize).min(self.width).max(x0 + 1);
+                    let y0 = (y as f64 * sy) as usize;
+                    let y1 = (((y + 1) as f64 * sy).ceil() as usize).min(self.height).max(y0 + 1);
+                    let weight = 1.0 / ((x1 - x0) * (y1 - y0)) as f32;
+                    for py in y0..y1 {
+                        for px in x0..x1 {
+                            add(premultiplied(px, py), weight);
+                        }
+                    }
+                } else {
+                    let fx = ((x as f64 + 0.5) * sx - 0.5).max(0.0);
+                    let fy = ((y as f64 + 0.5) * sy - 0.5).max(0.0);
+                    let (x0, y0) = (fx as usize, fy as usize);
+                    let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+                    let (wx, wy) = ((fx - x0 as f64) as f32, (fy - y0 as f64) as f32);
+                    add(premultiplied(x0, y0), (1.0 - wx) * (1.0 - wy));
+                    add(premultiplied(x1, y0), wx * (1.0 - wy));
+                    add(premultiplied(x0, y1), (1.0 - wx) * wy);
+                    add(premultiplied(x1, y1), wx * wy);
+                }
+
+                let [r, g, b, a] = sum;
+                pixels.push(if a > 0.0 { [r / a, g / a, b / a, a] } else { [0.0; 4] });
+            }
+        }
+
+        Ok(Self { width, height, pixels })
+    }
+}
+
+/// One component of an image converted to a format.
+struct Plane {
+    width: usize,
+    height: usize,
+    codes: Vec<f32>,
+    alpha: Vec<f32>,
+}
+
+/// An image converted to the code values, before shifting, of a format.
+pub struct Overlay {
+    planes: Vec<Plane>,
+}
+
+impl Overlay {
+    /// Converts `image` to the format of `info`. Subsampled components take
+    /// the mean of the pixels they span, weighted by their alpha.
+    pub fn new(image: &Image, info: &VideoInfo) -> Result<Self, String> {
+        let codes = image
+            .pixels
+            .iter()
+            .map(|&[r, g, b, _]| rgb_to_codes(info, [r, g, b]))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let finfo = info.format_info();
+        let planes = (0..finfo.n_components() as usize)
+            .map(|c| {
+                let w_sub = finfo.w_sub()[c];
+                let h_sub = finfo.h_sub()[c];
+                let width = (image.width + (1 << w_sub) - 1) >> w_sub;
+                let height = (image.height + (1 << h_sub) - 1) >> h_sub;
+
+                let mut plane = Plane {
+                    width,
+                    height,
+                    codes: Vec::with_capacity(width * height),
+                    alpha: Vec::with_capacity(width * height),
+                };
+                for cy in 0..height {
+                    for cx in 0..width {
+                        let (mut code, mut alpha, mut samples) = (0.0, 0.0, 0.0);
+                        for y in (cy << h_sub)..((cy + 1) << h_sub).min(image.height) {
+                            for x in (cx << w_sub)..((cx + 1) << w_sub).min(image.width) {
+                                let a = image.pixels[y * image.width + x][3];
+                                code += f32::from(codes[y * image.width + x][c]) * a;
+                                alpha += a;
+                                samples += 1.0;
+                            }
+                        }
+                        plane.codes.push(if alpha > 0.0 { code / alpha } else { 0.0 });
+                        plane.alpha.push(alpha / samples);
+                    }
+                }
+
+                plane
+            })
+            .collect();
+
+        Ok(Self { planes })
+    }
+
+    /// Blends the image into `frame` with its top left corner at `x`, `y`,
+    /// which may be outside of the frame.
+    pub fn blend(&self, frame: &mut VideoFrameRef<&mut BufferRef>, x: i32, y: i32, opacity: f32) {
+        let finfo = frame.format_info();
+        let little_endian = finfo.flags().contains(VideoFormatFlags::LE);
+
+        for (c, plane) in self.planes.iter().enumerate() {
+            let w_sub = finfo.w_sub()[c];
+            let h_sub = finfo.h_sub()[c];
+            let comp_width = finfo.scale_width(c as u8, frame.width()) as i64;
+            let comp_height = finfo.scale_height(c as u8, frame.height()) as i64;
+            let plane_index = finfo.plane()[c];
+            let stride = frame.plane_stride()[plane_index as usize] as usize;
+            let poffset = finfo.poffset()[c] as usize;
+            let pstride = finfo.pixel_stride()[c] as usize;
+            let shift = finfo.shift()[c];
+            let max = ((1u32 << finfo.depth()[c]) - 1) as f32;
+
+            let origin_x = i64::from(x) >> w_sub;
+            let origin_y = i64::from(y) >> h_sub;
+            let dst = frame.plane_data_mut(plane_index).unwrap();
+
+            for py in 0..plane.height {
+                let cy = origin_y + py as i64;
+                if cy < 0 || cy >= comp_height {
+                    continue;
+                }
+                let line = &mut dst[cy as usize * stride + poffset..];
+
+                for px in 0..plane.width {
+                    let cx = origin_x + px as i64;
+                    let alpha = plane.alpha[py * plane.width + px] * opacity;
+                    if cx < 0 || cx >= comp_width || alpha <= 0.0 {
+                        continue;
+                    }
+                    let cx = cx as usize;
+
+                    let mut value = f32::from(read_sample(line, cx, pstride, little_endian) >> shift);
+                    value += (plane.codes[py * plane.width + px] - value) * alpha;
+
+                    let code = (value.round().clamp(0.0, max) as u16) << shift;
+                    write_sample(line, cx, pstride, little_endian, code);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_pixels(image: &Image, width: usize, height: usize, expected: &[[u8; 4]]) {
+        assert_eq!((image.width, image.height), (width, height));
+        let pixels: Vec<[u8; 4]> = image
+            .pixels
+            .iter()
+            .map(|px| px.map(|v| (v * 255.0).round() as u8))
+            .collect();
+        assert_eq!(pixels, expected);
+    }
+
+    #[test]
+    fn test_png() {
+        // 2x2 RGBA: red, half transparent green, transparent blue and white.
+        #[rustfmt::skip]
+        let data = [
+            0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52,
+            0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02, 0x08, 0x06, 0x00, 0x00, 0x00, 0x72, 0xb6, 0x0d,
+            0x24, 0x00, 0x00, 0x00, 0x14, 0x49, 0x44, 0x41, 0x54, 0x78, 0x9c, 0x63, 0xf8, 0xcf, 0xc0, 0xf0,
+            0x1f, 0x08, 0x1b, 0x18, 0xc0, 0x34, 0x10, 0x00, 0x00, 0x3f, 0xd7, 0x08, 0x79, 0x8f, 0x13, 0x8a,
+            0x8a, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae, 0x42, 0x60, 0x82,
+        ];
+        let image = decode_png(&data).unwrap();
+        assert_pixels(
+            &image,
+            2,
+            2,
+            &[[255, 0, 0, 255], [0, 255, 0, 128], [0, 0, 255, 0], [255, 255, 255, 255]],
+        );
+
+        assert!(decode_png(&data[..40]).is_err());
+    }
+
+    #[test]
+    fn test_png_gray16() {
+        let mut data = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut data, 3, 1);
+            encoder.set_color(png::ColorType::Grayscale);
+            encoder.set_depth(png::BitDepth::Sixteen);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&[0x00, 0x00, 0x80, 0x40, 0xff, 0xff]).unwrap();
+        }
+
+        let image = decode_png(&data).unwrap();
+        assert_pixels(&image, 3, 1, &[[0, 0, 0, 255], [128, 128, 128, 255], [255, 255, 255, 255]]);
+    }
+
+    #[test]
+    fn test_pnm() {
+        let mut data = b"P6\n# comment\n2 1\n255\n".to_vec();
+        data.extend_from_slice(&[255, 0, 0, 0, 0, 255]);
+        assert_pixels(&decode_pnm(&data).unwrap(), 2, 1, &[[255, 0, 0, 255], [0, 0, 255, 255]]);
+        assert!(decode_pnm(&data[..data.len() - 1]).is_err());
+
+        // 16-bit samples are big-endian.
+        let mut data = b"P5 2 1 1023 ".to_vec();
+        data.extend_from_slice(&[0x03, 0xff, 0x00, 0x00]);
+        assert_pixels(&decode_pnm(&data).unwrap(), 2, 1, &[[255, 255, 255, 255], [0, 0, 0, 255]]);
+
+        let mut data = b"P7\nWIDTH 1\nHEIGHT 2\nDEPTH 2\nMAXVAL 255\nTUPLTYPE GRAYSCALE_ALPHA\nENDHDR\n".to_vec();
+        data.extend_from_slice(&[51, 255, 204, 0]);
+        assert_pixels(&decode_pnm(&data).unwrap(), 1, 2, &[[51, 51, 51, 255], [204, 204, 204, 0]]);
+
+        assert!(decode_pnm(b"P6\n0 1\n255\n").is_err());
+        assert!(decode_pnm(b"P5\n2 x\n255\n").is_err());
+    }
+
+    #[test]
+    fn test_qoi() {
+        let mut data = b"qoif".to_vec();
+        data.extend_from_slice(&7u32.to_be_bytes());
+        data.extend_from_slice(&1u32.to_be_bytes());
+        data.extend_from_slice(&[4, 0]);
+        data.extend_from_slice(&[
+            0xff, 10, 20, 30, 255, // RGBA
+            0x76, // DIFF: red +1, green -1
+            0xaa, 0x5a, // LUMA: green +10, red +7, blue +12
+            0xfe, 1, 2, 3, // RGB
+            0x09, // INDEX of the first pixel
+            0xc1, // RUN of 2
+        ]);
+        data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+
+        assert_pixels(
+            &decode_qoi(&data).unwrap(),
+            7,
+            1,
+            &[
+                [10, 20, 30, 255],
+                [11, 19, 30, 255],
+                [18, 29, 42, 255],
+                [1, 2, 3, 255],
+                [10, 20, 30, 255],
+                [10, 20, 30, 255],
+                [10, 20, 30, 255],
+            ],
+        );
+        assert!(decode_qoi(&data[..20]).is_err());
+    }
+
+    #[test]
+    fn test_scaled() {
+        let gray = |v: f32| [v, v, v, 1.0];
+        let image = Image {
+            width: 2,
+            height: 1,
+            pixels: vec![gray(0.0), gray(1.0)],
+        };
+
+        let larger = image.scaled(2.0).unwrap();
+        assert_eq!((larger.width, larger.height), (4, 2));
+        assert_eq!(larger.pixels[..4], [gray(0.0), gray(0.25), gray(0.75), gray(1.0)]);
+
+        let smaller = image.scaled(0.5).unwrap();
+        assert_eq!((smaller.width, smaller.height), (1, 1));
+        assert_eq!(smaller.pixels, [gray(0.5)]);
+
+        let wide = Image {
+            width: 1 << 16,
+            height: 1,
+            pixels: vec![gray(0.0); 1 << 16],
+        };
+        assert!(wide.scaled(1000.0).is_err());
+    }
+}
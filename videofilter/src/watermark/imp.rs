@@ -0,0 +1,362 @@
+use std::path::Path;
+
+use gst::{glib, gst_debug, gst_info, gst_log, BufferRef, Caps, ErrorMessage, FlowError, FlowSuccess, LoggableError, PadTemplate};
+use gst::subclass::ElementMetadata;
+use gst::subclass::prelude::*;
+use gst::prelude::*;
+use gst_base::prelude::*;
+use gst_base::subclass::{*, prelude::*};
+use parking_lot::Mutex;
+use crate::glib::once_cell::sync::Lazy;
+
+use super::image::{Image, Overlay};
+use crate::videofilter::get_all_video_formats;
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rswatermark",
+        gst::DebugColorFlags::empty(),
+        Some("Watermark overlay"),
+    )
+});
+
+const DEFAULT_X: i32 = 0;
+const DEFAULT_Y: i32 = 0;
+const DEFAULT_SCALE: f64 = 1.0;
+const DEFAULT_OPACITY: f64 = 1.0;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    location: Option<String>,
+    x: i32,
+    y: i32,
+    scale: f64,
+    opacity: f64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            location: None,
+            x: DEFAULT_X,
+            y: DEFAULT_Y,
+            scale: DEFAULT_SCALE,
+            opacity: DEFAULT_OPACITY,
+        }
+    }
+}
+
+struct State {
+    info: gst_video::VideoInfo,
+    /// The image converted to the negotiated format, with the location and
+    /// scale it was made for, so that it is made again when the settings
+    /// change while it is being made.
+    overlay: Option<(String, f64, Overlay)>,
+}
+
+#[derive(Default)]
+pub struct Watermark {
+    settings: Mutex<Settings>,
+    state: Mutex<Option<State>>,
+    /// Decoded image and its location, kept across caps changes.
+    image: Mutex<Option<(String, Image)>>,
+}
+
+impl Watermark {
+    /// Forgets the converted image, and the decoded one too if `reload`.
+    fn invalidate(&self, reload: bool) {
+        if reload {
+            let _ = self.image.lock().take();
+        }
+        if let Some(state) = self.state.lock().as_mut() {
+            state.overlay = None;
+        }
+    }
+
+    fn overlay(&self, element: &super::Watermark, location: &str, scale: f64, info: &gst_video::VideoInfo) -> Result<Overlay, FlowError> {
+        let mut image = self.image.lock();
+        if image.as_ref().is_none_or(|(loaded, _)| loaded != location) {
+            gst_debug!(CAT, obj: element, "Loading {}", location);
+            let loaded = Image::load(Path::new(location)).map_err(|err| {
+                gst::element_error!(
+                    element,
+                    gst::ResourceError::OpenRead,
+                    ["Failed to load watermark {}: {}", location, err]
+                );
+                FlowError::Error
+            })?;
+            *image = Some((String::from(location), loaded));
+        }
+
+        let (_, image) = image.as_ref().unwrap();
+        let image = image.scaled(scale).map_err(|err| {
+            gst::element_error!(element, gst::CoreError::Failed, ["Failed to scale watermark: {}", err]);
+            FlowError::Error
+        })?;
+        gst_debug!(CAT, obj: element, "Converting {}x{} watermark to {}", image.width, image.height, info.format().to_str());
+
+        Overlay::new(&image, info).map_err(|err| {
+            gst::element_error!(element, gst::CoreError::Failed, ["Failed to convert watermark: {}", err]);
+            FlowError::Error
+        })
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for Watermark {
+    const NAME: &'static str = "RsWatermark";
+    type Type = super::Watermark;
+    type ParentType = gst_base::BaseTransform;
+}
+
+impl ElementImpl for Watermark {
+    fn metadata() -> Option<&'static ElementMetadata> {
+        static METADATA: Lazy<ElementMetadata> = Lazy::new(|| {
+            ElementMetadata::new(
+                "Watermark",
+                "Filter/Effect/Video",
+                "Blend a PNG, PPM or QOI image onto the frames",
+                "Seiichi Uchida <topecongiro@fastmail.com>",
+            )
+        });
+
+        Some(&*METADATA)
+    }
+
+    fn pad_templates() -> &'static [PadTemplate] {
+        static PAD_TEMPLATE: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let caps = gst::Caps::builder("video/x-raw")
+                .field("format", gst::List::from(get_all_video_formats()))
+                .field("width", gst::IntRange::new(1, i32::MAX))
+                .field("height", gst::IntRange::new(1, i32::MAX))
+                .field("framerate", gst::FractionRange::new(
+                    gst::Fraction::new(0, 1),
+                    gst::Fraction::new(i32::MAX, 1),
+                ))
+                .build();
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &caps,
+            ).unwrap();
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &caps,
+            ).unwrap();
+
+            vec![sink_pad_template, src_pad_template]
+        });
+
+        PAD_TEMPLATE.as_ref()
+    }
+}
+
+impl BaseTransformImpl for Watermark {
+    const MODE: BaseTransformMode = BaseTransformMode::AlwaysInPlace;
+    const PASSTHROUGH_ON_SAME_CAPS: bool = false;
+    const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+
+    fn set_caps(&self, element: &Self::Type, incaps: &Caps, outcaps: &Caps) -> Result<(), LoggableError> {
+        let info = gst_video::VideoInfo::from_caps(incaps)
+            .map_err(|_| gst::loggable_error!(CAT, "Failed to parse input caps"))?;
+
+        gst_debug!(CAT, obj: element, "Configured for caps {} to {}", incaps, outcaps);
+
+        *self.state.lock() = Some(State { info, overlay: None });
+
+        Ok(())
+    }
+
+    fn stop(&self, element: &Self::Type) -> Result<(), ErrorMessage> {
+        let _ = self.state.lock().take();
+
+        gst_info!(CAT, obj: element, "Stopped");
+
+        Ok(())
+    }
+
+    fn transform_ip(&self, element: &Self::Type, buf: &mut BufferRef) -> Result<FlowSuccess, FlowError> {
+        gst_log!(CAT, obj: element, "transform_ip: {:?}", buf);
+
+        // Updates the controlled x, y and opacity.
+        let stream_time = element
+            .segment()
+            .downcast_ref::<gst::ClockTime>()
+            .and_then(|segment| segment.to_stream_time(buf.pts()));
+        if let Some(stream_time) = stream_time {
+            let _ = element.sync_values(stream_time);
+        }
+
+        let settings = self.settings.lock().clone();
+        let location = match settings.location {
+            Some(ref location) if settings.opacity > 0.0 => location,
+            _ => return Ok(FlowSuccess::Ok),
+        };
+
+        let mut state_guard = self.state.lock();
+        let state = state_guard.as_mut().ok_or_else(|| {
+            gst::element_error!(element, gst::CoreError::Negotiation, ["Have no state yet"]);
+            FlowError::NotNegotiated
+        })?;
+        let up_to_date = state
+            .overlay
+            .as_ref()
+            .is_some_and(|(made_for, scale, _)| made_for == location && *scale == settings.scale);
+        if !up_to_date {
+            let overlay = self.overlay(element, location, settings.scale, &state.info)?;
+            state.overlay = Some((location.clone(), settings.scale, overlay));
+        }
+        let (_, _, overlay) = state.overlay.as_ref().unwrap();
+
+        let mut frame = gst_video::VideoFrameRef::from_buffer_ref_writable(buf, &state.info).map_err(|err| {
+            gst::element_error!(
+                element,
+                gst::CoreError::Failed,
+                [&format!("Failed to map buffer writable: {}", err)]
+            );
+            FlowError::Error
+        })?;
+        overlay.blend(&mut frame, settings.x, settings.y, settings.opacity as f32);
+
+        Ok(FlowSuccess::Ok)
+    }
+}
+
+impl GstObjectImpl for Watermark {}
+
+impl ObjectImpl for Watermark {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecString::new(
+                    "location",
+                    "Location",
+                    "PNG, PPM, PGM, PAM or QOI image to blend",
+                    None,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecInt::new(
+                    "x",
+                    "X",
+                    "Horizontal position of the left edge of the image, in luma samples",
+                    i32::MIN,
+                    i32::MAX,
+                    DEFAULT_X,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING | gst::PARAM_FLAG_CONTROLLABLE,
+                ),
+                glib::ParamSpecInt::new(
+                    "y",
+                    "Y",
+                    "Vertical position of the top edge of the image, in luma samples",
+                    i32::MIN,
+                    i32::MAX,
+                    DEFAULT_Y,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING | gst::PARAM_FLAG_CONTROLLABLE,
+                ),
+                glib::ParamSpecDouble::new(
+                    "scale",
+                    "Scale",
+                    "Scale factor of the image",
+                    0.001,
+                    1000.0,
+                    DEFAULT_SCALE,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecDouble::new(
+                    "opacity",
+                    "Opacity",
+                    "Opacity of the image, multiplied with its alpha",
+                    0.0,
+                    1.0,
+                    DEFAULT_OPACITY,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING | gst::PARAM_FLAG_CONTROLLABLE,
+                ),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(&self, obj: &Self::Type, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        match pspec.name() {
+            "location" => {
+                let mut settings = self.settings.lock();
+                let location = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing location from {:?} to {:?}",
+                    settings.location, location
+                );
+                settings.location = location;
+                drop(settings);
+
+                self.invalidate(true);
+            }
+            // The controllable properties may change on every frame, so
+            // they are only logged at the log level.
+            "x" => {
+                let mut settings = self.settings.lock();
+                let x = value.get().unwrap();
+                gst_log!(CAT, obj: obj, "Changing x from {} to {}", settings.x, x);
+                settings.x = x;
+            }
+            "y" => {
+                let mut settings = self.settings.lock();
+                let y = value.get().unwrap();
+                gst_log!(CAT, obj: obj, "Changing y from {} to {}", settings.y, y);
+                settings.y = y;
+            }
+            "scale" => {
+                let mut settings = self.settings.lock();
+                let scale = value.get().unwrap();
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing scale from {} to {}",
+                    settings.scale, scale
+                );
+                settings.scale = scale;
+                drop(settings);
+
+                self.invalidate(false);
+            }
+            "opacity" => {
+                let mut settings = self.settings.lock();
+                let opacity = value.get().unwrap();
+                gst_log!(CAT, obj: obj, "Changing opacity from {} to {}", settings.opacity, opacity);
+                settings.opacity = opacity;
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn property(&self, _obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        match pspec.name() {
+            "location" => {
+                let settings = self.settings.lock();
+                settings.location.to_value()
+            }
+            "x" => {
+                let settings = self.settings.lock();
+                settings.x.to_value()
+            }
+            "y" => {
+                let settings = self.settings.lock();
+                settings.y.to_value()
+            }
+            "scale" => {
+                let settings = self.settings.lock();
+                settings.scale.to_value()
+            }
+            "opacity" => {
+                let settings = self.settings.lock();
+                settings.opacity.to_value()
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
@@ -0,0 +1,452 @@
+use std::str::FromStr;
+
+use gst::prelude::*;
+
+fn init() {
+    use std::sync::Once;
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        gst::init().unwrap();
+        videofilter::plugin_register_static().expect("videofilter test");
+    });
+}
+
+fn push_frame(h: &mut gst_check::Harness, caps: &str) -> Result<gst::FlowSuccess, gst::FlowError> {
+    let caps = gst::Caps::from_str(caps).unwrap();
+    let info = gst_video::VideoInfo::from_caps(&caps).unwrap();
+
+    h.set_src_caps(caps);
+    h.push(gst::Buffer::with_size(info.size()).unwrap())
+}
+
+fn output_caps(h: &gst_check::Harness) -> gst::Caps {
+    h.sinkpad().unwrap().current_caps().unwrap()
+}
+
+#[test]
+fn test_supported_format_passes_through() {
+    init();
+
+    let caps = "video/x-raw,format=I420,width=320,height=240,framerate=30/1";
+    let mut h = gst_check::Harness::new("videofilter");
+
+    assert_eq!(push_frame(&mut h, caps), Ok(gst::FlowSuccess::Ok));
+    h.pull().unwrap();
+
+    assert_eq!(output_caps(&h), gst::Caps::from_str(caps).unwrap());
+}
+
+#[test]
+fn test_unsupported_format_is_refused() {
+    init();
+
+    let caps = "video/x-raw,format=BGRx,width=320,height=240,framerate=30/1";
+    let mut h = gst_check::Harness::new("videofilter");
+
+    assert_eq!(push_frame(&mut h, caps), Err(gst::FlowError::NotNegotiated));
+}
+
+#[test]
+fn test_downstream_filter_is_honoured() {
+    init();
+
+    let mut h = gst_check::Harness::new("videofilter");
+    h.set_sink_caps_str("video/x-raw,format=BGRx");
+
+    let caps = "video/x-raw,format=I420,width=320,height=240,framerate=30/1";
+    assert_eq!(push_frame(&mut h, caps), Err(gst::FlowError::NotNegotiated));
+}
+
+#[test]
+fn test_mid_stream_caps_change() {
+    init();
+
+    let mut h = gst_check::Harness::new("videofilter");
+
+    let caps = "video/x-raw,format=I420,width=320,height=240,framerate=30/1";
+    assert_eq!(push_frame(&mut h, caps), Ok(gst::FlowSuccess::Ok));
+    h.pull().unwrap();
+    assert_eq!(output_caps(&h), gst::Caps::from_str(caps).unwrap());
+
+    let caps = "video/x-raw,format=GRAY8,width=160,height=120,framerate=30/1";
+    assert_eq!(push_frame(&mut h, caps), Ok(gst::FlowSuccess::Ok));
+    let buffer = h.pull().unwrap();
+    assert_eq!(output_caps(&h), gst::Caps::from_str(caps).unwrap());
+    assert_eq!(buffer.size(), 160 * 120);
+}
+
+#[test]
+fn test_connect_frame() {
+    use std::sync::{Arc, Mutex};
+
+    use videofilter::videofilter::{FrameTimestamps, VideoFilter};
+
+    init();
+
+    let mut h = gst_check::Harness::new("videofilter");
+    let filter = h.element().unwrap().downcast::<VideoFilter>().unwrap();
+
+    let frames = Arc::new(Mutex::new(Vec::new()));
+    let frames_clone = frames.clone();
+    filter.connect_frame(move |_, frame, timestamps| {
+        frames_clone.lock().unwrap().push((
+            frame.format(),
+            frame.width(),
+            frame.plane_data(0).unwrap()[0],
+            *timestamps,
+        ));
+    });
+
+    h.set_src_caps_str("video/x-raw,format=GRAY8,width=4,height=2,framerate=25/1");
+    let mut buffer = gst::Buffer::from_mut_slice(vec![7u8; 4 * 2]);
+    {
+        let buffer = buffer.get_mut().unwrap();
+        buffer.set_pts(gst::ClockTime::SECOND);
+        buffer.set_duration(gst::ClockTime::from_mseconds(40));
+    }
+    h.push_and_pull(buffer.clone()).unwrap();
+
+    let expected = FrameTimestamps {
+        pts: Some(gst::ClockTime::SECOND),
+        dts: None,
+        duration: Some(gst::ClockTime::from_mseconds(40)),
+    };
+    assert_eq!(
+        *frames.lock().unwrap(),
+        [(gst_video::VideoFormat::Gray8, 4, 7, expected)]
+    );
+
+    filter.set_property("signal-handoffs", false);
+    h.push_and_pull(buffer).unwrap();
+    assert_eq!(frames.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn test_convert_to_downstream_format() {
+    init();
+
+    let mut h = gst_check::Harness::new("videofilter");
+    h.set_sink_caps_str("video/x-raw,format=GRAY8");
+
+    let caps = gst::Caps::from_str("video/x-raw,format=I420,width=16,height=8,framerate=30/1").unwrap();
+    let info = gst_video::VideoInfo::from_caps(&caps).unwrap();
+    h.set_src_caps(caps);
+
+    // Limited range white in, full range white out.
+    let mut buffer = gst::Buffer::with_size(info.size()).unwrap();
+    {
+        let buffer = buffer.get_mut().unwrap();
+        let mut map = buffer.map_writable().unwrap();
+        let (y, uv) = map.split_at_mut(info.offset()[1]);
+        y.fill(235);
+        uv.fill(128);
+    }
+    assert_eq!(h.push(buffer), Ok(gst::FlowSuccess::Ok));
+
+    let buffer = h.pull().unwrap();
+    let out_info = gst_video::VideoInfo::from_caps(&output_caps(&h)).unwrap();
+    assert_eq!(out_info.format(), gst_video::VideoFormat::Gray8);
+
+    let map = buffer.map_readable().unwrap();
+    assert!(map.iter().take(16 * 8).all(|&v| v == 255));
+}
+
+#[test]
+fn test_scale_to_downstream_size() {
+    init();
+
+    let mut h = gst_check::Harness::new("videofilter");
+    h.set_sink_caps_str("video/x-raw,width=160");
+
+    let caps = "video/x-raw,format=GRAY8,width=320,height=240,framerate=30/1,pixel-aspect-ratio=1/1";
+    assert_eq!(push_frame(&mut h, caps), Ok(gst::FlowSuccess::Ok));
+
+    // The display aspect ratio is kept.
+    let buffer = h.pull().unwrap();
+    let out_info = gst_video::VideoInfo::from_caps(&output_caps(&h)).unwrap();
+    assert_eq!((out_info.width(), out_info.height()), (160, 120));
+    assert_eq!(buffer.size(), 160 * 120);
+}
+
+#[test]
+fn test_crop_with_meta() {
+    init();
+
+    let mut h = gst_check::Harness::new("videofilter");
+    h.add_propose_allocation_meta(gst_video::VideoMeta::meta_api(), None);
+    h.add_propose_allocation_meta(gst_video::VideoCropMeta::meta_api(), None);
+    let filter = h.element().unwrap();
+    filter.set_property("crop-left", 2u32);
+    filter.set_property("crop-top", 1u32);
+    h.set_src_caps_str("video/x-raw,format=GRAY8,width=8,height=4,framerate=25/1");
+
+    // The input is pushed as it is, with the crop rectangle.
+    let input: Vec<u8> = (0..8 * 4).collect();
+    let buffer = h.push_and_pull(gst::Buffer::from_mut_slice(input.clone())).unwrap();
+    assert_eq!(buffer.meta::<gst_video::VideoCropMeta>().unwrap().rect(), (2, 1, 6, 3));
+    assert_eq!(*buffer.map_readable().unwrap(), input[..]);
+
+    let out_info = gst_video::VideoInfo::from_caps(&output_caps(&h)).unwrap();
+    assert_eq!((out_info.width(), out_info.height()), (6, 3));
+
+    // Flipping cannot be expressed with the meta, so frames are processed
+    // again.
+    filter.set_property_from_str("flip", "horizontal");
+    let buffer = h.push_and_pull(gst::Buffer::from_mut_slice(input)).unwrap();
+    assert!(buffer.meta::<gst_video::VideoCropMeta>().is_none());
+
+    let map = buffer.map_readable().unwrap();
+    for y in 0..3 {
+        for x in 0..6 {
+            assert_eq!(map[y * 8 + x], ((y + 1) * 8 + 7 - x) as u8, "at {}x{}", x, y);
+        }
+    }
+}
+
+/// Pushes a 4x2 GRAY8 frame of `value` and returns the first output sample.
+fn push_gray(h: &mut gst_check::Harness, value: u8, discont: bool) -> u8 {
+    let mut buffer = gst::Buffer::from_mut_slice(vec![value; 4 * 2]);
+    if discont {
+        buffer.get_mut().unwrap().set_flags(gst::BufferFlags::DISCONT);
+    }
+
+    h.push_and_pull(buffer).unwrap().map_readable().unwrap()[0]
+}
+
+#[test]
+fn test_denoise_history_is_reset() {
+    init();
+
+    let mut h = gst_check::Harness::new("videofilter");
+    {
+        let filter = h.element().unwrap();
+        filter.set_property_from_str("denoise", "window");
+        filter.set_property("denoise-frames", 2u32);
+        filter.set_property("denoise-motion-threshold", 0u32);
+    }
+    h.set_src_caps_str("video/x-raw,format=GRAY8,width=4,height=2,framerate=25/1");
+
+    assert_eq!(push_gray(&mut h, 100, false), 100);
+    assert_eq!(push_gray(&mut h, 110, false), 105);
+
+    assert_eq!(push_gray(&mut h, 120, true), 120);
+
+    assert!(h.push_event(gst::event::FlushStart::new()));
+    assert!(h.push_event(gst::event::FlushStop::new(true)));
+    assert!(h.push_event(gst::event::Segment::new(&gst::FormattedSegment::<gst::ClockTime>::new())));
+    assert_eq!(push_gray(&mut h, 130, false), 130);
+
+    h.set_src_caps_str("video/x-raw,format=GRAY8,width=4,height=2,framerate=30/1");
+    assert_eq!(push_gray(&mut h, 140, false), 140);
+    assert_eq!(push_gray(&mut h, 150, false), 145);
+}
+
+/// A white GRAY8 frame with a 4 pixel black border on the left.
+fn left_border_frame(width: usize, discont: bool) -> gst::Buffer {
+    let mut buffer = gst::Buffer::from_mut_slice((0..width * 8).map(|i| if i % width < 4 { 0 } else { 255 }).collect::<Vec<u8>>());
+    if discont {
+        buffer.get_mut().unwrap().set_flags(gst::BufferFlags::DISCONT);
+    }
+    buffer
+}
+
+#[test]
+fn test_crop_detect_resets() {
+    init();
+
+    let mut h = gst_check::Harness::new("rscropdetect");
+    {
+        let detect = h.element().unwrap();
+        detect.set_property_from_str("mode", "apply");
+        detect.set_property("window", 2u32);
+    }
+    h.set_src_caps_str("video/x-raw,format=GRAY8,width=16,height=8,framerate=25/1");
+
+    // The border is cropped from the frame after the window, and detected
+    // again after a discontinuity.
+    let widths: Vec<u32> = [false, false, false, true, false, false]
+        .iter()
+        .map(|&discont| {
+            h.push_and_pull(left_border_frame(16, discont)).unwrap();
+            gst_video::VideoInfo::from_caps(&output_caps(&h)).unwrap().width()
+        })
+        .collect();
+    assert_eq!(widths, [16, 16, 12, 12, 16, 12]);
+
+    // The border of another size is not applied.
+    h.set_src_caps_str("video/x-raw,format=GRAY8,width=20,height=8,framerate=25/1");
+    h.push_and_pull(left_border_frame(20, false)).unwrap();
+    assert_eq!(gst_video::VideoInfo::from_caps(&output_caps(&h)).unwrap().width(), 20);
+}
+
+/// Luma of a black 16x16 frame pushed through `rsprivacymask` filling with
+/// white, with a "face" region at 0x0 and a "plate" region at 8x8, both 4x4.
+fn privacy_mask_frame(h: &mut gst_check::Harness) -> Vec<u8> {
+    let mut buffer = gst::Buffer::from_mut_slice(vec![0u8; 16 * 16]);
+    gst_video::VideoRegionOfInterestMeta::add(buffer.get_mut().unwrap(), "face", (0, 0, 4, 4));
+    gst_video::VideoRegionOfInterestMeta::add(buffer.get_mut().unwrap(), "plate", (8, 8, 4, 4));
+
+    h.push_and_pull(buffer).unwrap().map_readable().unwrap().to_vec()
+}
+
+fn masked_pixels(luma: &[u8]) -> Vec<(usize, usize)> {
+    (0..16 * 16).filter(|&i| luma[i] != 0).map(|i| (i % 16, i / 16)).collect()
+}
+
+fn square(x: usize, y: usize, size: usize) -> Vec<(usize, usize)> {
+    (y..y + size).flat_map(|y| (x..x + size).map(move |x| (x, y))).collect()
+}
+
+#[test]
+fn test_privacy_mask_regions() {
+    init();
+
+    let mut h = gst_check::Harness::new("rsprivacymask");
+    h.set_src_caps_str("video/x-raw,format=GRAY8,width=16,height=16,framerate=25/1");
+    let mask = h.element().unwrap();
+    mask.set_property_from_str("method", "fill");
+    mask.set_property("fill-color", 0xffff_ffffu32);
+
+    let mut both = square(0, 0, 4);
+    both.extend(square(8, 8, 4));
+    assert_eq!(masked_pixels(&privacy_mask_frame(&mut h)), both);
+
+    mask.set_property("roi-type", "plate");
+    assert_eq!(masked_pixels(&privacy_mask_frame(&mut h)), square(8, 8, 4));
+
+    mask.set_property("use-roi-meta", false);
+    mask.set_property("rectangles", "12,0,2,2");
+    assert_eq!(masked_pixels(&privacy_mask_frame(&mut h)), square(12, 0, 2));
+
+    // Invalid rectangles are rejected and the previous ones kept.
+    mask.set_property("rectangles", "12,0,2");
+    assert_eq!(mask.property::<Option<String>>("rectangles").as_deref(), Some("12,0,2,2"));
+    assert_eq!(masked_pixels(&privacy_mask_frame(&mut h)), square(12, 0, 2));
+}
+
+/// Luma of a black 16x16 frame pushed through `rsroioverlay` with a "face"
+/// region at 4x10 of size 8x6.
+fn roi_overlay_frame(show_labels: bool) -> Vec<u8> {
+    let mut h = gst_check::Harness::new("rsroioverlay");
+    {
+        let overlay = h.element().unwrap();
+        overlay.set_property("color", 0xffff_ffffu32);
+        overlay.set_property("line-width", 1u32);
+        overlay.set_property("show-labels", show_labels);
+    }
+
+    h.set_src_caps_str("video/x-raw,format=GRAY8,width=16,height=16,framerate=25/1");
+    let mut buffer = gst::Buffer::from_mut_slice(vec![0u8; 16 * 16]);
+    gst_video::VideoRegionOfInterestMeta::add(buffer.get_mut().unwrap(), "face", (4, 10, 8, 6));
+
+    h.push_and_pull(buffer).unwrap().map_readable().unwrap().to_vec()
+}
+
+#[test]
+fn test_roi_overlay_draws_regions() {
+    init();
+
+    let luma = roi_overlay_frame(false);
+    for y in 0..16 {
+        for x in 0..16 {
+            let outline = (4..12).contains(&x) && (10..16).contains(&y) && (x == 4 || x == 11 || y == 10 || y == 15);
+            assert_eq!(luma[y * 16 + x], if outline { 255 } else { 0 }, "at {}x{}", x, y);
+        }
+    }
+
+    // The label goes above the region.
+    let labelled = roi_overlay_frame(true);
+    assert_eq!(labelled[10 * 16..], luma[10 * 16..]);
+    assert!(labelled[..10 * 16].iter().any(|&v| v != 0));
+}
+
+#[test]
+fn test_scopes_interval_framerate() {
+    init();
+
+    let mut h = gst_check::Harness::new("rsscopes");
+    h.element().unwrap().set_property("interval", i32::MAX as u32);
+    h.set_src_caps_str("video/x-raw,format=GRAY8,width=16,height=8,framerate=30/1");
+
+    h.push_and_pull(gst::Buffer::from_mut_slice(vec![0u8; 16 * 8])).unwrap();
+    let s = output_caps(&h).structure(0).unwrap().to_owned();
+    assert_eq!(s.get::<gst::Fraction>("framerate").unwrap(), gst::Fraction::new(30, i32::MAX));
+
+    // Multiplying an output framerate by the interval overflows, which
+    // leaves the input framerate open instead of panicking.
+    let filter = gst::Caps::from_str("video/x-raw,framerate=30/1").unwrap();
+    h.element().unwrap().static_pad("src").unwrap().query_caps(Some(&filter));
+}
+
+fn test_source_frames(format: &str, pattern: &str, seed: u32) -> Vec<Vec<u8>> {
+    let mut h = gst_check::Harness::with_padnames("rsvideotestsrc", None, Some("src"));
+    {
+        let src = h.element().unwrap();
+        src.set_property_from_str("pattern", pattern);
+        src.set_property("seed", seed);
+        src.set_property("num-buffers", 2i32);
+    }
+    h.set_sink_caps_str(&format!("video/x-raw,format={},width=64,height=48,framerate=30/1", format));
+    h.play();
+
+    (0..2)
+        .map(|_| h.pull().unwrap().map_readable().unwrap().to_vec())
+        .collect()
+}
+
+#[test]
+fn test_source_is_reproducible() {
+    init();
+
+    for format in ["I420", "GBR_10LE", "A444_10BE", "BGRx"] {
+        for pattern in ["bars", "gradient", "checkers", "moving-box", "solid", "noise"] {
+            assert_eq!(
+                test_source_frames(format, pattern, 7),
+                test_source_frames(format, pattern, 7),
+                "{} {}",
+                format,
+                pattern
+            );
+        }
+    }
+
+    // The noise changes with the frame and the seed.
+    let frames = test_source_frames("GRAY8", "noise", 7);
+    assert_ne!(frames[0], frames[1]);
+    assert_ne!(frames, test_source_frames("GRAY8", "noise", 8));
+}
+
+/// CRC32 as in zlib.
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ u32::from(byte), |crc, _| (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg()))
+    })
+}
+
+#[test]
+fn test_source_golden_frames() {
+    init();
+
+    // CRC32 of frames 0 and 1 with seed 7. BGRx and GBR hold the 8-bit
+    // pattern samples as they are, so these only change with the patterns.
+    for (format, pattern, expected) in [
+        ("BGRx", "bars", [0x63d9a673, 0x63d9a673]),
+        ("BGRx", "gradient", [0x389ad1fa, 0x389ad1fa]),
+        ("BGRx", "checkers", [0xfe4d2e48, 0xfe4d2e48]),
+        ("BGRx", "moving-box", [0x52dd09e6, 0xd6917a8d]),
+        ("BGRx", "solid", [0xf1f68679, 0xf1f68679]),
+        ("BGRx", "noise", [0x4ecb77c5, 0x5782e183]),
+        ("GBR", "bars", [0xa3af7c89, 0xa3af7c89]),
+        ("GBR", "gradient", [0x89a0b19f, 0x89a0b19f]),
+        ("GBR", "checkers", [0x67393786, 0x67393786]),
+        ("GBR", "moving-box", [0x0edce4f5, 0xf0a85836]),
+        ("GBR", "solid", [0x3f1e2345, 0x3f1e2345]),
+        ("GBR", "noise", [0xb1eef7ac, 0xa35abd9e]),
+    ] {
+        let crcs: Vec<u32> = test_source_frames(format, pattern, 7).iter().map(|frame| crc32(frame)).collect();
+        assert_eq!(crcs, expected, "{} {}", format, pattern);
+    }
+
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+}
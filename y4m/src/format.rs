@@ -0,0 +1,555 @@
+//! The YUV4MPEG2 stream header and frame layout.
+//!
+//! A stream is a single header line followed by frames, each being a
+//! `FRAME` line and the planes one after the other without padding, Y' then
+//! Cb, Cr and alpha. Samples deeper than 8 bits take two little-endian
+//! bytes. GStreamer has no planar 16-bit 4:2:0 or 4:2:2 formats, so 4:2:0
+//! goes through the semi-planar P016_LE, with Cb and Cr interleaved, and
+//! 4:2:2 is demuxed to 4:4:4 with every chroma sample doubled. The latter is
+//! muxed back as 4:4:4.
+
+use std::fmt;
+
+use gst::BufferRef;
+use gst_video::{VideoChromaSite, VideoColorRange, VideoFrameRef, VideoInfo, VideoInterlaceMode};
+
+pub const MAGIC: &[u8] = b"YUV4MPEG2";
+pub const FRAME_MAGIC: &[u8] = b"FRAME";
+
+/// Whether `data` starts a YUV4MPEG2 stream.
+pub fn is_y4m(data: &[u8]) -> bool {
+    data.starts_with(MAGIC) && data.get(MAGIC.len()) == Some(&b' ')
+}
+
+/// `C` tags and the formats they map to.
+const COLORSPACES: &[(&str, &str)] = &[
+    ("mono", "GRAY8"),
+    ("mono16", "GRAY16_LE"),
+    ("420jpeg", "I420"),
+    ("420mpeg2", "I420"),
+    ("420paldv", "I420"),
+    ("420", "I420"),
+    ("411", "Y41B"),
+    ("422", "Y42B"),
+    ("444", "Y444"),
+    ("420p10", "I420_10LE"),
+    ("420p12", "I420_12LE"),
+    ("422p10", "I422_10LE"),
+    ("422p12", "I422_12LE"),
+    ("444p10", "Y444_10LE"),
+    ("444p12", "Y444_12LE"),
+    ("444p16", "Y444_16LE"),
+    ("420p16", "P016_LE"),
+    // After 444p16, which is what Y444_16LE is muxed to.
+    ("422p16", "Y444_16LE"),
+];
+
+/// Names of the formats of `COLORSPACES`.
+pub fn formats() -> Vec<&'static str> {
+    let mut formats = Vec::new();
+    for (_, format) in COLORSPACES {
+        if !formats.contains(format) {
+            formats.push(*format);
+        }
+    }
+    formats
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interlace {
+    Progressive,
+    TopFieldFirst,
+    BottomFieldFirst,
+    Mixed,
+}
+
+impl Interlace {
+    fn tag(self) -> char {
+        match self {
+            Interlace::Progressive => 'p',
+            Interlace::TopFieldFirst => 't',
+            Interlace::BottomFieldFirst => 'b',
+            Interlace::Mixed => 'm',
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub width: u32,
+    pub height: u32,
+    pub fps: gst::Fraction,
+    pub par: gst::Fraction,
+    pub interlace: Interlace,
+    /// `C` tag.
+    pub colorspace: &'static str,
+    /// `XCOLORRANGE`, if known.
+    pub full_range: Option<bool>,
+}
+
+fn parse_ratio(value: &str) -> Result<gst::Fraction, String> {
+    let (numer, denom) = value
+        .split_once(':')
+        .ok_or_else(|| format!("Invalid ratio {:?}", value))?;
+    let numer = numer.parse().map_err(|err| format!("Invalid ratio {:?}: {}", value, err))?;
+    let denom = denom.parse().map_err(|err| format!("Invalid ratio {:?}: {}", value, err))?;
+    Ok(gst::Fraction::new(numer, denom))
+}
+
+impl Header {
+    /// Parses a header line, without its newline.
+    pub fn parse(line: &[u8]) -> Result<Self, String> {
+        if !line.is_ascii() {
+            return Err(String::from("Header is not ASCII"));
+        }
+        let line = std::str::from_utf8(line).unwrap();
+        let mut tokens = line.split(' ').filter(|token| !token.is_empty());
+        if tokens.next().map(str::as_bytes) != Some(MAGIC) {
+            return Err(String::from("Not a YUV4MPEG2 stream"));
+        }
+
+        let mut header = Header {
+            width: 0,
+            height: 0,
+            fps: gst::Fraction::new(0, 1),
+            par: gst::Fraction::new(1, 1),
+            interlace: Interlace::Progressive,
+            colorspace: "420jpeg",
+            full_range: None,
+        };
+
+        for token in tokens {
+            let (tag, value) = token.split_at(1);
+            match tag {
+                "W" => header.width = value.parse().map_err(|err| format!("Invalid width {:?}: {}", value, err))?,
+                "H" => header.height = value.parse().map_err(|err| format!("Invalid height {:?}: {}", value, err))?,
+                "F" => header.fps = parse_ratio(value)?,
+                "A" => {
+                    // 0:0 is unknown.
+                    let par = parse_ratio(value)?;
+                    if par.numer() > 0 && par.denom() > 0 {
+                        header.par = par;
+                    }
+                }
+                "I" => {
+                    header.interlace = match value {
+                        "p" | "?" => Interlace::Progressive,
+                        "t" => Interlace::TopFieldFirst,
+                        "b" => Interlace::BottomFieldFirst,
+                        "m" => Interlace::Mixed,
+                        _ => return Err(format!("Invalid interlacing {:?}", value)),
+                    }
+                }
+                "C" => {
+                    header.colorspace = COLORSPACES
+                        .iter()
+                        .find(|(tag, _)| *tag == value)
+                        .map(|(tag, _)| *tag)
+                        .ok_or_else(|| format!("Unsupported colourspace {:?}", value))?;
+                }
+                "X" => match value {
+                    "COLORRANGE=FULL" => header.full_range = Some(true),
+                    "COLORRANGE=LIMITED" => header.full_range = Some(false),
+                    _ => (),
+                },
+                _ => (),
+            }
+        }
+
+        if header.width == 0 || header.height == 0 {
+            return Err(String::from("Header has no size"));
+        }
+        if header.fps.numer() < 0 || header.fps.denom() <= 0 {
+            header.fps = gst::Fraction::new(0, 1);
+        }
+
+        Ok(header)
+    }
+
+    /// Header of a stream of `info`. `field_order` is the `field-order`
+    /// field of interleaved caps.
+    pub fn from_info(info: &VideoInfo, field_order: Option<&str>) -> Result<Self, String> {
+        let format = info.format().to_str();
+        let colorspace = if format == "I420" {
+            let chroma_site = info.chroma_site();
+            if chroma_site.contains(VideoChromaSite::ALT_LINE) {
+                "420paldv"
+            } else if chroma_site.contains(VideoChromaSite::H_COSITED) {
+                "420mpeg2"
+            } else {
+                "420jpeg"
+            }
+        } else {
+            COLORSPACES
+                .iter()
+                .find(|(_, f)| *f == format)
+                .map(|(tag, _)| *tag)
+                .ok_or_else(|| format!("Unsupported format {}", format))?
+        };
+
+        let interlace = match info.interlace_mode() {
+            VideoInterlaceMode::Progressive => Interlace::Progressive,
+            VideoInterlaceMode::Mixed => Interlace::Mixed,
+            VideoInterlaceMode::Interleaved => match field_order {
+                Some("bottom-field-first") => Interlace::BottomFieldFirst,
+                _ => Interlace::TopFieldFirst,
+            },
+            mode => return Err(format!("Unsupported interlace mode {:?}", mode)),
+        };
+
+        let full_range = match info.colorimetry().range() {
+            VideoColorRange::Range0_255 => Some(true),
+            VideoColorRange::Range16_235 => Some(false),
+            _ => None,
+        };
+
+        Ok(Header {
+            width: info.width(),
+            height: info.height(),
+            fps: info.fps(),
+            par: info.par(),
+            interlace,
+            colorspace,
+            full_range,
+        })
+    }
+
+    /// Video info and caps of the frames of this stream.
+    pub fn to_caps(&self) -> Result<(VideoInfo, gst::Caps), String> {
+        let format = COLORSPACES
+            .iter()
+            .find(|(tag, _)| *tag == self.colorspace)
+            .map(|(_, format)| gst_video::VideoFormat::from_string(format))
+            .filter(|format| *format != gst_video::VideoFormat::Unknown)
+            .ok_or_else(|| format!("No format for colourspace {}", self.colorspace))?;
+
+        // Only the range is in the header, the rest of the colorimetry is
+        // the default of the format and size.
+        let colorimetry = match self.full_range {
+            Some(full_range) => {
+                let default = VideoInfo::builder(format, self.width, self.height)
+                    .build()
+                    .map_err(|err| err.to_string())?
+                    .colorimetry();
+                let range = if full_range {
+                    VideoColorRange::Range0_255
+                } else {
+                    VideoColorRange::Range16_235
+                };
+                Some(gst_video::VideoColorimetry::new(
+                    range,
+                    default.matrix(),
+                    default.transfer(),
+                    default.primaries(),
+                ))
+            }
+            None => None,
+        };
+
+        let mut builder = VideoInfo::builder(format, self.width, self.height)
+            .fps(self.fps)
+            .par(self.par)
+            .interlace_mode(match self.interlace {
+                Interlace::Progressive => VideoInterlaceMode::Progressive,
+                Interlace::TopFieldFirst | Interlace::BottomFieldFirst => VideoInterlaceMode::Interleaved,
+                Interlace::Mixed => VideoInterlaceMode::Mixed,
+            });
+        builder = match self.colorspace {
+            "420jpeg" | "420" => builder.chroma_site(VideoChromaSite::NONE),
+            "420mpeg2" => builder.chroma_site(VideoChromaSite::H_COSITED),
+            "420paldv" => builder.chroma_site(VideoChromaSite::COSITED | VideoChromaSite::ALT_LINE),
+            _ => builder,
+        };
+        if let Some(colorimetry) = colorimetry.as_ref() {
+            builder = builder.colorimetry(colorimetry);
+        }
+        let info = builder.build().map_err(|err| err.to_string())?;
+
+        let mut caps = info.to_caps().map_err(|err| err.to_string())?;
+        let field_order = match self.interlace {
+            Interlace::TopFieldFirst => Some("top-field-first"),
+            Interlace::BottomFieldFirst => Some("bottom-field-first"),
+            _ => None,
+        };
+        if let Some(field_order) = field_order {
+            caps.get_mut().unwrap().structure_mut(0).unwrap().set("field-order", field_order);
+        }
+
+        Ok((info, caps))
+    }
+}
+
+impl fmt::Display for Header {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "YUV4MPEG2 W{} H{} F{}:{} I{} A{}:{} C{}",
+            self.width,
+            self.height,
+            self.fps.numer(),
+            self.fps.denom(),
+            self.interlace.tag(),
+            self.par.numer(),
+            self.par.denom(),
+            self.colorspace
+        )?;
+        match self.full_range {
+            Some(true) => write!(f, " XCOLORRANGE=FULL"),
+            Some(false) => write!(f, " XCOLORRANGE=LIMITED"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// How the planes of the stream map to the planes of a GStreamer frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Packing {
+    /// One plane per stream plane.
+    Planar,
+    /// Cb and Cr interleaved in the second plane.
+    Interleaved,
+    /// Chroma planes twice as wide, each sample being doubled.
+    Doubled,
+}
+
+/// Frame layout of a stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    /// Width in bytes and height of every plane of the stream.
+    planes: Vec<(usize, usize)>,
+    packing: Packing,
+}
+
+impl Layout {
+    /// Layout of the stream of `header`, whose frames have `info`.
+    pub fn new(header: &Header, info: &VideoInfo) -> Self {
+        let packing = match header.colorspace {
+            "420p16" => Packing::Interleaved,
+            "422p16" => Packing::Doubled,
+            _ => Packing::Planar,
+        };
+
+        let finfo = info.format_info();
+        let bytes = if finfo.depth()[0] > 8 { 2 } else { 1 };
+        let planes = (0..finfo.n_components())
+            .map(|c| {
+                let width = if packing == Packing::Doubled && c > 0 {
+                    info.width().div_ceil(2)
+                } else {
+                    finfo.scale_width(c as u8, info.width())
+                };
+                let height = finfo.scale_height(c as u8, info.height()) as usize;
+                (width as usize * bytes, height)
+            })
+            .collect();
+
+        Layout { planes, packing }
+    }
+
+    /// Size of the frame data, without the `FRAME` line.
+    pub fn frame_size(&self) -> usize {
+        self.planes.iter().map(|(width, height)| width * height).sum()
+    }
+
+    /// Copies the frame data `data` into `frame`.
+    pub fn unpack_frame(&self, data: &[u8], frame: &mut VideoFrameRef<&mut BufferRef>) {
+        let mut data = data;
+        let frame_width = frame.info().width() as usize * 2;
+
+        for (plane, &(width, height)) in self.planes.iter().enumerate() {
+            let (src, rest) = data.split_at(width * height);
+            data = rest;
+
+            let (dst_plane, offset) = match self.packing {
+                Packing::Interleaved if plane > 0 => (1, (plane - 1) * 2),
+                _ => (plane, 0),
+            };
+            let stride = frame.plane_stride()[dst_plane] as usize;
+            let dst = frame.plane_data_mut(dst_plane as u32).unwrap();
+            for (line, src) in dst.chunks_mut(stride).zip(src.chunks_exact(width)) {
+                match self.packing {
+                    Packing::Interleaved if plane > 0 => {
+                        for (dst, src) in line[offset..].chunks_mut(4).zip(src.chunks_exact(2)) {
+                            dst[..2].copy_from_slice(src);
+                        }
+                    }
+                    Packing::Doubled if plane > 0 => {
+                        let line = &mut line[..frame_width];
+                        let samples = src.chunks_exact(2).flat_map(|sample| [sample, sample]);
+                        for (dst, src) in line.chunks_exact_mut(2).zip(samples) {
+                            dst.copy_from_slice(src);
+                        }
+                    }
+                    _ => line[..width].copy_from_slice(src),
+                }
+            }
+        }
+    }
+
+    /// Appends the frame data of `frame` to `out`.
+    pub fn pack_frame(&self, frame: &VideoFrameRef<&BufferRef>, out: &mut Vec<u8>) {
+        for (plane, &(width, height)) in self.planes.iter().enumerate() {
+            let (src_plane, offset) = match self.packing {
+                Packing::Interleaved if plane > 0 => (1, (plane - 1) * 2),
+                _ => (plane, 0),
+            };
+            let stride = frame.plane_stride()[src_plane] as usize;
+            let src = frame.plane_data(src_plane as u32).unwrap();
+            for line in src.chunks(stride).take(height) {
+                match self.packing {
+                    // Every other sample of the chroma, the first of each
+                    // pair being the one of the stream.
+                    Packing::Interleaved | Packing::Doubled if plane > 0 => {
+                        for sample in line[offset..].chunks(4).take(width / 2) {
+                            out.extend_from_slice(&sample[..2]);
+                        }
+                    }
+                    _ => out.extend_from_slice(&line[..width]),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_round_trip() {
+        gst::init().unwrap();
+
+        for line in [
+            "YUV4MPEG2 W320 H240 F30000:1001 Ip A1:1 C420jpeg",
+            "YUV4MPEG2 W720 H576 F25:1 It A16:15 C420paldv XCOLORRANGE=LIMITED",
+            "YUV4MPEG2 W64 H48 F0:1 Ib A4:3 C444p16 XCOLORRANGE=FULL",
+            "YUV4MPEG2 W64 H48 F25:1 Ip A1:1 C422p16",
+            "YUV4MPEG2 W1 H1 F1:1 Im A1:1 Cmono",
+        ] {
+            let header = Header::parse(line.as_bytes()).unwrap();
+            assert_eq!(header.to_string(), line);
+            assert_eq!(Header::parse(header.to_string().as_bytes()).unwrap(), header);
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        gst::init().unwrap();
+
+        // Missing tags take their defaults and unknown ones are skipped.
+        let header = Header::parse(b"YUV4MPEG2  W8 H4 A0:0 I? XYSCSS=420JPEG Zfoo").unwrap();
+        assert_eq!(
+            header,
+            Header {
+                width: 8,
+                height: 4,
+                fps: gst::Fraction::new(0, 1),
+                par: gst::Fraction::new(1, 1),
+                interlace: Interlace::Progressive,
+                colorspace: "420jpeg",
+                full_range: None,
+            }
+        );
+        assert_eq!(header.to_string(), "YUV4MPEG2 W8 H4 F0:1 Ip A1:1 C420jpeg");
+
+        assert!(Header::parse(b"YUV4MPEG W8 H4").is_err());
+        assert!(Header::parse(b"YUV4MPEG2 W8").is_err());
+        assert!(Header::parse(b"YUV4MPEG2 W8 H4 Cfoo").is_err());
+        assert!(Header::parse(b"YUV4MPEG2 W8 H4 Ix").is_err());
+        assert!(Header::parse(b"YUV4MPEG2 W8 H4 F25").is_err());
+        assert!(Header::parse("YUV4MPEG2 W8 H4 \u{e9}".as_bytes()).is_err());
+
+        assert!(is_y4m(b"YUV4MPEG2 W8"));
+        assert!(!is_y4m(b"YUV4MPEG2"));
+    }
+
+    #[test]
+    fn test_caps_round_trip() {
+        gst::init().unwrap();
+
+        // Caps always have a range, so the headers need one too.
+        for line in [
+            "YUV4MPEG2 W320 H240 F30000:1001 Ip A1:1 C420jpeg XCOLORRANGE=LIMITED",
+            "YUV4MPEG2 W320 H240 F25:1 Ip A1:1 C420mpeg2 XCOLORRANGE=FULL",
+            "YUV4MPEG2 W720 H576 F25:1 It A16:15 C420paldv XCOLORRANGE=LIMITED",
+            "YUV4MPEG2 W64 H48 F50:1 Ib A1:1 C422p10 XCOLORRANGE=LIMITED",
+            "YUV4MPEG2 W64 H48 F50:1 Im A1:1 C411 XCOLORRANGE=FULL",
+            "YUV4MPEG2 W64 H48 F50:1 Ip A1:1 Cmono16 XCOLORRANGE=FULL",
+            "YUV4MPEG2 W64 H48 F50:1 Ip A1:1 C420p16 XCOLORRANGE=LIMITED",
+        ] {
+            let header = Header::parse(line.as_bytes()).unwrap();
+            let (info, caps) = header.to_caps().unwrap();
+            let field_order = caps.structure(0).unwrap().get::<&str>("field-order").ok();
+            assert_eq!(Header::from_info(&info, field_order).unwrap(), header, "{}", caps);
+        }
+    }
+
+    /// Frame of `info` unpacked from `data`, and the data packed again.
+    fn unpack_and_pack(layout: &Layout, info: &VideoInfo, data: &[u8]) -> (gst::Buffer, Vec<u8>) {
+        let mut buffer = gst::Buffer::with_size(info.size()).unwrap();
+        {
+            let buffer = buffer.get_mut().unwrap();
+            let mut frame = VideoFrameRef::from_buffer_ref_writable(buffer, info).unwrap();
+            layout.unpack_frame(data, &mut frame);
+        }
+
+        let frame = VideoFrameRef::from_buffer_ref_readable(buffer.as_ref(), info).unwrap();
+        let mut packed = Vec::new();
+        layout.pack_frame(&frame, &mut packed);
+        drop(frame);
+        (buffer, packed)
+    }
+
+    #[test]
+    fn test_frame_round_trip() {
+        gst::init().unwrap();
+
+        // Odd sizes, so that the rows of the frames are padded.
+        for colorspace in ["420jpeg", "444p10", "420p16", "422p16"] {
+            let header = Header::parse(format!("YUV4MPEG2 W5 H3 C{}", colorspace).as_bytes()).unwrap();
+            let (info, _) = header.to_caps().unwrap();
+            let layout = Layout::new(&header, &info);
+            let data: Vec<u8> = (0..layout.frame_size()).map(|i| (i * 7 % 251) as u8).collect();
+
+            let (_, packed) = unpack_and_pack(&layout, &info, &data);
+            assert_eq!(packed, data, "{}", colorspace);
+        }
+
+        let header = Header::parse(b"YUV4MPEG2 W5 H3 C420jpeg").unwrap();
+        let layout = Layout::new(&header, &header.to_caps().unwrap().0);
+        assert_eq!(layout.frame_size(), 5 * 3 + 2 * 3 * 2);
+    }
+
+    #[test]
+    fn test_frame_16_bit_chroma() {
+        gst::init().unwrap();
+
+        // 16-bit samples numbered in stream order: Y' then Cb and Cr.
+        let stream = |count: u16| -> Vec<u8> { (0..count).flat_map(u16::to_le_bytes).collect() };
+        let samples = |data: &[u8]| -> Vec<u16> {
+            data.chunks_exact(2).map(|sample| u16::from_le_bytes([sample[0], sample[1]])).collect()
+        };
+
+        // 4:2:0 has Cb and Cr interleaved.
+        let header = Header::parse(b"YUV4MPEG2 W3 H2 C420p16").unwrap();
+        let (info, _) = header.to_caps().unwrap();
+        assert_eq!(info.format().to_str(), "P016_LE");
+        let layout = Layout::new(&header, &info);
+        assert_eq!(layout.frame_size(), (3 * 2 + 2 * 2) * 2);
+        let (buffer, _) = unpack_and_pack(&layout, &info, &stream(10));
+        let frame = VideoFrameRef::from_buffer_ref_readable(buffer.as_ref(), &info).unwrap();
+        assert_eq!(samples(&frame.plane_data(1).unwrap()[..8]), [6, 8, 7, 9]);
+
+        // 4:2:2 has every chroma sample doubled, and is muxed as 4:4:4.
+        let header = Header::parse(b"YUV4MPEG2 W3 H1 C422p16").unwrap();
+        let (info, _) = header.to_caps().unwrap();
+        assert_eq!(info.format().to_str(), "Y444_16LE");
+        assert_eq!(Header::from_info(&info, None).unwrap().colorspace, "444p16");
+        let layout = Layout::new(&header, &info);
+        assert_eq!(layout.frame_size(), (3 + 2 * 2) * 2);
+        let (buffer, _) = unpack_and_pack(&layout, &info, &stream(7));
+        let frame = VideoFrameRef::from_buffer_ref_readable(buffer.as_ref(), &info).unwrap();
+        assert_eq!(samples(&frame.plane_data(1).unwrap()[..6]), [3, 3, 4]);
+        assert_eq!(samples(&frame.plane_data(2).unwrap()[..6]), [5, 5, 6]);
+    }
+}
@@ -0,0 +1,44 @@
+use gst::glib;
+
+mod format;
+mod y4mdec;
+mod y4menc;
+
+fn plugin_init(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    y4mdec::register(plugin)?;
+    y4menc::register(plugin)?;
+
+    let caps = gst::Caps::builder("application/x-yuv4mpeg")
+        .field("y4mversion", 2i32)
+        .build();
+    let possible_caps = caps.clone();
+    gst::TypeFind::register(
+        Some(plugin),
+        "rsy4m_typefind",
+        gst::Rank::Primary,
+        Some("y4m"),
+        Some(&possible_caps),
+        move |typefind| {
+            let is_y4m = typefind
+                .peek(0, format::MAGIC.len() as u32 + 1)
+                .is_some_and(format::is_y4m);
+            if is_y4m {
+                typefind.suggest(gst::TypeFindProbability::Maximum, &caps);
+            }
+        },
+    )?;
+
+    Ok(())
+}
+
+gst::plugin_define!(
+    y4m,
+    env!("CARGO_PKG_DESCRIPTION"),
+    plugin_init,
+    concat!(env!("CARGO_PKG_VERSION"), "-", env!("COMMIT_ID")),
+    "MIT",
+    env!("CARGO_PKG_NAME"),
+    env!("CARGO_PKG_NAME"),
+    env!("CARGO_PKG_REPOSITORY"),
+    env!("BUILD_REL_DATE")
+);
@@ -0,0 +1,21 @@
+use gst::glib;
+use gst::prelude::*;
+use gst::Plugin;
+
+mod imp;
+
+glib::wrapper! {
+    pub struct Y4mDec(ObjectSubclass<imp::Y4mDec>) @extends gst::Element, gst::Object;
+}
+
+unsafe impl Send for Y4mDec {}
+unsafe impl Sync for Y4mDec {}
+
+pub fn register(plugin: &Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "rsy4mdec",
+        gst::Rank::None,
+        Y4mDec::static_type(),
+    )
+}
@@ -0,0 +1,476 @@
+use gst::{glib, gst_debug, gst_error, gst_info, gst_log, gst_warning, PadTemplate};
+use gst::subclass::ElementMetadata;
+use gst::subclass::prelude::*;
+use gst::prelude::*;
+use parking_lot::Mutex;
+use crate::glib::once_cell::sync::Lazy;
+
+use crate::format::{self, Header, Layout, FRAME_MAGIC};
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rsy4mdec",
+        gst::DebugColorFlags::empty(),
+        Some("YUV4MPEG2 demuxer"),
+    )
+});
+
+/// Longest header or `FRAME` line accepted.
+const MAX_LINE: usize = 4096;
+
+/// What is known after the header.
+struct Stream {
+    info: gst_video::VideoInfo,
+    layout: Layout,
+    /// Size of the header line, newline included.
+    header_size: u64,
+    frame_size: usize,
+}
+
+impl Stream {
+    /// Bytes per frame, assuming `FRAME` lines without parameters, which is
+    /// what seeking relies on.
+    fn frame_stride(&self) -> u64 {
+        (FRAME_MAGIC.len() + 1 + self.frame_size) as u64
+    }
+
+    fn frame_time(&self, frame: u64) -> Option<gst::ClockTime> {
+        let fps = self.info.fps();
+        gst::ClockTime::SECOND.mul_div_floor(frame * fps.denom() as u64, fps.numer() as u64)
+    }
+}
+
+#[derive(Default)]
+struct State {
+    /// Input not consumed yet.
+    pending: Vec<u8>,
+    stream: Option<Stream>,
+    /// Number of the next frame.
+    frame: u64,
+    /// Whether a segment must be pushed before the next frame.
+    need_segment: bool,
+    seek_seqnum: Option<gst::Seqnum>,
+}
+
+pub struct Y4mDec {
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    state: Mutex<State>,
+}
+
+impl Y4mDec {
+    /// Parses what `state.pending` holds and returns the events and buffers
+    /// to push, in order.
+    fn parse(&self, element: &super::Y4mDec, state: &mut State) -> Result<Vec<Output>, gst::FlowError> {
+        let mut output = Vec::new();
+
+        if state.stream.is_none() {
+            let line = match line(&state.pending, MAX_LINE) {
+                Ok(Some(line)) => line,
+                Ok(None) => return Ok(output),
+                Err(()) => {
+                    gst::element_error!(element, gst::StreamError::Format, ["Header line too long"]);
+                    return Err(gst::FlowError::Error);
+                }
+            };
+
+            let header = Header::parse(&state.pending[..line]).map_err(|err| {
+                gst::element_error!(element, gst::StreamError::Format, ["Invalid header: {}", err]);
+                gst::FlowError::Error
+            })?;
+            let (info, caps) = header.to_caps().map_err(|err| {
+                gst::element_error!(element, gst::StreamError::Format, ["Unsupported stream {}: {}", header, err]);
+                gst::FlowError::NotNegotiated
+            })?;
+
+            gst_info!(CAT, obj: element, "Parsed header {}, giving caps {}", header, caps);
+
+            state.pending.drain(..=line);
+            let layout = Layout::new(&header, &info);
+            state.stream = Some(Stream {
+                frame_size: layout.frame_size(),
+                layout,
+                info,
+                header_size: line as u64 + 1,
+            });
+            state.need_segment = true;
+            output.push(Output::Event(gst::event::Caps::new(&caps)));
+        }
+        let stream = state.stream.as_ref().unwrap();
+
+        loop {
+            let line = match line(&state.pending, MAX_LINE) {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(()) => {
+                    gst::element_error!(element, gst::StreamError::Demux, ["FRAME line too long"]);
+                    return Err(gst::FlowError::Error);
+                }
+            };
+            if !state.pending.starts_with(FRAME_MAGIC) {
+                gst::element_error!(element, gst::StreamError::Demux, ["Expected a FRAME line"]);
+                return Err(gst::FlowError::Error);
+            }
+            if state.pending.len() < line + 1 + stream.frame_size {
+                break;
+            }
+
+            let mut buffer = gst::Buffer::with_size(stream.info.size()).map_err(|_| gst::FlowError::Error)?;
+            {
+                let buffer = buffer.get_mut().unwrap();
+                let pts = stream.frame_time(state.frame);
+                buffer.set_pts(pts);
+                buffer.set_duration(
+                    stream
+                        .frame_time(state.frame + 1)
+                        .zip(pts)
+                        .map(|(end, pts)| end - pts),
+                );
+                buffer.set_offset(state.frame);
+                buffer.set_offset_end(state.frame + 1);
+
+                let mut frame = gst_video::VideoFrameRef::from_buffer_ref_writable(buffer, &stream.info)
+                    .map_err(|_| gst::FlowError::Error)?;
+                let data = &state.pending[line + 1..line + 1 + stream.frame_size];
+                stream.layout.unpack_frame(data, &mut frame);
+            }
+
+            if state.need_segment {
+                state.need_segment = false;
+                let mut segment = gst::FormattedSegment::<gst::ClockTime>::new();
+                if let Some(start) = stream.frame_time(state.frame) {
+                    segment.set_start(start);
+                    segment.set_time(start);
+                    segment.set_position(start);
+                }
+                let mut event = gst::event::Segment::builder(&segment);
+                if let Some(seqnum) = state.seek_seqnum.take() {
+                    event = event.seqnum(seqnum);
+                }
+                output.push(Output::Event(event.build()));
+            }
+
+            gst_log!(CAT, obj: element, "Frame {} at {:?}", state.frame, buffer.pts());
+
+            state.pending.drain(..line + 1 + stream.frame_size);
+            state.frame += 1;
+            output.push(Output::Buffer(buffer));
+        }
+
+        Ok(output)
+    }
+
+    fn sink_chain(&self, _pad: &gst::Pad, element: &super::Y4mDec, buffer: gst::Buffer) -> Result<gst::FlowSuccess, gst::FlowError> {
+        gst_log!(CAT, obj: element, "Handling {:?}", buffer);
+
+        let output = {
+            let mut state = self.state.lock();
+            let map = buffer.map_readable().map_err(|_| gst::FlowError::Error)?;
+            state.pending.extend_from_slice(&map);
+            self.parse(element, &mut state)?
+        };
+
+        for output in output {
+            match output {
+                Output::Event(event) => {
+                    self.srcpad.push_event(event);
+                }
+                Output::Buffer(buffer) => {
+                    self.srcpad.push(buffer)?;
+                }
+            }
+        }
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &super::Y4mDec, event: gst::Event) -> bool {
+        gst_log!(CAT, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            // The caps come from the header and the segment from the frames.
+            gst::EventView::Caps(_) => true,
+            gst::EventView::Segment(e) => {
+                let segment = e.segment();
+                let mut state = self.state.lock();
+                if segment.format() == gst::Format::Bytes {
+                    let start = segment.start().value().max(0) as u64;
+                    let frame = state
+                        .stream
+                        .as_ref()
+                        .filter(|stream| start >= stream.header_size)
+                        .map(|stream| {
+                            let offset = start - stream.header_size;
+                            if !offset.is_multiple_of(stream.frame_stride()) {
+                                gst_warning!(CAT, obj: element, "Byte {} is not at a frame", start);
+                            }
+                            offset / stream.frame_stride()
+                        });
+                    // From the start, the header is read again.
+                    if frame.is_none() {
+                        state.stream = None;
+                    }
+                    state.frame = frame.unwrap_or(0);
+                    state.pending.clear();
+                }
+                state.need_segment = true;
+                true
+            }
+            gst::EventView::FlushStop(_) => {
+                self.state.lock().pending.clear();
+                self.srcpad.push_event(event)
+            }
+            gst::EventView::Eos(_) => {
+                let pending = self.state.lock().pending.len();
+                if pending > 0 {
+                    gst_warning!(CAT, obj: element, "Dropping {} bytes of an incomplete frame", pending);
+                }
+                self.srcpad.push_event(event)
+            }
+            _ => self.srcpad.push_event(event),
+        }
+    }
+
+    fn src_event(&self, pad: &gst::Pad, element: &super::Y4mDec, event: gst::Event) -> bool {
+        gst_log!(CAT, obj: pad, "Handling event {:?}", event);
+
+        if let gst::EventView::Seek(e) = event.view() {
+            let (rate, flags, start_type, start, _, _) = e.get();
+            if start.format() != gst::Format::Time || start_type != gst::SeekType::Set || rate <= 0.0 {
+                gst_debug!(CAT, obj: element, "Only forward seeks to a time are supported");
+                return false;
+            }
+
+            // Seeking is done by upstream in bytes, to the frame of the time.
+            let offset = {
+                let mut state = self.state.lock();
+                let stream = match state.stream.as_ref() {
+                    Some(stream) if stream.info.fps().numer() > 0 => stream,
+                    _ => {
+                        gst_debug!(CAT, obj: element, "Cannot seek without a framerate");
+                        return false;
+                    }
+                };
+                let fps = stream.info.fps();
+                let frame = (start.value().max(0) as u64)
+                    .mul_div_floor(fps.numer() as u64, gst::ClockTime::SECOND.nseconds() * fps.denom() as u64)
+                    .unwrap_or(0);
+                let offset = stream.header_size + frame * stream.frame_stride();
+                state.seek_seqnum = Some(e.seqnum());
+
+                gst_debug!(CAT, obj: element, "Seeking to frame {} at byte {}", frame, offset);
+                offset
+            };
+
+            let seek = gst::event::Seek::builder(
+                rate,
+                flags,
+                gst::SeekType::Set,
+                gst::GenericFormattedValue::new(gst::Format::Bytes, offset as i64),
+                gst::SeekType::None,
+                gst::GenericFormattedValue::new(gst::Format::Bytes, -1),
+            )
+            .seqnum(e.seqnum())
+            .build();
+
+            return self.sinkpad.push_event(seek);
+        }
+
+        pad.event_default(Some(element), event)
+    }
+
+    /// Duration of the frames of the upstream size.
+    fn duration(&self) -> Option<gst::ClockTime> {
+        let mut bytes = gst::query::Duration::new(gst::Format::Bytes);
+        if !self.sinkpad.peer_query(&mut bytes) {
+            return None;
+        }
+        let size = bytes.result().value();
+
+        let state = self.state.lock();
+        let stream = state.stream.as_ref()?;
+        let frames = (size.max(0) as u64).checked_sub(stream.header_size)? / stream.frame_stride();
+        stream.frame_time(frames)
+    }
+
+    fn src_query(&self, pad: &gst::Pad, element: &super::Y4mDec, query: &mut gst::QueryRef) -> bool {
+        gst_log!(CAT, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            gst::QueryView::Duration(ref mut q) if q.format() == gst::Format::Time => {
+                return match self.duration() {
+                    Some(duration) => {
+                        q.set(duration);
+                        true
+                    }
+                    None => false,
+                };
+            }
+            // Seeks are translated to bytes, which needs a framerate.
+            gst::QueryView::Seeking(ref mut q) if q.format() == gst::Format::Time => {
+                let has_framerate = self
+                    .state
+                    .lock()
+                    .stream
+                    .as_ref()
+                    .is_some_and(|stream| stream.info.fps().numer() > 0);
+                let mut bytes = gst::query::Seeking::new(gst::Format::Bytes);
+                let seekable = has_framerate && self.sinkpad.peer_query(&mut bytes) && bytes.result().0;
+                q.set(seekable, Some(gst::ClockTime::ZERO), self.duration());
+                return true;
+            }
+            _ => (),
+        }
+
+        pad.query_default(Some(element), query)
+    }
+}
+
+enum Output {
+    Event(gst::Event),
+    Buffer(gst::Buffer),
+}
+
+/// Position of the first newline of `data`, if it is within `max` bytes.
+fn line(data: &[u8], max: usize) -> Result<Option<usize>, ()> {
+    match data.iter().take(max).position(|&b| b == b'\n') {
+        Some(position) => Ok(Some(position)),
+        None if data.len() >= max => Err(()),
+        None => Ok(None),
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for Y4mDec {
+    const NAME: &'static str = "RsY4mDec";
+    type Type = super::Y4mDec;
+    type ParentType = gst::Element;
+
+    fn with_class(klass: &Self::Class) -> Self {
+        let templ = klass.pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::builder_with_template(&templ, Some("sink"))
+            .chain_function(|pad, parent, buffer| {
+                Y4mDec::catch_panic_pad_function(
+                    parent,
+                    || Err(gst::FlowError::Error),
+                    |dec, element| dec.sink_chain(pad, element, buffer),
+                )
+            })
+            .event_function(|pad, parent, event| {
+                Y4mDec::catch_panic_pad_function(
+                    parent,
+                    || false,
+                    |dec, element| dec.sink_event(pad, element, event),
+                )
+            })
+            .build();
+
+        let templ = klass.pad_template("src").unwrap();
+        let srcpad = gst::Pad::builder_with_template(&templ, Some("src"))
+            .event_function(|pad, parent, event| {
+                Y4mDec::catch_panic_pad_function(
+                    parent,
+                    || false,
+                    |dec, element| dec.src_event(pad, element, event),
+                )
+            })
+            .query_function(|pad, parent, query| {
+                Y4mDec::catch_panic_pad_function(
+                    parent,
+                    || false,
+                    |dec, element| dec.src_query(pad, element, query),
+                )
+            })
+            .build();
+
+        Self {
+            sinkpad,
+            srcpad,
+            state: Mutex::new(State::default()),
+        }
+    }
+}
+
+impl ElementImpl for Y4mDec {
+    fn metadata() -> Option<&'static ElementMetadata> {
+        static METADATA: Lazy<ElementMetadata> = Lazy::new(|| {
+            ElementMetadata::new(
+                "YUV4MPEG2 demuxer",
+                "Codec/Demuxer",
+                "Demultiplex YUV4MPEG2 streams into raw video",
+                "Seiichi Uchida <topecongiro@fastmail.com>",
+            )
+        });
+
+        Some(&*METADATA)
+    }
+
+    fn pad_templates() -> &'static [PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let sink_caps = gst::Caps::builder("application/x-yuv4mpeg")
+                .field("y4mversion", 2i32)
+                .build();
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &sink_caps,
+            )
+            .unwrap();
+
+            let src_caps = gst::Caps::builder("video/x-raw")
+                .field("format", gst::List::new(format::formats()))
+                .field("width", gst::IntRange::new(1, i32::MAX))
+                .field("height", gst::IntRange::new(1, i32::MAX))
+                .field("framerate", gst::FractionRange::new(
+                    gst::Fraction::new(0, 1),
+                    gst::Fraction::new(i32::MAX, 1),
+                ))
+                .build();
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &src_caps,
+            )
+            .unwrap();
+
+            vec![src_pad_template, sink_pad_template]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+
+    fn change_state(
+        &self,
+        element: &Self::Type,
+        transition: gst::StateChange,
+    ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
+        gst_debug!(CAT, obj: element, "Changing state {:?}", transition);
+
+        if transition == gst::StateChange::ReadyToPaused {
+            *self.state.lock() = State::default();
+        }
+
+        let success = self.parent_change_state(element, transition).inspect_err(|_| {
+            gst_error!(CAT, obj: element, "Failed to change state {:?}", transition);
+        })?;
+
+        if transition == gst::StateChange::PausedToReady {
+            *self.state.lock() = State::default();
+        }
+
+        Ok(success)
+    }
+}
+
+impl GstObjectImpl for Y4mDec {}
+
+impl ObjectImpl for Y4mDec {
+    fn constructed(&self, obj: &Self::Type) {
+        self.parent_constructed(obj);
+
+        obj.add_pad(&self.sinkpad).unwrap();
+        obj.add_pad(&self.srcpad).unwrap();
+    }
+}
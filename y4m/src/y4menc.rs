@@ -0,0 +1,21 @@
+use gst::glib;
+use gst::prelude::*;
+use gst::Plugin;
+
+mod imp;
+
+glib::wrapper! {
+    pub struct Y4mEnc(ObjectSubclass<imp::Y4mEnc>) @extends gst::Element, gst::Object;
+}
+
+unsafe impl Send for Y4mEnc {}
+unsafe impl Sync for Y4mEnc {}
+
+pub fn register(plugin: &Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "rsy4menc",
+        gst::Rank::None,
+        Y4mEnc::static_type(),
+    )
+}
@@ -0,0 +1,244 @@
+use gst::{glib, gst_debug, gst_error, gst_info, gst_log, PadTemplate};
+use gst::subclass::ElementMetadata;
+use gst::subclass::prelude::*;
+use gst::prelude::*;
+use parking_lot::Mutex;
+use crate::glib::once_cell::sync::Lazy;
+
+use crate::format::{self, Header, Layout, FRAME_MAGIC};
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rsy4menc",
+        gst::DebugColorFlags::empty(),
+        Some("YUV4MPEG2 muxer"),
+    )
+});
+
+#[derive(Default)]
+struct State {
+    info: Option<gst_video::VideoInfo>,
+    header: Option<Header>,
+    layout: Option<Layout>,
+    /// Whether the header was written, after which it cannot change.
+    header_written: bool,
+}
+
+pub struct Y4mEnc {
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    state: Mutex<State>,
+}
+
+impl Y4mEnc {
+    fn sink_chain(&self, _pad: &gst::Pad, element: &super::Y4mEnc, buffer: gst::Buffer) -> Result<gst::FlowSuccess, gst::FlowError> {
+        gst_log!(CAT, obj: element, "Handling {:?}", buffer);
+
+        let mut state = self.state.lock();
+        let (info, header, layout) = match (state.info.clone(), state.header.clone(), state.layout.clone()) {
+            (Some(info), Some(header), Some(layout)) => (info, header, layout),
+            _ => {
+                gst::element_error!(element, gst::CoreError::Negotiation, ["Have no caps yet"]);
+                return Err(gst::FlowError::NotNegotiated);
+            }
+        };
+
+        let mut data = Vec::with_capacity(layout.frame_size() + 128);
+        if !state.header_written {
+            state.header_written = true;
+            data.extend_from_slice(header.to_string().as_bytes());
+            data.push(b'\n');
+        }
+        drop(state);
+
+        data.extend_from_slice(FRAME_MAGIC);
+        data.push(b'\n');
+        {
+            let frame = gst_video::VideoFrameRef::from_buffer_ref_readable(&buffer, &info).map_err(|err| {
+                gst::element_error!(
+                    element,
+                    gst::CoreError::Failed,
+                    [&format!("Failed to map buffer readable: {}", err)]
+                );
+                gst::FlowError::Error
+            })?;
+            layout.pack_frame(&frame, &mut data);
+        }
+
+        let mut outbuf = gst::Buffer::from_mut_slice(data);
+        {
+            let outbuf = outbuf.get_mut().unwrap();
+            outbuf.set_pts(buffer.pts());
+            outbuf.set_dts(buffer.dts());
+            outbuf.set_duration(buffer.duration());
+        }
+
+        self.srcpad.push(outbuf)
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &super::Y4mEnc, event: gst::Event) -> bool {
+        gst_log!(CAT, obj: pad, "Handling event {:?}", event);
+
+        if let gst::EventView::Caps(e) = event.view() {
+            let caps = e.caps();
+            let info = match gst_video::VideoInfo::from_caps(caps) {
+                Ok(info) => info,
+                Err(_) => {
+                    gst_error!(CAT, obj: element, "Failed to parse caps {}", caps);
+                    return false;
+                }
+            };
+            let field_order = caps.structure(0).and_then(|s| s.get::<&str>("field-order").ok());
+            let header = match Header::from_info(&info, field_order) {
+                Ok(header) => header,
+                Err(err) => {
+                    gst_error!(CAT, obj: element, "Cannot write caps {}: {}", caps, err);
+                    return false;
+                }
+            };
+
+            let mut state = self.state.lock();
+            if state.header_written && state.header.as_ref() != Some(&header) {
+                gst_error!(
+                    CAT,
+                    obj: element,
+                    "Cannot change the header from {} to {} in the middle of the stream",
+                    state.header.as_ref().unwrap(),
+                    header
+                );
+                return false;
+            }
+
+            gst_debug!(CAT, obj: element, "Configured for caps {} with header {}", caps, header);
+
+            state.layout = Some(Layout::new(&header, &info));
+            state.info = Some(info);
+            state.header = Some(header);
+            drop(state);
+
+            let caps = gst::Caps::builder("application/x-yuv4mpeg")
+                .field("y4mversion", 2i32)
+                .build();
+            return self.srcpad.push_event(gst::event::Caps::new(&caps));
+        }
+
+        pad.event_default(Some(element), event)
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for Y4mEnc {
+    const NAME: &'static str = "RsY4mEnc";
+    type Type = super::Y4mEnc;
+    type ParentType = gst::Element;
+
+    fn with_class(klass: &Self::Class) -> Self {
+        let templ = klass.pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::builder_with_template(&templ, Some("sink"))
+            .chain_function(|pad, parent, buffer| {
+                Y4mEnc::catch_panic_pad_function(
+                    parent,
+                    || Err(gst::FlowError::Error),
+                    |enc, element| enc.sink_chain(pad, element, buffer),
+                )
+            })
+            .event_function(|pad, parent, event| {
+                Y4mEnc::catch_panic_pad_function(
+                    parent,
+                    || false,
+                    |enc, element| enc.sink_event(pad, element, event),
+                )
+            })
+            .build();
+
+        let templ = klass.pad_template("src").unwrap();
+        let srcpad = gst::Pad::builder_with_template(&templ, Some("src")).build();
+
+        Self {
+            sinkpad,
+            srcpad,
+            state: Mutex::new(State::default()),
+        }
+    }
+}
+
+impl ElementImpl for Y4mEnc {
+    fn metadata() -> Option<&'static ElementMetadata> {
+        static METADATA: Lazy<ElementMetadata> = Lazy::new(|| {
+            ElementMetadata::new(
+                "YUV4MPEG2 muxer",
+                "Codec/Muxer",
+                "Multiplex raw video into a YUV4MPEG2 stream",
+                "Seiichi Uchida <topecongiro@fastmail.com>",
+            )
+        });
+
+        Some(&*METADATA)
+    }
+
+    fn pad_templates() -> &'static [PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let src_caps = gst::Caps::builder("application/x-yuv4mpeg")
+                .field("y4mversion", 2i32)
+                .build();
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &src_caps,
+            )
+            .unwrap();
+
+            let sink_caps = gst::Caps::builder("video/x-raw")
+                .field("format", gst::List::new(format::formats()))
+                .field("width", gst::IntRange::new(1, i32::MAX))
+                .field("height", gst::IntRange::new(1, i32::MAX))
+                .field("framerate", gst::FractionRange::new(
+                    gst::Fraction::new(0, 1),
+                    gst::Fraction::new(i32::MAX, 1),
+                ))
+                .build();
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &sink_caps,
+            )
+            .unwrap();
+
+            vec![src_pad_template, sink_pad_template]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+
+    fn change_state(
+        &self,
+        element: &Self::Type,
+        transition: gst::StateChange,
+    ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
+        gst_debug!(CAT, obj: element, "Changing state {:?}", transition);
+
+        let success = self.parent_change_state(element, transition).inspect_err(|_| {
+            gst_error!(CAT, obj: element, "Failed to change state {:?}", transition);
+        })?;
+
+        if transition == gst::StateChange::PausedToReady {
+            *self.state.lock() = State::default();
+            gst_info!(CAT, obj: element, "Stopped");
+        }
+
+        Ok(success)
+    }
+}
+
+impl GstObjectImpl for Y4mEnc {}
+
+impl ObjectImpl for Y4mEnc {
+    fn constructed(&self, obj: &Self::Type) {
+        self.parent_constructed(obj);
+
+        obj.add_pad(&self.sinkpad).unwrap();
+        obj.add_pad(&self.srcpad).unwrap();
+    }
+}
@@ -0,0 +1,186 @@
+use std::sync::{Arc, Mutex};
+
+use gst::prelude::*;
+
+fn init() {
+    use std::sync::Once;
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        gst::init().unwrap();
+        y4m::plugin_register_static().expect("y4m test");
+    });
+}
+
+const CAPS: &str = "video/x-raw,format=I420,width=8,height=2,framerate=25/1";
+/// I420 8x2 frames have no padding, so the frames are the same in buffers
+/// and in the stream.
+const FRAME_SIZE: usize = 8 * 2 + 2 * 4;
+
+fn frames() -> Vec<Vec<u8>> {
+    (0..3u8).map(|frame| (0..FRAME_SIZE as u8).map(|i| frame * 64 + i).collect()).collect()
+}
+
+/// Stream of `frames()` written by `rsy4menc`.
+fn encode() -> Vec<u8> {
+    let mut h = gst_check::Harness::new("rsy4menc");
+    h.set_src_caps_str(CAPS);
+
+    let mut stream = Vec::new();
+    for frame in frames() {
+        let buffer = h.push_and_pull(gst::Buffer::from_mut_slice(frame)).unwrap();
+        stream.extend_from_slice(&buffer.map_readable().unwrap());
+    }
+    stream
+}
+
+/// Size of the header line of `stream`, newline included.
+fn header_size(stream: &[u8]) -> usize {
+    stream.iter().position(|&b| b == b'\n').unwrap() + 1
+}
+
+#[test]
+fn test_typefind() {
+    init();
+
+    let (probability, caps) = gst::SliceTypeFind::type_find(encode());
+    assert_eq!(probability, gst::TypeFindProbability::Maximum);
+    assert_eq!(caps.unwrap().to_string(), "application/x-yuv4mpeg, y4mversion=(int)2");
+}
+
+#[test]
+fn test_round_trip() {
+    init();
+
+    let stream = encode();
+    let header = std::str::from_utf8(&stream[..header_size(&stream)]).unwrap();
+    assert!(header.starts_with("YUV4MPEG2 W8 H2 F25:1 Ip A1:1 C420"), "{}", header);
+    assert_eq!(stream.len(), header.len() + 3 * (6 + FRAME_SIZE));
+
+    // Pushed in pieces cutting through the lines and the frames.
+    let mut h = gst_check::Harness::new("rsy4mdec");
+    h.set_src_caps_str("application/x-yuv4mpeg,y4mversion=2");
+    for chunk in stream.chunks(7) {
+        h.push(gst::Buffer::from_slice(chunk.to_vec())).unwrap();
+    }
+
+    let caps = h.sinkpad().unwrap().current_caps().unwrap();
+    let info = gst_video::VideoInfo::from_caps(&caps).unwrap();
+    assert_eq!(info.format(), gst_video::VideoFormat::I420);
+    assert_eq!((info.width(), info.height(), info.fps()), (8, 2, gst::Fraction::new(25, 1)));
+
+    for (i, frame) in frames().into_iter().enumerate() {
+        let buffer = h.pull().unwrap();
+        assert_eq!(buffer.pts(), Some(gst::ClockTime::from_mseconds(40 * i as u64)));
+        assert_eq!(buffer.duration(), Some(gst::ClockTime::from_mseconds(40)));
+        assert_eq!(*buffer.map_readable().unwrap(), frame);
+    }
+}
+
+#[test]
+fn test_seek() {
+    init();
+
+    let stream = encode();
+    let header_size = header_size(&stream);
+    let frame_stride = 6 + FRAME_SIZE;
+
+    // Upstream answers in bytes and keeps the events it gets.
+    let upstream_events = Arc::new(Mutex::new(Vec::new()));
+    let upstream = {
+        let size = stream.len() as i64;
+        let upstream_events = upstream_events.clone();
+        gst::Pad::builder(Some("src"), gst::PadDirection::Src)
+            .query_function(move |_, _, query| match query.view_mut() {
+                gst::QueryView::Duration(ref mut q) if q.format() == gst::Format::Bytes => {
+                    q.set(gst::GenericFormattedValue::new(gst::Format::Bytes, size));
+                    true
+                }
+                gst::QueryView::Seeking(ref mut q) if q.format() == gst::Format::Bytes => {
+                    q.set(
+                        true,
+                        gst::GenericFormattedValue::new(gst::Format::Bytes, 0),
+                        gst::GenericFormattedValue::new(gst::Format::Bytes, size),
+                    );
+                    true
+                }
+                _ => false,
+            })
+            .event_function(move |_, _, event| {
+                upstream_events.lock().unwrap().push(event);
+                true
+            })
+            .build()
+    };
+
+    let mut h = gst_check::Harness::with_padnames("rsy4mdec", None, Some("src"));
+    upstream.set_active(true).unwrap();
+    upstream.link(&h.element().unwrap().static_pad("sink").unwrap()).unwrap();
+    upstream.push_event(gst::event::StreamStart::new("y4m"));
+    upstream.push_event(gst::event::Segment::new(&gst::FormattedSegment::<gst::format::Bytes>::new()));
+    upstream.push(gst::Buffer::from_slice(stream[..header_size + frame_stride].to_vec())).unwrap();
+    assert_eq!(h.pull().unwrap().pts(), Some(gst::ClockTime::ZERO));
+
+    // The duration and seekability follow from the upstream size.
+    let mut duration = gst::query::Duration::new(gst::Format::Time);
+    assert!(h.sinkpad().unwrap().peer_query(&mut duration));
+    assert_eq!(
+        duration.result(),
+        gst::GenericFormattedValue::from(gst::ClockTime::from_mseconds(120))
+    );
+    let mut seeking = gst::query::Seeking::new(gst::Format::Time);
+    assert!(h.sinkpad().unwrap().peer_query(&mut seeking));
+    assert!(seeking.result().0);
+
+    // A seek to the time of the last frame becomes one to its byte offset.
+    let seek = gst::event::Seek::new(
+        1.0,
+        gst::SeekFlags::FLUSH,
+        gst::SeekType::Set,
+        Some(gst::ClockTime::from_mseconds(90)),
+        gst::SeekType::None,
+        gst::ClockTime::NONE,
+    );
+    let seqnum = seek.seqnum();
+    assert!(h.push_upstream_event(seek));
+    let offset = header_size + 2 * frame_stride;
+    {
+        let upstream_events = upstream_events.lock().unwrap();
+        let seek = upstream_events.last().unwrap();
+        assert_eq!(seek.seqnum(), seqnum);
+        match seek.view() {
+            gst::EventView::Seek(e) => {
+                let (_, _, start_type, start, _, _) = e.get();
+                assert_eq!(start_type, gst::SeekType::Set);
+                assert_eq!(start, gst::GenericFormattedValue::new(gst::Format::Bytes, offset as i64));
+            }
+            _ => panic!("Expected a seek, got {:?}", seek),
+        }
+    }
+
+    // Upstream restarts at that offset, which the segment is rebuilt from.
+    upstream.push_event(gst::event::FlushStart::new());
+    upstream.push_event(gst::event::FlushStop::new(true));
+    let mut segment = gst::FormattedSegment::<gst::format::Bytes>::new();
+    segment.set_start(gst::format::Bytes(offset as u64));
+    upstream.push_event(gst::event::Segment::new(&segment));
+    upstream.push(gst::Buffer::from_slice(stream[offset..].to_vec())).unwrap();
+
+    let buffer = h.pull().unwrap();
+    assert_eq!(buffer.pts(), Some(gst::ClockTime::from_mseconds(80)));
+    assert_eq!(*buffer.map_readable().unwrap(), frames()[2]);
+
+    let segment = std::iter::from_fn(|| h.try_pull_event())
+        .filter(|event| event.type_() == gst::EventType::Segment)
+        .last()
+        .unwrap();
+    assert_eq!(segment.seqnum(), seqnum);
+    match segment.view() {
+        gst::EventView::Segment(e) => {
+            let segment = e.segment().downcast_ref::<gst::format::Time>().unwrap();
+            assert_eq!(segment.start(), Some(gst::ClockTime::from_mseconds(80)));
+            assert_eq!(segment.time(), Some(gst::ClockTime::from_mseconds(80)));
+        }
+        _ => unreachable!(),
+    }
+}